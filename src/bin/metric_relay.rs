@@ -1,14 +1,144 @@
+use std::path::PathBuf;
+
 use env_logger;
 
+use log::{error, info, warn};
+
+use structopt::StructOpt;
+
 use metric_relay::runtime;
+use metric_relay::sdnotify;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "metric_relay")]
+struct Opt {
+	/// Path to the configuration file
+	#[structopt(short, long, default_value = "config.toml", parse(from_os_str))]
+	config: PathBuf,
+	/// Seconds to wait for all sources to come up before giving up
+	#[structopt(long, default_value = "120")]
+	startup_timeout: u64,
+	#[structopt(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+	/// Run the relay (default)
+	Run,
+	/// Print the JSON Schema of the configuration file and exit
+	Schema,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+	let opt = Opt::from_args();
+	match opt.command.unwrap_or(Command::Run) {
+		Command::Schema => {
+			println!(
+				"{}",
+				serde_json::to_string_pretty(&runtime::Config::schema())?
+			);
+			return Ok(());
+		}
+		Command::Run => (),
+	}
+
 	env_logger::init();
-	let config_s = std::fs::read_to_string("config.toml")?;
-	let config: runtime::Config = toml::from_str(&config_s)?;
-	let _runtime = config.build()?;
+	let config_s = std::fs::read_to_string(&opt.config)?;
+	let config = runtime::Config::from_toml(&config_s)?;
+	let notifier = sdnotify::Notifier::from_env()?;
+	let runtime = config.build()?;
+	let shutdown = shutdown_signal();
+	tokio::pin!(shutdown);
+	let startup_timeout = core::time::Duration::new(opt.startup_timeout, 0);
+	tokio::select! {
+		result = tokio::time::timeout(startup_timeout, runtime.ready()) => match result {
+			Ok(Ok(())) => (),
+			Ok(Err(node)) => {
+				return Err(format!("source failed during startup: {}", node).into());
+			}
+			Err(_) => {
+				return Err(format!(
+					"sources not running after {}s: {}",
+					opt.startup_timeout,
+					runtime.status_summary()
+				)
+				.into());
+			}
+		},
+		result = &mut shutdown => {
+			result?;
+			info!("shutting down during startup");
+			return Ok(());
+		}
+	}
+	info!("started: {}", runtime.status_summary());
+	let watchdog = match notifier.as_ref() {
+		Some(notifier) => {
+			notifier.ready(&runtime.status_summary())?;
+			sdnotify::watchdog_interval()
+		}
+		None => None,
+	};
+
+	let mut status_interval = tokio::time::interval(core::time::Duration::new(20, 0));
+	// ping twice per interval so that a late tick does not trip the watchdog
+	let watchdog_period = watchdog
+		.map(|x| x / 2)
+		.unwrap_or(core::time::Duration::new(3600, 0));
+	let mut watchdog_interval = tokio::time::interval(watchdog_period);
 	loop {
-		tokio::time::sleep(core::time::Duration::new(20, 0)).await;
+		tokio::select! {
+			_ = status_interval.tick() => {
+				for node in runtime.failed_nodes() {
+					error!("node permanently failed: {}", node);
+				}
+				for node in runtime.degraded_nodes() {
+					warn!("node degraded: {}", node);
+				}
+				if let Some(notifier) = notifier.as_ref() {
+					if let Err(e) = notifier.status(&runtime.status_summary()) {
+						warn!("failed to notify service manager: {}", e);
+					}
+				}
+			}
+			_ = watchdog_interval.tick(), if watchdog.is_some() => {
+				// withholding the ping makes systemd restart us once the
+				// supervision got stuck; nodes which failed permanently are
+				// reported in the status instead, as a restart would not
+				// help the others
+				let stalled = runtime.stalled(watchdog_period);
+				for problem in stalled.iter() {
+					error!("supervision stalled: {}", problem);
+				}
+				if stalled.is_empty() {
+					if let Some(notifier) = notifier.as_ref() {
+						if let Err(e) = notifier.watchdog() {
+							warn!("failed to ping service manager watchdog: {}", e);
+						}
+					}
+				}
+			}
+			result = &mut shutdown => {
+				result?;
+				break;
+			}
+		}
+	}
+	info!("shutting down");
+	if let Some(notifier) = notifier.as_ref() {
+		let _ = notifier.stopping();
+	}
+	// dropping the runtime persists the node state
+	drop(runtime);
+	Ok(())
+}
+
+async fn shutdown_signal() -> std::io::Result<()> {
+	let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+	tokio::select! {
+		result = tokio::signal::ctrl_c() => result,
+		_ = sigterm.recv() => Ok(()),
 	}
 }
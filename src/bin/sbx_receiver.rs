@@ -38,7 +38,9 @@ fn dump<R: Buf>(
 			if let sbx::Message::Status(ref status) = msg {
 				if hdr.timestamp != 0 {
 					let rtc = Utc.timestamp(hdr.timestamp as i64, 0);
-					rtcifier.align(rtc, status.uptime);
+					rtcifier
+						.align(rtc, status.uptime)
+						.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 					*aligned = true;
 				}
 			}
@@ -49,7 +51,10 @@ fn dump<R: Buf>(
 					Bytes::copy_from_slice(&msg.readout[..]));
 			} */
 			if *aligned {
-				for readout in msg.readouts(rtcifier) {
+				let readouts = msg
+					.readouts(rtcifier)
+					.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+				for readout in readouts {
 					println!("  {}", readout.timestamp);
 					println!(
 						"    {} @ {}",
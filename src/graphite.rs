@@ -0,0 +1,158 @@
+//! # Carbon plaintext protocol
+//!
+//! Each component of a readout becomes one line
+//!
+//! ```text
+//! <path> <value> <timestamp>
+//! ```
+//!
+//! with the timestamp in seconds since the epoch. The path is built from a
+//! template over `{device_type}`, `{instance}` and `{component}`; dots,
+//! whitespace and other characters with a meaning in Graphite are replaced
+//! by underscores within each placeholder, and slashes in instances (such
+//! as `/sbx/i2c-2/76`) separate nodes.
+use std::fmt::Write;
+
+use microtemplate::{render, Substitutions};
+
+use crate::metric;
+
+#[derive(Substitutions)]
+struct TemplateArgs<'a> {
+	device_type: &'a str,
+	instance: &'a str,
+	component: &'a str,
+}
+
+/// Replace everything which is not safe within a single node of a path.
+pub fn sanitize_node(node: &str) -> String {
+	node.chars()
+		.map(|ch| match ch {
+			'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | ':' => ch,
+			_ => '_',
+		})
+		.collect()
+}
+
+pub struct PathTemplate {
+	template: String,
+	/// Put between the parts of instances separated by slashes; `.` makes
+	/// them nodes of their own.
+	instance_separator: String,
+}
+
+impl PathTemplate {
+	pub fn new(template: String, instance_separator: String) -> Self {
+		Self {
+			template,
+			instance_separator,
+		}
+	}
+
+	pub fn render(&self, path: &metric::DevicePath, component: &str) -> String {
+		let instance = path
+			.instance
+			.split('/')
+			.filter(|part| !part.is_empty())
+			.map(sanitize_node)
+			.collect::<Vec<_>>()
+			.join(&self.instance_separator);
+		let rendered = render(
+			&self.template,
+			TemplateArgs {
+				device_type: &sanitize_node(&path.device_type),
+				instance: &instance,
+				component: &sanitize_node(component),
+			},
+		);
+		// empty placeholders must not leave empty nodes behind
+		rendered
+			.split('.')
+			.filter(|node| !node.is_empty())
+			.collect::<Vec<_>>()
+			.join(".")
+	}
+}
+
+/// Append the lines for a readout to `out` and return their number.
+///
+/// Values which are not finite are skipped, as Graphite has no use for
+/// them.
+pub fn write_lines(readout: &metric::Readout, template: &PathTemplate, out: &mut String) -> usize {
+	let timestamp = readout.timestamp.timestamp();
+	let mut n = 0;
+	for (component, value) in readout.components.iter() {
+		if !value.magnitude.is_finite() {
+			continue;
+		}
+		let _ = writeln!(
+			out,
+			"{} {} {}",
+			template.render(&readout.path, component),
+			value.magnitude,
+			timestamp
+		);
+		n += 1;
+	}
+	n
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::{TimeZone, Utc};
+
+	fn path(instance: &str) -> metric::DevicePath {
+		metric::DevicePath {
+			device_type: "bme280".into(),
+			instance: instance.into(),
+		}
+	}
+
+	#[test]
+	fn renders_paths() {
+		let template = PathTemplate::new(
+			"sensors.{device_type}.{instance}.{component}".into(),
+			".".into(),
+		);
+		assert_eq!(
+			template.render(&path("/sbx/i2c-2/76"), "temperature"),
+			"sensors.bme280.sbx.i2c-2.76.temperature"
+		);
+		assert_eq!(
+			template.render(&path(""), "rel humidity"),
+			"sensors.bme280.rel_humidity"
+		);
+		let flat = PathTemplate::new("{device_type}.{instance}.{component}".into(), "_".into());
+		assert_eq!(flat.render(&path("i2c-2/0.76"), "t"), "bme280.i2c-2_0_76.t");
+	}
+
+	#[test]
+	fn writes_lines_for_finite_values() {
+		let mut components = metric::OrderedVec::new();
+		components.insert(
+			"pressure".into(),
+			metric::Value {
+				magnitude: 101325.5,
+				unit: metric::Unit::Pascal,
+			},
+		);
+		components.insert(
+			"temperature".into(),
+			metric::Value {
+				magnitude: f64::NAN,
+				unit: metric::Unit::Celsius,
+			},
+		);
+		let readout = metric::Readout {
+			timestamp: Utc.timestamp_opt(1600000000, 500_000_000).unwrap(),
+			path: path("a"),
+			components,
+		};
+		let template = PathTemplate::new("{device_type}.{instance}.{component}".into(), ".".into());
+		let mut out = String::new();
+		assert_eq!(write_lines(&readout, &template, &mut out), 1);
+		assert_eq!(out, "bme280.a.pressure 101325.5 1600000000\n");
+	}
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+use super::Auth;
+
+impl Auth {
+	pub fn apply(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+		match self {
+			Self::None => req,
+			Self::Basic { username, password } => req.basic_auth(username, Some(password)),
+			Self::Bearer { token } => req.bearer_auth(token),
+		}
+	}
+}
+
+/// Whether a transport error may go away if the request is sent again.
+///
+/// This is the case for timeouts, connection failures (refused, name
+/// resolution) and errors while sending the request, but not for requests
+/// which could not be built or responses which could not be read.
+pub fn is_transient(err: &reqwest::Error) -> bool {
+	err.is_timeout() || err.is_connect() || err.is_request()
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Request(reqwest::Error),
+	/// The server rejected the request (4xx); sending it again will not
+	/// help.
+	Rejected(reqwest::StatusCode),
+	/// The server is overloaded or otherwise temporarily unable to handle
+	/// the request (5xx, 429).
+	Unavailable(reqwest::StatusCode),
+}
+
+impl Error {
+	/// Whether the same request may succeed later on.
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			Self::Request(e) => is_transient(e),
+			Self::Unavailable(_) => true,
+			Self::Rejected(_) => false,
+		}
+	}
+
+	/// Map the status of a response to an error, if it is not a success.
+	pub fn check_status(status: reqwest::StatusCode) -> Result<(), Self> {
+		if status.is_success() {
+			Ok(())
+		} else if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+			Err(Self::Unavailable(status))
+		} else {
+			Err(Self::Rejected(status))
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Request(e) => fmt::Display::fmt(e, f),
+			Self::Rejected(status) => write!(f, "request rejected ({})", status),
+			Self::Unavailable(status) => write!(f, "server unavailable ({})", status),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+	fn from(err: reqwest::Error) -> Self {
+		Self::Request(err)
+	}
+}
+
+/// Send a request and map an unsuccessful response to an error.
+pub async fn send(req: reqwest::RequestBuilder) -> Result<(), Error> {
+	let resp = req.send().await?;
+	Error::check_status(resp.status())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use reqwest::StatusCode;
+
+	#[test]
+	fn maps_status() {
+		assert!(Error::check_status(StatusCode::NO_CONTENT).is_ok());
+		for status in [
+			StatusCode::SERVICE_UNAVAILABLE,
+			StatusCode::TOO_MANY_REQUESTS,
+		] {
+			let err = Error::check_status(status).unwrap_err();
+			assert!(matches!(err, Error::Unavailable(_)));
+			assert!(err.is_retryable());
+		}
+		let err = Error::check_status(StatusCode::BAD_REQUEST).unwrap_err();
+		assert!(matches!(err, Error::Rejected(_)));
+		assert!(!err.is_retryable());
+	}
+
+	#[tokio::test]
+	async fn malformed_url_is_not_retryable() {
+		let err = send(reqwest::Client::new().get("not a url"))
+			.await
+			.unwrap_err();
+		assert!(matches!(err, Error::Request(_)));
+		assert!(!err.is_retryable());
+	}
+}
@@ -0,0 +1,21 @@
+//! # Shared bits of the HTTP based sinks
+#[cfg(feature = "http")]
+mod client;
+
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+
+#[cfg(feature = "http")]
+pub use client::{is_transient, send, Error};
+
+/// Credentials sent along with each request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum Auth {
+	#[default]
+	None,
+	/// HTTP basic authentication.
+	Basic { username: String, password: String },
+	/// Bearer token in the `Authorization` header.
+	Bearer { token: String },
+}
@@ -9,6 +9,8 @@ use glob::{MatchOptions, Pattern};
 #[cfg(feature = "regex")]
 use regex::Regex;
 
+use crate::metric::Unit;
+
 use super::readout::{Readout, Sample};
 
 pub trait Filter: Send + Sync {
@@ -104,6 +106,13 @@ impl Filter for Transpose {
 		std::mem::swap(&mut new_samples, &mut readout_mut.samples);
 		readout_mut.fields.clear();
 		readout_mut.fields.push(self.field.clone());
+		// the unit survives only if all transposed fields agree on it
+		let unit = match readout_mut.units.split_first() {
+			Some((first, rest)) if rest.iter().all(|x| x == first) => first.clone(),
+			_ => Unit::Arbitrary,
+		};
+		readout_mut.units.clear();
+		readout_mut.units.push(unit);
 		Some(readout)
 	}
 }
@@ -134,8 +143,14 @@ impl Filter for TagToField {
 		}
 
 		let readout_mut = Arc::make_mut(&mut readout);
+		let unit = readout_mut
+			.units
+			.first()
+			.cloned()
+			.unwrap_or(Unit::Arbitrary);
 		readout_mut.fields.clear();
 		readout_mut.fields.reserve(readout_mut.samples.len());
+		readout_mut.units.clear();
 		let mut new_fieldv = Vec::with_capacity(readout_mut.samples.len());
 		let mut new_tagv = Vec::with_capacity(1);
 
@@ -152,7 +167,8 @@ impl Filter for TagToField {
 				);
 			}
 			readout_mut.fields.push(field_name);
-			new_fieldv.push(sample.fieldv[0]);
+			readout_mut.units.push(unit.clone());
+			new_fieldv.push(sample.fieldv[0].clone());
 		}
 
 		readout_mut.samples.push(Sample {
@@ -164,6 +180,247 @@ impl Filter for TagToField {
 	}
 }
 
+/// Add tags with fixed values to all samples.
+pub struct AddTags {
+	pub predicate: Select,
+	pub tags: Vec<(SmartString, SmartString)>,
+}
+
+impl Filter for AddTags {
+	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
+		if !self.predicate.matches(&readout) {
+			return Some(readout);
+		}
+
+		let readout_mut = Arc::make_mut(&mut readout);
+		for (tag, value) in self.tags.iter() {
+			match readout_mut.tags.iter().position(|x| x == tag) {
+				Some(index) => {
+					for sample in readout_mut.samples.iter_mut() {
+						sample.tagv[index] = value.clone();
+					}
+				}
+				None => {
+					readout_mut.tags.push(tag.clone());
+					for sample in readout_mut.samples.iter_mut() {
+						sample.tagv.push(value.clone());
+					}
+				}
+			}
+		}
+		Some(readout)
+	}
+}
+
+/// Rewrite the measurement name using a regular expression.
+#[cfg(feature = "regex")]
+pub struct RenameMeasurement {
+	pub predicate: Select,
+	pub expr: Regex,
+	pub replacement: SmartString,
+}
+
+#[cfg(feature = "regex")]
+impl Filter for RenameMeasurement {
+	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
+		if !self.predicate.matches(&readout) || !self.expr.is_match(&readout.measurement) {
+			return Some(readout);
+		}
+
+		let readout_mut = Arc::make_mut(&mut readout);
+		readout_mut.measurement = self
+			.expr
+			.replace(&readout_mut.measurement, &self.replacement[..])
+			.into();
+		Some(readout)
+	}
+}
+
+/// Rewrite field names using a regular expression.
+#[cfg(feature = "regex")]
+pub struct RenameFields {
+	pub predicate: Select,
+	pub expr: Regex,
+	pub replacement: SmartString,
+}
+
+#[cfg(feature = "regex")]
+impl Filter for RenameFields {
+	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
+		if !self.predicate.matches(&readout) {
+			return Some(readout);
+		}
+
+		let readout_mut = Arc::make_mut(&mut readout);
+		for field in readout_mut.fields.iter_mut() {
+			if self.expr.is_match(field) {
+				*field = self.expr.replace(field, &self.replacement[..]).into();
+			}
+		}
+		Some(readout)
+	}
+}
+
+/// Split the value of a tag at a separator into several tags.
+///
+/// Empty segments (e.g. from a leading separator) are ignored. If there are
+/// more segments than tag names, the remaining segments are kept joined in
+/// the last tag; missing segments result in empty (and thus omitted) tags.
+pub struct SplitTag {
+	pub predicate: Select,
+	pub tag: SmartString,
+	pub separator: char,
+	pub into: Vec<SmartString>,
+	/// Keep the original tag next to the new ones.
+	pub keep: bool,
+}
+
+impl SplitTag {
+	fn split(&self, value: &str) -> Vec<SmartString> {
+		let mut result = Vec::with_capacity(self.into.len());
+		let mut rest = value.trim_start_matches(self.separator);
+		while result.len() + 1 < self.into.len() {
+			let (head, tail) = match rest.split_once(self.separator) {
+				Some(v) => v,
+				None => (rest, ""),
+			};
+			result.push(head.into());
+			rest = tail.trim_start_matches(self.separator);
+		}
+		if !self.into.is_empty() {
+			result.push(rest.trim_end_matches(self.separator).into());
+		}
+		result
+	}
+}
+
+impl Filter for SplitTag {
+	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
+		if !self.predicate.matches(&readout) {
+			return Some(readout);
+		}
+		let index = match readout.tags.iter().position(|x| *x == self.tag) {
+			Some(v) => v,
+			None => return Some(readout),
+		};
+
+		let readout_mut = Arc::make_mut(&mut readout);
+		for sample in readout_mut.samples.iter_mut() {
+			let parts = self.split(&sample.tagv[index]);
+			if !self.keep {
+				sample.tagv.remove(index);
+			}
+			sample.tagv.extend(parts);
+		}
+		if !self.keep {
+			readout_mut.tags.remove(index);
+		}
+		readout_mut.tags.extend(self.into.iter().cloned());
+		Some(readout)
+	}
+}
+
+/// Remove fields whose names match any of the patterns.
+///
+/// Readouts without any remaining fields are dropped.
+pub struct DropFields {
+	pub predicate: Select,
+	pub fields: Vec<Pattern>,
+}
+
+impl Filter for DropFields {
+	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
+		if !self.predicate.matches(&readout) {
+			return Some(readout);
+		}
+		let keep: Vec<bool> = readout
+			.fields
+			.iter()
+			.map(|field| !self.fields.iter().any(|p| p.matches(field)))
+			.collect();
+		if keep.iter().all(|x| *x) {
+			return Some(readout);
+		}
+		if !keep.iter().any(|x| *x) {
+			return None;
+		}
+
+		fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+			let mut index = 0;
+			values.retain(|_| {
+				index += 1;
+				keep.get(index - 1).copied().unwrap_or(false)
+			});
+		}
+
+		let readout_mut = Arc::make_mut(&mut readout);
+		retain(&mut readout_mut.fields, &keep);
+		retain(&mut readout_mut.units, &keep);
+		for sample in readout_mut.samples.iter_mut() {
+			retain(&mut sample.fieldv, &keep);
+		}
+		Some(readout)
+	}
+}
+
+/// How [`UnitLabel`] records the unit of a field.
+pub enum UnitLabelMode {
+	/// Add a tag with the given name. Requires all fields of a readout to
+	/// share the same unit.
+	Tag(SmartString),
+	/// Append the unit to the field name, after the given separator.
+	Suffix(SmartString),
+}
+
+/// Make the unit of fields visible in InfluxDB.
+///
+/// Fields without a unit symbol (e.g. counters) are left alone.
+pub struct UnitLabel {
+	pub predicate: Select,
+	pub mode: UnitLabelMode,
+}
+
+impl Filter for UnitLabel {
+	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
+		if !self.predicate.matches(&readout) {
+			return Some(readout);
+		}
+
+		match &self.mode {
+			UnitLabelMode::Tag(tag) => {
+				let unit = match readout.units.split_first() {
+					Some((first, rest)) if rest.iter().all(|x| x == first) => first.to_string(),
+					Some(_) => {
+						warn!(
+							"cannot tag {:?} with a unit as its fields have different units; transpose it first",
+							readout.measurement
+						);
+						return Some(readout);
+					}
+					None => return Some(readout),
+				};
+				let readout_mut = Arc::make_mut(&mut readout);
+				readout_mut.tags.push(tag.clone());
+				for sample in readout_mut.samples.iter_mut() {
+					sample.tagv.push(unit.as_str().into());
+				}
+			}
+			UnitLabelMode::Suffix(separator) => {
+				let readout_mut = Arc::make_mut(&mut readout);
+				for (field, unit) in readout_mut.fields.iter_mut().zip(readout_mut.units.iter()) {
+					let symbol = unit.to_string();
+					if symbol.is_empty() {
+						continue;
+					}
+					field.push_str(separator);
+					field.push_str(&symbol);
+				}
+			}
+		}
+		Some(readout)
+	}
+}
+
 impl Filter for Vec<Box<dyn Filter>> {
 	fn process(&self, mut readout: Arc<Readout>) -> Option<Arc<Readout>> {
 		for filter in self.iter() {
@@ -172,3 +429,153 @@ impl Filter for Vec<Box<dyn Filter>> {
 		Some(readout)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::{TimeZone, Utc};
+
+	use super::super::readout::{FieldValue, Precision};
+
+	fn readout(fields: Vec<(&str, Unit)>) -> Arc<Readout> {
+		let fieldv = fields.iter().map(|_| FieldValue::Float(1.0)).collect();
+		let (fields, units) = fields
+			.into_iter()
+			.map(|(k, unit)| (SmartString::from(k), unit))
+			.unzip();
+		Arc::new(Readout {
+			ts: Utc.timestamp_opt(1600000000, 0).unwrap(),
+			measurement: "bme280".into(),
+			precision: Precision::Seconds,
+			tags: vec!["instance".into()],
+			fields,
+			units,
+			samples: vec![Sample {
+				tagv: vec!["/i2c-2/76".into()],
+				fieldv,
+			}],
+		})
+	}
+
+	fn to_string(readout: &Readout) -> String {
+		let mut buf = Vec::new();
+		readout.write(&mut buf).unwrap();
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn add_tags_appends_and_overrides() {
+		let filter = AddTags {
+			predicate: Select::default(),
+			tags: vec![
+				("site".into(), "lab".into()),
+				("instance".into(), "x".into()),
+			],
+		};
+		let r = filter.process(readout(vec![("t", Unit::Celsius)])).unwrap();
+		assert_eq!(
+			to_string(&r),
+			"bme280,instance=x,site=lab t=1.0 1600000000\n"
+		);
+	}
+
+	#[test]
+	fn split_tag_distributes_segments() {
+		let filter = SplitTag {
+			predicate: Select::default(),
+			tag: "instance".into(),
+			separator: '/',
+			into: vec!["bus".into(), "address".into()],
+			keep: false,
+		};
+		let r = filter.process(readout(vec![("t", Unit::Celsius)])).unwrap();
+		assert_eq!(
+			to_string(&r),
+			"bme280,bus=i2c-2,address=76 t=1.0 1600000000\n"
+		);
+		assert_eq!(filter.split("a/b/c"), vec!["a", "b/c"]);
+		assert_eq!(filter.split("a"), vec!["a", ""]);
+	}
+
+	#[test]
+	fn drop_fields_removes_matching_fields() {
+		let filter = DropFields {
+			predicate: Select::default(),
+			fields: vec![Pattern::new("gas*").unwrap()],
+		};
+		let r = filter
+			.process(readout(vec![
+				("gas_resistance", Unit::Arbitrary),
+				("t", Unit::Celsius),
+			]))
+			.unwrap();
+		assert_eq!(r.fields, vec!["t"]);
+		assert_eq!(r.units, vec![Unit::Celsius]);
+		assert_eq!(r.samples[0].fieldv.len(), 1);
+		assert!(filter
+			.process(readout(vec![("gas", Unit::Arbitrary)]))
+			.is_none());
+	}
+
+	#[test]
+	fn unit_label_as_suffix_or_tag() {
+		let suffix = UnitLabel {
+			predicate: Select::default(),
+			mode: UnitLabelMode::Suffix("_".into()),
+		};
+		let r = suffix
+			.process(readout(vec![("p", Unit::Pascal), ("n", Unit::Total)]))
+			.unwrap();
+		assert_eq!(r.fields, vec!["p_Pa", "n"]);
+
+		let tag = UnitLabel {
+			predicate: Select::default(),
+			mode: UnitLabelMode::Tag("unit".into()),
+		};
+		let r = tag.process(readout(vec![("t", Unit::Kelvin)])).unwrap();
+		assert_eq!(
+			to_string(&r),
+			"bme280,instance=/i2c-2/76,unit=K t=1.0 1600000000\n"
+		);
+		let mixed = readout(vec![("t", Unit::Kelvin), ("p", Unit::Pascal)]);
+		assert_eq!(tag.process(mixed).unwrap().tags.len(), 1);
+	}
+
+	#[test]
+	fn transpose_keeps_common_unit() {
+		let filter = Transpose {
+			predicate: Select::default(),
+			tag: "axis".into(),
+			field: "value".into(),
+		};
+		let r = filter
+			.process(readout(vec![
+				("x", Unit::MeterPerSqSecond),
+				("y", Unit::MeterPerSqSecond),
+			]))
+			.unwrap();
+		assert_eq!(r.units, vec![Unit::MeterPerSqSecond]);
+	}
+
+	#[cfg(feature = "regex")]
+	#[test]
+	fn rename_rewrites_measurement_and_fields() {
+		let r = RenameMeasurement {
+			predicate: Select::default(),
+			expr: Regex::new("^bme(\\d+)$").unwrap(),
+			replacement: "env_$1".into(),
+		}
+		.process(readout(vec![("temp", Unit::Celsius), ("p", Unit::Pascal)]))
+		.unwrap();
+		let r = RenameFields {
+			predicate: Select::default(),
+			expr: Regex::new("^temp$").unwrap(),
+			replacement: "temperature".into(),
+		}
+		.process(r)
+		.unwrap();
+		assert_eq!(r.measurement, "env_280");
+		assert_eq!(r.fields, vec!["temperature", "p"]);
+	}
+}
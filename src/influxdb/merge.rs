@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::debug;
+
+use smartstring::alias::String as SmartString;
+
+use chrono::{DateTime, Utc};
+
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+
+use super::readout::{Precision, Readout, Sample};
+
+/// What to do if two lines being merged carry the same field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+pub enum Conflict {
+	/// Keep the value seen first.
+	First,
+	/// Keep the value seen last; this is what InfluxDB itself does with
+	/// duplicate points.
+	#[default]
+	Last,
+	/// Do not merge the lines, but write them separately.
+	Separate,
+}
+
+/// Combine readouts which only differ in their samples into one readout.
+///
+/// Readouts are grouped if they share the measurement, timestamp, precision
+/// and tag and field names. This allows mappings such as
+/// [`Transpose`](super::Transpose) or `TagToField` to operate across
+/// sensors of the same type.
+pub fn group(readouts: Vec<Arc<Readout>>) -> Vec<Arc<Readout>> {
+	let mut result: Vec<Arc<Readout>> = Vec::with_capacity(readouts.len());
+	for readout in readouts {
+		let existing = result.iter_mut().find(|x| {
+			x.measurement == readout.measurement
+				&& x.ts == readout.ts
+				&& x.precision == readout.precision
+				&& x.tags == readout.tags
+				&& x.fields == readout.fields
+				&& x.units == readout.units
+		});
+		match existing {
+			Some(target) => Arc::make_mut(target)
+				.samples
+				.extend(readout.samples.iter().cloned()),
+			None => result.push(readout),
+		}
+	}
+	result
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct LineKey {
+	measurement: SmartString,
+	ts: DateTime<Utc>,
+	precision: Precision,
+	tags: Vec<(SmartString, SmartString)>,
+}
+
+/// Merge lines with the same measurement, tag set and timestamp into a
+/// single line carrying the union of their fields.
+///
+/// The result contains one readout per line, in order of first appearance.
+pub fn coalesce(readouts: Vec<Arc<Readout>>, conflict: Conflict) -> Vec<Arc<Readout>> {
+	let mut result: Vec<Readout> = Vec::with_capacity(readouts.len());
+	let mut index: HashMap<LineKey, usize> = HashMap::new();
+	for readout in readouts.iter() {
+		for sample in readout.samples.iter() {
+			let mut tags: Vec<(SmartString, SmartString)> = readout
+				.tags
+				.iter()
+				.zip(sample.tagv.iter())
+				.filter(|(_, v)| !v.is_empty())
+				.map(|(k, v)| (k.clone(), v.clone()))
+				.collect();
+			tags.sort();
+			let key = LineKey {
+				measurement: readout.measurement.clone(),
+				ts: readout.ts,
+				precision: readout.precision,
+				tags,
+			};
+
+			if let Some(&at) = index.get(&key) {
+				let target = &mut result[at];
+				let conflicts = readout
+					.fields
+					.iter()
+					.any(|field| target.fields.contains(field));
+				if !(conflicts && conflict == Conflict::Separate) {
+					merge_into(target, readout, sample, conflict);
+					continue;
+				}
+				debug!(
+					"not merging line of {:?} due to conflicting fields",
+					readout.measurement
+				);
+			}
+
+			let (tags, tagv) = key.tags.iter().cloned().unzip();
+			index.insert(key, result.len());
+			result.push(Readout {
+				ts: readout.ts,
+				measurement: readout.measurement.clone(),
+				precision: readout.precision,
+				tags,
+				fields: readout.fields.clone(),
+				units: readout.units.clone(),
+				samples: vec![Sample {
+					tagv,
+					fieldv: sample.fieldv.clone(),
+				}],
+			});
+		}
+	}
+	result.into_iter().map(Arc::new).collect()
+}
+
+fn merge_into(target: &mut Readout, readout: &Readout, sample: &Sample, conflict: Conflict) {
+	for (i, (field, value)) in readout.fields.iter().zip(sample.fieldv.iter()).enumerate() {
+		match target.fields.iter().position(|x| x == field) {
+			Some(at) => {
+				if conflict == Conflict::Last {
+					target.samples[0].fieldv[at] = value.clone();
+					if let Some(unit) = readout.units.get(i) {
+						target.units[at] = unit.clone();
+					}
+				}
+			}
+			None => {
+				target.fields.push(field.clone());
+				target.units.push(
+					readout
+						.units
+						.get(i)
+						.cloned()
+						.unwrap_or(crate::metric::Unit::Arbitrary),
+				);
+				target.samples[0].fieldv.push(value.clone());
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::TimeZone;
+
+	use crate::metric::Unit;
+
+	use super::super::readout::FieldValue;
+
+	fn readout(instance: &str, ts: i64, fields: Vec<(&str, f64)>) -> Arc<Readout> {
+		let (fields, fieldv): (Vec<_>, Vec<_>) = fields
+			.into_iter()
+			.map(|(k, v)| (SmartString::from(k), FieldValue::Float(v)))
+			.unzip();
+		Arc::new(Readout {
+			ts: Utc.timestamp_opt(ts, 0).unwrap(),
+			measurement: "tcs3200".into(),
+			precision: Precision::Seconds,
+			tags: vec!["instance".into()],
+			units: vec![Unit::Arbitrary; fields.len()],
+			fields,
+			samples: vec![Sample {
+				tagv: vec![instance.into()],
+				fieldv,
+			}],
+		})
+	}
+
+	fn to_string(readouts: &[Arc<Readout>]) -> String {
+		let mut buf = Vec::new();
+		for readout in readouts {
+			readout.write(&mut buf).unwrap();
+		}
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn group_collects_samples_of_same_shape() {
+		let grouped = group(vec![
+			readout("a", 1, vec![("v", 1.0)]),
+			readout("b", 1, vec![("v", 2.0)]),
+			readout("c", 2, vec![("v", 3.0)]),
+		]);
+		assert_eq!(grouped.len(), 2);
+		assert_eq!(grouped[0].samples.len(), 2);
+		assert_eq!(
+			to_string(&grouped),
+			"tcs3200,instance=a v=1.0 1\ntcs3200,instance=b v=2.0 1\ntcs3200,instance=c v=3.0 2\n"
+		);
+	}
+
+	#[test]
+	fn coalesce_merges_fields_of_same_series_and_time() {
+		let merged = coalesce(
+			vec![
+				readout("a", 1, vec![("red", 1.0)]),
+				readout("b", 1, vec![("red", 5.0)]),
+				readout("a", 1, vec![("green", 2.0)]),
+				readout("a", 2, vec![("blue", 3.0)]),
+			],
+			Conflict::Last,
+		);
+		assert_eq!(
+			to_string(&merged),
+			"tcs3200,instance=a red=1.0,green=2.0 1\ntcs3200,instance=b red=5.0 1\ntcs3200,instance=a blue=3.0 2\n"
+		);
+	}
+
+	#[test]
+	fn coalesce_resolves_conflicts() {
+		let input = vec![
+			readout("a", 1, vec![("v", 1.0)]),
+			readout("a", 1, vec![("v", 2.0), ("w", 3.0)]),
+		];
+		assert_eq!(
+			to_string(&coalesce(input.clone(), Conflict::First)),
+			"tcs3200,instance=a v=1.0,w=3.0 1\n"
+		);
+		assert_eq!(
+			to_string(&coalesce(input.clone(), Conflict::Last)),
+			"tcs3200,instance=a v=2.0,w=3.0 1\n"
+		);
+		assert_eq!(
+			to_string(&coalesce(input, Conflict::Separate)),
+			"tcs3200,instance=a v=1.0 1\ntcs3200,instance=a v=2.0,w=3.0 1\n"
+		);
+	}
+}
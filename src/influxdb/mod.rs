@@ -1,28 +1,49 @@
 use std::fmt;
+use std::io::Write;
 use std::sync::Arc;
+use std::time::Duration;
 
 use log::trace;
 
 use base64::Engine;
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
+
+use flate2::write::GzEncoder;
 use reqwest;
 
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 
 mod filter;
+mod merge;
 mod readout;
+mod spool;
 
+pub use filter::{
+	AddTags, DropFields, Filter, Select, SplitTag, Transpose, UnitLabel, UnitLabelMode,
+};
 #[cfg(feature = "regex")]
-pub use filter::TagToField;
-pub use filter::{Filter, Select, Transpose};
-pub use readout::{Precision, Readout};
+pub use filter::{RenameFields, RenameMeasurement, TagToField};
+pub use merge::{coalesce, group, Conflict};
+pub use readout::{FieldValue, Precision, Readout, Sample};
+pub use spool::{DiskBuffer, SharedDiskBuffer, SpoolEntry};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum Auth {
 	None,
-	HTTP { username: String, password: String },
-	Query { username: String, password: String },
+	HTTP {
+		username: String,
+		password: String,
+	},
+	Query {
+		username: String,
+		password: String,
+	},
+	/// API token, as used by InfluxDB 2.x and later.
+	Token {
+		token: String,
+	},
 }
 
 impl Auth {
@@ -38,16 +59,55 @@ impl Auth {
 				),
 			),
 			Self::Query { username, password } => req.query(&[("u", username), ("p", password)]),
+			Self::Token { token } => req.header("Authorization", format!("Token {}", token)),
 		}
 	}
 }
 
+/// Where written data ends up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+	/// Database and retention policy, using the 1.x `/write` endpoint.
+	V1 {
+		database: String,
+		retention_policy: Option<String>,
+	},
+	/// Organization and bucket, using the 2.x `/api/v2/write` endpoint.
+	V2 { org: String, bucket: String },
+}
+
+#[derive(Debug)]
 pub enum Error {
 	Request(reqwest::Error),
 	PermissionError,
 	DataError,
 	DatabaseNotFound,
+	/// The server is overloaded or otherwise temporarily unable to accept
+	/// writes (5xx, 429).
+	Unavailable(reqwest::StatusCode),
 	UnexpectedSuccessStatus,
+	/// A query was accepted, but its execution failed.
+	QueryFailed(String),
+}
+
+impl Error {
+	/// Whether the same request may succeed later on.
+	///
+	/// This is the case for transport errors (connection refused, timeouts,
+	/// name resolution) and for server-side errors, but not if the server
+	/// rejected the request itself or the request could not be built or
+	/// its response not be read.
+	pub fn is_retryable(&self) -> bool {
+		match self {
+			Self::Request(e) => crate::http::is_transient(e),
+			Self::Unavailable(_) => true,
+			Self::PermissionError
+			| Self::DataError
+			| Self::DatabaseNotFound
+			| Self::UnexpectedSuccessStatus
+			| Self::QueryFailed(_) => false,
+		}
+	}
 }
 
 impl fmt::Display for Error {
@@ -56,8 +116,10 @@ impl fmt::Display for Error {
 			Self::Request(e) => fmt::Display::fmt(e, f),
 			Self::PermissionError => write!(f, "permission denied"),
 			Self::DataError => write!(f, "malformed data"),
-			Self::DatabaseNotFound => write!(f, "database not found"),
+			Self::DatabaseNotFound => write!(f, "database or bucket not found"),
+			Self::Unavailable(status) => write!(f, "server unavailable ({})", status),
 			Self::UnexpectedSuccessStatus => write!(f, "unexpected success status"),
+			Self::QueryFailed(msg) => write!(f, "query failed: {}", msg),
 		}
 	}
 }
@@ -68,37 +130,97 @@ impl From<reqwest::Error> for Error {
 	}
 }
 
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Settings of a retention policy created together with its database.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+	pub name: String,
+	/// Duration in InfluxQL syntax, e.g. `30d` or `INF`.
+	pub duration: String,
+	pub replication: u32,
+	/// Make this the default retention policy of the database.
+	pub default: bool,
+}
+
+/// Quote an identifier for use in InfluxQL.
+fn quote_ident(ident: &str) -> String {
+	format!("\"{}\"", ident.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Map error statuses of a response to errors.
+fn check_status(resp: reqwest::Response) -> Result<reqwest::Response, Error> {
+	match resp.error_for_status() {
+		Ok(resp) => Ok(resp),
+		Err(e) => match e.status().unwrap() {
+			reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::UNAUTHORIZED => {
+				Err(Error::PermissionError)
+			}
+			reqwest::StatusCode::BAD_REQUEST
+			| reqwest::StatusCode::PAYLOAD_TOO_LARGE
+			| reqwest::StatusCode::UNPROCESSABLE_ENTITY => Err(Error::DataError),
+			reqwest::StatusCode::NOT_FOUND => Err(Error::DatabaseNotFound),
+			status
+				if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS =>
+			{
+				Err(Error::Unavailable(status))
+			}
+			_ => Err(Error::Request(e)),
+		},
+	}
+}
+
 pub struct Client {
 	client: reqwest::Client,
-	write_url: String,
+	api_url: String,
 	auth: Auth,
+	gzip: bool,
+}
+
+fn compress(body: &[u8]) -> Vec<u8> {
+	let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+	// writing to a Vec is infallible
+	encoder.write_all(body).unwrap();
+	encoder.finish().unwrap()
 }
 
 impl Client {
 	pub fn new(api_url: String, auth: Auth) -> Self {
 		Self {
-			client: reqwest::Client::new(),
-			write_url: format!("{}/write", api_url),
+			client: reqwest::Client::builder()
+				.timeout(REQUEST_TIMEOUT)
+				.build()
+				.expect("building the http client"),
+			api_url,
 			auth,
+			gzip: false,
 		}
 	}
 
+	/// Compress request bodies with gzip.
+	pub fn with_gzip(mut self, gzip: bool) -> Self {
+		self.gzip = gzip;
+		self
+	}
+
 	pub async fn post(
 		&self,
-		database: &'_ str,
-		retention_policy: Option<&'_ str>,
+		target: &'_ Target,
 		auth: Option<&'_ Auth>,
 		precision: Precision,
 		readouts: &[Arc<Readout>],
 	) -> Result<(), Error> {
-		let req = self.client.post(self.write_url.clone());
-		let req = auth.unwrap_or_else(|| &self.auth).apply(req);
-		let req = req.query(&[("db", database), ("precision", precision.value())]);
-		let req = match retention_policy {
-			Some(policy) => req.query(&[("rp", policy)]),
-			None => req,
-		};
+		self.post_body(
+			target,
+			auth,
+			precision,
+			Self::serialize(precision, readouts),
+		)
+		.await
+	}
 
+	/// Serialize readouts into a line protocol request body.
+	pub fn serialize(precision: Precision, readouts: &[Arc<Readout>]) -> Bytes {
 		let body = BytesMut::new();
 		let mut body_writer = body.writer();
 		trace!("serializing {} readouts", readouts.len());
@@ -108,25 +230,287 @@ impl Client {
 			}
 			readout.write(&mut body_writer).unwrap(); // BytesMut is infallible
 		}
+		body_writer.into_inner().freeze()
+	}
 
-		let body = body_writer.into_inner();
-		let req = req.body(body.freeze());
-		let resp = req.send().await?;
-		match resp.error_for_status() {
-			Ok(resp) => match resp.status() {
-				reqwest::StatusCode::NO_CONTENT => Ok(()),
-				_ => Err(Error::UnexpectedSuccessStatus),
-			},
-			Err(e) => match e.status().unwrap() {
-				reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::UNAUTHORIZED => {
-					Err(Error::PermissionError)
+	/// Submit an already serialized line protocol body.
+	pub async fn post_body(
+		&self,
+		target: &'_ Target,
+		auth: Option<&'_ Auth>,
+		precision: Precision,
+		body: Bytes,
+	) -> Result<(), Error> {
+		let req = match target {
+			Target::V1 {
+				database,
+				retention_policy,
+			} => {
+				let req = self.client.post(format!("{}/write", self.api_url));
+				let req = req.query(&[("db", database.as_str()), ("precision", precision.value())]);
+				match retention_policy {
+					Some(policy) => req.query(&[("rp", policy)]),
+					None => req,
 				}
-				reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::PAYLOAD_TOO_LARGE => {
-					Err(Error::DataError)
+			}
+			Target::V2 { org, bucket } => self
+				.client
+				.post(format!("{}/api/v2/write", self.api_url))
+				.query(&[
+					("org", org.as_str()),
+					("bucket", bucket.as_str()),
+					("precision", precision.v2_value()),
+				]),
+		};
+		let req = auth.unwrap_or(&self.auth).apply(req);
+
+		let req = if self.gzip {
+			req.header("Content-Encoding", "gzip").body(compress(&body))
+		} else {
+			req.body(body)
+		};
+		let resp = check_status(req.send().await?)?;
+		match resp.status() {
+			reqwest::StatusCode::NO_CONTENT => Ok(()),
+			_ => Err(Error::UnexpectedSuccessStatus),
+		}
+	}
+
+	/// Check whether the server is up, using the `/ping` endpoint.
+	pub async fn ping(&self) -> Result<(), Error> {
+		let req = self
+			.auth
+			.apply(self.client.get(format!("{}/ping", self.api_url)));
+		let resp = check_status(req.send().await?)?;
+		match resp.status() {
+			reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::OK => Ok(()),
+			_ => Err(Error::UnexpectedSuccessStatus),
+		}
+	}
+
+	/// Run an InfluxQL statement via the 1.x `/query` endpoint.
+	pub async fn query(&self, statement: &str) -> Result<(), Error> {
+		let req = self
+			.client
+			.post(format!("{}/query", self.api_url))
+			.query(&[("q", statement)]);
+		let resp = check_status(self.auth.apply(req).send().await?)?;
+		let body = resp.bytes().await?;
+		if body.is_empty() {
+			return Ok(());
+		}
+		let result: serde_json::Value = match serde_json::from_slice(&body) {
+			Ok(v) => v,
+			Err(e) => return Err(Error::QueryFailed(format!("invalid response: {}", e))),
+		};
+		let error = result.get("error").or_else(|| {
+			result
+				.get("results")?
+				.as_array()?
+				.iter()
+				.find_map(|x| x.get("error"))
+		});
+		match error {
+			Some(e) => Err(Error::QueryFailed(
+				e.as_str().map(Into::into).unwrap_or_else(|| e.to_string()),
+			)),
+			None => Ok(()),
+		}
+	}
+
+	/// Create a 1.x database and optionally a retention policy on it.
+	///
+	/// Creating a database which exists already is not an error.
+	pub async fn create_database(
+		&self,
+		database: &str,
+		retention_policy: Option<&RetentionPolicy>,
+	) -> Result<(), Error> {
+		self.query(&format!("CREATE DATABASE {}", quote_ident(database)))
+			.await?;
+		if let Some(rp) = retention_policy {
+			let mut settings = format!(
+				"{} ON {} DURATION {} REPLICATION {}",
+				quote_ident(&rp.name),
+				quote_ident(database),
+				rp.duration,
+				rp.replication
+			);
+			if rp.default {
+				settings.push_str(" DEFAULT");
+			}
+			match self
+				.query(&format!("CREATE RETENTION POLICY {}", settings))
+				.await
+			{
+				// exists with different settings
+				Err(Error::QueryFailed(msg)) if msg.contains("already exists") => {
+					self.query(&format!("ALTER RETENTION POLICY {}", settings))
+						.await?
 				}
-				reqwest::StatusCode::NOT_FOUND => Err(Error::DatabaseNotFound),
-				_ => Err(Error::Request(e)),
-			},
+				other => other?,
+			}
 		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::testutil::StandIn;
+
+	#[tokio::test]
+	async fn v1_writes_to_database() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let client = Client::new(
+			server.url.clone(),
+			Auth::Query {
+				username: "user".into(),
+				password: "pass".into(),
+			},
+		);
+		let target = Target::V1 {
+			database: "db".into(),
+			retention_policy: Some("rp".into()),
+		};
+		client
+			.post_body(
+				&target,
+				None,
+				Precision::Microseconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await
+			.unwrap();
+		let requests = server.requests();
+		assert_eq!(
+			requests[0].target(),
+			"/write?db=db&precision=u&rp=rp&u=user&p=pass"
+		);
+		assert_eq!(requests[0].body_str(), "m v=1 1\n");
+	}
+
+	#[tokio::test]
+	async fn v2_writes_to_bucket_with_token() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let client = Client::new(
+			server.url.clone(),
+			Auth::Token {
+				token: "s3cr3t".into(),
+			},
+		);
+		let target = Target::V2 {
+			org: "my org".into(),
+			bucket: "sensors".into(),
+		};
+		client
+			.post_body(
+				&target,
+				None,
+				Precision::Microseconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await
+			.unwrap();
+		let requests = server.requests();
+		assert_eq!(
+			requests[0].target(),
+			"/api/v2/write?org=my+org&bucket=sensors&precision=us"
+		);
+		assert_eq!(requests[0].header("authorization"), Some("Token s3cr3t"));
+	}
+
+	#[tokio::test]
+	async fn gzip_compresses_body() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let client = Client::new(server.url.clone(), Auth::None).with_gzip(true);
+		let target = Target::V1 {
+			database: "db".into(),
+			retention_policy: None,
+		};
+		client
+			.post_body(
+				&target,
+				None,
+				Precision::Seconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await
+			.unwrap();
+		let request = &server.requests()[0];
+		assert_eq!(request.header("content-encoding"), Some("gzip"));
+		let mut body = String::new();
+		std::io::Read::read_to_string(
+			&mut flate2::read::GzDecoder::new(&request.body[..]),
+			&mut body,
+		)
+		.unwrap();
+		assert_eq!(body, "m v=1 1\n");
+	}
+
+	#[tokio::test]
+	async fn v2_missing_bucket_is_reported() {
+		let server = StandIn::start(Box::new(|_| 404)).await;
+		let client = Client::new(server.url.clone(), Auth::None);
+		let target = Target::V2 {
+			org: "org".into(),
+			bucket: "missing".into(),
+		};
+		let result = client
+			.post_body(
+				&target,
+				None,
+				Precision::Seconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await;
+		assert!(matches!(result, Err(Error::DatabaseNotFound)));
+	}
+
+	#[tokio::test]
+	async fn creates_database_and_retention_policy() {
+		let server = StandIn::start(Box::new(|_| 200)).await;
+		let client = Client::new(server.url.clone(), Auth::None);
+		client
+			.create_database(
+				"my \"db\"",
+				Some(&RetentionPolicy {
+					name: "month".into(),
+					duration: "30d".into(),
+					replication: 1,
+					default: true,
+				}),
+			)
+			.await
+			.unwrap();
+		let requests = server.requests();
+		assert_eq!(
+			requests[0].target(),
+			"/query?q=CREATE+DATABASE+%22my+%5C%22db%5C%22%22"
+		);
+		assert_eq!(
+			requests[1].target(),
+			"/query?q=CREATE+RETENTION+POLICY+%22month%22+ON+%22my+%5C%22db%5C%22%22+DURATION+30d+REPLICATION+1+DEFAULT"
+		);
+	}
+
+	#[tokio::test]
+	async fn ping_reports_unreachable_server() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let client = Client::new(server.url.clone(), Auth::None);
+		client.ping().await.unwrap();
+		assert_eq!(server.requests()[0].target(), "/ping");
+		server.set_responder(Box::new(|_| 503));
+		assert!(client.ping().await.unwrap_err().is_retryable());
+	}
+
+	#[tokio::test]
+	async fn malformed_url_is_not_retryable() {
+		let client = Client::new("not a url".into(), Auth::None);
+		let err = client.ping().await.unwrap_err();
+		assert!(matches!(err, Error::Request(_)));
+		assert!(!err.is_retryable());
 	}
 }
@@ -2,6 +2,7 @@ use std::io;
 
 use smartstring::alias::String as SmartString;
 
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 
 use enum_map::Enum;
@@ -10,7 +11,7 @@ use chrono::{DateTime, Utc};
 
 use crate::metric;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Enum, JsonSchema)]
 pub enum Precision {
 	Nanoseconds,
 	Microseconds,
@@ -28,6 +29,26 @@ impl Precision {
 		}
 	}
 
+	/// Value of the `precision` parameter of the 2.x write API.
+	pub fn v2_value(&self) -> &'static str {
+		match self {
+			Self::Nanoseconds => "ns",
+			Self::Microseconds => "us",
+			Self::Milliseconds => "ms",
+			Self::Seconds => "s",
+		}
+	}
+
+	pub fn from_value(value: &str) -> Option<Self> {
+		match value {
+			"ns" => Some(Self::Nanoseconds),
+			"u" => Some(Self::Microseconds),
+			"ms" => Some(Self::Milliseconds),
+			"s" => Some(Self::Seconds),
+			_ => None,
+		}
+	}
+
 	pub fn encode_timestamp<W: io::Write>(&self, w: &mut W, ts: &DateTime<Utc>) -> io::Result<()> {
 		// XXX: do something about leap seconds
 		match self {
@@ -51,10 +72,101 @@ impl Precision {
 	}
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+	Float(f64),
+	Integer(i64),
+	Boolean(bool),
+	String(SmartString),
+}
+
+impl FieldValue {
+	/// Write the value in line protocol syntax.
+	///
+	/// Returns false without writing anything if the value cannot be
+	/// represented (NaN and infinite floats).
+	fn write<W: io::Write>(&self, w: &mut W) -> io::Result<bool> {
+		match self {
+			Self::Float(v) => {
+				if !v.is_finite() {
+					return Ok(false);
+				}
+				// Debug always includes a decimal point or exponent, so the
+				// value cannot be mistaken for an integer
+				write!(w, "{:?}", v)?
+			}
+			Self::Integer(v) => write!(w, "{}i", v)?,
+			Self::Boolean(v) => w.write_all(if *v { b"true" } else { b"false" })?,
+			Self::String(v) => {
+				w.write_all(b"\"")?;
+				write_escaped(w, v, &['"', '\\'])?;
+				w.write_all(b"\"")?;
+			}
+		}
+		Ok(true)
+	}
+}
+
+impl From<f64> for FieldValue {
+	fn from(other: f64) -> Self {
+		Self::Float(other)
+	}
+}
+
+impl From<i64> for FieldValue {
+	fn from(other: i64) -> Self {
+		Self::Integer(other)
+	}
+}
+
+impl From<bool> for FieldValue {
+	fn from(other: bool) -> Self {
+		Self::Boolean(other)
+	}
+}
+
+impl From<SmartString> for FieldValue {
+	fn from(other: SmartString) -> Self {
+		Self::String(other)
+	}
+}
+
+const MEASUREMENT_SPECIALS: &[char] = &[',', ' '];
+const KEY_SPECIALS: &[char] = &[',', '=', ' '];
+
+/// Write `s` with a backslash in front of each of `specials`.
+///
+/// Newlines cannot be escaped in line protocol, so they are replaced by
+/// spaces (which are then escaped as needed). Backslashes are taken
+/// literally, except where they would escape what follows: before an
+/// escaped character, another backslash or the delimiter after `s`.
+fn write_escaped<W: io::Write>(w: &mut W, s: &str, specials: &[char]) -> io::Result<()> {
+	let mut buf = [0u8; 4];
+	let mut chars = s
+		.chars()
+		.map(|ch| if ch == '\n' || ch == '\r' { ' ' } else { ch })
+		.peekable();
+	while let Some(ch) = chars.next() {
+		let escape = match ch {
+			_ if specials.contains(&ch) => true,
+			'\\' => match chars.peek() {
+				Some(next) => *next == '\\' || specials.contains(next),
+				None => true,
+			},
+			_ => false,
+		};
+		if escape {
+			w.write_all(b"\\")?;
+		}
+		w.write_all(ch.encode_utf8(&mut buf).as_bytes())?;
+	}
+	Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct Sample {
 	pub tagv: Vec<SmartString>,
-	pub fieldv: Vec<f64>,
+	pub fieldv: Vec<FieldValue>,
 }
 
 #[derive(Debug, Clone)]
@@ -64,17 +176,34 @@ pub struct Readout {
 	pub precision: Precision,
 	pub tags: Vec<SmartString>,
 	pub fields: Vec<SmartString>,
+	/// Unit of each field, if known.
+	pub units: Vec<metric::Unit>,
 	pub samples: Vec<Sample>,
 }
 
 impl Readout {
-	pub fn from_metric(readout: &metric::Readout, precision: Precision) -> Self {
+	/// Convert a metric readout, mapping components to fields.
+	///
+	/// If `integer_counters` is set, components with [`metric::Unit::Total`]
+	/// are written as integer fields; otherwise all fields are floats.
+	pub fn from_metric(
+		readout: &metric::Readout,
+		precision: Precision,
+		integer_counters: bool,
+	) -> Self {
 		let tags = vec!["instance".into()];
 		let mut fields = Vec::with_capacity(readout.components.len());
 		let mut fieldv = Vec::with_capacity(readout.components.len());
+		let mut units = Vec::with_capacity(readout.components.len());
 		for (k, v) in readout.components.iter() {
 			fields.push(k.clone());
-			fieldv.push(v.magnitude);
+			units.push(v.unit.clone());
+			fieldv.push(match v.unit {
+				metric::Unit::Total if integer_counters && v.magnitude.is_finite() => {
+					FieldValue::Integer(v.magnitude.round() as i64)
+				}
+				_ => FieldValue::Float(v.magnitude),
+			});
 		}
 
 		let samples = vec![Sample {
@@ -87,26 +216,163 @@ impl Readout {
 			precision,
 			tags,
 			fields,
+			units,
 			samples,
 		}
 	}
 
+	/// Write the samples in line protocol, one line per sample.
+	///
+	/// Tags with empty values and fields with unrepresentable values are
+	/// omitted. Samples which end up without any fields are skipped
+	/// entirely, as line protocol requires at least one field.
 	pub fn write<W: io::Write>(&self, dest: &mut W) -> io::Result<()> {
-		// TODO: some proper escaping :>
+		let mut line = Vec::new();
 		for sample in self.samples.iter() {
-			dest.write_all(&self.measurement.as_bytes())?;
+			line.clear();
+			write_escaped(&mut line, &self.measurement, MEASUREMENT_SPECIALS)?;
 			for (k, v) in self.tags.iter().zip(sample.tagv.iter()) {
-				write!(dest, ",{}={}", k, v)?;
+				if v.is_empty() {
+					continue;
+				}
+				line.push(b',');
+				write_escaped(&mut line, k, KEY_SPECIALS)?;
+				line.push(b'=');
+				write_escaped(&mut line, v, KEY_SPECIALS)?;
 			}
 			let mut first = true;
 			for (k, v) in self.fields.iter().zip(sample.fieldv.iter()) {
-				write!(dest, "{}{}={:?}", if first { ' ' } else { ',' }, k, v)?;
-				first = false;
+				let rollback = line.len();
+				line.push(if first { b' ' } else { b',' });
+				write_escaped(&mut line, k, KEY_SPECIALS)?;
+				line.push(b'=');
+				if v.write(&mut line)? {
+					first = false;
+				} else {
+					line.truncate(rollback);
+				}
 			}
-			dest.write_all(&b" "[..])?;
-			self.precision.encode_timestamp(dest, &self.ts)?;
-			dest.write_all(&b"\n"[..])?;
+			if first {
+				continue;
+			}
+			line.push(b' ');
+			self.precision.encode_timestamp(&mut line, &self.ts)?;
+			line.push(b'\n');
+			dest.write_all(&line)?;
 		}
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::TimeZone;
+
+	fn readout(fields: Vec<(&str, FieldValue)>) -> Readout {
+		let (fields, fieldv): (Vec<_>, Vec<_>) = fields
+			.into_iter()
+			.map(|(k, v)| (SmartString::from(k), v))
+			.unzip();
+		Readout {
+			ts: Utc.timestamp_opt(1600000000, 0).unwrap(),
+			measurement: "bme280".into(),
+			precision: Precision::Seconds,
+			tags: vec!["instance".into()],
+			units: vec![metric::Unit::Arbitrary; fields.len()],
+			fields,
+			samples: vec![Sample {
+				tagv: vec!["i2c-2/76 east".into()],
+				fieldv,
+			}],
+		}
+	}
+
+	fn to_string(readout: &Readout) -> String {
+		let mut buf = Vec::new();
+		readout.write(&mut buf).unwrap();
+		String::from_utf8(buf).unwrap()
+	}
+
+	#[test]
+	fn escapes_identifiers() {
+		let mut r = readout(vec![("a=b,c d", 1.0.into())]);
+		r.measurement = "my measurement,x=1".into();
+		r.tags[0] = "tag key".into();
+		assert_eq!(
+			to_string(&r),
+			"my\\ measurement\\,x=1,tag\\ key=i2c-2/76\\ east a\\=b\\,c\\ d=1.0 1600000000\n"
+		);
+	}
+
+	#[test]
+	fn escapes_backslashes_before_delimiters() {
+		let mut r = readout(vec![("f\\", 1.0.into())]);
+		r.measurement = "m\\".into();
+		r.tags[0] = "k\\".into();
+		r.samples[0].tagv[0] = "a\\b\\\\".into();
+		assert_eq!(
+			to_string(&r),
+			"m\\\\,k\\\\=a\\b\\\\\\\\ f\\\\=1.0 1600000000\n"
+		);
+		r.samples[0].tagv[0] = "a\\,b".into();
+		assert!(to_string(&r).starts_with("m\\\\,k\\\\=a\\\\\\,b f"));
+	}
+
+	#[test]
+	fn writes_typed_fields() {
+		let r = readout(vec![
+			("f", 2.5.into()),
+			("i", 42i64.into()),
+			("b", true.into()),
+			("s", SmartString::from("say \"hi\" \\o/").into()),
+		]);
+		assert_eq!(
+			to_string(&r),
+			"bme280,instance=i2c-2/76\\ east f=2.5,i=42i,b=true,s=\"say \\\"hi\\\" \\\\o/\" 1600000000\n"
+		);
+	}
+
+	#[test]
+	fn skips_non_finite_values() {
+		let r = readout(vec![("nan", f64::NAN.into()), ("ok", 1.0.into())]);
+		assert_eq!(
+			to_string(&r),
+			"bme280,instance=i2c-2/76\\ east ok=1.0 1600000000\n"
+		);
+		let r = readout(vec![("inf", f64::INFINITY.into())]);
+		assert_eq!(to_string(&r), "");
+	}
+
+	#[test]
+	fn omits_empty_tag_values() {
+		let mut r = readout(vec![("v", 1.0.into())]);
+		r.samples[0].tagv[0] = "".into();
+		assert_eq!(to_string(&r), "bme280 v=1.0 1600000000\n");
+	}
+
+	#[test]
+	fn writes_counters_as_integers_if_requested() {
+		let mut components = metric::OrderedVec::new();
+		components.insert(
+			"count".into(),
+			metric::Value {
+				magnitude: 23.0,
+				unit: metric::Unit::Total,
+			},
+		);
+		let metric_readout = metric::Readout {
+			timestamp: Utc.timestamp_opt(1600000000, 0).unwrap(),
+			path: metric::DevicePath {
+				device_type: "link".into(),
+				instance: "a".into(),
+			},
+			components,
+		};
+		let r = Readout::from_metric(&metric_readout, Precision::Seconds, true);
+		assert_eq!(to_string(&r), "link,instance=a count=23i 1600000000\n");
+		let r = Readout::from_metric(&metric_readout, Precision::Seconds, false);
+		assert_eq!(to_string(&r), "link,instance=a count=23.0 1600000000\n");
+	}
+}
@@ -0,0 +1,347 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+
+use bytes::Bytes;
+
+use super::readout::Precision;
+
+/// A request body which could not be submitted yet.
+#[derive(Debug, Clone)]
+pub struct SpoolEntry {
+	/// Identifies the body for [`DiskBuffer::remove`].
+	pub id: u64,
+	pub precision: Precision,
+	pub body: Bytes,
+}
+
+#[derive(Debug)]
+struct Meta {
+	id: u64,
+	precision: Precision,
+	size: u64,
+}
+
+/// On-disk FIFO of serialized line protocol request bodies.
+///
+/// Each body is stored in its own file, named after a sequence number and
+/// the timestamp precision. If the total size exceeds the limit, the oldest
+/// bodies are discarded.
+#[derive(Debug)]
+pub struct DiskBuffer {
+	directory: PathBuf,
+	max_bytes: u64,
+	entries: VecDeque<Meta>,
+	total: u64,
+	next_id: u64,
+}
+
+fn parse_name(name: &str) -> Option<(u64, Precision)> {
+	let name = name.strip_suffix(".lp")?;
+	let (id, precision) = name.split_once('.')?;
+	Some((id.parse().ok()?, Precision::from_value(precision)?))
+}
+
+impl DiskBuffer {
+	/// Open the buffer in `directory`, picking up bodies left over from a
+	/// previous run.
+	pub fn open(directory: PathBuf, max_bytes: u64) -> io::Result<Self> {
+		fs::create_dir_all(&directory)?;
+		let mut entries = Vec::new();
+		for item in fs::read_dir(&directory)? {
+			let item = item?;
+			let name = item.file_name();
+			let name = match name.to_str() {
+				Some(v) => v,
+				None => continue,
+			};
+			if name.ends_with(".tmp") {
+				// incomplete write from a crash
+				let _ = fs::remove_file(item.path());
+				continue;
+			}
+			if let Some((id, precision)) = parse_name(name) {
+				entries.push(Meta {
+					id,
+					precision,
+					size: item.metadata()?.len(),
+				});
+			}
+		}
+		entries.sort_by_key(|x| x.id);
+		let next_id = entries.last().map(|x| x.id + 1).unwrap_or(0);
+		let total = entries.iter().map(|x| x.size).sum();
+		Ok(Self {
+			directory,
+			max_bytes,
+			entries: entries.into(),
+			total,
+			next_id,
+		})
+	}
+
+	fn path(&self, id: u64, precision: Precision) -> PathBuf {
+		self.directory
+			.join(format!("{:020}.{}.lp", id, precision.value()))
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Total size of the buffered bodies in bytes.
+	pub fn size(&self) -> u64 {
+		self.total
+	}
+
+	/// Append a body, evicting the oldest ones if the size limit is
+	/// exceeded.
+	pub fn push(&mut self, precision: Precision, body: &[u8]) -> io::Result<()> {
+		let id = self.next_id;
+		let path = self.path(id, precision);
+		let mut tmp_path = path.clone().into_os_string();
+		tmp_path.push(".tmp");
+		{
+			let mut f = fs::File::create(&tmp_path)?;
+			f.write_all(body)?;
+			f.sync_all()?;
+		}
+		fs::rename(&tmp_path, &path)?;
+		self.next_id += 1;
+		self.entries.push_back(Meta {
+			id,
+			precision,
+			size: body.len() as u64,
+		});
+		self.total += body.len() as u64;
+
+		let mut evicted = 0;
+		while self.total > self.max_bytes && self.entries.len() > 1 {
+			self.pop_front()?;
+			evicted += 1;
+		}
+		if evicted > 0 {
+			warn!(
+				"lost samples: evicted {} batches from the influxdb disk buffer because it is full",
+				evicted
+			);
+		}
+		Ok(())
+	}
+
+	/// Read the oldest body.
+	pub fn front(&self) -> io::Result<Option<SpoolEntry>> {
+		let meta = match self.entries.front() {
+			Some(v) => v,
+			None => return Ok(None),
+		};
+		let body = fs::read(self.path(meta.id, meta.precision))?;
+		Ok(Some(SpoolEntry {
+			id: meta.id,
+			precision: meta.precision,
+			body: body.into(),
+		}))
+	}
+
+	/// Discard the oldest body.
+	pub fn pop_front(&mut self) -> io::Result<()> {
+		match self.entries.pop_front() {
+			Some(meta) => self.discard(meta),
+			None => Ok(()),
+		}
+	}
+
+	/// Discard the body read as `id`, unless it was evicted already.
+	pub fn remove(&mut self, id: u64) -> io::Result<()> {
+		match self.entries.iter().position(|x| x.id == id) {
+			Some(at) => {
+				let meta = self.entries.remove(at).unwrap();
+				self.discard(meta)
+			}
+			None => Ok(()),
+		}
+	}
+
+	fn discard(&mut self, meta: Meta) -> io::Result<()> {
+		self.total -= meta.size;
+		match fs::remove_file(self.path(meta.id, meta.precision)) {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Handle to a [`DiskBuffer`] which can be shared between async tasks.
+///
+/// The file operations run on the blocking thread pool. The buffer is only
+/// locked for the duration of a single operation, and its length and size
+/// can be checked without locking it at all.
+#[derive(Debug, Clone)]
+pub struct SharedDiskBuffer {
+	inner: Arc<Mutex<DiskBuffer>>,
+	len: Arc<AtomicUsize>,
+	size: Arc<AtomicU64>,
+}
+
+impl SharedDiskBuffer {
+	pub fn new(buffer: DiskBuffer) -> Self {
+		Self {
+			len: Arc::new(AtomicUsize::new(buffer.len())),
+			size: Arc::new(AtomicU64::new(buffer.size())),
+			inner: Arc::new(Mutex::new(buffer)),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	pub fn len(&self) -> usize {
+		self.len.load(Ordering::Acquire)
+	}
+
+	/// Total size of the buffered bodies in bytes.
+	pub fn size(&self) -> u64 {
+		self.size.load(Ordering::Acquire)
+	}
+
+	async fn with<T, F>(&self, f: F) -> io::Result<T>
+	where
+		F: FnOnce(&mut DiskBuffer) -> io::Result<T> + Send + 'static,
+		T: Send + 'static,
+	{
+		let inner = self.inner.clone();
+		let len = self.len.clone();
+		let size = self.size.clone();
+		tokio::task::spawn_blocking(move || {
+			let mut buffer = inner.lock().unwrap();
+			let result = f(&mut buffer);
+			len.store(buffer.len(), Ordering::Release);
+			size.store(buffer.size(), Ordering::Release);
+			result
+		})
+		.await
+		.map_err(io::Error::other)?
+	}
+
+	/// See [`DiskBuffer::push`].
+	pub async fn push(&self, precision: Precision, body: Bytes) -> io::Result<()> {
+		self.with(move |buffer| buffer.push(precision, &body)).await
+	}
+
+	/// See [`DiskBuffer::front`].
+	///
+	/// A body which cannot be read is discarded, as it would otherwise
+	/// block everything behind it.
+	pub async fn front(&self) -> io::Result<Option<SpoolEntry>> {
+		self.with(|buffer| match buffer.front() {
+			Err(e) => {
+				buffer.pop_front()?;
+				Err(e)
+			}
+			other => other,
+		})
+		.await
+	}
+
+	/// See [`DiskBuffer::remove`].
+	pub async fn remove(&self, id: u64) -> io::Result<()> {
+		self.with(move |buffer| buffer.remove(id)).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tempdir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!(
+			"metric-relay-spool-{}-{}",
+			name,
+			std::process::id()
+		));
+		let _ = fs::remove_dir_all(&dir);
+		dir
+	}
+
+	#[test]
+	fn entries_survive_reopening_in_order() {
+		let dir = tempdir("reopen");
+		{
+			let mut buffer = DiskBuffer::open(dir.clone(), 1024).unwrap();
+			buffer.push(Precision::Seconds, b"a v=1 1\n").unwrap();
+			buffer
+				.push(Precision::Milliseconds, b"b v=2 2000\n")
+				.unwrap();
+		}
+		let mut buffer = DiskBuffer::open(dir.clone(), 1024).unwrap();
+		assert_eq!(buffer.len(), 2);
+		let entry = buffer.front().unwrap().unwrap();
+		assert_eq!(entry.precision, Precision::Seconds);
+		assert_eq!(&entry.body[..], b"a v=1 1\n");
+		buffer.pop_front().unwrap();
+		buffer.push(Precision::Seconds, b"c v=3 3\n").unwrap();
+		assert_eq!(&buffer.front().unwrap().unwrap().body[..], b"b v=2 2000\n");
+		buffer.pop_front().unwrap();
+		assert_eq!(&buffer.front().unwrap().unwrap().body[..], b"c v=3 3\n");
+		buffer.pop_front().unwrap();
+		assert!(buffer.is_empty());
+		assert_eq!(buffer.size(), 0);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn evicts_oldest_when_full() {
+		let dir = tempdir("evict");
+		let mut buffer = DiskBuffer::open(dir.clone(), 20).unwrap();
+		buffer.push(Precision::Seconds, b"0123456789").unwrap();
+		buffer.push(Precision::Seconds, b"abcdefghij").unwrap();
+		buffer.push(Precision::Seconds, b"ABCDEFGHIJ").unwrap();
+		assert_eq!(buffer.len(), 2);
+		assert_eq!(&buffer.front().unwrap().unwrap().body[..], b"abcdefghij");
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn remove_spares_entries_behind_an_evicted_one() {
+		let dir = tempdir("remove");
+		let mut buffer = DiskBuffer::open(dir.clone(), 20).unwrap();
+		buffer.push(Precision::Seconds, b"0123456789").unwrap();
+		buffer.push(Precision::Seconds, b"abcdefghij").unwrap();
+		let entry = buffer.front().unwrap().unwrap();
+		// evicts the entry which was just read
+		buffer.push(Precision::Seconds, b"ABCDEFGHIJ").unwrap();
+		buffer.remove(entry.id).unwrap();
+		assert_eq!(buffer.len(), 2);
+		assert_eq!(&buffer.front().unwrap().unwrap().body[..], b"abcdefghij");
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn shared_buffer_tracks_length() {
+		let dir = tempdir("shared");
+		let buffer = SharedDiskBuffer::new(DiskBuffer::open(dir.clone(), 1024).unwrap());
+		assert!(buffer.is_empty());
+		buffer
+			.push(Precision::Seconds, Bytes::from_static(b"a v=1 1\n"))
+			.await
+			.unwrap();
+		assert_eq!(buffer.len(), 1);
+		assert_eq!(buffer.size(), 8);
+		let entry = buffer.front().await.unwrap().unwrap();
+		assert_eq!(&entry.body[..], b"a v=1 1\n");
+		buffer.remove(entry.id).await.unwrap();
+		assert!(buffer.is_empty());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -2,10 +2,19 @@
 pub mod bme280;
 #[cfg(any(feature = "smbus", feature = "sbm"))]
 pub mod bme68x;
+#[cfg(feature = "graphite")]
+pub mod graphite;
+pub mod http;
 #[cfg(feature = "influxdb")]
 pub mod influxdb;
+#[cfg(feature = "lineproto")]
+pub mod lineproto;
 pub mod meteo;
 pub mod metric;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(any(feature = "prometheus", feature = "prometheus-remote-write"))]
+pub mod prometheus;
 #[cfg(feature = "pubsub")]
 pub mod pubsub;
 #[cfg(feature = "relay")]
@@ -16,9 +25,18 @@ pub mod sbm;
 #[cfg(feature = "sbx")]
 pub mod sbx;
 pub mod script;
+pub mod sdnotify;
 pub mod serial;
 #[cfg(feature = "smbus")]
 pub mod smbus;
 #[cfg(feature = "sbm")]
 pub mod snurl;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "statsd")]
+pub mod statsd;
 pub mod stream;
+#[cfg(all(test, feature = "http"))]
+pub(crate) mod testutil;
+#[cfg(feature = "webhook")]
+pub mod webhook;
@@ -0,0 +1,359 @@
+//! # InfluxDB line protocol parser
+//!
+//! Parses the textual format spoken by InfluxDB and many tools talking to
+//! it, e.g.:
+//!
+//! ```text
+//! weather,location=us-midwest temperature=82,humidity=71i 1465839830100400200
+//! ```
+use std::fmt;
+
+use smartstring::alias::String as SmartString;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+	Float(f64),
+	Integer(i64),
+	UInteger(u64),
+	Boolean(bool),
+	String(String),
+}
+
+impl FieldValue {
+	/// Numeric value of the field; booleans map to 0 and 1, strings have
+	/// none.
+	pub fn as_f64(&self) -> Option<f64> {
+		match self {
+			Self::Float(v) => Some(*v),
+			Self::Integer(v) => Some(*v as f64),
+			Self::UInteger(v) => Some(*v as f64),
+			Self::Boolean(v) => Some(if *v { 1.0 } else { 0.0 }),
+			Self::String(_) => None,
+		}
+	}
+}
+
+/// A single parsed line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+	pub measurement: SmartString,
+	pub tags: Vec<(SmartString, SmartString)>,
+	pub fields: Vec<(SmartString, FieldValue)>,
+	/// Timestamp in the unit given by the request's precision, if present.
+	pub timestamp: Option<i64>,
+}
+
+impl Line {
+	pub fn tag(&self, key: &str) -> Option<&str> {
+		self.tags
+			.iter()
+			.find(|(k, _)| k == key)
+			.map(|(_, v)| v.as_str())
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+	MissingMeasurement,
+	InvalidTag,
+	MissingFields,
+	InvalidField,
+	InvalidFieldValue,
+	UnterminatedString,
+	InvalidTimestamp,
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::MissingMeasurement => write!(f, "missing measurement"),
+			Self::InvalidTag => write!(f, "invalid tag"),
+			Self::MissingFields => write!(f, "missing fields"),
+			Self::InvalidField => write!(f, "invalid field"),
+			Self::InvalidFieldValue => write!(f, "invalid field value"),
+			Self::UnterminatedString => write!(f, "unterminated string field value"),
+			Self::InvalidTimestamp => write!(f, "invalid timestamp"),
+		}
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+/// Timestamp precision of a write request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+	#[default]
+	Nanoseconds,
+	Microseconds,
+	Milliseconds,
+	Seconds,
+	Minutes,
+	Hours,
+}
+
+impl Precision {
+	/// Parse the `precision` query parameter of the 1.x or 2.x write API.
+	pub fn from_param(value: &str) -> Option<Self> {
+		match value {
+			"n" | "ns" => Some(Self::Nanoseconds),
+			"u" | "us" | "µ" | "µs" => Some(Self::Microseconds),
+			"ms" => Some(Self::Milliseconds),
+			"s" => Some(Self::Seconds),
+			"m" => Some(Self::Minutes),
+			"h" => Some(Self::Hours),
+			_ => None,
+		}
+	}
+
+	pub fn to_datetime(&self, ts: i64) -> Option<DateTime<Utc>> {
+		let (secs, nanos) = match self {
+			Self::Nanoseconds => (ts.div_euclid(1_000_000_000), ts.rem_euclid(1_000_000_000)),
+			Self::Microseconds => (ts.div_euclid(1_000_000), ts.rem_euclid(1_000_000) * 1_000),
+			Self::Milliseconds => (ts.div_euclid(1_000), ts.rem_euclid(1_000) * 1_000_000),
+			Self::Seconds => (ts, 0),
+			Self::Minutes => (ts.checked_mul(60)?, 0),
+			Self::Hours => (ts.checked_mul(3600)?, 0),
+		};
+		Utc.timestamp_opt(secs, nanos as u32).single()
+	}
+}
+
+/// Read up to the first unescaped byte in `stops`, resolving backslash
+/// escapes of `stops`.
+fn take_escaped<'x>(s: &'x str, stops: &[u8]) -> (SmartString, &'x str) {
+	let bytes = s.as_bytes();
+	let mut result = SmartString::new();
+	let mut start = 0;
+	let mut i = 0;
+	while i < bytes.len() {
+		let b = bytes[i];
+		if b == b'\\'
+			&& i + 1 < bytes.len()
+			&& (stops.contains(&bytes[i + 1]) || bytes[i + 1] == b'\\')
+		{
+			result.push_str(&s[start..i]);
+			start = i + 1;
+			i += 2;
+			continue;
+		}
+		if stops.contains(&b) {
+			break;
+		}
+		i += 1;
+	}
+	result.push_str(&s[start..i]);
+	(result, &s[i..])
+}
+
+fn parse_string(s: &str) -> Result<(String, &str), ParseError> {
+	let mut result = String::new();
+	let mut chars = s.char_indices();
+	while let Some((i, ch)) = chars.next() {
+		match ch {
+			'"' => return Ok((result, &s[i + 1..])),
+			'\\' => match chars.next() {
+				Some((_, next @ ('"' | '\\'))) => result.push(next),
+				Some((_, next)) => {
+					result.push('\\');
+					result.push(next);
+				}
+				None => return Err(ParseError::UnterminatedString),
+			},
+			ch => result.push(ch),
+		}
+	}
+	Err(ParseError::UnterminatedString)
+}
+
+fn parse_value(token: &str) -> Result<FieldValue, ParseError> {
+	match token {
+		"t" | "T" | "true" | "True" | "TRUE" => return Ok(FieldValue::Boolean(true)),
+		"f" | "F" | "false" | "False" | "FALSE" => return Ok(FieldValue::Boolean(false)),
+		_ => (),
+	}
+	if let Some(v) = token.strip_suffix('i') {
+		return v
+			.parse()
+			.map(FieldValue::Integer)
+			.map_err(|_| ParseError::InvalidFieldValue);
+	}
+	if let Some(v) = token.strip_suffix('u') {
+		return v
+			.parse()
+			.map(FieldValue::UInteger)
+			.map_err(|_| ParseError::InvalidFieldValue);
+	}
+	// reject things like "inf" or "NaN" which rust would happily accept
+	if !token
+		.bytes()
+		.all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'-' | b'+' | b'e' | b'E'))
+	{
+		return Err(ParseError::InvalidFieldValue);
+	}
+	token
+		.parse()
+		.map(FieldValue::Float)
+		.map_err(|_| ParseError::InvalidFieldValue)
+}
+
+/// Parse a single line; returns `None` for blank lines and comments.
+pub fn parse_line(line: &str) -> Result<Option<Line>, ParseError> {
+	let line = line.trim_end_matches(['\r', '\n']);
+	let line = line.trim_start();
+	if line.is_empty() || line.starts_with('#') {
+		return Ok(None);
+	}
+
+	let (measurement, mut rest) = take_escaped(line, b", ");
+	if measurement.is_empty() {
+		return Err(ParseError::MissingMeasurement);
+	}
+
+	let mut tags = Vec::new();
+	while let Some(tail) = rest.strip_prefix(',') {
+		let (key, tail) = take_escaped(tail, b",= ");
+		let tail = tail.strip_prefix('=').ok_or(ParseError::InvalidTag)?;
+		let (value, tail) = take_escaped(tail, b", ");
+		if key.is_empty() || value.is_empty() {
+			return Err(ParseError::InvalidTag);
+		}
+		tags.push((key, value));
+		rest = tail;
+	}
+
+	rest = rest.trim_start_matches(' ');
+	if rest.is_empty() {
+		return Err(ParseError::MissingFields);
+	}
+	let mut fields = Vec::new();
+	loop {
+		let (key, tail) = take_escaped(rest, b",= ");
+		let tail = tail.strip_prefix('=').ok_or(ParseError::InvalidField)?;
+		if key.is_empty() {
+			return Err(ParseError::InvalidField);
+		}
+		let (value, tail) = match tail.strip_prefix('"') {
+			Some(tail) => {
+				let (value, tail) = parse_string(tail)?;
+				(FieldValue::String(value), tail)
+			}
+			None => {
+				let end = tail.find([',', ' ']).unwrap_or(tail.len());
+				(parse_value(&tail[..end])?, &tail[end..])
+			}
+		};
+		fields.push((key, value));
+		match tail.strip_prefix(',') {
+			Some(tail) => rest = tail,
+			None => {
+				rest = tail;
+				break;
+			}
+		}
+	}
+
+	let rest = rest.trim();
+	let timestamp = if rest.is_empty() {
+		None
+	} else {
+		Some(rest.parse().map_err(|_| ParseError::InvalidTimestamp)?)
+	};
+
+	Ok(Some(Line {
+		measurement,
+		tags,
+		fields,
+		timestamp,
+	}))
+}
+
+/// Parse a request body, collecting the valid lines and the (1-based)
+/// numbers of invalid ones together with the reason.
+pub fn parse(body: &str) -> (Vec<Line>, Vec<(usize, ParseError)>) {
+	let mut lines = Vec::new();
+	let mut errors = Vec::new();
+	for (i, line) in body.lines().enumerate() {
+		match parse_line(line) {
+			Ok(Some(v)) => lines.push(v),
+			Ok(None) => (),
+			Err(e) => errors.push((i + 1, e)),
+		}
+	}
+	(lines, errors)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_full_line() {
+		let line = parse_line(
+			"weather,location=us\\ midwest,season=summer temperature=82,humidity=71i,ok=t,note=\"a \\\"b\\\"\" 1465839830100400200",
+		)
+		.unwrap()
+		.unwrap();
+		assert_eq!(line.measurement, "weather");
+		assert_eq!(line.tag("location"), Some("us midwest"));
+		assert_eq!(line.tag("season"), Some("summer"));
+		assert_eq!(
+			line.fields,
+			vec![
+				("temperature".into(), FieldValue::Float(82.0)),
+				("humidity".into(), FieldValue::Integer(71)),
+				("ok".into(), FieldValue::Boolean(true)),
+				("note".into(), FieldValue::String("a \"b\"".into())),
+			]
+		);
+		assert_eq!(line.timestamp, Some(1465839830100400200));
+	}
+
+	#[test]
+	fn parses_minimal_line_and_escapes() {
+		let line = parse_line("my\\,meas f\\=x=-1.5e3").unwrap().unwrap();
+		assert_eq!(line.measurement, "my,meas");
+		assert!(line.tags.is_empty());
+		assert_eq!(
+			line.fields,
+			vec![("f=x".into(), FieldValue::Float(-1500.0))]
+		);
+		assert_eq!(line.timestamp, None);
+		assert_eq!(parse_line("# comment").unwrap(), None);
+		assert_eq!(parse_line("   ").unwrap(), None);
+	}
+
+	#[test]
+	fn rejects_malformed_lines() {
+		assert_eq!(parse_line("m"), Err(ParseError::MissingFields));
+		assert_eq!(parse_line("m,t v=1"), Err(ParseError::InvalidTag));
+		assert_eq!(parse_line("m v=abc"), Err(ParseError::InvalidFieldValue));
+		assert_eq!(parse_line("m v=NaN"), Err(ParseError::InvalidFieldValue));
+		assert_eq!(
+			parse_line("m v=\"open"),
+			Err(ParseError::UnterminatedString)
+		);
+		assert_eq!(parse_line("m v=1 12x"), Err(ParseError::InvalidTimestamp));
+		assert_eq!(parse_line(",t=1 v=1"), Err(ParseError::MissingMeasurement));
+	}
+
+	#[test]
+	fn parse_reports_line_numbers() {
+		let (lines, errors) = parse("m v=1 1\nbroken\n\nm v=2 2\n");
+		assert_eq!(lines.len(), 2);
+		assert_eq!(errors, vec![(2, ParseError::MissingFields)]);
+	}
+
+	#[test]
+	fn converts_timestamps() {
+		let ts = Precision::Milliseconds
+			.to_datetime(1_600_000_000_123)
+			.unwrap();
+		assert_eq!(ts.timestamp(), 1_600_000_000);
+		assert_eq!(ts.timestamp_subsec_millis(), 123);
+		assert_eq!(Precision::from_param("us"), Some(Precision::Microseconds));
+		assert_eq!(Precision::from_param("u"), Some(Precision::Microseconds));
+		assert_eq!(Precision::from_param("x"), None);
+	}
+}
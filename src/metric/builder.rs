@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use super::{DevicePath, OrderedVec, Readout, Unit, Value};
+
+/// Builder for readouts used in tests.
+///
+/// Unless overridden, readouts are taken from a bme280 at `/sbx/i2c/76` at
+/// 2020-09-13T12:26:40Z and have no components.
+pub struct ReadoutBuilder(Readout);
+
+impl ReadoutBuilder {
+	pub fn new() -> Self {
+		Self(Readout {
+			timestamp: Utc.timestamp_opt(1600000000, 0).unwrap(),
+			path: DevicePath {
+				device_type: "bme280".into(),
+				instance: "/sbx/i2c/76".into(),
+			},
+			components: OrderedVec::new(),
+		})
+	}
+
+	pub fn device_type(mut self, device_type: &str) -> Self {
+		self.0.path.device_type = device_type.into();
+		self
+	}
+
+	pub fn instance(mut self, instance: &str) -> Self {
+		self.0.path.instance = instance.into();
+		self
+	}
+
+	pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> Self {
+		self.0.timestamp = timestamp;
+		self
+	}
+
+	/// Set the timestamp to `secs` seconds since the epoch.
+	pub fn at(self, secs: i64) -> Self {
+		self.timestamp(Utc.timestamp_opt(secs, 0).unwrap())
+	}
+
+	pub fn component(mut self, name: &str, magnitude: f64, unit: Unit) -> Self {
+		self.0
+			.components
+			.insert(name.into(), Value { magnitude, unit });
+		self
+	}
+
+	pub fn temperature(self, celsius: f64) -> Self {
+		self.component("temperature", celsius, Unit::Celsius)
+	}
+
+	pub fn build(self) -> Readout {
+		self.0
+	}
+
+	pub fn arc(self) -> Arc<Readout> {
+		Arc::new(self.0)
+	}
+}
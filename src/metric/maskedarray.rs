@@ -14,9 +14,6 @@ use serde::{
 
 use bitvec::prelude::{BitSlice, BitVec, Lsb0};
 
-#[cfg(feature = "metric-serde")]
-use serde_derive::{Deserialize, Serialize};
-
 type MaskVec = BitVec<usize, Lsb0>;
 type MaskSlice = BitSlice<usize, Lsb0>;
 
@@ -9,9 +9,13 @@ use std::time::Duration;
 #[cfg(feature = "metric-serde")]
 use serde_derive::{Deserialize, Serialize};
 
+#[cfg(test)]
+mod builder;
 mod maskedarray;
 mod orderedvec;
 
+#[cfg(test)]
+pub use builder::ReadoutBuilder;
 pub use maskedarray::{MaskedArray, MaskedArrayWriter};
 pub use orderedvec::OrderedVec;
 
@@ -45,6 +49,18 @@ pub enum Unit {
 }
 
 impl Unit {
+	/// Names under which units can be parsed, e.g. from configuration.
+	pub const NAMED: [(&'static str, Unit); 8] = [
+		("%", Self::Percent),
+		("K", Self::Kelvin),
+		("°C", Self::Celsius),
+		("T", Self::Tesla),
+		("AU", Self::Arbitrary),
+		("cnt", Self::Total),
+		("Pa", Self::Pascal),
+		("dB", Self::DeciBel),
+	];
+
 	fn as_str(&self) -> &str {
 		match self {
 			Self::Arbitrary => "",
@@ -75,17 +91,11 @@ impl FromStr for Unit {
 	type Err = &'static str;
 
 	fn from_str(s: &str) -> Result<Self, Self::Err> {
-		match s {
-			"%" => Ok(Self::Percent),
-			"K" => Ok(Self::Kelvin),
-			"°C" => Ok(Self::Celsius),
-			"T" => Ok(Self::Tesla),
-			"AU" => Ok(Self::Arbitrary),
-			"cnt" => Ok(Self::Total),
-			"Pa" => Ok(Self::Pascal),
-			"dB" => Ok(Self::DeciBel),
-			_ => Err("unknown unit"),
-		}
+		Self::NAMED
+			.iter()
+			.find(|(name, _)| *name == s)
+			.map(|(_, unit)| unit.clone())
+			.ok_or("unknown unit")
 	}
 }
 
@@ -125,6 +125,10 @@ impl<K, V> OrderedVec<K, V> {
 		self.0.len()
 	}
 
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
 	pub fn capacity(&self) -> usize {
 		self.0.capacity()
 	}
@@ -0,0 +1,462 @@
+//! # Mapping between readouts and MQTT messages
+//!
+//! Topics are built from templates over `{device_type}`, `{instance}` and
+//! `{component}`, e.g. `sensors/{device_type}{instance}/{component}`. The
+//! same templates are used to take topics of received messages apart
+//! again.
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use microtemplate::{render, Substitutions};
+
+use schemars::JsonSchema;
+use serde_derive::Deserialize;
+
+use smartstring::alias::String as SmartString;
+
+use crate::metric;
+
+#[cfg(test)]
+pub(crate) mod testutil;
+
+#[derive(Substitutions)]
+struct TemplateArgs<'a> {
+	device_type: &'a str,
+	instance: &'a str,
+	component: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+	Literal(String),
+	DeviceType,
+	Instance,
+	Component,
+}
+
+/// Topic template over `{device_type}`, `{instance}` and `{component}`.
+#[derive(Debug, Clone)]
+pub struct TopicTemplate {
+	template: String,
+	tokens: Vec<Token>,
+}
+
+/// Parts of a readout taken from a topic.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicFields {
+	pub device_type: Option<SmartString>,
+	pub instance: Option<SmartString>,
+	pub component: Option<SmartString>,
+}
+
+impl TopicTemplate {
+	pub fn parse(template: &str) -> Result<Self, String> {
+		let mut tokens = Vec::new();
+		let mut rest = template;
+		while let Some(start) = rest.find('{') {
+			if start > 0 {
+				tokens.push(Token::Literal(rest[..start].into()));
+			}
+			let end = rest[start..]
+				.find('}')
+				.ok_or_else(|| format!("unterminated placeholder in topic {:?}", template))?;
+			tokens.push(match &rest[start + 1..start + end] {
+				"device_type" => Token::DeviceType,
+				"instance" => Token::Instance,
+				"component" => Token::Component,
+				other => return Err(format!("unknown placeholder {{{}}} in topic", other)),
+			});
+			rest = &rest[start + end + 1..];
+		}
+		if !rest.is_empty() {
+			tokens.push(Token::Literal(rest.into()));
+		}
+		Ok(Self {
+			template: template.into(),
+			tokens,
+		})
+	}
+
+	/// Whether each component gets a topic of its own.
+	pub fn has_component(&self) -> bool {
+		self.tokens.contains(&Token::Component)
+	}
+
+	/// Build a topic; MQTT wildcards in the values are replaced by
+	/// underscores.
+	pub fn render(&self, path: &metric::DevicePath, component: &str) -> String {
+		let device_type = path.device_type.replace(['+', '#'], "_");
+		let instance = path.instance.replace(['+', '#'], "_");
+		let component = component.replace(['+', '#'], "_");
+		render(
+			&self.template,
+			TemplateArgs {
+				device_type: &device_type,
+				instance: &instance,
+				component: &component,
+			},
+		)
+	}
+
+	/// Take a topic apart according to the template.
+	///
+	/// The instance may span multiple topic levels, the other placeholders
+	/// match within a single level.
+	pub fn match_topic(&self, topic: &str) -> Option<TopicFields> {
+		let mut fields = TopicFields::default();
+		if match_tokens(&self.tokens, topic, &mut fields) {
+			Some(fields)
+		} else {
+			None
+		}
+	}
+}
+
+fn match_tokens(tokens: &[Token], topic: &str, fields: &mut TopicFields) -> bool {
+	let (token, rest) = match tokens.split_first() {
+		Some(v) => v,
+		None => return topic.is_empty(),
+	};
+	if let Token::Literal(s) = token {
+		return match topic.strip_prefix(s.as_str()) {
+			Some(topic) => match_tokens(rest, topic, fields),
+			None => false,
+		};
+	}
+	// the instance may span levels and takes as little as possible, the
+	// others take as much of their level as possible
+	let limit = match token {
+		Token::Instance => topic.len(),
+		_ => topic.find('/').unwrap_or(topic.len()),
+	};
+	let mut ends: Vec<usize> = topic[..limit]
+		.char_indices()
+		.map(|(i, _)| i)
+		.skip(1)
+		.chain(Some(limit))
+		.collect();
+	if *token != Token::Instance {
+		ends.reverse();
+	}
+	for end in ends {
+		let mut attempt = fields.clone();
+		let value = Some(topic[..end].into());
+		match token {
+			Token::DeviceType => attempt.device_type = value,
+			Token::Instance => attempt.instance = value,
+			Token::Component => attempt.component = value,
+			Token::Literal(_) => unreachable!(),
+		}
+		if match_tokens(rest, &topic[end..], &mut attempt) {
+			*fields = attempt;
+			return true;
+		}
+	}
+	false
+}
+
+/// Encoding of values in message payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+pub enum PayloadFormat {
+	/// JSON objects with value, unit and timestamp.
+	Json,
+	/// Just the number as text; one message per component.
+	Plain,
+}
+
+fn unit_str(unit: &metric::Unit) -> String {
+	match unit {
+		metric::Unit::Arbitrary => "".into(),
+		metric::Unit::Total => "cnt".into(),
+		other => other.to_string(),
+	}
+}
+
+fn value_json(value: &metric::Value) -> serde_json::Value {
+	let mut result = serde_json::Map::new();
+	result.insert("value".into(), value.magnitude.into());
+	let unit = unit_str(&value.unit);
+	if !unit.is_empty() {
+		result.insert("unit".into(), unit.into());
+	}
+	result.into()
+}
+
+/// Messages (topic and payload) for a readout.
+///
+/// If the template has no `{component}` placeholder, all components are
+/// sent in one JSON message.
+pub fn encode(
+	readout: &metric::Readout,
+	topic: &TopicTemplate,
+	format: PayloadFormat,
+) -> Vec<(String, Vec<u8>)> {
+	let timestamp = readout
+		.timestamp
+		.to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+	if !topic.has_component() {
+		let components: serde_json::Map<_, _> = readout
+			.components
+			.iter()
+			.map(|(k, v)| (k.to_string(), value_json(v)))
+			.collect();
+		let payload = serde_json::json!({
+			"timestamp": timestamp,
+			"components": components,
+		});
+		return vec![(
+			topic.render(&readout.path, ""),
+			payload.to_string().into_bytes(),
+		)];
+	}
+	readout
+		.components
+		.iter()
+		.map(|(component, value)| {
+			let payload = match format {
+				PayloadFormat::Plain => value.magnitude.to_string(),
+				PayloadFormat::Json => {
+					let mut v = value_json(value);
+					v["timestamp"] = timestamp.clone().into();
+					v.to_string()
+				}
+			};
+			(topic.render(&readout.path, component), payload.into_bytes())
+		})
+		.collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+	NotUtf8,
+	InvalidNumber,
+	InvalidJson(String),
+	InvalidTimestamp,
+	/// Neither the topic nor the payload name a component.
+	MissingComponent,
+	NoValues,
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::NotUtf8 => f.write_str("payload is not valid utf-8"),
+			Self::InvalidNumber => f.write_str("payload is not a number"),
+			Self::InvalidJson(e) => write!(f, "invalid json: {}", e),
+			Self::InvalidTimestamp => f.write_str("invalid timestamp"),
+			Self::MissingComponent => f.write_str("no component in topic or payload"),
+			Self::NoValues => f.write_str("payload contains no numeric values"),
+		}
+	}
+}
+
+impl std::error::Error for DecodeError {}
+
+fn parse_unit(value: Option<&serde_json::Value>) -> metric::Unit {
+	match value.and_then(|v| v.as_str()) {
+		None | Some("") => metric::Unit::Arbitrary,
+		Some(s) => s.parse().unwrap_or_else(|_| metric::Unit::Other(s.into())),
+	}
+}
+
+/// Value of a JSON component: a number, boolean or an object with `value`
+/// and optionally `unit`.
+fn json_value(v: &serde_json::Value) -> Option<metric::Value> {
+	match v {
+		serde_json::Value::Number(n) => Some(metric::Value {
+			magnitude: n.as_f64()?,
+			unit: metric::Unit::Arbitrary,
+		}),
+		serde_json::Value::Bool(b) => Some(metric::Value {
+			magnitude: if *b { 1.0 } else { 0.0 },
+			unit: metric::Unit::Arbitrary,
+		}),
+		serde_json::Value::Object(obj) => Some(metric::Value {
+			magnitude: obj.get("value")?.as_f64()?,
+			unit: parse_unit(obj.get("unit")),
+		}),
+		_ => None,
+	}
+}
+
+/// Turn a received message into a readout.
+///
+/// Without a component in the topic, JSON payloads may either carry a
+/// `components` object as sent by [`encode`], or be a flat object whose
+/// numeric fields become components, as many devices send it.
+pub fn decode(
+	fields: &TopicFields,
+	payload: &[u8],
+	format: PayloadFormat,
+	now: DateTime<Utc>,
+) -> Result<metric::Readout, DecodeError> {
+	let text = std::str::from_utf8(payload).map_err(|_| DecodeError::NotUtf8)?;
+	let mut timestamp = now;
+	let mut components = metric::OrderedVec::new();
+	match format {
+		PayloadFormat::Plain => {
+			let component = fields
+				.component
+				.clone()
+				.ok_or(DecodeError::MissingComponent)?;
+			let magnitude = text
+				.trim()
+				.parse()
+				.map_err(|_| DecodeError::InvalidNumber)?;
+			components.insert(
+				component,
+				metric::Value {
+					magnitude,
+					unit: metric::Unit::Arbitrary,
+				},
+			);
+		}
+		PayloadFormat::Json => {
+			let v: serde_json::Value =
+				serde_json::from_str(text).map_err(|e| DecodeError::InvalidJson(e.to_string()))?;
+			if let Some(ts) = v.get("timestamp").and_then(|ts| ts.as_str()) {
+				timestamp = DateTime::parse_from_rfc3339(ts)
+					.map_err(|_| DecodeError::InvalidTimestamp)?
+					.with_timezone(&Utc);
+			}
+			match (fields.component.as_ref(), v.get("components")) {
+				(Some(component), _) => {
+					if let Some(value) = json_value(&v) {
+						components.insert(component.clone(), value);
+					}
+				}
+				(None, Some(serde_json::Value::Object(map))) => {
+					for (k, v) in map.iter() {
+						if let Some(value) = json_value(v) {
+							components.insert(k.into(), value);
+						}
+					}
+				}
+				(None, _) => match v.as_object() {
+					Some(map) => {
+						for (k, v) in map.iter() {
+							match v {
+								serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+									if let Some(value) = json_value(v) {
+										components.insert(k.into(), value);
+									}
+								}
+								_ => (),
+							}
+						}
+					}
+					None => return Err(DecodeError::MissingComponent),
+				},
+			}
+		}
+	}
+	if components.is_empty() {
+		return Err(DecodeError::NoValues);
+	}
+	Ok(metric::Readout {
+		timestamp,
+		path: metric::DevicePath {
+			device_type: fields.device_type.clone().unwrap_or_default(),
+			instance: fields.instance.clone().unwrap_or_default(),
+		},
+		components,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::TimeZone;
+
+	fn readout() -> metric::Readout {
+		metric::ReadoutBuilder::new()
+			.instance("/sbx/i2c/0x76")
+			.temperature(21.5)
+			.component("count", 3.0, metric::Unit::Arbitrary)
+			.build()
+	}
+
+	#[test]
+	fn renders_and_matches_topics() {
+		let template = TopicTemplate::parse("sensors/{device_type}{instance}/{component}").unwrap();
+		let path = readout().path;
+		let topic = template.render(&path, "temperature");
+		assert_eq!(topic, "sensors/bme280/sbx/i2c/0x76/temperature");
+		assert_eq!(
+			template.match_topic(&topic),
+			Some(TopicFields {
+				device_type: Some("bme280".into()),
+				instance: Some("/sbx/i2c/0x76".into()),
+				component: Some("temperature".into()),
+			})
+		);
+		assert_eq!(template.match_topic("other/bme280/x/t"), None);
+		assert!(TopicTemplate::parse("a/{nope}").is_err());
+	}
+
+	#[test]
+	fn encodes_messages_per_component() {
+		let template = TopicTemplate::parse("s/{device_type}/{component}").unwrap();
+		let messages = encode(&readout(), &template, PayloadFormat::Json);
+		assert_eq!(messages.len(), 2);
+		assert_eq!(messages[1].0, "s/bme280/temperature");
+		let v: serde_json::Value = serde_json::from_slice(&messages[1].1).unwrap();
+		assert_eq!(
+			v,
+			serde_json::json!({
+				"value": 21.5,
+				"unit": "°C",
+				"timestamp": "2020-09-13T12:26:40.000000Z",
+			})
+		);
+		let plain = encode(&readout(), &template, PayloadFormat::Plain);
+		assert_eq!(plain[0], ("s/bme280/count".into(), b"3".to_vec()));
+	}
+
+	#[test]
+	fn roundtrips_readouts_without_component_in_topic() {
+		let template = TopicTemplate::parse("s/{device_type}{instance}").unwrap();
+		let messages = encode(&readout(), &template, PayloadFormat::Json);
+		assert_eq!(messages.len(), 1);
+		let fields = template.match_topic(&messages[0].0).unwrap();
+		let decoded = decode(&fields, &messages[0].1, PayloadFormat::Json, Utc::now()).unwrap();
+		assert_eq!(decoded, readout());
+	}
+
+	#[test]
+	fn decodes_flat_json_and_plain_values() {
+		let now = Utc.timestamp_opt(1700000000, 0).unwrap();
+		let fields = TopicFields {
+			device_type: Some("plug".into()),
+			instance: Some("kitchen".into()),
+			component: None,
+		};
+		let readout = decode(
+			&fields,
+			br#"{"power": 12.5, "state": true, "name": "x"}"#,
+			PayloadFormat::Json,
+			now,
+		)
+		.unwrap();
+		assert_eq!(readout.timestamp, now);
+		assert_eq!(readout.components.len(), 2);
+		assert_eq!(readout.components.get("state").unwrap().magnitude, 1.0);
+
+		assert_eq!(
+			decode(&fields, b"12", PayloadFormat::Plain, now),
+			Err(DecodeError::MissingComponent)
+		);
+		let fields = TopicFields {
+			component: Some("power".into()),
+			..fields
+		};
+		let readout = decode(&fields, b" 7.25\n", PayloadFormat::Plain, now).unwrap();
+		assert_eq!(readout.components.get("power").unwrap().magnitude, 7.25);
+		assert_eq!(
+			decode(&fields, b"on", PayloadFormat::Plain, now),
+			Err(DecodeError::InvalidNumber)
+		);
+	}
+}
@@ -0,0 +1,198 @@
+//! Minimal stand-in for an MQTT 3.1.1 broker, for use in tests.
+//!
+//! It accepts any connection, records published messages and forwards them
+//! at QoS 0 to matching subscriptions. Sessions, retained messages and
+//! QoS 2 are not supported.
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+	pub topic: String,
+	pub payload: Vec<u8>,
+	pub qos: u8,
+	pub retain: bool,
+}
+
+impl Message {
+	pub fn payload_str(&self) -> String {
+		String::from_utf8_lossy(&self.payload).into()
+	}
+}
+
+type Subscriber = (String, mpsc::UnboundedSender<Vec<u8>>);
+
+#[derive(Default)]
+struct State {
+	published: Vec<Message>,
+	subscribers: Vec<Subscriber>,
+}
+
+pub struct Broker {
+	pub host: String,
+	pub port: u16,
+	state: Arc<Mutex<State>>,
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(sock: &mut R) -> Option<(u8, Vec<u8>)> {
+	let header = sock.read_u8().await.ok()?;
+	let mut length = 0usize;
+	for shift in (0..28).step_by(7) {
+		let b = sock.read_u8().await.ok()?;
+		length |= ((b & 0x7f) as usize) << shift;
+		if b & 0x80 == 0 {
+			break;
+		}
+	}
+	let mut body = vec![0u8; length];
+	sock.read_exact(&mut body).await.ok()?;
+	Some((header, body))
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+	let mut result = vec![header];
+	let mut length = body.len();
+	loop {
+		let mut b = (length & 0x7f) as u8;
+		length >>= 7;
+		if length > 0 {
+			b |= 0x80;
+		}
+		result.push(b);
+		if length == 0 {
+			break;
+		}
+	}
+	result.extend_from_slice(body);
+	result
+}
+
+fn read_str(body: &[u8]) -> (String, &[u8]) {
+	let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+	(
+		String::from_utf8_lossy(&body[2..2 + len]).into(),
+		&body[2 + len..],
+	)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+	let mut body = (topic.len() as u16).to_be_bytes().to_vec();
+	body.extend_from_slice(topic.as_bytes());
+	body.extend_from_slice(payload);
+	packet(0x30, &body)
+}
+
+impl State {
+	fn forward(&self, topic: &str, payload: &[u8]) {
+		for (filter, tx) in self.subscribers.iter() {
+			if rumqttc::matches(topic, filter) {
+				let _ = tx.send(publish_packet(topic, payload));
+			}
+		}
+	}
+}
+
+async fn serve(sock: tokio::net::TcpStream, state: Arc<Mutex<State>>) {
+	let (mut rx, mut tx) = sock.into_split();
+	let (out, mut outq) = mpsc::unbounded_channel::<Vec<u8>>();
+	tokio::spawn(async move {
+		while let Some(buf) = outq.recv().await {
+			if tx.write_all(&buf).await.is_err() {
+				return;
+			}
+		}
+	});
+	while let Some((header, body)) = read_packet(&mut rx).await {
+		match header >> 4 {
+			// CONNECT
+			1 => {
+				let _ = out.send(packet(0x20, &[0, 0]));
+			}
+			// PUBLISH
+			3 => {
+				let qos = (header >> 1) & 3;
+				let (topic, mut rest) = read_str(&body);
+				if qos > 0 {
+					let _ = out.send(packet(0x40, &rest[..2]));
+					rest = &rest[2..];
+				}
+				let mut state = state.lock().unwrap();
+				state.forward(&topic, rest);
+				state.published.push(Message {
+					topic,
+					payload: rest.to_vec(),
+					qos,
+					retain: header & 1 != 0,
+				});
+			}
+			// SUBSCRIBE
+			8 => {
+				let mut ack = body[..2].to_vec();
+				let mut rest = &body[2..];
+				while !rest.is_empty() {
+					let (filter, tail) = read_str(rest);
+					state
+						.lock()
+						.unwrap()
+						.subscribers
+						.push((filter, out.clone()));
+					ack.push(0);
+					rest = &tail[1..];
+				}
+				let _ = out.send(packet(0x90, &ack));
+			}
+			// PINGREQ
+			12 => {
+				let _ = out.send(packet(0xd0, &[]));
+			}
+			// DISCONNECT
+			14 => break,
+			_ => (),
+		}
+	}
+}
+
+impl Broker {
+	pub async fn start() -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+		let state = Arc::new(Mutex::new(State::default()));
+		let result = Self {
+			host: addr.ip().to_string(),
+			port: addr.port(),
+			state: state.clone(),
+		};
+		tokio::spawn(async move {
+			loop {
+				let (sock, _) = listener.accept().await.unwrap();
+				tokio::spawn(serve(sock, state.clone()));
+			}
+		});
+		result
+	}
+
+	/// Send a message to all matching subscriptions, as if another client
+	/// had published it.
+	pub fn publish(&self, topic: &str, payload: &[u8]) {
+		self.state.lock().unwrap().forward(topic, payload);
+	}
+
+	/// All messages published by clients so far.
+	pub fn published(&self) -> Vec<Message> {
+		self.state.lock().unwrap().published.clone()
+	}
+
+	/// Topic filters of all subscriptions made so far.
+	pub fn subscriptions(&self) -> Vec<String> {
+		self.state
+			.lock()
+			.unwrap()
+			.subscribers
+			.iter()
+			.map(|(filter, _)| filter.clone())
+			.collect()
+	}
+}
@@ -0,0 +1,379 @@
+//! # Prometheus metric naming and exposition
+//!
+//! Readouts become one series per component, named after the device type
+//! and component with the unit as suffix, e.g.
+//!
+//! ```text
+//! bme280_temperature_celsius{instance="/sbx/i2c/0x76"} 21.5
+//! ```
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+use log::warn;
+
+use chrono::{DateTime, Utc};
+
+use crate::metric;
+
+#[cfg(feature = "prometheus-remote-write")]
+pub mod remote_write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MetricType {
+	Gauge,
+	Counter,
+}
+
+impl MetricType {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Self::Gauge => "gauge",
+			Self::Counter => "counter",
+		}
+	}
+}
+
+/// Replace everything which is not allowed in a metric or label name by
+/// underscores.
+pub fn sanitize_name(name: &str) -> String {
+	let mut result = String::with_capacity(name.len() + 1);
+	for (i, ch) in name.chars().enumerate() {
+		match ch {
+			'a'..='z' | 'A'..='Z' | '_' | ':' => result.push(ch),
+			'0'..='9' => {
+				if i == 0 {
+					result.push('_');
+				}
+				result.push(ch);
+			}
+			_ => result.push('_'),
+		}
+	}
+	result
+}
+
+/// Whether `name` is a valid label name, i.e. matches
+/// `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub fn is_label_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	match chars.next() {
+		Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => {}
+		_ => return false,
+	}
+	chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// Base unit name used as metric name suffix, if any.
+pub fn unit_suffix(unit: &metric::Unit) -> Option<String> {
+	let suffix = match unit {
+		metric::Unit::Arbitrary | metric::Unit::Status | metric::Unit::Total => return None,
+		metric::Unit::Other(s) => {
+			let s = sanitize_name(s).to_lowercase();
+			let s = s.trim_matches('_');
+			if s.is_empty() {
+				return None;
+			}
+			return Some(s.into());
+		}
+		metric::Unit::Percent => "percent",
+		metric::Unit::Kelvin => "kelvin",
+		metric::Unit::Celsius => "celsius",
+		metric::Unit::MeterPerSqSecond => "meters_per_second_squared",
+		metric::Unit::Tesla => "tesla",
+		metric::Unit::Pascal => "pascals",
+		metric::Unit::DeciBel => "decibels",
+	};
+	Some(suffix.into())
+}
+
+/// Name of the metric family of a component, and its type.
+///
+/// The `_total` suffix of counters is not part of the family name.
+pub fn family_name(
+	prefix: &str,
+	device_type: &str,
+	component: &str,
+	unit: &metric::Unit,
+) -> (String, MetricType) {
+	let mut name = sanitize_name(&format!("{}{}_{}", prefix, device_type, component));
+	if let Some(suffix) = unit_suffix(unit) {
+		if !name.ends_with(&format!("_{}", suffix)) {
+			name.push('_');
+			name.push_str(&suffix);
+		}
+	}
+	match unit {
+		metric::Unit::Total => {
+			if let Some(stripped) = name.strip_suffix("_total") {
+				name.truncate(stripped.len());
+			}
+			(name, MetricType::Counter)
+		}
+		_ => (name, MetricType::Gauge),
+	}
+}
+
+/// Latest value of a single series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+	pub family: String,
+	pub kind: MetricType,
+	pub unit: Option<String>,
+	pub labels: Vec<(String, String)>,
+	pub value: f64,
+	pub timestamp: DateTime<Utc>,
+}
+
+impl Series {
+	/// Name of the sample, including the `_total` suffix for counters.
+	pub fn sample_name(&self) -> String {
+		match self.kind {
+			MetricType::Gauge => self.family.clone(),
+			MetricType::Counter => format!("{}_total", self.family),
+		}
+	}
+}
+
+/// Series of a single component of a device, with the instance as label
+/// (unless it is empty).
+pub fn component_series(
+	prefix: &str,
+	instance_label: &str,
+	path: &metric::DevicePath,
+	component: &str,
+	value: &metric::Value,
+	timestamp: DateTime<Utc>,
+) -> Series {
+	let (family, kind) = family_name(prefix, &path.device_type, component, &value.unit);
+	let mut labels = Vec::new();
+	if !path.instance.is_empty() {
+		labels.push((instance_label.into(), path.instance.to_string()));
+	}
+	Series {
+		family,
+		kind,
+		unit: unit_suffix(&value.unit),
+		labels,
+		value: value.magnitude,
+		timestamp,
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+	/// The classic text format (version 0.0.4).
+	Text,
+	OpenMetrics,
+}
+
+impl Format {
+	/// Pick the format from the `Accept` header of a scrape request.
+	pub fn negotiate(accept: Option<&str>) -> Self {
+		match accept {
+			Some(v) if v.contains("application/openmetrics-text") => Self::OpenMetrics,
+			_ => Self::Text,
+		}
+	}
+
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Self::Text => "text/plain; version=0.0.4; charset=utf-8",
+			Self::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+		}
+	}
+}
+
+fn escape_label_value(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+	if value.is_nan() {
+		"NaN".into()
+	} else if value == f64::INFINITY {
+		"+Inf".into()
+	} else if value == f64::NEG_INFINITY {
+		"-Inf".into()
+	} else {
+		value.to_string()
+	}
+}
+
+/// Render series in the given format, grouped by family.
+///
+/// Families whose name or sample name is already taken by another family,
+/// e.g. a gauge `x_total` next to a counter `x`, are skipped.
+pub fn render(series: &[Series], format: Format, timestamps: bool) -> String {
+	let mut families: BTreeMap<(&str, MetricType), Vec<&Series>> = BTreeMap::new();
+	for s in series {
+		families.entry((&s.family, s.kind)).or_default().push(s);
+	}
+
+	let mut out = String::new();
+	let mut names = HashSet::new();
+	for ((family, kind), mut members) in families {
+		let sample_name = members[0].sample_name();
+		if names.contains(family) || names.contains(&sample_name) {
+			warn!(
+				"skipping {} {}, its name clashes with another metric",
+				kind.as_str(),
+				sample_name
+			);
+			continue;
+		}
+		names.insert(family.to_string());
+		names.insert(sample_name);
+		members.sort_by(|a, b| a.labels.cmp(&b.labels));
+		match format {
+			Format::Text => {
+				let _ = writeln!(out, "# TYPE {} {}", members[0].sample_name(), kind.as_str());
+			}
+			Format::OpenMetrics => {
+				let _ = writeln!(out, "# TYPE {} {}", family, kind.as_str());
+				if let Some(unit) = members[0].unit.as_ref() {
+					if family.ends_with(&format!("_{}", unit)) {
+						let _ = writeln!(out, "# UNIT {} {}", family, unit);
+					}
+				}
+			}
+		}
+		for s in members {
+			out.push_str(&s.sample_name());
+			if !s.labels.is_empty() {
+				out.push('{');
+				for (i, (k, v)) in s.labels.iter().enumerate() {
+					if i > 0 {
+						out.push(',');
+					}
+					let _ = write!(out, "{}=\"{}\"", k, escape_label_value(v));
+				}
+				out.push('}');
+			}
+			let _ = write!(out, " {}", format_value(s.value));
+			if timestamps {
+				match format {
+					Format::Text => {
+						let _ = write!(out, " {}", s.timestamp.timestamp_millis());
+					}
+					Format::OpenMetrics => {
+						let _ = write!(
+							out,
+							" {:.3}",
+							s.timestamp.timestamp_millis() as f64 / 1000.0
+						);
+					}
+				}
+			}
+			out.push('\n');
+		}
+	}
+	if format == Format::OpenMetrics {
+		out.push_str("# EOF\n");
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::TimeZone;
+
+	fn series(family: &str, kind: MetricType, instance: &str, value: f64) -> Series {
+		Series {
+			family: family.into(),
+			kind,
+			unit: None,
+			labels: vec![("instance".into(), instance.into())],
+			value,
+			timestamp: Utc.timestamp_opt(1600000000, 500_000_000).unwrap(),
+		}
+	}
+
+	#[test]
+	fn names_follow_conventions() {
+		assert_eq!(
+			family_name("", "bme280", "temperature", &metric::Unit::Celsius),
+			("bme280_temperature_celsius".into(), MetricType::Gauge)
+		);
+		assert_eq!(
+			family_name("relay_", "sbx-rx", "packets", &metric::Unit::Total),
+			("relay_sbx_rx_packets".into(), MetricType::Counter)
+		);
+		assert_eq!(
+			family_name("", "x", "bytes_total", &metric::Unit::Total),
+			("x_bytes".into(), MetricType::Counter)
+		);
+		assert_eq!(
+			family_name("", "9dof", "p_pascals", &metric::Unit::Pascal),
+			("_9dof_p_pascals".into(), MetricType::Gauge)
+		);
+		assert_eq!(
+			family_name("", "m", "v", &metric::Unit::Other("ppm".into())),
+			("m_v_ppm".into(), MetricType::Gauge)
+		);
+	}
+
+	#[test]
+	fn renders_text_format() {
+		let text = render(
+			&[
+				series("m_v", MetricType::Gauge, "b", f64::NAN),
+				series("m_v", MetricType::Gauge, "a\"x", 1.5),
+				series("m_n", MetricType::Counter, "a", 3.0),
+			],
+			Format::Text,
+			true,
+		);
+		assert_eq!(
+			text,
+			"# TYPE m_n_total counter\nm_n_total{instance=\"a\"} 3 1600000000500\n\
+			 # TYPE m_v gauge\nm_v{instance=\"a\\\"x\"} 1.5 1600000000500\nm_v{instance=\"b\"} NaN 1600000000500\n"
+		);
+	}
+
+	#[test]
+	fn renders_openmetrics_format() {
+		let mut s = series("m_t_celsius", MetricType::Gauge, "a", 20.0);
+		s.unit = Some("celsius".into());
+		let text = render(
+			&[s, series("m_n", MetricType::Counter, "a", 3.0)],
+			Format::OpenMetrics,
+			false,
+		);
+		assert_eq!(
+			text,
+			"# TYPE m_n counter\nm_n_total{instance=\"a\"} 3\n\
+			 # TYPE m_t_celsius gauge\n# UNIT m_t_celsius celsius\nm_t_celsius{instance=\"a\"} 20\n\
+			 # EOF\n"
+		);
+	}
+
+	#[test]
+	fn skips_clashing_families() {
+		let series = [
+			series("m_n_total", MetricType::Gauge, "a", 1.0),
+			series("m_n", MetricType::Counter, "a", 3.0),
+			series("m_v", MetricType::Counter, "a", 4.0),
+			series("m_v", MetricType::Gauge, "a", 2.0),
+		];
+		assert_eq!(
+			render(&series, Format::Text, false),
+			"# TYPE m_n_total counter\nm_n_total{instance=\"a\"} 3\n\
+			 # TYPE m_v gauge\nm_v{instance=\"a\"} 2\n"
+		);
+	}
+
+	#[test]
+	fn checks_label_names() {
+		for name in &["instance", "_x", "a1_B"] {
+			assert!(is_label_name(name), "{}", name);
+		}
+		for name in &["", "1a", "a-b", "a:b", "größe"] {
+			assert!(!is_label_name(name), "{}", name);
+		}
+	}
+}
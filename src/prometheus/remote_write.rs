@@ -0,0 +1,145 @@
+//! Client for the Prometheus remote write protocol (version 1), as spoken
+//! by Prometheus, VictoriaMetrics, Mimir and others.
+use std::time::Duration;
+
+use crate::http;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WriteRequest {
+	#[prost(message, repeated, tag = "1")]
+	pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TimeSeries {
+	/// Labels including `__name__`, sorted by name.
+	#[prost(message, repeated, tag = "1")]
+	pub labels: Vec<Label>,
+	#[prost(message, repeated, tag = "2")]
+	pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Label {
+	#[prost(string, tag = "1")]
+	pub name: String,
+	#[prost(string, tag = "2")]
+	pub value: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sample {
+	#[prost(double, tag = "1")]
+	pub value: f64,
+	/// Milliseconds since the epoch.
+	#[prost(int64, tag = "2")]
+	pub timestamp: i64,
+}
+
+impl WriteRequest {
+	/// Serialize and compress the request body.
+	pub fn encode_body(&self) -> Vec<u8> {
+		let raw = prost::Message::encode_to_vec(self);
+		// compressing into a Vec cannot fail except for absurd sizes
+		snap::raw::Encoder::new()
+			.compress_vec(&raw)
+			.expect("compressing remote write request")
+	}
+
+	/// Decompress and parse a request body.
+	pub fn decode_body(body: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+		let raw = snap::raw::Decoder::new().decompress_vec(body)?;
+		Ok(prost::Message::decode(&raw[..])?)
+	}
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Client {
+	client: reqwest::Client,
+	url: String,
+	auth: http::Auth,
+}
+
+impl Client {
+	pub fn new(url: String, auth: http::Auth) -> Self {
+		Self {
+			client: reqwest::Client::builder()
+				.timeout(REQUEST_TIMEOUT)
+				.build()
+				.expect("building the http client"),
+			url,
+			auth,
+		}
+	}
+
+	pub async fn post(&self, request: &WriteRequest) -> Result<(), http::Error> {
+		let req = self
+			.client
+			.post(&self.url)
+			.header("Content-Encoding", "snappy")
+			.header("Content-Type", "application/x-protobuf")
+			.header("X-Prometheus-Remote-Write-Version", "0.1.0")
+			.body(request.encode_body());
+		http::send(self.auth.apply(req)).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::testutil::StandIn;
+
+	fn request() -> WriteRequest {
+		WriteRequest {
+			timeseries: vec![TimeSeries {
+				labels: vec![
+					Label {
+						name: "__name__".into(),
+						value: "m_v".into(),
+					},
+					Label {
+						name: "instance".into(),
+						value: "a".into(),
+					},
+				],
+				samples: vec![Sample {
+					value: 1.5,
+					timestamp: 1600000000000,
+				}],
+			}],
+		}
+	}
+
+	#[test]
+	fn body_roundtrips() {
+		let body = request().encode_body();
+		assert_eq!(WriteRequest::decode_body(&body).unwrap(), request());
+	}
+
+	#[tokio::test]
+	async fn posts_compressed_protobuf_with_auth() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let client = Client::new(
+			format!("{}/api/v1/write", server.url),
+			http::Auth::Bearer {
+				token: "s3cr3t".into(),
+			},
+		);
+		client.post(&request()).await.unwrap();
+		let requests = server.requests();
+		assert_eq!(requests[0].target(), "/api/v1/write");
+		assert_eq!(requests[0].header("authorization"), Some("Bearer s3cr3t"));
+		assert_eq!(requests[0].header("content-encoding"), Some("snappy"));
+		assert_eq!(
+			WriteRequest::decode_body(&requests[0].body).unwrap(),
+			request()
+		);
+
+		server.set_responder(Box::new(|_| 400));
+		assert!(!client.post(&request()).await.unwrap_err().is_retryable());
+		server.set_responder(Box::new(|_| 503));
+		assert!(client.post(&request()).await.unwrap_err().is_retryable());
+	}
+}
@@ -1,70 +1,252 @@
 use std::fmt::Write;
+use std::time::Duration;
 
 use microtemplate::{render, Substitutions};
 use xml::escape::escape_str_attribute;
 
+use crate::http;
 use crate::metric;
 
+/// Namespace of the `sample-batch` elements, unless configured otherwise.
+pub const DEFAULT_NAMESPACE: &str = "https://xmlns.zombofant.net/hint/sensor/1.0";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Substitutions)]
 struct TemplateArgs<'a> {
 	instance: &'a str,
 	device_type: &'a str,
 }
 
-pub struct Client {
-	client: reqwest::Client,
-	api_url: String,
-	node_template: String,
-	override_host: Option<String>,
+/// How readouts are rendered into XML payloads.
+pub struct Format {
+	/// XML namespace of the elements, which identifies the schema version.
+	pub namespace: String,
+	/// Add a `unit` attribute to the `numeric` elements.
+	pub units: bool,
 }
 
-impl Client {
-	pub fn new(api_url: String, node_template: String, override_host: Option<String>) -> Self {
+impl Default for Format {
+	fn default() -> Self {
 		Self {
-			client: reqwest::Client::new(),
-			api_url,
-			node_template,
-			override_host,
+			namespace: DEFAULT_NAMESPACE.into(),
+			units: true,
 		}
 	}
+}
 
-	pub async fn post(&self, readout: &metric::Readout) -> Result<(), reqwest::Error> {
-		let mut payload = format!(
-			"<sample-batch xmlns='https://xmlns.zombofant.net/hint/sensor/1.0'  timestamp='{}' part='{}' instance='{}'>",
-			readout.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+impl Format {
+	fn write_sample_batch(&self, out: &mut String, readout: &metric::Readout, xmlns: bool) {
+		out.push_str("<sample-batch");
+		if xmlns {
+			write!(out, " xmlns='{}'", escape_str_attribute(&self.namespace)).unwrap();
+		}
+		write!(
+			out,
+			" timestamp='{}' part='{}' instance='{}'>",
+			readout
+				.timestamp
+				.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
 			escape_str_attribute(&readout.path.device_type),
 			escape_str_attribute(&readout.path.instance),
-		);
+		)
+		.unwrap();
 
 		for (k, v) in readout.components.iter() {
 			write!(
-				payload,
-				"<numeric subpart='{}' value='{:?}'/>",
+				out,
+				"<numeric subpart='{}' value='{:?}'",
 				escape_str_attribute(k),
 				v.magnitude
 			)
 			.unwrap();
+			if self.units {
+				let unit = v.unit.to_string();
+				if !unit.is_empty() {
+					write!(out, " unit='{}'", escape_str_attribute(&unit)).unwrap();
+				}
+			}
+			out.push_str("/>");
+		}
+		out.push_str("</sample-batch>");
+	}
+
+	/// A `sample-batch` document for a single readout.
+	pub fn sample_batch(&self, readout: &metric::Readout) -> String {
+		let mut out = String::new();
+		self.write_sample_batch(&mut out, readout, true);
+		out
+	}
+
+	/// A `sample-batches` document containing a `sample-batch` for each
+	/// readout.
+	pub fn sample_batches<'x, I: IntoIterator<Item = &'x metric::Readout>>(
+		&self,
+		readouts: I,
+	) -> String {
+		let mut out = format!(
+			"<sample-batches xmlns='{}'>",
+			escape_str_attribute(&self.namespace)
+		);
+		for readout in readouts {
+			self.write_sample_batch(&mut out, readout, false);
+		}
+		out.push_str("</sample-batches>");
+		out
+	}
+}
+
+pub struct Client {
+	client: reqwest::Client,
+	api_url: String,
+	node_template: String,
+	override_host: Option<String>,
+	auth: http::Auth,
+}
+
+impl Client {
+	pub fn new(
+		api_url: String,
+		node_template: String,
+		override_host: Option<String>,
+		auth: http::Auth,
+	) -> Self {
+		Self {
+			client: reqwest::Client::builder()
+				.timeout(REQUEST_TIMEOUT)
+				.build()
+				.expect("building the http client"),
+			api_url,
+			node_template,
+			override_host,
+			auth,
 		}
-		payload.write_str("</sample-batch>").unwrap();
+	}
 
-		let node = render(
+	/// The node a readout is published to.
+	pub fn node(&self, readout: &metric::Readout) -> String {
+		render(
 			&self.node_template,
 			TemplateArgs {
 				instance: &readout.path.instance,
 				device_type: &readout.path.device_type,
 			},
-		);
+		)
+	}
 
+	/// Publish an XML payload to a node.
+	pub async fn post(&self, node: &str, payload: String) -> Result<(), http::Error> {
 		let req = self.client.post(format!("{}/{}", self.api_url, node));
 		let req = req.header("Content-Type", "application/xml");
 		let req = match self.override_host.as_ref() {
 			Some(v) => req.header("Host", v),
 			None => req,
 		};
-		let req = req.body(payload);
+		let req = self.auth.apply(req).body(payload);
+
+		http::send(req).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use xml::reader::{EventReader, XmlEvent};
+
+	fn readout(instance: &str) -> metric::Readout {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.temperature(21.5)
+			.component("status", 1.0, metric::Unit::Arbitrary)
+			.build()
+	}
+
+	/// Namespace, name and attributes of an element.
+	type Element = (String, String, Vec<(String, String)>);
+
+	/// Elements in document order.
+	fn elements(doc: &str) -> Vec<Element> {
+		let mut result = Vec::new();
+		for ev in EventReader::from_str(doc) {
+			if let XmlEvent::StartElement {
+				name, attributes, ..
+			} = ev.unwrap()
+			{
+				result.push((
+					name.namespace.unwrap_or_default(),
+					name.local_name,
+					attributes
+						.into_iter()
+						.map(|attr| (attr.name.local_name, attr.value))
+						.collect(),
+				));
+			}
+		}
+		result
+	}
+
+	fn attr<'x>(attrs: &'x [(String, String)], name: &str) -> Option<&'x str> {
+		attrs
+			.iter()
+			.find(|(k, _)| k == name)
+			.map(|(_, v)| v.as_str())
+	}
 
-		let resp = req.send().await?;
-		resp.error_for_status()?;
-		Ok(())
+	#[test]
+	fn renders_sample_batch_with_units() {
+		let doc = Format::default().sample_batch(&readout("/sbx/i2c/'76'"));
+		let parsed = elements(&doc);
+		assert_eq!(parsed.len(), 3);
+		let (ns, name, attrs) = &parsed[0];
+		assert_eq!(
+			(ns.as_str(), name.as_str()),
+			(DEFAULT_NAMESPACE, "sample-batch")
+		);
+		assert_eq!(
+			attr(attrs, "timestamp"),
+			Some("2020-09-13T12:26:40.000000Z")
+		);
+		assert_eq!(attr(attrs, "instance"), Some("/sbx/i2c/'76'"));
+
+		// components are ordered by name
+		let (ns, name, attrs) = &parsed[1];
+		assert_eq!((ns.as_str(), name.as_str()), (DEFAULT_NAMESPACE, "numeric"));
+		assert_eq!(attr(attrs, "subpart"), Some("status"));
+		assert_eq!(attr(attrs, "unit"), None);
+		let (_, _, attrs) = &parsed[2];
+		assert_eq!(attr(attrs, "value"), Some("21.5"));
+		assert_eq!(attr(attrs, "unit"), Some("°C"));
+
+		let doc = Format {
+			namespace: "urn:example:sensor:2".into(),
+			units: false,
+		}
+		.sample_batch(&readout("a"));
+		let parsed = elements(&doc);
+		assert_eq!(parsed[0].0, "urn:example:sensor:2");
+		assert_eq!(attr(&parsed[2].2, "unit"), None);
+	}
+
+	#[test]
+	fn renders_sample_batches() {
+		let readouts = [readout("a"), readout("b")];
+		let doc = Format::default().sample_batches(readouts.iter());
+		let parsed = elements(&doc);
+		let names: Vec<_> = parsed.iter().map(|(_, name, _)| name.as_str()).collect();
+		assert_eq!(
+			names,
+			vec![
+				"sample-batches",
+				"sample-batch",
+				"numeric",
+				"numeric",
+				"sample-batch",
+				"numeric",
+				"numeric"
+			]
+		);
+		assert!(parsed.iter().all(|(ns, _, _)| ns == DEFAULT_NAMESPACE));
+		assert_eq!(attr(&parsed[4].2, "instance"), Some("b"));
 	}
 }
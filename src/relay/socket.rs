@@ -386,8 +386,11 @@ struct SendState<T: tokio::net::ToSocketAddrs + Sync + Send + 'static> {
 }
 
 impl<T: tokio::net::ToSocketAddrs + Sync + Send + 'static> SendState<T> {
-	pub fn new(data: mpsc::Receiver<frame::DataFrame>, addrs: T) -> Self {
-		let client_id: u128 = rand::thread_rng().gen::<u128>();
+	pub fn new(
+		data: mpsc::Receiver<frame::DataFrame>,
+		addrs: T,
+		client_id: frame::ClientId,
+	) -> Self {
 		Self {
 			client_id,
 			data,
@@ -505,13 +508,28 @@ pub struct SendSocket {
 
 impl SendSocket {
 	pub fn new<T: tokio::net::ToSocketAddrs + Send + Sync + 'static>(addrs: T) -> Self {
+		Self::with_client_id(addrs, Self::random_client_id())
+	}
+
+	/// Create a socket which identifies itself with a fixed client id.
+	///
+	/// The receiver keeps a session per client id, so reusing the id across
+	/// restarts allows it to pick up where the previous connection left off.
+	pub fn with_client_id<T: tokio::net::ToSocketAddrs + Send + Sync + 'static>(
+		addrs: T,
+		client_id: frame::ClientId,
+	) -> Self {
 		let (sink, data_ch) = mpsc::channel(8);
 		let result = Self { sink };
-		let mut state = SendState::new(data_ch, addrs);
+		let mut state = SendState::new(data_ch, addrs, client_id);
 		tokio::spawn(async move { state.run().await });
 		result
 	}
 
+	pub fn random_client_id() -> frame::ClientId {
+		rand::thread_rng().gen::<u128>()
+	}
+
 	pub async fn send(&self, frame: frame::DataFrame) {
 		match self.sink.send(frame).await {
 			Ok(()) => (),
@@ -1,10 +1,9 @@
 #[cfg(feature = "debug")]
 use std::fmt;
-#[cfg(feature = "debug")]
 use std::sync::Arc;
 
 #[allow(unused_imports)]
-use log::{debug, trace, warn};
+use log::{debug, trace};
 
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
@@ -12,6 +11,7 @@ use tokio::sync::mpsc;
 #[cfg(feature = "debug")]
 use crate::stream;
 
+use super::linkstats::LinkStats;
 #[cfg(feature = "debug")]
 use super::payload;
 
@@ -25,7 +25,7 @@ impl<T: 'static + Clone + Send> Serializer<T> {
 		(Self { sink: sender }, receiver)
 	}
 
-	pub fn attach(&self, mut src: broadcast::Receiver<T>) {
+	pub fn attach(&self, mut src: broadcast::Receiver<T>, link: Arc<LinkStats>) {
 		let sink = self.sink.clone();
 		tokio::spawn(async move {
 			loop {
@@ -36,7 +36,7 @@ impl<T: 'static + Clone + Send> Serializer<T> {
 						return;
 					}
 					Err(broadcast::error::RecvError::Lagged(nlost)) => {
-						warn!("serializer was too slow; lost {} items", nlost);
+						link.record_lag(nlost);
 						continue;
 					}
 					Ok(item) => item,
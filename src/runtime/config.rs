@@ -1,3 +1,10 @@
+#[cfg(any(
+	feature = "influxdb",
+	feature = "prometheus-remote-write",
+	feature = "statsd",
+	feature = "webhook"
+))]
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
@@ -6,6 +13,7 @@ use std::io;
 use std::net;
 #[cfg(feature = "sbm")]
 use std::net::IpAddr;
+use std::num::NonZeroUsize;
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -17,10 +25,16 @@ use smartstring::alias::String as SmartString;
 use serde::{de, Deserialize as DeserializeTrait, Deserializer};
 use serde_derive::Deserialize;
 
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, RootSchema, Schema, SchemaObject};
+use schemars::JsonSchema;
+
 use log::Level;
 
 use glob;
 
+#[cfg(feature = "csv")]
+use super::csvexport;
 #[cfg(feature = "csv")]
 use super::csvinject;
 #[cfg(feature = "debug")]
@@ -30,13 +44,27 @@ use super::detrend;
 #[cfg(feature = "fft")]
 use super::fft;
 use super::filter;
+#[cfg(feature = "graphite")]
+use super::graphite;
 use super::hwmon;
 #[cfg(feature = "influxdb")]
 use super::influxdb;
+#[cfg(feature = "jsonl")]
+use super::jsonl;
+#[cfg(feature = "lineproto")]
+use super::lineproto;
+use super::linkstats;
+#[cfg(feature = "mqtt")]
+use super::mqtt;
+#[cfg(feature = "prometheus")]
+use super::prometheus;
 #[cfg(feature = "pubsub")]
 use super::pubsub;
 #[cfg(feature = "relay")]
 use super::relay;
+#[cfg(feature = "prometheus-remote-write")]
+use super::remote_write;
+use super::retry;
 use super::router;
 use super::samplify;
 #[cfg(feature = "sbm")]
@@ -45,12 +73,20 @@ use super::sbm;
 use super::sbx;
 #[cfg(feature = "smbus")]
 use super::smbus;
+#[cfg(feature = "sqlite")]
+use super::sqlite;
+use super::state;
+#[cfg(feature = "statsd")]
+use super::statsd;
 #[cfg(feature = "stream-filearchive")]
 use super::stream as runtime_stream;
 use super::streamify;
 #[cfg(feature = "summary")]
 use super::summary;
+use super::supervisor;
 use super::traits;
+#[cfg(feature = "webhook")]
+use super::webhook;
 
 use crate::metric;
 use crate::script;
@@ -77,6 +113,9 @@ pub enum BuildError {
 		which: String,
 		feature_name: &'static str,
 	},
+	/// The node configuration is inconsistent in a way the schema cannot
+	/// express.
+	Invalid(String),
 	Other(Box<dyn Error>),
 }
 
@@ -105,6 +144,7 @@ impl fmt::Display for BuildError {
 					which, feature_name
 				)
 			}
+			Self::Invalid(message) => write!(f, "invalid configuration: {}", message),
 			Self::Other(e) => write!(f, "{:?}", e),
 		}
 	}
@@ -112,6 +152,117 @@ impl fmt::Display for BuildError {
 
 impl Error for BuildError {}
 
+#[derive(Debug)]
+pub enum LoadError {
+	Syntax(toml::de::Error),
+	Node {
+		name: String,
+		class: Option<String>,
+		line: usize,
+		column: usize,
+		message: String,
+	},
+}
+
+impl fmt::Display for LoadError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Syntax(e) => fmt::Display::fmt(e, f),
+			Self::Node {
+				name,
+				class,
+				line,
+				column,
+				message,
+			} => {
+				write!(f, "in [node.{}]", name)?;
+				if let Some(class) = class {
+					write!(f, " (class {:?})", class)?;
+				}
+				write!(f, " at line {}, column {}: {}", line, column, message)
+			}
+		}
+	}
+}
+
+impl Error for LoadError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Syntax(e) => Some(e),
+			Self::Node { .. } => None,
+		}
+	}
+}
+
+impl From<toml::de::Error> for LoadError {
+	fn from(other: toml::de::Error) -> Self {
+		Self::Syntax(other)
+	}
+}
+
+/// Convert a byte offset into the source into a 1-based line and column.
+fn line_column(src: &str, offset: usize) -> (usize, usize) {
+	let offset = offset.min(src.len());
+	let before = &src[..offset];
+	let line = before.matches('\n').count() + 1;
+	let column = match before.rfind('\n') {
+		Some(nl) => before[nl + 1..].chars().count() + 1,
+		None => before.chars().count() + 1,
+	};
+	(line, column)
+}
+
+/// Try to find the line within the given span of the source which the serde
+/// error message refers to, either by a key (``missing field `foo` ``) or by
+/// a string value (``unknown variant `Foo` ``, `invalid type: string "foo"`).
+fn locate_in_span(src: &str, span: &std::ops::Range<usize>, message: &str) -> Option<usize> {
+	let mut tokens = Vec::new();
+	for delim in ['`', '"'] {
+		let mut parts = message.split(delim);
+		parts.next();
+		while let Some(token) = parts.next() {
+			if !token.is_empty() {
+				tokens.push(token);
+			}
+			parts.next();
+		}
+	}
+
+	let section = src.get(span.clone())?;
+	let mut offset = 0;
+	for line in section.split_inclusive('\n') {
+		let trimmed = line.trim_start();
+		for token in tokens.iter() {
+			let is_key = trimmed
+				.strip_prefix(token)
+				.map(|rest| rest.trim_start().starts_with('='))
+				.unwrap_or(false);
+			let is_value = match trimmed.split_once('=') {
+				Some((_, value)) => value.trim() == format!("\"{}\"", token),
+				None => false,
+			};
+			if is_key || is_value {
+				return Some(span.start + offset + (line.len() - trimmed.len()));
+			}
+		}
+		offset += line.len();
+	}
+	None
+}
+
+fn string_schema(description: &str, enum_values: Option<&[&str]>) -> Schema {
+	SchemaObject {
+		instance_type: Some(InstanceType::String.into()),
+		metadata: Some(Box::new(Metadata {
+			description: Some(description.into()),
+			..Default::default()
+		})),
+		enum_values: enum_values.map(|values| values.iter().map(|v| (*v).into()).collect()),
+		..Default::default()
+	}
+	.into()
+}
+
 fn default_local_address() -> net::IpAddr {
 	"0.0.0.0".parse::<net::IpAddr>().unwrap()
 }
@@ -128,6 +279,10 @@ fn bool_false() -> bool {
 	false
 }
 
+fn bool_true() -> bool {
+	true
+}
+
 fn f64_one() -> f64 {
 	1.0
 }
@@ -164,6 +319,16 @@ impl<'de> DeserializeTrait<'de> for PatternWrap {
 	}
 }
 
+impl JsonSchema for PatternWrap {
+	fn schema_name() -> String {
+		"GlobPattern".into()
+	}
+
+	fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+		string_schema("Shell-style glob pattern", None)
+	}
+}
+
 #[derive(Debug, Clone)]
 #[cfg(feature = "regex")]
 pub struct RegexWrap(pub regex::Regex);
@@ -196,6 +361,17 @@ impl<'de> DeserializeTrait<'de> for RegexWrap {
 	}
 }
 
+#[cfg(feature = "regex")]
+impl JsonSchema for RegexWrap {
+	fn schema_name() -> String {
+		"Regex".into()
+	}
+
+	fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+		string_schema("Regular expression", None)
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct ScriptWrap(pub Arc<Box<dyn script::Evaluate>>);
 
@@ -220,6 +396,16 @@ impl<'de> DeserializeTrait<'de> for ScriptWrap {
 	}
 }
 
+impl JsonSchema for ScriptWrap {
+	fn schema_name() -> String {
+		"Script".into()
+	}
+
+	fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+		string_schema("Expression in the filter scripting language", None)
+	}
+}
+
 #[derive(Debug, Clone)]
 pub struct UnitWrap(pub metric::Unit);
 
@@ -248,8 +434,20 @@ impl<'de> DeserializeTrait<'de> for UnitWrap {
 	}
 }
 
+impl JsonSchema for UnitWrap {
+	fn schema_name() -> String {
+		"Unit".into()
+	}
+
+	fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+		let names: Vec<&str> = metric::Unit::NAMED.iter().map(|(name, _)| *name).collect();
+		string_schema("Unit of a value", Some(&names))
+	}
+}
+
 #[cfg_attr(not(feature = "sbx"), allow(dead_code))]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SNURLConfig {
 	#[serde(default = "default_local_address")]
 	local_address: net::IpAddr,
@@ -262,22 +460,64 @@ pub struct SNURLConfig {
 	passive: bool,
 }
 
+#[cfg(feature = "sbm")]
+impl SNURLConfig {
+	/// The IPv4 group and interface address to join, if any.
+	fn multicast_v4(&self) -> Result<Option<(net::Ipv4Addr, net::Ipv4Addr)>, &'static str> {
+		match (self.multicast_group, self.local_address) {
+			(None, _) => Ok(None),
+			(Some(IpAddr::V4(group)), IpAddr::V4(ifaddr)) => Ok(Some((group, ifaddr))),
+			(Some(IpAddr::V6(_)), IpAddr::V6(_)) => {
+				Err("multicast operation not supported on ipv6")
+			}
+			(Some(_), _) => Err("multicast group address family differs from local address family"),
+		}
+	}
+
+	fn check(&self) -> Result<(), BuildError> {
+		self.multicast_v4()
+			.map(|_| ())
+			.map_err(|e| BuildError::Invalid(e.into()))
+	}
+
+	fn endpoint(&self) -> io::Result<snurl::Endpoint> {
+		let raw_sock =
+			net::UdpSocket::bind(net::SocketAddr::new(self.local_address, self.local_port))?;
+
+		raw_sock.set_nonblocking(true)?;
+		let multicast = self
+			.multicast_v4()
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+		if let Some((group, ifaddr)) = multicast {
+			raw_sock.join_multicast_v4(&group, &ifaddr)?;
+		}
+		let sock = snurl::Socket::new(
+			tokio::net::UdpSocket::from_std(raw_sock)?,
+			net::SocketAddr::new(self.remote_address, self.remote_port),
+			self.passive,
+		);
+		Ok(snurl::Endpoint::new(sock))
+	}
+}
+
 #[cfg(feature = "serial")]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct SerialConfig {
 	port: String,
 	baudrate: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum SBXTransportConfig {
 	SNURL(SNURLConfig),
 	#[cfg(feature = "serial")]
 	Serial(SerialConfig),
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct RandomComponent {
 	#[cfg_attr(not(feature = "debug"), allow(dead_code))]
 	unit: UnitWrap,
@@ -287,8 +527,8 @@ pub struct RandomComponent {
 	max: f64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "mode")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "mode", deny_unknown_fields)]
 pub enum StreamBufferConfig {
 	InMemory { slice: i64 },
 }
@@ -305,7 +545,8 @@ impl StreamBufferConfig {
 }
 
 #[cfg(feature = "influxdb")]
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct InfluxDBPredicate {
 	match_measurement: Option<PatternWrap>,
 	#[serde(default = "bool_false")]
@@ -326,8 +567,8 @@ impl InfluxDBPredicate {
 }
 
 #[cfg(feature = "influxdb")]
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum InfluxDBMapping {
 	Transpose {
 		predicate: Option<InfluxDBPredicate>,
@@ -341,6 +582,69 @@ pub enum InfluxDBMapping {
 		new_tag_value: String,
 		field_name: String,
 	},
+	/// Add tags with fixed values, e.g. the site or host.
+	AddTags {
+		predicate: Option<InfluxDBPredicate>,
+		tags: BTreeMap<String, String>,
+	},
+	/// Rewrite the measurement name; `replacement` may refer to capture
+	/// groups of `expr`.
+	#[cfg(feature = "regex")]
+	RenameMeasurement {
+		predicate: Option<InfluxDBPredicate>,
+		expr: RegexWrap,
+		replacement: String,
+	},
+	/// Rewrite field names; `replacement` may refer to capture groups of
+	/// `expr`.
+	#[cfg(feature = "regex")]
+	RenameFields {
+		predicate: Option<InfluxDBPredicate>,
+		expr: RegexWrap,
+		replacement: String,
+	},
+	/// Split a tag value (e.g. `i2c-2/76` in `instance`) into several tags.
+	SplitTag {
+		predicate: Option<InfluxDBPredicate>,
+		tag: String,
+		#[serde(default = "default_split_separator")]
+		separator: char,
+		into: Vec<String>,
+		#[serde(default = "bool_false")]
+		keep: bool,
+	},
+	/// Drop fields whose names match any of the glob patterns.
+	DropFields {
+		predicate: Option<InfluxDBPredicate>,
+		fields: Vec<PatternWrap>,
+	},
+	/// Add the unit of the fields as a tag.
+	UnitTag {
+		predicate: Option<InfluxDBPredicate>,
+		#[serde(default = "default_unit_tag")]
+		tag: String,
+	},
+	/// Append the unit to the field names.
+	UnitSuffix {
+		predicate: Option<InfluxDBPredicate>,
+		#[serde(default = "default_unit_separator")]
+		separator: String,
+	},
+}
+
+#[cfg(feature = "influxdb")]
+fn default_split_separator() -> char {
+	'/'
+}
+
+#[cfg(feature = "influxdb")]
+fn default_unit_tag() -> String {
+	"unit".into()
+}
+
+#[cfg(feature = "influxdb")]
+fn default_unit_separator() -> String {
+	"_".into()
 }
 
 #[cfg(feature = "influxdb")]
@@ -352,10 +656,7 @@ impl InfluxDBMapping {
 				tag,
 				field,
 			} => Ok(Box::new(crate::influxdb::Transpose {
-				predicate: predicate
-					.clone()
-					.and_then(|cfg| Some(cfg.build()))
-					.unwrap_or_else(|| crate::influxdb::Select::default()),
+				predicate: build_predicate(predicate),
 				tag: tag.clone().into(),
 				field: field.clone().into(),
 			})),
@@ -366,19 +667,79 @@ impl InfluxDBMapping {
 				new_tag_value,
 				field_name,
 			} => Ok(Box::new(crate::influxdb::TagToField {
-				predicate: predicate
-					.clone()
-					.and_then(|cfg| Some(cfg.build()))
-					.unwrap_or_else(|| crate::influxdb::Select::default()),
+				predicate: build_predicate(predicate),
 				expr: expr.0.clone(),
 				new_tag_value: new_tag_value.clone().into(),
 				field_name: field_name.clone().into(),
 			})),
+			Self::AddTags { predicate, tags } => Ok(Box::new(crate::influxdb::AddTags {
+				predicate: build_predicate(predicate),
+				tags: tags
+					.iter()
+					.map(|(k, v)| (k.clone().into(), v.clone().into()))
+					.collect(),
+			})),
+			#[cfg(feature = "regex")]
+			Self::RenameMeasurement {
+				predicate,
+				expr,
+				replacement,
+			} => Ok(Box::new(crate::influxdb::RenameMeasurement {
+				predicate: build_predicate(predicate),
+				expr: expr.0.clone(),
+				replacement: replacement.clone().into(),
+			})),
+			#[cfg(feature = "regex")]
+			Self::RenameFields {
+				predicate,
+				expr,
+				replacement,
+			} => Ok(Box::new(crate::influxdb::RenameFields {
+				predicate: build_predicate(predicate),
+				expr: expr.0.clone(),
+				replacement: replacement.clone().into(),
+			})),
+			Self::SplitTag {
+				predicate,
+				tag,
+				separator,
+				into,
+				keep,
+			} => Ok(Box::new(crate::influxdb::SplitTag {
+				predicate: build_predicate(predicate),
+				tag: tag.clone().into(),
+				separator: *separator,
+				into: into.iter().map(|x| x.clone().into()).collect(),
+				keep: *keep,
+			})),
+			Self::DropFields { predicate, fields } => Ok(Box::new(crate::influxdb::DropFields {
+				predicate: build_predicate(predicate),
+				fields: fields.iter().map(|x| x.0.clone()).collect(),
+			})),
+			Self::UnitTag { predicate, tag } => Ok(Box::new(crate::influxdb::UnitLabel {
+				predicate: build_predicate(predicate),
+				mode: crate::influxdb::UnitLabelMode::Tag(tag.clone().into()),
+			})),
+			Self::UnitSuffix {
+				predicate,
+				separator,
+			} => Ok(Box::new(crate::influxdb::UnitLabel {
+				predicate: build_predicate(predicate),
+				mode: crate::influxdb::UnitLabelMode::Suffix(separator.clone().into()),
+			})),
 		}
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[cfg(feature = "influxdb")]
+fn build_predicate(predicate: &Option<InfluxDBPredicate>) -> crate::influxdb::Select {
+	predicate
+		.as_ref()
+		.map(|cfg| cfg.build())
+		.unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub enum BME280Instance {
 	Primary,
 	Secondary,
@@ -394,7 +755,7 @@ impl BME280Instance {
 	}
 }
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
 pub enum DetrendMode {
 	Constant,
 	Linear,
@@ -410,7 +771,8 @@ impl From<DetrendMode> for detrend::Mode {
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct StreamifyDescription {
 	device_type: String,
 	instance: String,
@@ -419,7 +781,8 @@ pub struct StreamifyDescription {
 	slice_ms: i64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct HwmonSensor {
 	name: String,
 	sensor: u32,
@@ -428,16 +791,335 @@ pub struct HwmonSensor {
 	component: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[cfg_attr(not(feature = "csv"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
 pub struct CsvComponentMapping {
 	column: String,
 	component: String,
 	unit: UnitWrap,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "class")]
+/// A column written by `ToCsv`; a `unit` as in `FromCsv` is ignored, so
+/// that the same column list can be used for both.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct CsvColumn {
+	column: String,
+	component: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "csv"), allow(dead_code))]
+pub enum CsvTimestampFormat {
+	/// Nanoseconds since the epoch, as read by `FromCsv`.
+	#[default]
+	UnixNanos,
+	/// RFC 3339 date and time; easier on humans, but not readable by
+	/// `FromCsv`.
+	Rfc3339,
+}
+
+fn default_csv_prefix() -> String {
+	"readouts".into()
+}
+
+fn default_csv_device_type_column() -> String {
+	"device_type".into()
+}
+
+fn default_csv_instance_column() -> String {
+	"instance".into()
+}
+
+fn default_csv_timestamp_column() -> String {
+	"timestamp".into()
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "pubsub"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct PubSubBatch {
+	/// Maximum time (in milliseconds) readouts are held back to be sent
+	/// together with others.
+	#[serde(default = "default_pubsub_flush_interval_ms")]
+	flush_interval_ms: u64,
+	/// Maximum number of readouts per request.
+	#[serde(default = "default_pubsub_max_readouts")]
+	max_readouts: usize,
+}
+
+fn default_pubsub_namespace() -> String {
+	"https://xmlns.zombofant.net/hint/sensor/1.0".into()
+}
+
+fn default_pubsub_flush_interval_ms() -> u64 {
+	1000
+}
+
+fn default_pubsub_max_readouts() -> usize {
+	100
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_max_lines() -> usize {
+	5000
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_max_bytes() -> usize {
+	1024 * 1024
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_max_in_flight() -> usize {
+	1
+}
+
+/// Grouping of lines into write requests.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBBatch {
+	/// Maximum time lines are held back to collect more for the same
+	/// request. With zero, a request is sent whenever the queue runs empty.
+	#[serde(default)]
+	flush_interval_ms: u64,
+	#[serde(default = "default_influxdb_max_lines")]
+	max_lines: usize,
+	#[serde(default = "default_influxdb_max_bytes")]
+	max_bytes: usize,
+	/// Number of requests which may be in flight at the same time. Lines of
+	/// the same measurement are always written in order.
+	#[serde(default = "default_influxdb_max_in_flight")]
+	max_in_flight: usize,
+}
+
+impl Default for InfluxDBBatch {
+	fn default() -> Self {
+		Self {
+			flush_interval_ms: 0,
+			max_lines: default_influxdb_max_lines(),
+			max_bytes: default_influxdb_max_bytes(),
+			max_in_flight: default_influxdb_max_in_flight(),
+		}
+	}
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_decimation() -> usize {
+	1
+}
+
+/// Merging of readouts with the same measurement and timestamp.
+#[cfg(feature = "influxdb")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBMerge {
+	/// Which value to keep if merged lines carry the same field.
+	#[serde(default)]
+	conflict: crate::influxdb::Conflict,
+}
+
+/// Writing of stream blocks as individual points.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBStreams {
+	/// Field name for the stream values; by default, the last element of the
+	/// stream's instance path is used.
+	fixed_component: Option<String>,
+	/// Only write every n-th sample.
+	#[serde(default = "default_influxdb_decimation")]
+	decimation: usize,
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_buffer_max_size() -> u64 {
+	64 * 1024 * 1024
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBBuffer {
+	directory: PathBuf,
+	/// Maximum size of the buffer in bytes; the oldest batches are dropped
+	/// beyond that.
+	#[serde(default = "default_influxdb_buffer_max_size")]
+	max_size: u64,
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_health_interval_ms() -> u64 {
+	60000
+}
+
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+fn default_influxdb_replication() -> u32 {
+	1
+}
+
+/// Checks whether InfluxDB is reachable and the database exists.
+///
+/// Problems are reported in the node's status.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBHealth {
+	/// Interval (in milliseconds) in which the server is pinged.
+	#[serde(default = "default_influxdb_health_interval_ms")]
+	interval_ms: u64,
+	/// Create the database on startup and when writes report it missing
+	/// (InfluxDB 1.x only). This requires admin privileges.
+	#[serde(default = "bool_false")]
+	create_database: bool,
+	/// Retention policy to create together with the database, named by
+	/// the node's `retention_policy`.
+	retention: Option<InfluxDBRetentionPolicy>,
+}
+
+impl Default for InfluxDBHealth {
+	fn default() -> Self {
+		Self {
+			interval_ms: default_influxdb_health_interval_ms(),
+			create_database: false,
+			retention: None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "influxdb"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBRetentionPolicy {
+	/// Duration in InfluxQL syntax, e.g. `30d` or `INF`.
+	duration: String,
+	#[serde(default = "default_influxdb_replication")]
+	replication: u32,
+	/// Make this the default retention policy of the database.
+	#[serde(default = "bool_false")]
+	default: bool,
+}
+
+/// Check for an InfluxQL duration literal (`INF` or e.g. `4w2d`), as it is
+/// pasted into the retention policy query without quoting.
+#[cfg(feature = "influxdb")]
+fn is_influxql_duration(s: &str) -> bool {
+	if s.eq_ignore_ascii_case("inf") {
+		return true;
+	}
+	let mut rest = s;
+	while !rest.is_empty() {
+		let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+		if digits == 0 {
+			return false;
+		}
+		rest = &rest[digits..];
+		match ["ns", "ms", "u", "µ", "s", "m", "h", "d", "w"]
+			.iter()
+			.find(|unit| rest.starts_with(*unit))
+		{
+			Some(unit) => rest = &rest[unit.len()..],
+			None => return false,
+		}
+	}
+	!s.is_empty()
+}
+
+#[cfg(feature = "influxdb")]
+fn influxdb_health_check(
+	health: &InfluxDBHealth,
+	target: &crate::influxdb::Target,
+) -> Result<influxdb::HealthCheck, BuildError> {
+	if health.interval_ms == 0 {
+		return Err(BuildError::Invalid(
+			"influxdb: health.interval_ms must be positive".into(),
+		));
+	}
+	if let Some(rp) = health.retention.as_ref() {
+		if !is_influxql_duration(&rp.duration) {
+			return Err(BuildError::Invalid(format!(
+				"influxdb: invalid health.retention.duration {:?}",
+				rp.duration
+			)));
+		}
+	}
+	let create_database = match (health.create_database, target) {
+		(false, _) => {
+			if health.retention.is_some() {
+				return Err(BuildError::Invalid(
+					"influxdb: health.retention requires health.create_database".into(),
+				));
+			}
+			None
+		}
+		(
+			true,
+			crate::influxdb::Target::V1 {
+				retention_policy, ..
+			},
+		) => match (&health.retention, retention_policy) {
+			(Some(rp), Some(name)) => Some(Some(crate::influxdb::RetentionPolicy {
+				name: name.clone(),
+				duration: rp.duration.clone(),
+				replication: rp.replication,
+				default: rp.default,
+			})),
+			(Some(_), None) => {
+				return Err(BuildError::Invalid(
+					"influxdb: health.retention requires retention_policy to be set".into(),
+				))
+			}
+			(None, _) => Some(None),
+		},
+		(true, crate::influxdb::Target::V2 { .. }) => {
+			return Err(BuildError::Invalid(
+				"influxdb: health.create_database is only supported with database".into(),
+			))
+		}
+	};
+	Ok(influxdb::HealthCheck {
+		interval: std::time::Duration::from_millis(health.interval_ms),
+		create_database,
+	})
+}
+
+/// Pick the write API from the configured destination: `database` (and
+/// optionally `retention_policy`) for InfluxDB 1.x, `org` and `bucket` for
+/// 2.x and later.
+#[cfg(feature = "influxdb")]
+fn influxdb_target(
+	database: &Option<String>,
+	retention_policy: &Option<String>,
+	org: &Option<String>,
+	bucket: &Option<String>,
+) -> Result<crate::influxdb::Target, BuildError> {
+	match (database, org, bucket) {
+		(Some(database), None, None) => Ok(crate::influxdb::Target::V1 {
+			database: database.clone(),
+			retention_policy: retention_policy.clone(),
+		}),
+		(None, Some(org), Some(bucket)) => {
+			if retention_policy.is_some() {
+				return Err(BuildError::Invalid(
+					"influxdb: retention_policy cannot be used with org and bucket".into(),
+				));
+			}
+			Ok(crate::influxdb::Target::V2 {
+				org: org.clone(),
+				bucket: bucket.clone(),
+			})
+		}
+		_ => Err(BuildError::Invalid(
+			"influxdb: either database or both org and bucket must be set".into(),
+		)),
+	}
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "class", deny_unknown_fields)]
 pub enum Node {
 	SBX {
 		path_prefix: String,
@@ -463,7 +1145,7 @@ pub enum Node {
 	Connect {
 		peer_address: String,
 	},
-	DebugStdout,
+	DebugStdout {},
 	Route {
 		filters: Vec<Filter>,
 	},
@@ -471,15 +1153,54 @@ pub enum Node {
 	InfluxDB {
 		api_url: String,
 		auth: crate::influxdb::Auth,
-		database: String,
+		/// Database to write to (InfluxDB 1.x).
+		database: Option<String>,
 		retention_policy: Option<String>,
+		/// Organization to write to (InfluxDB 2.x and later).
+		org: Option<String>,
+		/// Bucket to write to (InfluxDB 2.x and later).
+		bucket: Option<String>,
 		precision: crate::influxdb::Precision,
 		mapping: Vec<InfluxDBMapping>,
+		/// Merge readouts of the same sample which share measurement and
+		/// timestamp before applying the mapping, and lines which share
+		/// measurement, tags and timestamp after it.
+		merge: Option<InfluxDBMerge>,
+		/// Write components with the `Total` unit as integer fields.
+		#[serde(default = "bool_false")]
+		integer_counters: bool,
+		/// Also write stream blocks, one point per sample.
+		streams: Option<InfluxDBStreams>,
+		#[serde(default)]
+		batch: InfluxDBBatch,
+		/// Compress request bodies with gzip.
+		#[serde(default = "bool_false")]
+		gzip: bool,
+		#[serde(default)]
+		retry: retry::RetryConfig,
+		/// Buffer batches on disk while InfluxDB is unavailable.
+		buffer: Option<InfluxDBBuffer>,
+		#[serde(default)]
+		health: InfluxDBHealth,
 	},
 	PubSub {
 		api_url: String,
 		node_template: String,
 		override_host: Option<String>,
+		#[serde(default)]
+		auth: crate::http::Auth,
+		/// XML namespace of the payloads, which identifies the schema
+		/// version.
+		#[serde(default = "default_pubsub_namespace")]
+		namespace: String,
+		/// Add units to the values.
+		#[serde(default = "bool_true")]
+		units: bool,
+		/// Send the readouts of each node in batches, as `sample-batches`
+		/// documents, instead of one request per readout.
+		batch: Option<PubSubBatch>,
+		#[serde(default)]
+		retry: retry::RetryConfig,
 	},
 	Sine {
 		nsamples: u16,
@@ -535,13 +1256,627 @@ pub enum Node {
 		end_time: chrono::DateTime<chrono::Utc>,
 		sleep_ms: u32,
 	},
+	/// Appends readouts to CSV files which can be read back with
+	/// `FromCsv`, rotating them daily and/or by size.
+	ToCsv {
+		directory: PathBuf,
+		#[serde(default = "default_csv_prefix")]
+		prefix: String,
+		#[serde(default = "default_csv_device_type_column")]
+		device_type_column: String,
+		#[serde(default = "default_csv_instance_column")]
+		instance_column: String,
+		#[serde(default = "default_csv_timestamp_column")]
+		timestamp_column: String,
+		/// One column per component, in this order; components missing
+		/// from a readout are left empty.
+		components: Vec<CsvColumn>,
+		#[serde(default)]
+		timestamp_format: CsvTimestampFormat,
+		#[serde(default = "bool_true")]
+		rotate_daily: bool,
+		/// Start a new file rather than growing the current one beyond
+		/// this many bytes.
+		max_file_bytes: Option<u64>,
+	},
 	Samplify {
 		fixed_component: Option<String>,
 	},
+	/// Emits the lag counters of all links as readouts every `interval`
+	/// milliseconds.
+	LinkStats {
+		interval: u32,
+	},
+	/// Accepts InfluxDB line protocol on the 1.x `/write` and 2.x
+	/// `/api/v2/write` HTTP endpoints.
+	LineProtocolHTTP {
+		listen_address: String,
+		#[serde(flatten)]
+		mapping: LineProtocolMapping,
+		#[serde(default = "default_lineproto_max_body_size")]
+		max_body_size: usize,
+	},
+	/// Accepts InfluxDB line protocol as UDP datagrams and/or
+	/// newline-delimited over TCP connections.
+	LineProtocolSocket {
+		udp_address: Option<String>,
+		tcp_address: Option<String>,
+		#[serde(flatten)]
+		mapping: LineProtocolMapping,
+		/// Precision of the timestamps sent by the clients (`ns`, `us`,
+		/// `ms`, `s`, `m` or `h`).
+		#[serde(default = "default_lineproto_precision")]
+		precision: String,
+		/// Longer lines received over TCP are discarded.
+		#[serde(default = "default_lineproto_max_line_length")]
+		max_line_length: usize,
+		/// No further TCP connections are accepted while this many are
+		/// open.
+		#[serde(default = "default_lineproto_max_connections")]
+		max_connections: usize,
+		/// TCP connections which send nothing for this many seconds are
+		/// closed.
+		#[serde(default = "default_lineproto_read_timeout_s")]
+		read_timeout_s: u64,
+	},
+	/// Serves the latest value of each component on `/metrics` for
+	/// Prometheus to scrape.
+	Prometheus {
+		listen_address: String,
+		/// Prepended to all metric names.
+		#[serde(default)]
+		prefix: String,
+		/// Label carrying the instance of the device path. Note that
+		/// Prometheus renames an `instance` label to `exported_instance`
+		/// unless `honor_labels` is set.
+		#[serde(default = "default_prometheus_instance_label")]
+		instance_label: String,
+		/// Series which have not been updated for this many seconds are
+		/// dropped.
+		#[serde(default = "default_prometheus_staleness_s")]
+		staleness_s: u64,
+		/// Include the timestamps of the readouts in the exposition.
+		#[serde(default = "bool_false")]
+		timestamps: bool,
+	},
+	/// Pushes samples to a Prometheus remote write receiver, such as
+	/// VictoriaMetrics or Mimir.
+	#[cfg(feature = "prometheus-remote-write")]
+	PrometheusRemoteWrite {
+		url: String,
+		#[serde(default)]
+		auth: crate::http::Auth,
+		/// Prepended to all metric names.
+		#[serde(default)]
+		prefix: String,
+		/// Label carrying the instance of the device path.
+		#[serde(default = "default_prometheus_instance_label")]
+		instance_label: String,
+		/// Extra labels added to all series. Their names must differ from
+		/// `instance_label` and `le`.
+		#[serde(default)]
+		labels: BTreeMap<String, String>,
+		/// Maximum time (in milliseconds) samples are held back to be sent
+		/// together with others.
+		#[serde(default = "default_remote_write_flush_interval_ms")]
+		flush_interval_ms: u64,
+		#[serde(default = "default_remote_write_max_samples")]
+		max_samples: usize,
+		#[serde(default)]
+		retry: retry::RetryConfig,
+	},
+	/// Publishes readouts to an MQTT broker.
+	#[cfg(feature = "mqtt")]
+	MqttPublish {
+		#[serde(flatten)]
+		connection: MqttConnection,
+		/// Topic template over `{device_type}`, `{instance}` and
+		/// `{component}`. Without `{component}`, all components of a
+		/// readout are sent in a single JSON message.
+		topic: String,
+		format: crate::mqtt::PayloadFormat,
+		/// 0 (at most once) or 1 (at least once).
+		#[serde(default)]
+		qos: u8,
+		#[serde(default = "bool_false")]
+		retain: bool,
+	},
+	/// Subscribes to topic filters on an MQTT broker and turns the
+	/// messages into readouts.
+	#[cfg(feature = "mqtt")]
+	MqttSubscribe {
+		#[serde(flatten)]
+		connection: MqttConnection,
+		subscriptions: Vec<MqttSubscription>,
+		/// 0 (at most once) or 1 (at least once).
+		#[serde(default)]
+		qos: u8,
+	},
+	/// Writes readouts into a SQLite database file, for local storage
+	/// without a database server.
+	#[cfg(feature = "sqlite")]
+	SQLite {
+		path: PathBuf,
+		/// Also record the metadata (time, period, length, scale) of stream
+		/// blocks.
+		#[serde(default = "bool_false")]
+		stream_blocks: bool,
+		/// Maximum time (in milliseconds) rows are held back to be written
+		/// in one transaction with others.
+		#[serde(default = "default_sqlite_flush_interval_ms")]
+		flush_interval_ms: u64,
+		#[serde(default = "default_sqlite_max_rows")]
+		max_rows: usize,
+		/// Delete values older than this many seconds.
+		retention_s: Option<u64>,
+		/// How often (in seconds) old values are deleted.
+		#[serde(default = "default_sqlite_prune_interval_s")]
+		prune_interval_s: u64,
+	},
+	/// Sends readouts to Graphite (Carbon) in the plaintext protocol.
+	#[cfg(feature = "graphite")]
+	Graphite {
+		/// `host:port` of the Carbon daemon.
+		address: String,
+		#[serde(default)]
+		protocol: GraphiteProtocol,
+		/// Metric path template over `{device_type}`, `{instance}` and
+		/// `{component}`.
+		#[serde(default = "default_graphite_path")]
+		path: String,
+		/// Replaces the slashes in instances; the default `.` turns the
+		/// parts of an instance into nodes of their own.
+		#[serde(default = "default_graphite_instance_separator")]
+		instance_separator: String,
+		/// Maximum time (in milliseconds) lines are held back to be sent
+		/// together with others.
+		#[serde(default = "default_graphite_flush_interval_ms")]
+		flush_interval_ms: u64,
+		#[serde(default = "default_graphite_max_batch_bytes")]
+		max_batch_bytes: usize,
+		#[serde(default)]
+		retry: retry::RetryConfig,
+	},
+	/// Sends readouts to a StatsD agent over UDP; components with a
+	/// total unit as counters, all others as gauges.
+	#[cfg(feature = "statsd")]
+	StatsD {
+		/// `host:port` of the agent.
+		address: String,
+		/// Metric name template over `{device_type}`, `{instance}` and
+		/// `{component}`.
+		#[serde(default = "default_statsd_name")]
+		name: String,
+		/// Add DogStatsD tags to each metric.
+		dogstatsd: Option<StatsDTags>,
+		/// Maximum time (in milliseconds) metrics are held back to be sent
+		/// together with others.
+		#[serde(default = "default_statsd_flush_interval_ms")]
+		flush_interval_ms: u64,
+		#[serde(default = "default_statsd_max_packet_size")]
+		max_packet_size: usize,
+	},
+	/// Sends readouts to an HTTP endpoint, in requests built from
+	/// templates.
+	///
+	/// See [`crate::webhook`] for the fields available in templates.
+	#[cfg(feature = "webhook")]
+	Webhook {
+		url: String,
+		#[serde(default = "default_webhook_method")]
+		method: String,
+		/// Header templates, e.g. for authorization.
+		#[serde(default)]
+		headers: BTreeMap<String, String>,
+		/// Body template; no body is sent if unset.
+		body: Option<String>,
+		#[serde(default)]
+		body_format: WebhookBodyFormat,
+		/// Send readouts in batches instead of one request per readout.
+		batch: Option<WebhookBatch>,
+		#[serde(default)]
+		retry: retry::RetryConfig,
+	},
+	/// Writes readouts and stream blocks as JSON Lines.
+	#[cfg(feature = "jsonl")]
+	ToJsonLines {
+		/// File to append to; stdout if unset.
+		path: Option<PathBuf>,
+	},
+	/// Reads readouts and stream blocks from JSON Lines, as written by
+	/// `ToJsonLines`.
+	#[cfg(feature = "jsonl")]
+	FromJsonLines {
+		/// File or named pipe to read from; stdin if unset.
+		path: Option<PathBuf>,
+		/// Replay at the pace given by the timestamps, sped up by this
+		/// factor (1 for real time). Without it, lines are read as fast as
+		/// the sinks keep up.
+		speed: Option<f64>,
+	},
+}
+
+#[cfg(feature = "statsd")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StatsDTags {
+	/// Name of the tag carrying the device type; empty to omit it.
+	#[serde(default = "default_statsd_device_type_tag")]
+	device_type_tag: String,
+	/// Name of the tag carrying the instance; empty to omit it.
+	#[serde(default = "default_statsd_instance_tag")]
+	instance_tag: String,
+	/// Extra tags added to all metrics.
+	#[serde(default)]
+	tags: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "statsd")]
+impl StatsDTags {
+	fn build(&self) -> crate::statsd::Tags {
+		let non_empty = |s: &String| Some(s.clone()).filter(|s| !s.is_empty());
+		crate::statsd::Tags {
+			device_type: non_empty(&self.device_type_tag),
+			instance: non_empty(&self.instance_tag),
+			extra: self
+				.tags
+				.iter()
+				.map(|(k, v)| (k.clone(), v.clone()))
+				.collect(),
+		}
+	}
+}
+
+#[cfg(feature = "statsd")]
+fn default_statsd_name() -> String {
+	"{device_type}.{instance}.{component}".into()
+}
+
+#[cfg(feature = "statsd")]
+fn default_statsd_device_type_tag() -> String {
+	"device_type".into()
+}
+
+#[cfg(feature = "statsd")]
+fn default_statsd_instance_tag() -> String {
+	"instance".into()
+}
+
+#[cfg(feature = "statsd")]
+fn default_statsd_flush_interval_ms() -> u64 {
+	1000
+}
+
+#[cfg(feature = "statsd")]
+fn default_statsd_max_packet_size() -> usize {
+	// fits into an ethernet frame with ipv6 and udp headers
+	1432
+}
+
+#[cfg(feature = "webhook")]
+#[derive(Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+pub enum WebhookBodyFormat {
+	/// Substituted strings are escaped for use within JSON strings.
+	#[default]
+	Json,
+	/// Substituted values are inserted as they are.
+	Text,
+}
+
+#[cfg(feature = "webhook")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookBatch {
+	/// Maximum time (in milliseconds) readouts are held back to be sent
+	/// together with others.
+	#[serde(default = "default_webhook_flush_interval_ms")]
+	flush_interval_ms: u64,
+	#[serde(default = "default_webhook_max_readouts")]
+	max_readouts: usize,
+}
+
+#[cfg(feature = "webhook")]
+fn default_webhook_method() -> String {
+	"POST".into()
+}
+
+#[cfg(feature = "webhook")]
+fn default_webhook_flush_interval_ms() -> u64 {
+	5000
+}
+
+#[cfg(feature = "webhook")]
+fn default_webhook_max_readouts() -> usize {
+	100
+}
+
+#[cfg(feature = "graphite")]
+#[derive(Debug, Clone, Copy, Default, Deserialize, JsonSchema)]
+pub enum GraphiteProtocol {
+	#[default]
+	Tcp,
+	Udp,
+}
+
+#[cfg(feature = "graphite")]
+impl From<GraphiteProtocol> for graphite::Protocol {
+	fn from(other: GraphiteProtocol) -> Self {
+		match other {
+			GraphiteProtocol::Tcp => Self::Tcp,
+			GraphiteProtocol::Udp => Self::Udp,
+		}
+	}
+}
+
+#[cfg(feature = "graphite")]
+fn default_graphite_path() -> String {
+	"{device_type}.{instance}.{component}".into()
+}
+
+#[cfg(feature = "graphite")]
+fn default_graphite_instance_separator() -> String {
+	".".into()
+}
+
+#[cfg(feature = "graphite")]
+fn default_graphite_flush_interval_ms() -> u64 {
+	1000
+}
+
+#[cfg(feature = "graphite")]
+fn default_graphite_max_batch_bytes() -> usize {
+	64 * 1024
+}
+
+#[cfg(feature = "sqlite")]
+fn default_sqlite_flush_interval_ms() -> u64 {
+	5000
+}
+
+#[cfg(feature = "sqlite")]
+fn default_sqlite_max_rows() -> usize {
+	1000
+}
+
+#[cfg(feature = "sqlite")]
+fn default_sqlite_prune_interval_s() -> u64 {
+	3600
+}
+
+#[cfg(feature = "mqtt")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct MqttConnection {
+	host: String,
+	#[serde(default = "default_mqtt_port")]
+	port: u16,
+	/// Must be unique among all clients of the broker.
+	client_id: String,
+	username: Option<String>,
+	password: Option<String>,
+	/// Interval of pings while idle, in seconds; 0 disables them.
+	#[serde(default = "default_mqtt_keep_alive_s")]
+	keep_alive_s: u64,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttConnection {
+	fn build(&self) -> Result<mqtt::Connection, BuildError> {
+		let credentials = match (self.username.as_ref(), self.password.as_ref()) {
+			(Some(username), password) => {
+				Some((username.clone(), password.cloned().unwrap_or_default()))
+			}
+			(None, Some(_)) => {
+				return Err(BuildError::Invalid(
+					"mqtt password given without username".into(),
+				))
+			}
+			(None, None) => None,
+		};
+		Ok(mqtt::Connection {
+			host: self.host.clone(),
+			port: self.port,
+			client_id: self.client_id.clone(),
+			credentials,
+			keep_alive: std::time::Duration::from_secs(self.keep_alive_s),
+		})
+	}
+}
+
+/// A topic filter to subscribe to, with the template to take the topics of
+/// its messages apart.
+#[cfg(feature = "mqtt")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MqttSubscription {
+	/// MQTT topic filter, which may contain `+` and `#` wildcards.
+	filter: String,
+	/// Topic template over `{device_type}`, `{instance}` and
+	/// `{component}`; messages whose topic does not match are ignored.
+	topic: String,
+	format: crate::mqtt::PayloadFormat,
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_port() -> u16 {
+	1883
+}
+
+#[cfg(feature = "mqtt")]
+fn default_mqtt_keep_alive_s() -> u64 {
+	30
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt_qos(qos: u8) -> Result<rumqttc::QoS, BuildError> {
+	match qos {
+		0 => Ok(rumqttc::QoS::AtMostOnce),
+		1 => Ok(rumqttc::QoS::AtLeastOnce),
+		other => Err(BuildError::Invalid(format!(
+			"unsupported mqtt qos {}, use 0 or 1",
+			other
+		))),
+	}
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt_topic(
+	template: &str,
+	format: crate::mqtt::PayloadFormat,
+) -> Result<crate::mqtt::TopicTemplate, BuildError> {
+	let topic = crate::mqtt::TopicTemplate::parse(template).map_err(BuildError::Invalid)?;
+	if format == crate::mqtt::PayloadFormat::Plain && !topic.has_component() {
+		return Err(BuildError::Invalid(format!(
+			"plain payloads need {{component}} in the topic {:?}",
+			template
+		)));
+	}
+	Ok(topic)
+}
+
+#[cfg(feature = "prometheus-remote-write")]
+fn default_remote_write_flush_interval_ms() -> u64 {
+	1000
+}
+
+#[cfg(feature = "prometheus-remote-write")]
+fn default_remote_write_max_samples() -> usize {
+	2000
+}
+
+fn default_prometheus_instance_label() -> String {
+	"instance".into()
+}
+
+fn default_prometheus_staleness_s() -> u64 {
+	300
+}
+
+/// Check a label name given as `option`; names starting with `__` are
+/// reserved for Prometheus' own use.
+#[cfg(any(feature = "prometheus", feature = "prometheus-remote-write"))]
+fn prometheus_label_name(option: &str, name: &str) -> Result<String, BuildError> {
+	if !crate::prometheus::is_label_name(name) {
+		return Err(BuildError::Invalid(format!(
+			"prometheus: invalid {} {:?}",
+			option, name
+		)));
+	}
+	if name.starts_with("__") {
+		return Err(BuildError::Invalid(format!(
+			"prometheus: {} {:?} is reserved",
+			option, name
+		)));
+	}
+	Ok(name.into())
+}
+
+/// Check the labels of a remote write node. Extra labels must not take the
+/// place of the instance label or of `le`, which is reserved for histogram
+/// buckets.
+#[cfg(feature = "prometheus-remote-write")]
+fn remote_write_labels(
+	prefix: &str,
+	instance_label: &str,
+	labels: &BTreeMap<String, String>,
+) -> Result<remote_write::Labels, BuildError> {
+	let instance_label = prometheus_label_name("instance_label", instance_label)?;
+	let mut extra = Vec::with_capacity(labels.len());
+	for (name, value) in labels.iter() {
+		let name = prometheus_label_name("label", name)?;
+		if name == instance_label || name == "le" {
+			return Err(BuildError::Invalid(format!(
+				"prometheus: label {:?} clashes with a derived label",
+				name
+			)));
+		}
+		extra.push((name, value.clone()));
+	}
+	Ok(remote_write::Labels {
+		prefix: prefix.into(),
+		instance_label,
+		extra,
+	})
+}
+
+fn default_lineproto_instance_tag() -> String {
+	"instance".into()
+}
+
+fn default_lineproto_max_body_size() -> usize {
+	16 * 1024 * 1024
+}
+
+fn default_lineproto_precision() -> String {
+	"ns".into()
+}
+
+fn default_lineproto_max_line_length() -> usize {
+	64 * 1024
+}
+
+fn default_lineproto_max_connections() -> usize {
+	64
+}
+
+fn default_lineproto_read_timeout_s() -> u64 {
+	300
+}
+
+/// Conversion of line protocol points into readouts.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "lineproto"), allow(dead_code))]
+pub struct LineProtocolMapping {
+	/// Tag whose value becomes the instance of the readouts.
+	#[serde(default = "default_lineproto_instance_tag")]
+	instance_tag: String,
+	/// Units of fields, keyed by `measurement.field` or just `field`.
+	#[serde(default)]
+	units: HashMap<String, UnitWrap>,
+}
+
+impl LineProtocolMapping {
+	#[cfg(feature = "lineproto")]
+	fn build(&self) -> lineproto::Mapping {
+		lineproto::Mapping {
+			instance_tag: self.instance_tag.clone().into(),
+			units: self
+				.units
+				.iter()
+				.map(|(k, v)| (k.clone().into(), v.0.clone()))
+				.collect(),
+		}
+	}
+}
+
+/// A node definition together with the settings common to all node classes.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct NodeConfig {
+	#[serde(flatten)]
+	pub class: Node,
+	#[serde(default)]
+	pub restart: supervisor::RestartPolicy,
+	/// Depth of the output channels of the node, or of its input queue if
+	/// the node is a pure sink. If unset, a default suitable for the class
+	/// is used.
+	pub capacity: Option<NonZeroUsize>,
+}
+
+/// Everything a node needs from the runtime while it is being built.
+pub struct BuildContext {
+	pub scope: supervisor::Scope,
+	pub capacity: Option<NonZeroUsize>,
+	pub links: linkstats::LinkRegistry,
+	pub state: state::StateHandle,
+}
+
+impl BuildContext {
+	fn capacity_or(&self, default: usize) -> usize {
+		self.capacity.map(|v| v.get()).unwrap_or(default)
+	}
 }
 
 impl Node {
-	pub fn build(&self) -> Result<traits::Node, BuildError> {
+	pub fn build(&self, ctx: &BuildContext) -> Result<traits::Node, BuildError> {
 		match self {
 			Self::SBX {
 				path_prefix,
@@ -552,53 +1887,29 @@ impl Node {
 				{
 					let source = match transport {
 						SBXTransportConfig::SNURL(transport) => {
+							transport.check()?;
 							let transport = transport.clone();
 							sbx::SBXSource::new(
-								Box::new(move || -> io::Result<snurl::Endpoint> {
-									let raw_sock = net::UdpSocket::bind(net::SocketAddr::new(
-										transport.local_address,
-										transport.local_port,
-									))?;
-
-									raw_sock.set_nonblocking(true)?;
-									if let Some(multicast_group) =
-										transport.multicast_group.as_ref()
-									{
-										match (multicast_group, transport.local_address) {
-										(IpAddr::V4(mc_group), IpAddr::V4(ifaddr)) => {
-											raw_sock.join_multicast_v4(mc_group, &ifaddr)?
-										},
-										(IpAddr::V6(_), IpAddr::V6(_)) => {
-											panic!("multicast operation not supported on ipv6")
-										},
-										_ => panic!("multicast group address family differs from local address family")
-									}
-									}
-									let sock = snurl::Socket::new(
-										tokio::net::UdpSocket::from_std(raw_sock)
-											.expect("conversion to tokio socket"),
-										net::SocketAddr::new(
-											transport.remote_address,
-											transport.remote_port,
-										),
-										transport.passive,
-									);
-									Ok(snurl::Endpoint::new(sock))
-								}),
+								&ctx.scope,
+								Box::new(move || transport.endpoint()),
 								path_prefix.clone(),
 								*rewrite_bme68x,
+								ctx.state.clone(),
+								ctx.capacity_or(384),
+								ctx.capacity_or(1024),
 							)
 							.map_err(|e| BuildError::Other(Box::new(e)))?
 						}
 						#[cfg(feature = "serial")]
 						SBXTransportConfig::Serial(transport) => sbx::SBXSource::with_serial(
-							tokio_serial::SerialStream::open(&tokio_serial::new(
-								&transport.port,
-								transport.baudrate,
-							))
-							.expect("open serial port"),
+							&ctx.scope,
+							transport.port.clone(),
+							transport.baudrate,
 							path_prefix.clone(),
 							*rewrite_bme68x,
+							ctx.state.clone(),
+							ctx.capacity_or(384),
+							ctx.capacity_or(1024),
 						),
 					};
 					Ok(traits::Node::from_source(source))
@@ -619,39 +1930,15 @@ impl Node {
 			} => {
 				#[cfg(feature = "sbm")]
 				{
+					transport.check()?;
 					let transport = transport.clone();
 					let source = sbm::MininodeSource::new(
-						Box::new(move || -> io::Result<snurl::Endpoint> {
-							let raw_sock = net::UdpSocket::bind(net::SocketAddr::new(
-								transport.local_address,
-								transport.local_port,
-							))?;
-
-							raw_sock.set_nonblocking(true)?;
-							if let Some(multicast_group) = transport.multicast_group.as_ref() {
-								match (multicast_group, transport.local_address) {
-								(IpAddr::V4(mc_group), IpAddr::V4(ifaddr)) => {
-									raw_sock.join_multicast_v4(mc_group, &ifaddr)?
-								},
-								(IpAddr::V6(_), IpAddr::V6(_)) => {
-									panic!("multicast operation not supported on ipv6")
-								},
-								_ => panic!("multicast group address family differs from local address family")
-							}
-							}
-							let sock = snurl::Socket::new(
-								tokio::net::UdpSocket::from_std(raw_sock)
-									.expect("conversion to tokio socket"),
-								net::SocketAddr::new(
-									transport.remote_address,
-									transport.remote_port,
-								),
-								transport.passive,
-							);
-							Ok(snurl::Endpoint::new(sock))
-						}),
+						&ctx.scope,
+						Box::new(move || transport.endpoint()),
 						path_prefix.clone(),
 						*rewrite_bme68x,
+						ctx.capacity_or(384),
+						ctx.capacity_or(1024),
 					)
 					.map_err(|e| BuildError::Other(Box::new(e)))?;
 					Ok(traits::Node::from_source(source))
@@ -688,10 +1975,12 @@ impl Node {
 						);
 					}
 					Ok(traits::Node::from_source(debug::RandomSource::new(
+						&ctx.scope,
 						time::Duration::from_secs_f64(*interval),
 						instance.into(),
 						device_type.into(),
 						components_out,
+						ctx.capacity_or(8),
 					)))
 				}
 				#[cfg(not(feature = "debug"))]
@@ -712,10 +2001,13 @@ impl Node {
 					};
 					raw_sock
 						.set_nonblocking(true)
-						.expect("setting the tcp socket to be non-blocking");
+						.map_err(|e| BuildError::Other(Box::new(e)))?;
+					let sock = tokio::net::TcpListener::from_std(raw_sock)
+						.map_err(|e| BuildError::Other(Box::new(e)))?;
 					Ok(traits::Node::from_source(relay::RelaySource::new(
-						tokio::net::TcpListener::from_std(raw_sock)
-							.expect("conversion to tokio socket"),
+						&ctx.scope,
+						sock,
+						ctx.capacity_or(8),
 					)))
 				}
 				#[cfg(not(feature = "relay"))]
@@ -731,7 +2023,10 @@ impl Node {
 				#[cfg(feature = "relay")]
 				{
 					Ok(traits::Node::from_sink(relay::RelaySink::new(
+						&ctx.scope,
 						peer_address.clone(),
+						&ctx.state,
+						ctx.capacity_or(8),
 					)))
 				}
 				#[cfg(not(feature = "relay"))]
@@ -743,10 +2038,13 @@ impl Node {
 					})
 				}
 			}
-			Self::DebugStdout => {
+			Self::DebugStdout {} => {
 				#[cfg(feature = "debug")]
 				{
-					Ok(traits::Node::from_sink(debug::DebugStdoutSink::new()))
+					Ok(traits::Node::from_sink(debug::DebugStdoutSink::new(
+						&ctx.scope,
+						ctx.capacity_or(128),
+					)))
 				}
 				#[cfg(not(feature = "debug"))]
 				{
@@ -761,7 +2059,11 @@ impl Node {
 				for filter in filters.iter() {
 					built_filters.push(filter.build()?);
 				}
-				Ok(traits::Node::from(router::Router::new(built_filters)))
+				Ok(traits::Node::from(router::Router::new(
+					&ctx.scope,
+					built_filters,
+					ctx.capacity_or(128),
+				)))
 			}
 			#[cfg(feature = "influxdb")]
 			Self::InfluxDB {
@@ -769,38 +2071,110 @@ impl Node {
 				auth,
 				database,
 				retention_policy,
+				org,
+				bucket,
 				precision,
 				mapping,
+				merge,
+				integer_counters,
+				streams,
+				batch,
+				gzip,
+				retry,
+				buffer,
+				health,
 			} => {
+				let target = influxdb_target(database, retention_policy, org, bucket)?;
+				let health_check = influxdb_health_check(health, &target)?;
 				let mut built_filters = Vec::new();
 				for filter in mapping.iter() {
 					built_filters.push(filter.build()?);
 				}
+				let buffer = match buffer {
+					Some(cfg) => Some(
+						crate::influxdb::DiskBuffer::open(cfg.directory.clone(), cfg.max_size)
+							.map_err(|e| BuildError::Other(Box::new(e)))?,
+					),
+					None => None,
+				};
+				let settings = influxdb::Settings {
+					target,
+					precision: *precision,
+					integer_counters: *integer_counters,
+					filters: built_filters,
+					merge: merge.as_ref().map(|cfg| cfg.conflict),
+					streams: streams.as_ref().map(|cfg| influxdb::StreamPoints {
+						component: match cfg.fixed_component.as_ref() {
+							Some(v) => samplify::ComponentMode::Static(v.into()),
+							None => samplify::ComponentMode::PopFromPath,
+						},
+						decimation: cfg.decimation.max(1),
+					}),
+					batching: influxdb::Batching {
+						flush_interval: std::time::Duration::from_millis(batch.flush_interval_ms),
+						max_lines: batch.max_lines.max(1),
+						max_bytes: batch.max_bytes,
+						max_in_flight: batch.max_in_flight.max(1),
+					},
+					gzip: *gzip,
+					delivery: retry.build(),
+					buffer,
+					health_check,
+				};
 				Ok(traits::Node::from_sink(influxdb::InfluxDBSink::new(
+					&ctx.scope,
 					api_url.clone(),
 					auth.clone(),
-					database.clone(),
-					retention_policy.clone(),
-					*precision,
-					built_filters,
+					settings,
+					ctx.capacity_or(128),
 				)))
 			}
 			Self::PubSub {
 				api_url,
 				node_template,
 				override_host,
+				auth,
+				namespace,
+				units,
+				batch,
+				retry,
 			} => {
 				#[cfg(feature = "pubsub")]
 				{
 					Ok(traits::Node::from_sink(pubsub::PubSubSink::new(
-						api_url.clone(),
-						node_template.clone(),
-						override_host.clone(),
+						&ctx.scope,
+						crate::pubsub::Client::new(
+							api_url.clone(),
+							node_template.clone(),
+							override_host.clone(),
+							auth.clone(),
+						),
+						crate::pubsub::Format {
+							namespace: namespace.clone(),
+							units: *units,
+						},
+						batch.as_ref().map(|batch| pubsub::Batching {
+							flush_interval: std::time::Duration::from_millis(
+								batch.flush_interval_ms,
+							),
+							max_readouts: batch.max_readouts.max(1),
+						}),
+						retry.build(),
+						ctx.capacity_or(32),
 					)))
 				}
 				#[cfg(not(feature = "pubsub"))]
 				{
-					let _ = (api_url, node_template, override_host);
+					let _ = (
+						api_url,
+						node_template,
+						override_host,
+						auth,
+						namespace,
+						units,
+						batch,
+						retry,
+					);
 					Err(BuildError::FeatureNotAvailable {
 						which: "PubSub node".into(),
 						feature_name: "pubsub",
@@ -822,6 +2196,7 @@ impl Node {
 				#[cfg(feature = "debug")]
 				{
 					Ok(traits::Node::from_source(debug::SineSource::new(
+						&ctx.scope,
 						*nsamples,
 						time::Duration::from_millis(*sample_period as u64),
 						metric::DevicePath {
@@ -839,6 +2214,7 @@ impl Node {
 							period: *period,
 						},
 						buffer.build(),
+						ctx.capacity_or(8),
 					)))
 				}
 				#[cfg(not(feature = "debug"))]
@@ -864,7 +2240,11 @@ impl Node {
 			Self::FFT { size } => {
 				#[cfg(feature = "fft")]
 				{
-					Ok(traits::Node::from(fft::Fft::new(*size)))
+					Ok(traits::Node::from(fft::Fft::new(
+						&ctx.scope,
+						*size,
+						ctx.capacity_or(128),
+					)))
 				}
 				#[cfg(not(feature = "fft"))]
 				{
@@ -878,7 +2258,11 @@ impl Node {
 			Self::Summary { size } => {
 				#[cfg(feature = "summary")]
 				{
-					Ok(traits::Node::from(summary::Summary::new(*size)))
+					Ok(traits::Node::from(summary::Summary::new(
+						&ctx.scope,
+						*size,
+						ctx.capacity_or(128),
+					)))
 				}
 				#[cfg(not(feature = "summary"))]
 				{
@@ -899,11 +2283,13 @@ impl Node {
 				#[cfg(feature = "smbus")]
 				{
 					let node = match smbus::BME280::new(
+						&ctx.scope,
 						device,
 						instance.addr(),
 						path_prefix.into(),
 						std::time::Duration::from_millis(*interval as u64),
 						reconfigure_each.unwrap_or(1024) as usize,
+						ctx.capacity_or(8),
 					) {
 						Ok(v) => v,
 						Err(e) => return Err(BuildError::Other(Box::new(e))),
@@ -927,14 +2313,18 @@ impl Node {
 				};
 				let archive = Box::new(stream::SimpleFileArchive::new(dir, 0o640));
 				Ok(traits::Node::from_sink(runtime_stream::Archiver::new(
+					&ctx.scope,
 					archive,
+					ctx.capacity_or(32),
 				)))
 			}
 			Self::Detrend { mode } => {
 				#[cfg(feature = "detrend")]
 				{
 					Ok(traits::Node::from(detrend::Detrend::new(
+						&ctx.scope,
 						mode.clone().into(),
+						ctx.capacity_or(128),
 					)))
 				}
 				#[cfg(not(feature = "detrend"))]
@@ -962,7 +2352,11 @@ impl Node {
 						descriptor,
 					);
 				}
-				Ok(traits::Node::from(streamify::Streamify::new(descriptors)))
+				Ok(traits::Node::from(streamify::Streamify::new(
+					&ctx.scope,
+					descriptors,
+					ctx.capacity_or(128),
+				)))
 			}
 			Self::Hwmon {
 				interval,
@@ -983,6 +2377,7 @@ impl Node {
 					}
 				}
 				Ok(traits::Node::from_source(hwmon::Hwmon::new(
+					&ctx.scope,
 					hwmon::Scrape::new(
 						std::time::Duration::from_millis(*interval as u64),
 						metric::DevicePath {
@@ -991,6 +2386,7 @@ impl Node {
 						},
 						sensors,
 					),
+					ctx.capacity_or(8),
 				)))
 			}
 			Self::FromCsv {
@@ -1019,6 +2415,7 @@ impl Node {
 						));
 					}
 					let node = match csvinject::Injector::new(
+						&ctx.scope,
 						Box::new(file),
 						device_type_column,
 						instance_column,
@@ -1029,6 +2426,7 @@ impl Node {
 						chrono::Duration::seconds(0),
 						*batch_size,
 						std::time::Duration::from_millis(*sleep_ms as u64),
+						ctx.capacity_or(8),
 					) {
 						Ok(v) => v,
 						Err(e) => return Err(BuildError::Other(Box::new(e))),
@@ -1054,18 +2452,456 @@ impl Node {
 					})
 				}
 			}
+			Self::ToCsv {
+				directory,
+				prefix,
+				device_type_column,
+				instance_column,
+				timestamp_column,
+				components,
+				timestamp_format,
+				rotate_daily,
+				max_file_bytes,
+			} => {
+				#[cfg(feature = "csv")]
+				{
+					if let Err(e) = std::fs::create_dir_all(directory) {
+						return Err(BuildError::Other(Box::new(e)));
+					}
+					let writer = csvexport::RotatingWriter::new(
+						directory.clone(),
+						prefix.clone(),
+						csvexport::Columns {
+							timestamp: timestamp_column.clone(),
+							device_type: device_type_column.clone(),
+							instance: instance_column.clone(),
+							components: components
+								.iter()
+								.map(|decl| (decl.column.clone(), decl.component.clone().into()))
+								.collect(),
+						},
+						match timestamp_format {
+							CsvTimestampFormat::UnixNanos => csvexport::TimestampFormat::UnixNanos,
+							CsvTimestampFormat::Rfc3339 => csvexport::TimestampFormat::Rfc3339,
+						},
+						csvexport::Rotation {
+							daily: *rotate_daily,
+							max_bytes: *max_file_bytes,
+						},
+					);
+					Ok(traits::Node::from_sink(csvexport::CsvSink::new(
+						&ctx.scope,
+						writer,
+						ctx.capacity_or(128),
+					)))
+				}
+				#[cfg(not(feature = "csv"))]
+				{
+					let _ = (
+						directory,
+						prefix,
+						device_type_column,
+						instance_column,
+						timestamp_column,
+						components,
+						timestamp_format,
+						rotate_daily,
+						max_file_bytes,
+					);
+					Err(BuildError::FeatureNotAvailable {
+						which: "ToCsv node".into(),
+						feature_name: "csv",
+					})
+				}
+			}
 			Self::Samplify { fixed_component } => Ok(traits::Node::from(samplify::Samplify::new(
+				&ctx.scope,
 				match fixed_component {
 					Some(v) => samplify::ComponentMode::Static(v.into()),
 					None => samplify::ComponentMode::PopFromPath,
 				},
+				ctx.capacity_or(128),
+			))),
+			Self::LineProtocolHTTP {
+				listen_address,
+				mapping,
+				max_body_size,
+			} => {
+				#[cfg(feature = "lineproto-http")]
+				{
+					let listener = net::TcpListener::bind(&listen_address[..])
+						.map_err(|e| BuildError::Other(Box::new(e)))?;
+					listener
+						.set_nonblocking(true)
+						.map_err(|e| BuildError::Other(Box::new(e)))?;
+					Ok(traits::Node::from_source(
+						lineproto::LineProtocolHTTPSource::new(
+							&ctx.scope,
+							listener,
+							mapping.build(),
+							*max_body_size,
+							ctx.capacity_or(128),
+						),
+					))
+				}
+				#[cfg(not(feature = "lineproto-http"))]
+				{
+					let _ = (listen_address, mapping, max_body_size);
+					Err(BuildError::FeatureNotAvailable {
+						which: "LineProtocolHTTP node".into(),
+						feature_name: "lineproto-http",
+					})
+				}
+			}
+			Self::LineProtocolSocket {
+				udp_address,
+				tcp_address,
+				mapping,
+				precision,
+				max_line_length,
+				max_connections,
+				read_timeout_s,
+			} => {
+				#[cfg(feature = "lineproto")]
+				{
+					if udp_address.is_none() && tcp_address.is_none() {
+						return Err(BuildError::Invalid(
+							"LineProtocolSocket needs udp_address, tcp_address or both".into(),
+						));
+					}
+					if *max_connections == 0 {
+						return Err(BuildError::Invalid(
+							"LineProtocolSocket needs max_connections of at least 1".into(),
+						));
+					}
+					let precision =
+						crate::lineproto::Precision::from_param(precision).ok_or_else(|| {
+							BuildError::Invalid(format!("unknown precision {:?}", precision))
+						})?;
+					let udp = match udp_address {
+						Some(addr) => {
+							let socket = net::UdpSocket::bind(&addr[..])
+								.map_err(|e| BuildError::Other(Box::new(e)))?;
+							socket
+								.set_nonblocking(true)
+								.map_err(|e| BuildError::Other(Box::new(e)))?;
+							Some(socket)
+						}
+						None => None,
+					};
+					let tcp = match tcp_address {
+						Some(addr) => {
+							let listener = net::TcpListener::bind(&addr[..])
+								.map_err(|e| BuildError::Other(Box::new(e)))?;
+							listener
+								.set_nonblocking(true)
+								.map_err(|e| BuildError::Other(Box::new(e)))?;
+							Some((
+								listener,
+								lineproto::TcpLimits {
+									max_connections: *max_connections,
+									read_timeout: std::time::Duration::from_secs(*read_timeout_s),
+								},
+							))
+						}
+						None => None,
+					};
+					Ok(traits::Node::from_source(
+						lineproto::LineProtocolSocketSource::new(
+							&ctx.scope,
+							udp,
+							tcp,
+							mapping.build(),
+							precision,
+							*max_line_length,
+							ctx.capacity_or(128),
+						),
+					))
+				}
+				#[cfg(not(feature = "lineproto"))]
+				{
+					let _ = (
+						udp_address,
+						tcp_address,
+						mapping,
+						precision,
+						max_line_length,
+						max_connections,
+						read_timeout_s,
+					);
+					Err(BuildError::FeatureNotAvailable {
+						which: "LineProtocolSocket node".into(),
+						feature_name: "lineproto",
+					})
+				}
+			}
+			Self::Prometheus {
+				listen_address,
+				prefix,
+				instance_label,
+				staleness_s,
+				timestamps,
+			} => {
+				#[cfg(feature = "prometheus")]
+				{
+					let instance_label = prometheus_label_name("instance_label", instance_label)?;
+					let listener = net::TcpListener::bind(&listen_address[..])
+						.map_err(|e| BuildError::Other(Box::new(e)))?;
+					listener
+						.set_nonblocking(true)
+						.map_err(|e| BuildError::Other(Box::new(e)))?;
+					Ok(traits::Node::from_sink(prometheus::PrometheusSink::new(
+						&ctx.scope,
+						listener,
+						prometheus::Exposition {
+							prefix: prefix.clone(),
+							instance_label,
+							staleness: std::time::Duration::from_secs(*staleness_s),
+							timestamps: *timestamps,
+						},
+						ctx.capacity_or(128),
+					)))
+				}
+				#[cfg(not(feature = "prometheus"))]
+				{
+					let _ = (
+						listen_address,
+						prefix,
+						instance_label,
+						staleness_s,
+						timestamps,
+					);
+					Err(BuildError::FeatureNotAvailable {
+						which: "Prometheus node".into(),
+						feature_name: "prometheus",
+					})
+				}
+			}
+			#[cfg(feature = "prometheus-remote-write")]
+			Self::PrometheusRemoteWrite {
+				url,
+				auth,
+				prefix,
+				instance_label,
+				labels,
+				flush_interval_ms,
+				max_samples,
+				retry,
+			} => Ok(traits::Node::from_sink(remote_write::RemoteWriteSink::new(
+				&ctx.scope,
+				url.clone(),
+				auth.clone(),
+				remote_write_labels(prefix, instance_label, labels)?,
+				remote_write::Batching {
+					flush_interval: std::time::Duration::from_millis(*flush_interval_ms),
+					max_samples: (*max_samples).max(1),
+				},
+				retry.build(),
+				ctx.capacity_or(128),
+			))),
+			#[cfg(feature = "mqtt")]
+			Self::MqttPublish {
+				connection,
+				topic,
+				format,
+				qos,
+				retain,
+			} => Ok(traits::Node::from_sink(mqtt::MqttSink::new(
+				&ctx.scope,
+				connection.build()?,
+				mqtt::Publication {
+					topic: mqtt_topic(topic, *format)?,
+					format: *format,
+					qos: mqtt_qos(*qos)?,
+					retain: *retain,
+				},
+				ctx.capacity_or(128),
+			))),
+			#[cfg(feature = "mqtt")]
+			Self::MqttSubscribe {
+				connection,
+				subscriptions,
+				qos,
+			} => {
+				let subscriptions = subscriptions
+					.iter()
+					.map(|s| {
+						if !rumqttc::valid_filter(&s.filter) {
+							return Err(BuildError::Invalid(format!(
+								"invalid mqtt topic filter {:?}",
+								s.filter
+							)));
+						}
+						Ok(mqtt::Subscription {
+							filter: s.filter.clone(),
+							topic: mqtt_topic(&s.topic, s.format)?,
+							format: s.format,
+						})
+					})
+					.collect::<Result<Vec<_>, _>>()?;
+				Ok(traits::Node::from_source(mqtt::MqttSource::new(
+					&ctx.scope,
+					connection.build()?,
+					subscriptions,
+					mqtt_qos(*qos)?,
+					ctx.capacity_or(128),
+				)))
+			}
+			#[cfg(feature = "sqlite")]
+			Self::SQLite {
+				path,
+				stream_blocks,
+				flush_interval_ms,
+				max_rows,
+				retention_s,
+				prune_interval_s,
+			} => Ok(traits::Node::from_sink(
+				sqlite::SqliteSink::new(
+					&ctx.scope,
+					path,
+					*stream_blocks,
+					sqlite::Batching {
+						flush_interval: std::time::Duration::from_millis(*flush_interval_ms),
+						max_rows: (*max_rows).max(1),
+					},
+					retention_s.map(|max_age| sqlite::Retention {
+						max_age: std::time::Duration::from_secs(max_age),
+						interval: std::time::Duration::from_secs((*prune_interval_s).max(1)),
+					}),
+					ctx.capacity_or(128),
+				)
+				.map_err(|e| BuildError::Other(Box::new(e)))?,
+			)),
+			#[cfg(feature = "graphite")]
+			Self::Graphite {
+				address,
+				protocol,
+				path,
+				instance_separator,
+				flush_interval_ms,
+				max_batch_bytes,
+				retry,
+			} => Ok(traits::Node::from_sink(graphite::GraphiteSink::new(
+				&ctx.scope,
+				(*protocol).into(),
+				address.clone(),
+				crate::graphite::PathTemplate::new(path.clone(), instance_separator.clone()),
+				graphite::Batching {
+					flush_interval: std::time::Duration::from_millis(*flush_interval_ms),
+					max_bytes: (*max_batch_bytes).max(1),
+				},
+				retry.build(),
+				ctx.capacity_or(128),
+			))),
+			#[cfg(feature = "statsd")]
+			Self::StatsD {
+				address,
+				name,
+				dogstatsd,
+				flush_interval_ms,
+				max_packet_size,
+			} => Ok(traits::Node::from_sink(statsd::StatsdSink::new(
+				&ctx.scope,
+				address.clone(),
+				crate::statsd::Encoder::new(
+					crate::statsd::NameTemplate::new(name.clone()),
+					dogstatsd.as_ref().map(|tags| tags.build()),
+				),
+				statsd::Batching {
+					flush_interval: std::time::Duration::from_millis(*flush_interval_ms),
+					max_packet_size: (*max_packet_size).max(1),
+				},
+				ctx.capacity_or(128),
+			))),
+			#[cfg(feature = "webhook")]
+			Self::Webhook {
+				url,
+				method,
+				headers,
+				body,
+				body_format,
+				batch,
+				retry,
+			} => {
+				let batched = batch.is_some();
+				let template = |s: &str| {
+					crate::webhook::Template::parse(s, batched)
+						.map_err(|e| BuildError::Invalid(e.to_string()))
+				};
+				let request = crate::webhook::RequestTemplate {
+					method: reqwest::Method::from_bytes(method.as_bytes()).map_err(|_| {
+						BuildError::Invalid(format!("invalid http method {:?}", method))
+					})?,
+					url: template(url)?,
+					headers: headers
+						.iter()
+						.map(|(k, v)| Ok((k.clone(), template(v)?)))
+						.collect::<Result<_, BuildError>>()?,
+					body: body.as_deref().map(template).transpose()?,
+					body_escape: match body_format {
+						WebhookBodyFormat::Json => crate::webhook::Escape::Json,
+						WebhookBodyFormat::Text => crate::webhook::Escape::Text,
+					},
+				};
+				Ok(traits::Node::from_sink(webhook::WebhookSink::new(
+					&ctx.scope,
+					request,
+					batch.as_ref().map(|batch| webhook::Batching {
+						flush_interval: std::time::Duration::from_millis(batch.flush_interval_ms),
+						max_readouts: batch.max_readouts.max(1),
+					}),
+					retry.build(),
+					ctx.capacity_or(128),
+				)))
+			}
+			#[cfg(feature = "jsonl")]
+			Self::ToJsonLines { path } => Ok(traits::Node::from_sink(jsonl::JsonLinesSink::new(
+				&ctx.scope,
+				match path {
+					Some(path) => jsonl::Output::File(path.clone()),
+					None => jsonl::Output::Stdout,
+				},
+				ctx.capacity_or(128),
 			))),
+			#[cfg(feature = "jsonl")]
+			Self::FromJsonLines { path, speed } => {
+				if let Some(speed) = speed {
+					if !(speed.is_finite() && *speed > 0.0) {
+						return Err(BuildError::Invalid(format!(
+							"speed must be a positive number, got {}",
+							speed
+						)));
+					}
+				}
+				Ok(traits::Node::from_source(jsonl::JsonLinesSource::new(
+					&ctx.scope,
+					match path {
+						Some(path) => jsonl::Input::File(path.clone()),
+						None => jsonl::Input::Stdin,
+					},
+					*speed,
+					ctx.capacity_or(128),
+				)))
+			}
+			Self::LinkStats { interval } => {
+				if *interval == 0 {
+					return Err(BuildError::Invalid(
+						"linkstats: interval must be positive".into(),
+					));
+				}
+				Ok(traits::Node::from_source(linkstats::LinkStatsSource::new(
+					&ctx.scope,
+					ctx.links.clone(),
+					std::time::Duration::from_millis(*interval as u64),
+					ctx.capacity_or(8),
+				)))
+			}
 		}
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub struct FilterPredicate {
 	#[serde(default = "bool_false")]
 	invert: bool,
@@ -1083,15 +2919,16 @@ impl FilterPredicate {
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct MapInstanceAndComponentEntry {
 	old_component: String,
 	new_component: String,
 	new_instance: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type")]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum Filter {
 	SelectByPath {
 		#[serde(default = "bool_false")]
@@ -1115,10 +2952,12 @@ pub enum Filter {
 	},
 	MapInstance {
 		predicate: Option<FilterPredicate>,
+		#[schemars(with = "HashMap<String, String>")]
 		mapping: HashMap<SmartString, SmartString>,
 	},
 	MapDeviceType {
 		predicate: Option<FilterPredicate>,
+		#[schemars(with = "HashMap<String, String>")]
 		mapping: HashMap<SmartString, SmartString>,
 	},
 	Map {
@@ -1130,6 +2969,7 @@ pub enum Filter {
 		predicate: Option<FilterPredicate>,
 		unit: UnitWrap,
 		component_name: String,
+		#[schemars(with = "HashMap<String, f64>")]
 		mapping: HashMap<SmartString, f64>,
 	},
 	KeepIfPlausible {
@@ -1268,7 +3108,8 @@ impl Filter {
 	}
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Route {
 	pub source: String,
 	pub sink: String,
@@ -1276,14 +3117,391 @@ pub struct Route {
 	pub filters: Vec<Filter>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Link {
 	pub source: String,
 	pub sink: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+fn default_state_flush_interval() -> u64 {
+	60
+}
+
+/// Where and how often nodes persist state which should survive restarts.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StateConfig {
+	/// Directory to store the state in. Without it, state is kept in memory
+	/// only and lost when the process exits.
+	pub directory: Option<PathBuf>,
+	/// Interval (in seconds) in which changed state is written to disk.
+	#[serde(default = "default_state_flush_interval")]
+	pub flush_interval: u64,
+}
+
+impl Default for StateConfig {
+	fn default() -> Self {
+		Self {
+			directory: None,
+			flush_interval: default_state_flush_interval(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-	pub node: HashMap<String, Node>,
+	#[serde(default)]
+	pub state: StateConfig,
+	pub node: HashMap<String, NodeConfig>,
 	pub link: Vec<Link>,
 }
+
+/// Config as seen on the first pass: nodes are kept as raw TOML together
+/// with their location so that errors can be attributed to them.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+	#[serde(default)]
+	state: StateConfig,
+	node: HashMap<String, toml::Spanned<toml::Value>>,
+	link: Vec<Link>,
+}
+
+impl Config {
+	/// Parse a TOML configuration.
+	///
+	/// Unlike plain `toml::from_str`, errors in node definitions carry the
+	/// name of the node and the line and column of the offending table or
+	/// key.
+	pub fn from_toml(src: &str) -> Result<Self, LoadError> {
+		let raw: RawConfig = toml::from_str(src)?;
+		let mut node = HashMap::with_capacity(raw.node.len());
+		for (name, value) in raw.node.into_iter() {
+			let span = value.span();
+			let value = value.into_inner();
+			let class = value
+				.get("class")
+				.and_then(|v| v.as_str())
+				.map(|v| v.to_string());
+			match NodeConfig::deserialize(value) {
+				Ok(v) => {
+					node.insert(name, v);
+				}
+				Err(e) => {
+					let message = e.message().to_string();
+					let offset = locate_in_span(src, &span, &message).unwrap_or(span.start);
+					let (line, column) = line_column(src, offset);
+					return Err(LoadError::Node {
+						name,
+						class,
+						line,
+						column,
+						message,
+					});
+				}
+			}
+		}
+		Ok(Self {
+			state: raw.state,
+			node,
+			link: raw.link,
+		})
+	}
+
+	/// Generate a JSON Schema describing the configuration file format.
+	pub fn schema() -> RootSchema {
+		let mut schema = schemars::schema_for!(Config);
+		// `Node` is flattened into `NodeConfig`, so each class only lists its
+		// own keys; add the common ones so that the classes accept them and
+		// reject anything else alike.
+		if let Some(Schema::Object(node)) = schema.definitions.get_mut("NodeConfig") {
+			let common = node
+				.object
+				.as_ref()
+				.map(|v| v.properties.clone())
+				.unwrap_or_default();
+			if let Some(classes) = node.subschemas.as_mut().and_then(|v| v.one_of.as_mut()) {
+				for class in classes.iter_mut() {
+					if let Schema::Object(class) = class {
+						let object = class.object();
+						object.properties.extend(common.clone());
+						object.additional_properties = Some(Box::new(Schema::Bool(false)));
+					}
+				}
+			}
+		}
+		schema
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn from_toml_reports_node_name_and_location() {
+		let src = "link = []\n\n[node.ok]\nclass = \"DebugStdout\"\n\n[node.broken]\nclass = \"Summary\"\nsiez = 3\n";
+		match Config::from_toml(src) {
+			Err(LoadError::Node {
+				name,
+				class,
+				line,
+				column,
+				message,
+			}) => {
+				assert_eq!(name, "broken");
+				assert_eq!(class.as_deref(), Some("Summary"));
+				assert_eq!((line, column), (8, 1));
+				assert!(message.contains("unknown field `siez`"));
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn from_toml_points_at_offending_key() {
+		let src = "link = []\n\n[node.archive]\nclass = \"Detrend\"\n  mode = \"Quadratic\"\n";
+		let err = Config::from_toml(src).unwrap_err();
+		let text = err.to_string();
+		assert!(text.starts_with("in [node.archive] (class \"Detrend\")"));
+		match err {
+			LoadError::Node { line, column, .. } => assert_eq!((line, column), (5, 3)),
+			other => panic!("unexpected error: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn from_toml_rejects_unknown_keys() {
+		let src = "link = []\n\n[node.sink]\nclass = \"DebugStdout\"\n\n[node.sink.restart]\nmode = \"OnFailure\"\nmax_restart = 3\n";
+		match Config::from_toml(src) {
+			Err(LoadError::Node { name, message, .. }) => {
+				assert_eq!(name, "sink");
+				assert!(message.contains("unknown field `max_restart`"));
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+		let src = "link = []\nnodes = {}\n";
+		assert!(Config::from_toml(src)
+			.unwrap_err()
+			.to_string()
+			.contains("unknown field `nodes`"));
+	}
+
+	#[test]
+	fn from_toml_rejects_unknown_keys_for_nodes_without_options() {
+		let src = "link = []\n\n[node.sink]\nclass = \"DebugStdout\"\ncapacty = 3\n";
+		match Config::from_toml(src) {
+			Err(LoadError::Node { name, message, .. }) => {
+				assert_eq!(name, "sink");
+				assert!(message.contains("unknown field `capacty`"));
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+		let src = "link = []\n\n[node.sink]\nclass = \"DebugStdout\"\ncapacity = 3\n";
+		assert!(Config::from_toml(src).is_ok());
+	}
+
+	#[test]
+	fn from_toml_rejects_unknown_units() {
+		let src = "link = []\n\n[node.lp]\nclass = \"LineProtocolHTTP\"\nlisten_address = \"127.0.0.1:8086\"\n\n[node.lp.units]\n\"cpu.temp\" = \"°c\"\n";
+		match Config::from_toml(src) {
+			Err(LoadError::Node { name, message, .. }) => {
+				assert_eq!(name, "lp");
+				assert!(message.contains("unknown unit"));
+			}
+			other => panic!("unexpected result: {:?}", other),
+		}
+		assert!(Config::from_toml(&src.replace("°c", "°C")).is_ok());
+	}
+
+	#[test]
+	fn from_toml_accepts_valid_config() {
+		let src = "[[link]]\nsource = \"a\"\nsink = \"b\"\n\n[node.a]\nclass = \"Summary\"\nsize = 16\n\n[node.b]\nclass = \"DebugStdout\"\n";
+		let cfg = Config::from_toml(src).unwrap();
+		assert_eq!(cfg.node.len(), 2);
+		assert_eq!(cfg.link.len(), 1);
+	}
+
+	#[test]
+	fn from_toml_reads_state_section() {
+		let src = "link = []\n\n[state]\ndirectory = \"/var/lib/metric-relay\"\n\n[node.a]\nclass = \"DebugStdout\"\n";
+		let cfg = Config::from_toml(src).unwrap();
+		assert_eq!(
+			cfg.state.directory.as_deref(),
+			Some(std::path::Path::new("/var/lib/metric-relay"))
+		);
+		assert_eq!(cfg.state.flush_interval, 60);
+	}
+
+	#[cfg(feature = "influxdb")]
+	#[test]
+	fn influxdb_target_selects_api_version() {
+		let some = |s: &str| Some(s.to_string());
+		assert_eq!(
+			influxdb_target(&some("db"), &some("rp"), &None, &None).unwrap(),
+			crate::influxdb::Target::V1 {
+				database: "db".into(),
+				retention_policy: Some("rp".into()),
+			}
+		);
+		assert_eq!(
+			influxdb_target(&None, &None, &some("org"), &some("bucket")).unwrap(),
+			crate::influxdb::Target::V2 {
+				org: "org".into(),
+				bucket: "bucket".into(),
+			}
+		);
+		assert!(influxdb_target(&some("db"), &None, &some("org"), &some("bucket")).is_err());
+		assert!(influxdb_target(&None, &None, &some("org"), &None).is_err());
+		assert!(influxdb_target(&None, &some("rp"), &some("org"), &some("bucket")).is_err());
+	}
+
+	#[cfg(feature = "influxdb")]
+	#[test]
+	fn influxdb_health_check_validates_database_creation() {
+		let health: InfluxDBHealth =
+			toml::from_str("create_database = true\n[retention]\nduration = \"30d\"\n").unwrap();
+		let v1 = |rp: Option<&str>| crate::influxdb::Target::V1 {
+			database: "db".into(),
+			retention_policy: rp.map(Into::into),
+		};
+		let check = influxdb_health_check(&health, &v1(Some("month"))).unwrap();
+		let rp = check.create_database.unwrap().unwrap();
+		assert_eq!(rp.name, "month");
+		assert_eq!(rp.replication, 1);
+		assert!(influxdb_health_check(&health, &v1(None)).is_err());
+		let v2 = crate::influxdb::Target::V2 {
+			org: "org".into(),
+			bucket: "bucket".into(),
+		};
+		assert!(influxdb_health_check(&health, &v2).is_err());
+		assert!(influxdb_health_check(&InfluxDBHealth::default(), &v2)
+			.unwrap()
+			.create_database
+			.is_none());
+	}
+
+	#[cfg(feature = "influxdb")]
+	#[test]
+	fn influxdb_health_check_rejects_bad_settings() {
+		let v1 = crate::influxdb::Target::V1 {
+			database: "db".into(),
+			retention_policy: Some("rp".into()),
+		};
+		let check = |cfg: &str| {
+			let health: InfluxDBHealth = toml::from_str(cfg).unwrap();
+			influxdb_health_check(&health, &v1)
+		};
+		assert!(check("interval_ms = 0\n").is_err());
+		for duration in &[
+			"INF", "inf", "30d", "4w2d", "1h30m", "500ms", "10u", "10µ", "1ns",
+		] {
+			let cfg = format!(
+				"create_database = true\n[retention]\nduration = \"{}\"\n",
+				duration
+			);
+			assert!(check(&cfg).is_ok(), "{}", duration);
+		}
+		for duration in &["", "30", "d", "30 d", "1y", "30d; DROP DATABASE db", "-1d"] {
+			let cfg = format!(
+				"create_database = true\n[retention]\nduration = \"{}\"\n",
+				duration
+			);
+			assert!(check(&cfg).is_err(), "{}", duration);
+		}
+	}
+
+	#[cfg(feature = "mqtt")]
+	#[test]
+	fn mqtt_settings_are_validated() {
+		use crate::mqtt::PayloadFormat;
+
+		assert!(mqtt_qos(1).is_ok());
+		assert!(mqtt_qos(2).is_err());
+		assert!(mqtt_topic("s/{device_type}{instance}", PayloadFormat::Json).is_ok());
+		assert!(mqtt_topic("s/{device_type}{instance}", PayloadFormat::Plain).is_err());
+		assert!(mqtt_topic("s/{device}/{component}", PayloadFormat::Plain).is_err());
+
+		let connection: MqttConnection =
+			toml::from_str("host = \"broker\"\nclient_id = \"relay\"\npassword = \"x\"\n").unwrap();
+		assert_eq!(connection.port, 1883);
+		assert!(connection.build().is_err());
+	}
+
+	#[cfg(feature = "prometheus")]
+	#[test]
+	fn prometheus_label_names_are_validated() {
+		assert_eq!(
+			prometheus_label_name("instance_label", "device").unwrap(),
+			"device"
+		);
+		for name in &["", "9dof", "device-path", "__name__"] {
+			assert!(prometheus_label_name("instance_label", name).is_err());
+		}
+	}
+
+	#[cfg(feature = "prometheus-remote-write")]
+	#[test]
+	fn remote_write_labels_are_validated() {
+		let check = |instance_label: &str, name: &str| {
+			let labels = std::iter::once((name.to_string(), "x".to_string())).collect();
+			remote_write_labels("", instance_label, &labels)
+		};
+		let labels = check("device", "site").unwrap();
+		assert_eq!(labels.extra, vec![("site".to_string(), "x".to_string())]);
+		assert!(check("device-path", "site").is_err());
+		for name in &["site-1", "__name__", "device", "le"] {
+			assert!(check("device", name).is_err(), "{}", name);
+		}
+		assert!(check("instance", "instance").is_err());
+	}
+
+	#[test]
+	fn line_column_is_one_based() {
+		let src = "ab\ncd\n";
+		assert_eq!(line_column(src, 0), (1, 1));
+		assert_eq!(line_column(src, 4), (2, 2));
+	}
+
+	#[test]
+	fn schema_covers_node_classes() {
+		let schema = serde_json::to_value(Config::schema()).unwrap();
+		let text = schema.to_string();
+		assert!(schema["definitions"]["NodeConfig"].is_object());
+		assert!(text.contains("\"DebugStdout\""));
+		assert!(text.contains("\"SelectByPath\""));
+		#[cfg(feature = "influxdb")]
+		assert!(text.contains("\"Transpose\""));
+	}
+
+	#[test]
+	fn schema_lists_all_units() {
+		let schema = serde_json::to_value(Config::schema()).unwrap();
+		let listed = schema["definitions"]["Unit"]["enum"].as_array().unwrap();
+		assert_eq!(listed.len(), metric::Unit::NAMED.len());
+		for (name, unit) in metric::Unit::NAMED.iter() {
+			assert!(listed.contains(&serde_json::Value::from(*name)));
+			assert_eq!(&name.parse::<metric::Unit>().unwrap(), unit);
+		}
+	}
+
+	#[test]
+	fn schema_accepts_common_node_keys() {
+		let schema = serde_json::to_value(Config::schema()).unwrap();
+		let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+		let validate = |src: &str| {
+			let value: toml::Value = toml::from_str(src).unwrap();
+			schema.is_valid(&serde_json::to_value(value).unwrap())
+		};
+		let src = "[[link]]\nsource = \"a\"\nsink = \"b\"\n\n[node.a]\nclass = \"Summary\"\nsize = 16\ncapacity = 8\n\n[node.b]\nclass = \"DebugStdout\"\ncapacity = 3\n\n[node.b.restart]\nmode = \"OnFailure\"\nmax_restarts = 3\n\n[node.c]\nclass = \"LineProtocolHTTP\"\nlisten_address = \"127.0.0.1:8086\"\ncapacity = 3\n";
+		assert!(Config::from_toml(src).is_ok());
+		assert!(validate(src));
+		assert!(!validate(&src.replace("capacity = 3", "capacty = 3")));
+		assert!(!validate(&src.replace(
+			"listen_address = \"127.0.0.1:8086\"",
+			"listen_address = \"127.0.0.1:8086\"\nbogus = 1"
+		)));
+	}
+}
@@ -0,0 +1,440 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex};
+
+use log::{debug, warn};
+
+use smartstring::alias::String as SmartString;
+
+use tokio::sync::mpsc;
+use tokio::task::spawn_blocking;
+
+use csv;
+
+use crate::metric;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+	/// Nanoseconds since the epoch, as read by the CSV injector.
+	UnixNanos,
+	/// RFC 3339, for humans and spreadsheets.
+	Rfc3339,
+}
+
+/// The columns of the written files, in order.
+pub struct Columns {
+	pub timestamp: String,
+	pub device_type: String,
+	pub instance: String,
+	/// Column titles and the components written into them.
+	pub components: Vec<(String, SmartString)>,
+}
+
+/// When to start a new file.
+pub struct Rotation {
+	/// Start a new file for each day (UTC) of the readout timestamps.
+	pub daily: bool,
+	/// Start a new file rather than growing the current one beyond this
+	/// size.
+	pub max_bytes: Option<u64>,
+}
+
+struct OpenFile {
+	day: Option<chrono::NaiveDate>,
+	index: u32,
+	file: io::BufWriter<fs::File>,
+	bytes: u64,
+}
+
+/// Appends readouts to a set of rotating CSV files.
+///
+/// Files are named `<prefix>-<date>.csv` with daily rotation and
+/// `<prefix>.csv` without. When rotating by size, further files of the
+/// same day get a sequence number, as in `<prefix>-<date>.1.csv`.
+///
+/// Existing files are appended to (unless they are full already) without
+/// checking that their header matches the configured columns.
+pub struct RotatingWriter {
+	directory: PathBuf,
+	prefix: String,
+	columns: Columns,
+	timestamp_format: TimestampFormat,
+	rotation: Rotation,
+	current: Option<OpenFile>,
+}
+
+impl RotatingWriter {
+	pub fn new(
+		directory: PathBuf,
+		prefix: String,
+		columns: Columns,
+		timestamp_format: TimestampFormat,
+		rotation: Rotation,
+	) -> Self {
+		Self {
+			directory,
+			prefix,
+			columns,
+			timestamp_format,
+			rotation,
+			current: None,
+		}
+	}
+
+	fn path(&self, day: Option<chrono::NaiveDate>, index: u32) -> PathBuf {
+		let mut name = self.prefix.clone();
+		if let Some(day) = day {
+			name.push_str(&format!("-{}", day.format("%Y-%m-%d")));
+		}
+		if index > 0 {
+			name.push_str(&format!(".{}", index));
+		}
+		name.push_str(".csv");
+		self.directory.join(name)
+	}
+
+	/// Encode a single record, to know its size before writing it.
+	fn encode<'x, I: IntoIterator<Item = &'x str>>(fields: I) -> io::Result<Vec<u8>> {
+		let mut encoder = csv::Writer::from_writer(Vec::new());
+		encoder.write_record(fields)?;
+		encoder
+			.into_inner()
+			.map_err(|e| io::Error::new(e.error().kind(), e.to_string()))
+	}
+
+	fn header(&self) -> io::Result<Vec<u8>> {
+		let mut fields = vec![
+			self.columns.timestamp.clone(),
+			self.columns.device_type.clone(),
+			self.columns.instance.clone(),
+		];
+		fields.extend(
+			self.columns
+				.components
+				.iter()
+				.map(|(title, _)| title.clone()),
+		);
+		Self::encode(fields.iter().map(|x| x.as_str()))
+	}
+
+	/// Whether a record of `len` bytes may be added to a file of `bytes`.
+	fn fits(&self, bytes: u64, len: u64) -> bool {
+		match self.rotation.max_bytes {
+			// a record which is too large on its own gets a file anyway
+			Some(max) => bytes == 0 || bytes + len <= max,
+			None => true,
+		}
+	}
+
+	/// Open the first file with room for a record of `len` bytes, starting
+	/// at `index`.
+	fn open(&mut self, day: Option<chrono::NaiveDate>, mut index: u32, len: u64) -> io::Result<()> {
+		if let Some(mut old) = self.current.take() {
+			old.file.flush()?;
+		}
+		loop {
+			let path = self.path(day, index);
+			let bytes = match fs::metadata(&path) {
+				Ok(meta) => meta.len(),
+				Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+				Err(e) => return Err(e),
+			};
+			if !self.fits(bytes, len) {
+				index += 1;
+				continue;
+			}
+			debug!("writing CSV to {:?}", path);
+			let mut file = io::BufWriter::new(
+				fs::OpenOptions::new()
+					.create(true)
+					.append(true)
+					.open(&path)?,
+			);
+			let mut bytes = bytes;
+			if bytes == 0 {
+				let header = self.header()?;
+				file.write_all(&header)?;
+				bytes = header.len() as u64;
+			}
+			self.current = Some(OpenFile {
+				day,
+				index,
+				file,
+				bytes,
+			});
+			return Ok(());
+		}
+	}
+
+	pub fn write(&mut self, readout: &metric::Readout) -> io::Result<()> {
+		let timestamp = match self.timestamp_format {
+			TimestampFormat::UnixNanos => match readout
+				.timestamp
+				.timestamp()
+				.checked_mul(1_000_000_000)
+				.and_then(|ns| ns.checked_add(readout.timestamp.timestamp_subsec_nanos() as i64))
+			{
+				Some(ts) => ts.to_string(),
+				None => {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidInput,
+						"timestamp out of range",
+					))
+				}
+			},
+			TimestampFormat::Rfc3339 => readout
+				.timestamp
+				.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+		};
+		let mut fields = vec![
+			timestamp,
+			readout.path.device_type.to_string(),
+			readout.path.instance.to_string(),
+		];
+		for (_, component) in self.columns.components.iter() {
+			// missing components are left empty, which the injector skips
+			fields.push(
+				readout
+					.components
+					.get(component)
+					.map(|v| v.magnitude.to_string())
+					.unwrap_or_default(),
+			);
+		}
+		let record = Self::encode(fields.iter().map(|x| x.as_str()))?;
+
+		let day = if self.rotation.daily {
+			Some(readout.timestamp.date_naive())
+		} else {
+			None
+		};
+		let len = record.len() as u64;
+		match self.current.as_ref() {
+			Some(current) if current.day != day => self.open(day, 0, len)?,
+			Some(current) if !self.fits(current.bytes, len) => {
+				let index = current.index + 1;
+				self.open(day, index, len)?;
+			}
+			Some(_) => (),
+			None => self.open(day, 0, len)?,
+		}
+		// open() either succeeded or returned early
+		let current = self.current.as_mut().unwrap();
+		current.file.write_all(&record)?;
+		current.bytes += len;
+		Ok(())
+	}
+
+	pub fn flush(&mut self) -> io::Result<()> {
+		match self.current.as_mut() {
+			Some(current) => current.file.flush(),
+			None => Ok(()),
+		}
+	}
+}
+
+struct CsvWorker {
+	writer: Arc<StdMutex<RotatingWriter>>,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+}
+
+impl CsvWorker {
+	async fn write(&self, sample: payload::Sample) {
+		let writer = self.writer.clone();
+		let n = sample.len();
+		let result = spawn_blocking(move || {
+			let mut writer = writer.lock().unwrap();
+			for readout in sample.iter() {
+				writer.write(readout)?;
+			}
+			writer.flush()
+		})
+		.await;
+		match result {
+			Ok(Ok(())) => (),
+			Ok(Err(e)) => warn!("lost up to {} readouts: failed to write CSV: {}", n, e),
+			Err(e) => warn!("lost up to {} readouts: CSV writer crashed: {}", n, e),
+		}
+	}
+
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		while let Some(sample) = samples.recv().await {
+			self.write(sample).await;
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Appends readouts to rotating CSV files, in the format read by the CSV
+/// injector.
+pub struct CsvSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl CsvSink {
+	pub fn new(scope: &supervisor::Scope, writer: RotatingWriter, capacity: usize) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let worker = Arc::new(CsvWorker {
+			writer: Arc::new(StdMutex::new(writer)),
+			samples: supervisor::Resumable::new(samples),
+		});
+		let guard = scope.spawn("csv", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
+		Self {
+			samples: serializer,
+			guard,
+		}
+	}
+}
+
+impl traits::Sink for CsvSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn readout(t: i64, instance: &str, value: f64) -> metric::Readout {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.at(t)
+			.temperature(value)
+			.build()
+	}
+
+	fn directory(name: &str) -> PathBuf {
+		let path =
+			std::env::temp_dir().join(format!("sbx-csvexport-{}-{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&path);
+		fs::create_dir_all(&path).unwrap();
+		path
+	}
+
+	fn writer(directory: PathBuf, rotation: Rotation) -> RotatingWriter {
+		RotatingWriter::new(
+			directory,
+			"readouts".into(),
+			Columns {
+				timestamp: "time".into(),
+				device_type: "type".into(),
+				instance: "instance".into(),
+				components: vec![
+					("t".into(), "temperature".into()),
+					("rh".into(), "humidity".into()),
+				],
+			},
+			TimestampFormat::UnixNanos,
+			rotation,
+		)
+	}
+
+	#[test]
+	fn rotates_daily() {
+		let dir = directory("daily");
+		let mut w = writer(
+			dir.clone(),
+			Rotation {
+				daily: true,
+				max_bytes: None,
+			},
+		);
+		w.write(&readout(1600000000, "a, b", 21.5)).unwrap();
+		w.write(&readout(1600000001, "c", -1.0)).unwrap();
+		w.write(&readout(1600100000, "c", 20.0)).unwrap();
+		w.flush().unwrap();
+		drop(w);
+
+		assert_eq!(
+			fs::read_to_string(dir.join("readouts-2020-09-13.csv")).unwrap(),
+			"time,type,instance,t,rh\n\
+			 1600000000000000000,bme280,\"a, b\",21.5,\n\
+			 1600000001000000000,bme280,c,-1,\n"
+		);
+		assert_eq!(
+			fs::read_to_string(dir.join("readouts-2020-09-14.csv")).unwrap(),
+			"time,type,instance,t,rh\n1600100000000000000,bme280,c,20,\n"
+		);
+
+		// appends without repeating the header
+		let mut w = writer(
+			dir.clone(),
+			Rotation {
+				daily: true,
+				max_bytes: None,
+			},
+		);
+		w.write(&readout(1600100001, "d", 19.0)).unwrap();
+		w.flush().unwrap();
+		assert_eq!(
+			fs::read_to_string(dir.join("readouts-2020-09-14.csv"))
+				.unwrap()
+				.lines()
+				.count(),
+			3
+		);
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn rotates_by_size() {
+		let dir = directory("size");
+		// room for the header and one row
+		let mut w = writer(
+			dir.clone(),
+			Rotation {
+				daily: false,
+				max_bytes: Some(60),
+			},
+		);
+		for i in 0..3 {
+			w.write(&readout(1600000000 + i, "a", i as f64)).unwrap();
+		}
+		w.flush().unwrap();
+		drop(w);
+
+		for (name, value) in [
+			("readouts.csv", "0"),
+			("readouts.1.csv", "1"),
+			("readouts.2.csv", "2"),
+		] {
+			let content = fs::read_to_string(dir.join(name)).unwrap();
+			let mut lines = content.lines();
+			assert_eq!(lines.next(), Some("time,type,instance,t,rh"));
+			assert!(lines.next().unwrap().ends_with(&format!(",{},", value)));
+			assert_eq!(lines.next(), None);
+		}
+
+		// full files are skipped on restart
+		let mut w = writer(
+			dir.clone(),
+			Rotation {
+				daily: false,
+				max_bytes: Some(60),
+			},
+		);
+		w.write(&readout(1600000010, "a", 3.0)).unwrap();
+		w.flush().unwrap();
+		assert!(dir.join("readouts.3.csv").exists());
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
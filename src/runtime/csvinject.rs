@@ -15,6 +15,7 @@ use csv;
 use crate::metric;
 
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Source};
 
 fn try_get(rec: &csv::StringRecord, i: usize) -> io::Result<&str> {
@@ -67,10 +68,15 @@ impl InjectionWorker {
 		let instance: SmartString = try_get(&record, self.instance_index)?.into();
 		let mut components = metric::OrderedVec::new();
 		for (offset, name, unit) in self.components.iter() {
+			let value = try_get(&record, *offset)?;
+			// empty cells are components missing from the readout
+			if value.is_empty() {
+				continue;
+			}
 			components.insert(
 				name.clone(),
 				metric::Value {
-					magnitude: decode_value(try_get(&record, *offset)?)?,
+					magnitude: decode_value(value)?,
 					unit: unit.clone(),
 				},
 			);
@@ -94,8 +100,8 @@ impl InjectionWorker {
 
 	async fn run(
 		&mut self,
-		mut source: csv::Reader<Box<dyn io::Read + Send + Sync + 'static>>,
-		sink: broadcast::Sender<payload::Sample>,
+		source: &mut csv::Reader<Box<dyn io::Read + Send + Sync + 'static>>,
+		sink: &broadcast::Sender<payload::Sample>,
 	) {
 		// initial sleep to allow things to be set up
 		tokio::time::sleep(self.sleep).await;
@@ -120,12 +126,12 @@ impl InjectionWorker {
 			if buffer.len() >= self.batch {
 				let mut new = Vec::with_capacity(self.batch);
 				std::mem::swap(&mut new, &mut buffer);
-				Self::submit_buffer(new, &sink);
+				Self::submit_buffer(new, sink);
 				tokio::time::sleep(self.sleep).await;
 			}
 		}
 		if buffer.len() > 0 {
-			Self::submit_buffer(buffer, &sink);
+			Self::submit_buffer(buffer, sink);
 		}
 		info!("end of file in CSV");
 	}
@@ -145,10 +151,13 @@ fn try_find(rec: &csv::StringRecord, name: &str) -> io::Result<usize> {
 
 pub struct Injector {
 	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Injector {
 	pub fn new(
+		scope: &supervisor::Scope,
 		source: Box<dyn io::Read + Sync + Send + 'static>,
 		device_type_column_name: &str,
 		instance_column_name: &str,
@@ -159,6 +168,7 @@ impl Injector {
 		offset: chrono::Duration,
 		batch_size: usize,
 		sleep: Duration,
+		capacity: usize,
 	) -> io::Result<Injector> {
 		let mut reader = csv::ReaderBuilder::default()
 			.has_headers(true)
@@ -171,7 +181,7 @@ impl Injector {
 		for (header, component, unit) in component_mapping.drain(..) {
 			components.push((try_find(&headers, header)?, component, unit));
 		}
-		let mut worker = InjectionWorker {
+		let worker = InjectionWorker {
 			device_type_index,
 			instance_index,
 			timestamp_index,
@@ -182,12 +192,20 @@ impl Injector {
 			batch: batch_size,
 			sleep,
 		};
-		let (zygote, _) = broadcast::channel(8);
+		let (zygote, _) = broadcast::channel(capacity);
 		let sink = zygote.clone();
-		tokio::spawn(async move {
-			worker.run(reader, sink).await;
+		let state = supervisor::Resumable::new((worker, reader));
+		let guard = scope.spawn("csv", move || {
+			let state = state.clone();
+			let sink = sink.clone();
+			async move {
+				let mut state = state.resume().await;
+				let (worker, reader) = &mut *state;
+				worker.run(reader, &sink).await;
+				Ok(())
+			}
 		});
-		Ok(Self { zygote })
+		Ok(Self { zygote, guard })
 	}
 }
 
@@ -200,3 +218,84 @@ impl Source for Injector {
 		null_receiver()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::fs;
+
+	use super::super::csvexport::{Columns, RotatingWriter, Rotation, TimestampFormat};
+
+	#[test]
+	fn reads_what_csvexport_writes() {
+		let dir = std::env::temp_dir().join(format!("sbx-csvinject-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		let mut writer = RotatingWriter::new(
+			dir.clone(),
+			"readouts".into(),
+			Columns {
+				timestamp: "time".into(),
+				device_type: "type".into(),
+				instance: "instance".into(),
+				components: vec![
+					("t".into(), "temperature".into()),
+					("rh".into(), "humidity".into()),
+				],
+			},
+			TimestampFormat::UnixNanos,
+			Rotation {
+				daily: false,
+				max_bytes: None,
+			},
+		);
+		let full = metric::ReadoutBuilder::new()
+			.instance("a, b")
+			.temperature(21.5)
+			.component("humidity", 40.25, metric::Unit::Percent)
+			.build();
+		// humidity is missing
+		let partial = metric::ReadoutBuilder::new()
+			.at(1600000001)
+			.temperature(-1.0)
+			.build();
+		writer.write(&full).unwrap();
+		writer.write(&partial).unwrap();
+		writer.flush().unwrap();
+
+		let mut reader = csv::ReaderBuilder::default()
+			.has_headers(true)
+			.from_reader(fs::File::open(dir.join("readouts.csv")).unwrap());
+		let headers = reader.headers().unwrap().clone();
+		let worker = InjectionWorker {
+			device_type_index: try_find(&headers, "type").unwrap(),
+			instance_index: try_find(&headers, "instance").unwrap(),
+			timestamp_index: try_find(&headers, "time").unwrap(),
+			start_time: chrono::Utc.timestamp_opt(0, 0).unwrap(),
+			end_time: chrono::Utc.timestamp_opt(2000000000, 0).unwrap(),
+			offset: chrono::Duration::zero(),
+			components: vec![
+				(
+					try_find(&headers, "t").unwrap(),
+					"temperature".into(),
+					metric::Unit::Celsius,
+				),
+				(
+					try_find(&headers, "rh").unwrap(),
+					"humidity".into(),
+					metric::Unit::Percent,
+				),
+			],
+			sleep: Duration::from_secs(0),
+			batch: 1,
+		};
+		let readouts: Vec<_> = reader
+			.records()
+			.map(|record| worker.unpack(record.unwrap()).unwrap().unwrap())
+			.collect();
+		assert_eq!(*readouts[0], full);
+		assert_eq!(*readouts[1], partial);
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
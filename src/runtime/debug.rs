@@ -6,10 +6,8 @@ use num_traits::float::FloatConst;
 
 use smartstring::alias::String as SmartString;
 
-use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
-use tokio::sync::oneshot;
 
 use core::time::Duration;
 
@@ -22,29 +20,43 @@ use crate::metric;
 use crate::stream;
 
 use super::adapter::{BufferedStream, BufferedStreamError, Serializer};
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits;
 
 pub struct DebugStdoutSink {
 	samples: Serializer<payload::Sample>,
 	stream: Serializer<payload::Stream>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl DebugStdoutSink {
-	pub fn new() -> DebugStdoutSink {
-		let (samples, samples_src) = Serializer::new(128);
-		let (stream, stream_src) = Serializer::new(128);
-		let result = DebugStdoutSink { samples, stream };
-		tokio::spawn(async move {
-			Self::process(samples_src, stream_src).await;
-			debug!("DebugStdoutSink terminating");
+	pub fn new(scope: &supervisor::Scope, capacity: usize) -> DebugStdoutSink {
+		let (samples, samples_src) = Serializer::new(capacity);
+		let (stream, stream_src) = Serializer::new(capacity);
+		let sources = supervisor::Resumable::new((samples_src, stream_src));
+		let guard = scope.spawn("stdout", move || {
+			let sources = sources.clone();
+			async move {
+				let mut sources = sources.resume().await;
+				let (samples, stream) = &mut *sources;
+				Self::process(samples, stream).await;
+				debug!("DebugStdoutSink terminating");
+				Ok(())
+			}
 		});
-		result
+		DebugStdoutSink {
+			samples,
+			stream,
+			guard,
+		}
 	}
 
 	async fn process(
-		mut samples: mpsc::Receiver<payload::Sample>,
-		mut stream: mpsc::Receiver<payload::Stream>,
+		samples: &mut mpsc::Receiver<payload::Sample>,
+		stream: &mut mpsc::Receiver<payload::Stream>,
 	) {
 		loop {
 			tokio::select! {
@@ -76,10 +88,11 @@ impl DebugStdoutSink {
 }
 
 impl traits::Sink for DebugStdoutSink {
-	fn attach_source<'x>(&self, src: &'x dyn traits::Source) {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
 		debug!("connecting debug sink");
-		self.samples.attach(src.subscribe_to_samples());
-		self.stream.attach(src.subscribe_to_streams());
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+		self.stream.attach(src.subscribe_to_streams(), link.clone());
 	}
 }
 
@@ -91,62 +104,79 @@ pub struct RandomComponent {
 
 pub struct RandomSource {
 	sink: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl RandomSource {
 	pub fn new(
+		scope: &supervisor::Scope,
 		interval: Duration,
 		instance: SmartString,
 		device_type: SmartString,
 		components: metric::OrderedVec<SmartString, RandomComponent>,
+		capacity: usize,
 	) -> Self {
-		let (sink, _) = broadcast::channel(8);
-		let result = Self { sink };
-		result.spawn_into_background(interval, instance, device_type, components);
-		result
+		let (sink, _) = broadcast::channel(capacity);
+		let guard = Self::spawn_into_background(
+			scope,
+			sink.clone(),
+			interval,
+			instance,
+			device_type,
+			components,
+		);
+		Self { sink, guard }
 	}
 
 	fn spawn_into_background(
-		&self,
+		scope: &supervisor::Scope,
+		sink: broadcast::Sender<payload::Sample>,
 		interval: Duration,
 		instance: SmartString,
 		device_type: SmartString,
 		components: metric::OrderedVec<SmartString, RandomComponent>,
-	) {
-		let sink = self.sink.clone();
-		tokio::spawn(async move {
-			loop {
-				let timestamp = Utc::now();
-				let mut result = metric::Readout {
-					timestamp,
-					path: metric::DevicePath {
-						instance: instance.clone(),
-						device_type: device_type.clone(),
-					},
-					components: metric::OrderedVec::new(),
-				};
-				{
-					let mut rng = rand::thread_rng();
-					for (k, v) in components.iter() {
-						result.components.insert(
-							k.clone(),
-							metric::Value {
-								unit: v.unit.clone(),
-								magnitude: rng.gen::<f64>() * (v.max - v.min) + v.min,
-							},
-						);
+	) -> supervisor::TaskGuard {
+		let components = Arc::new(components);
+		scope.spawn("random", move || {
+			let sink = sink.clone();
+			let instance = instance.clone();
+			let device_type = device_type.clone();
+			let components = components.clone();
+			async move {
+				loop {
+					let timestamp = Utc::now();
+					let mut result = metric::Readout {
+						timestamp,
+						path: metric::DevicePath {
+							instance: instance.clone(),
+							device_type: device_type.clone(),
+						},
+						components: metric::OrderedVec::new(),
+					};
+					{
+						let mut rng = rand::thread_rng();
+						for (k, v) in components.iter() {
+							result.components.insert(
+								k.clone(),
+								metric::Value {
+									unit: v.unit.clone(),
+									magnitude: rng.gen::<f64>() * (v.max - v.min) + v.min,
+								},
+							);
+						}
 					}
-				}
-				match sink.send(vec![Arc::new(result)]) {
-					Ok(_) => (),
-					Err(_) => {
-						warn!("random sample lost, no receivers");
-						continue;
+					match sink.send(vec![Arc::new(result)]) {
+						Ok(_) => (),
+						Err(_) => {
+							warn!("random sample lost, no receivers");
+							continue;
+						}
 					}
+					tokio::time::sleep(interval).await;
 				}
-				tokio::time::sleep(interval).await;
 			}
-		});
+		})
 	}
 }
 
@@ -176,11 +206,11 @@ pub struct SineSourceWorker<T: stream::StreamBuffer + Send + Sync + 'static + ?S
 	sink: BufferedStream<T>,
 	seq: u16,
 	sint: usize,
-	stop_ch: oneshot::Receiver<()>,
 }
 
 impl<T: stream::StreamBuffer + Send + Sync + 'static + ?Sized> SineSourceWorker<T> {
 	pub fn start(
+		scope: &supervisor::Scope,
 		nsamples: u16,
 		sample_period: Duration,
 		path: metric::DevicePath,
@@ -188,9 +218,8 @@ impl<T: stream::StreamBuffer + Send + Sync + 'static + ?Sized> SineSourceWorker<
 		cfg: SineConfig,
 		buffer: Box<T>,
 		sink: broadcast::Sender<payload::Stream>,
-		stop_ch: oneshot::Receiver<()>,
-	) {
-		let mut worker = SineSourceWorker {
+	) -> supervisor::TaskGuard {
+		let worker = supervisor::Resumable::new(SineSourceWorker {
 			nsamples,
 			sample_period,
 			path,
@@ -199,11 +228,14 @@ impl<T: stream::StreamBuffer + Send + Sync + 'static + ?Sized> SineSourceWorker<
 			sink: BufferedStream::new(buffer, sink),
 			seq: 0,
 			sint: 0,
-			stop_ch,
-		};
-		tokio::spawn(async move {
-			worker.run().await;
 		});
+		scope.spawn("sine", move || {
+			let worker = worker.clone();
+			async move {
+				worker.resume().await.run().await;
+				Ok(())
+			}
+		})
 	}
 
 	async fn run(&mut self) {
@@ -211,13 +243,7 @@ impl<T: stream::StreamBuffer + Send + Sync + 'static + ?Sized> SineSourceWorker<
 		loop {
 			let t0 = Utc::now();
 			let seq0 = self.seq;
-			select! {
-				_ = tokio::time::sleep(emit_interval) => (),
-				_ = &mut self.stop_ch => {
-					debug!("SineSourceWorker stop_ch returned, shutting down");
-					return
-				},
-			};
+			tokio::time::sleep(emit_interval).await;
 			self.seq = self.seq.wrapping_add(self.nsamples);
 			let mut buf = Vec::new();
 			buf.reserve(self.nsamples as usize);
@@ -249,21 +275,23 @@ impl<T: stream::StreamBuffer + Send + Sync + 'static + ?Sized> SineSourceWorker<
 pub struct SineSource {
 	zygote: broadcast::Sender<payload::Stream>,
 	#[allow(dead_code)]
-	guard: oneshot::Sender<()>,
+	guard: supervisor::TaskGuard,
 }
 
 impl SineSource {
 	pub fn new<T: stream::StreamBuffer + Send + Sync + 'static + ?Sized>(
+		scope: &supervisor::Scope,
 		nsamples: u16,
 		sample_period: Duration,
 		path: metric::DevicePath,
 		scale: metric::Value,
 		cfg: SineConfig,
 		buffer: Box<T>,
+		capacity: usize,
 	) -> Self {
-		let (guard, stop_ch) = oneshot::channel();
-		let (zygote, _) = broadcast::channel(8);
-		SineSourceWorker::start(
+		let (zygote, _) = broadcast::channel(capacity);
+		let guard = SineSourceWorker::start(
+			scope,
 			nsamples,
 			sample_period,
 			path,
@@ -271,7 +299,6 @@ impl SineSource {
 			cfg,
 			buffer,
 			zygote.clone(),
-			stop_ch,
 		);
 		Self { zygote, guard }
 	}
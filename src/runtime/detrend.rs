@@ -9,7 +9,9 @@ use tokio::task::spawn_blocking;
 use crate::metric;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Sink, Source};
 
 #[derive(Debug, Clone, Copy)]
@@ -19,7 +21,7 @@ pub enum Mode {
 }
 
 struct DetrendWorker {
-	source: mpsc::Receiver<payload::Stream>,
+	source: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
 	sink: broadcast::Sender<payload::Stream>,
 	mode: Mode,
 }
@@ -69,12 +71,23 @@ fn detrend<'x>(ps: &'x mut [(f32, f32)]) {
 
 impl DetrendWorker {
 	pub fn spawn(
+		scope: &supervisor::Scope,
 		source: mpsc::Receiver<payload::Stream>,
 		sink: broadcast::Sender<payload::Stream>,
 		mode: Mode,
-	) {
-		let mut worker = Self { source, sink, mode };
-		tokio::spawn(async move { worker.run().await });
+	) -> supervisor::TaskGuard {
+		let worker = Arc::new(Self {
+			source: supervisor::Resumable::new(source),
+			sink,
+			mode,
+		});
+		scope.spawn("detrend", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		})
 	}
 
 	fn process(block: payload::Stream, sink: broadcast::Sender<payload::Stream>, mode: Mode) {
@@ -122,9 +135,10 @@ impl DetrendWorker {
 		}
 	}
 
-	async fn run(&mut self) {
+	async fn run(&self) {
+		let mut source = self.source.resume().await;
 		loop {
-			let block = match self.source.recv().await {
+			let block = match source.recv().await {
 				Some(v) => v,
 				None => {
 					debug!("DetrendWorker shutting down");
@@ -149,14 +163,20 @@ impl DetrendWorker {
 pub struct Detrend {
 	serializer: Serializer<payload::Stream>,
 	zygote: broadcast::Sender<payload::Stream>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Detrend {
-	pub fn new(mode: Mode) -> Self {
-		let (zygote, _) = broadcast::channel(128);
+	pub fn new(scope: &supervisor::Scope, mode: Mode, capacity: usize) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
 		let (serializer, source) = Serializer::new(8);
-		DetrendWorker::spawn(source, zygote.clone(), mode);
-		Self { serializer, zygote }
+		let guard = DetrendWorker::spawn(scope, source, zygote.clone(), mode);
+		Self {
+			serializer,
+			zygote,
+			guard,
+		}
 	}
 }
 
@@ -171,8 +191,9 @@ impl Source for Detrend {
 }
 
 impl Sink for Detrend {
-	fn attach_source<'x>(&self, src: &'x dyn Source) {
-		self.serializer.attach(src.subscribe_to_streams())
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>) {
+		self.serializer
+			.attach(src.subscribe_to_streams(), link.clone())
 	}
 }
 
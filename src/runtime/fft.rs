@@ -18,7 +18,9 @@ use crate::metric;
 use crate::metric::MaskedArray;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Sink, Source};
 
 struct FftWorker {
@@ -43,18 +45,23 @@ fn masked_re_avg(r: Range<usize>, ma: &MaskedArray<Complex<f32>>) -> Option<f32>
 
 impl FftWorker {
 	pub fn spawn(
+		scope: &supervisor::Scope,
 		inner: Arc<dyn FftImpl<f32>>,
 		source: mpsc::Receiver<payload::Stream>,
 		sink: broadcast::Sender<payload::Sample>,
-	) {
-		let mut worker = FftWorker {
+	) -> supervisor::TaskGuard {
+		let worker = supervisor::Resumable::new(FftWorker {
 			inner,
 			source,
 			sink,
-		};
-		tokio::spawn(async move {
-			worker.run().await;
 		});
+		scope.spawn("fft", move || {
+			let worker = worker.clone();
+			async move {
+				worker.resume().await.run().await;
+				Ok(())
+			}
+		})
 	}
 
 	fn process(batch: payload::Stream, fft: Arc<dyn FftImpl<f32>>) -> Vec<(usize, Vec<f32>)> {
@@ -214,15 +221,21 @@ impl FftWorker {
 pub struct Fft {
 	serializer: Serializer<payload::Stream>,
 	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Fft {
-	pub fn new(size: usize) -> Self {
-		let (zygote, _) = broadcast::channel(128);
+	pub fn new(scope: &supervisor::Scope, size: usize, capacity: usize) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
 		let (serializer, source) = Serializer::new(8);
 		let fft = FftPlanner::new().plan_fft_forward(size);
-		FftWorker::spawn(fft, source, zygote.clone());
-		Self { serializer, zygote }
+		let guard = FftWorker::spawn(scope, fft, source, zygote.clone());
+		Self {
+			serializer,
+			zygote,
+			guard,
+		}
 	}
 }
 
@@ -237,7 +250,8 @@ impl Source for Fft {
 }
 
 impl Sink for Fft {
-	fn attach_source<'x>(&self, src: &'x dyn Source) {
-		self.serializer.attach(src.subscribe_to_streams())
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>) {
+		self.serializer
+			.attach(src.subscribe_to_streams(), link.clone())
 	}
 }
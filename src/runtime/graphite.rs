@@ -0,0 +1,347 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::graphite;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::retry::Backoff;
+use super::supervisor;
+use super::traits;
+
+/// Datagrams are kept below the typical MTU to avoid fragmentation.
+const MAX_DATAGRAM: usize = 1400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+	Tcp,
+	Udp,
+}
+
+/// How lines are grouped before sending them.
+pub struct Batching {
+	/// Maximum time lines are held back to wait for more.
+	pub flush_interval: Duration,
+	pub max_bytes: usize,
+}
+
+enum Connection {
+	Tcp(TcpStream),
+	Udp(UdpSocket),
+}
+
+impl Connection {
+	async fn open(protocol: Protocol, address: &str) -> io::Result<Self> {
+		match protocol {
+			Protocol::Tcp => Ok(Self::Tcp(TcpStream::connect(address).await?)),
+			Protocol::Udp => {
+				let addr = tokio::net::lookup_host(address)
+					.await?
+					.next()
+					.ok_or_else(|| {
+						io::Error::new(io::ErrorKind::NotFound, "address did not resolve")
+					})?;
+				let local = if addr.is_ipv4() {
+					"0.0.0.0:0"
+				} else {
+					"[::]:0"
+				};
+				let socket = UdpSocket::bind(local).await?;
+				socket.connect(addr).await?;
+				Ok(Self::Udp(socket))
+			}
+		}
+	}
+
+	/// Whether the server has closed the connection.
+	///
+	/// Carbon never sends anything, so anything but "would block" means
+	/// the connection is gone. Without this check, the first write after
+	/// the server went away would seemingly succeed and its data be lost.
+	fn is_closed(&self) -> bool {
+		match self {
+			Self::Tcp(stream) => {
+				let mut buf = [0u8; 1];
+				!matches!(stream.try_read(&mut buf), Err(ref e) if e.kind() == io::ErrorKind::WouldBlock)
+			}
+			Self::Udp(_) => false,
+		}
+	}
+
+	async fn send(&mut self, batch: &str) -> io::Result<()> {
+		match self {
+			Self::Tcp(stream) => stream.write_all(batch.as_bytes()).await,
+			Self::Udp(socket) => {
+				for datagram in datagrams(batch, MAX_DATAGRAM) {
+					socket.send(datagram.as_bytes()).await?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+/// Split a batch at line boundaries into chunks of at most `max` bytes
+/// (unless a single line is longer).
+fn datagrams(batch: &str, max: usize) -> Vec<&str> {
+	let mut result = Vec::new();
+	let mut start = 0;
+	let mut end = 0;
+	for (i, _) in batch.match_indices('\n') {
+		if i + 1 - start > max && end > start {
+			result.push(&batch[start..end]);
+			start = end;
+		}
+		end = i + 1;
+	}
+	if end > start {
+		result.push(&batch[start..end]);
+	}
+	result
+}
+
+struct GraphiteWorker {
+	protocol: Protocol,
+	address: String,
+	template: graphite::PathTemplate,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	batching: Batching,
+	retry: Backoff,
+}
+
+impl GraphiteWorker {
+	/// Send a batch over the connection, opening a new one if there is
+	/// none. The connection is dropped on errors.
+	async fn attempt(
+		&self,
+		conn: Option<Connection>,
+		batch: &str,
+	) -> (io::Result<()>, Option<Connection>) {
+		let conn = match conn {
+			Some(conn) if conn.is_closed() => {
+				debug!("graphite server closed the connection");
+				None
+			}
+			other => other,
+		};
+		let mut conn = match conn {
+			Some(conn) => conn,
+			None => match Connection::open(self.protocol, &self.address).await {
+				Ok(new) => new,
+				Err(e) => return (Err(e), None),
+			},
+		};
+		match conn.send(batch).await {
+			Ok(()) => (Ok(()), Some(conn)),
+			Err(e) => (Err(e), None),
+		}
+	}
+
+	async fn send(&self, conn: &mut Option<Connection>, batch: &str) {
+		let (result, new) = self
+			.retry
+			.run_with(
+				"send to graphite",
+				conn.take(),
+				|_| true,
+				|conn| self.attempt(conn, batch),
+			)
+			.await;
+		*conn = new;
+		if let Err(e) = result {
+			warn!(
+				"lost {} lines: failed to send to graphite: {}",
+				batch.lines().count(),
+				e
+			);
+		}
+	}
+
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		let mut conn = None;
+		let mut batch = String::new();
+		let mut since = None;
+		loop {
+			let deadline = since.map(|x: Instant| x + self.batching.flush_interval);
+			tokio::select! {
+				v = samples.recv() => match v {
+					Some(sample) => {
+						for readout in sample.iter() {
+							graphite::write_lines(readout, &self.template, &mut batch);
+						}
+						if !batch.is_empty() {
+							since.get_or_insert_with(Instant::now);
+						}
+						if batch.len() >= self.batching.max_bytes {
+							self.send(&mut conn, &batch).await;
+							batch.clear();
+							since = None;
+						}
+					}
+					None => break,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					self.send(&mut conn, &batch).await;
+					batch.clear();
+					since = None;
+				}
+			}
+		}
+		if !batch.is_empty() {
+			self.send(&mut conn, &batch).await;
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Sends readouts to Graphite in the Carbon plaintext format.
+pub struct GraphiteSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl GraphiteSink {
+	pub fn new(
+		scope: &supervisor::Scope,
+		protocol: Protocol,
+		address: String,
+		template: graphite::PathTemplate,
+		batching: Batching,
+		retry: Backoff,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let worker = Arc::new(GraphiteWorker {
+			protocol,
+			address,
+			template,
+			samples: supervisor::Resumable::new(samples),
+			batching,
+			retry,
+		});
+		let guard = scope.spawn("graphite", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
+		Self {
+			samples: serializer,
+			guard,
+		}
+	}
+}
+
+impl traits::Sink for GraphiteSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use tokio::io::AsyncReadExt;
+
+	use crate::metric;
+
+	fn readout(instance: &str, t: i64, value: f64) -> Arc<metric::Readout> {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.at(t)
+			.temperature(value)
+			.arc()
+	}
+
+	fn worker(protocol: Protocol, address: String) -> GraphiteWorker {
+		let (_, samples) = mpsc::channel(1);
+		GraphiteWorker {
+			protocol,
+			address,
+			template: graphite::PathTemplate::new(
+				"{device_type}.{instance}.{component}".into(),
+				".".into(),
+			),
+			samples: supervisor::Resumable::new(samples),
+			batching: Batching {
+				flush_interval: Duration::from_millis(10),
+				max_bytes: 1 << 16,
+			},
+			retry: Backoff {
+				max_retries: 3,
+				initial_backoff: Duration::from_millis(1),
+				max_backoff: Duration::from_millis(10),
+			},
+		}
+	}
+
+	#[test]
+	fn splits_batches_into_datagrams() {
+		assert_eq!(
+			datagrams("a 1 1\nb 2 2\nc 3 3\n", 12),
+			vec!["a 1 1\nb 2 2\n", "c 3 3\n"]
+		);
+		assert_eq!(datagrams("long line\n", 4), vec!["long line\n"]);
+		assert!(datagrams("", 4).is_empty());
+	}
+
+	#[tokio::test]
+	async fn reconnects_after_server_closed_connection() {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let worker = worker(Protocol::Tcp, listener.local_addr().unwrap().to_string());
+		let mut conn = None;
+
+		worker.send(&mut conn, "a 1 1\n").await;
+		let (mut sock, _) = listener.accept().await.unwrap();
+		let mut buf = vec![0u8; 6];
+		sock.read_exact(&mut buf).await.unwrap();
+		assert_eq!(buf, b"a 1 1\n");
+		drop(sock);
+		// give the close some time to arrive
+		tokio::time::sleep(Duration::from_millis(50)).await;
+
+		worker.send(&mut conn, "b 2 2\n").await;
+		let (mut sock, _) = listener.accept().await.unwrap();
+		let mut received = String::new();
+		drop(conn);
+		sock.read_to_string(&mut received).await.unwrap();
+		assert_eq!(received, "b 2 2\n");
+	}
+
+	#[tokio::test]
+	async fn sends_batches_over_udp() {
+		let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let (tx, samples) = mpsc::channel(8);
+		let mut worker = worker(Protocol::Udp, server.local_addr().unwrap().to_string());
+		worker.samples = supervisor::Resumable::new(samples);
+		let run = tokio::spawn(async move { worker.run().await });
+		tx.send(vec![
+			readout("/sbx/i2c-2/76", 1600000000, 21.5),
+			readout("b", 1600000001, 20.0),
+		])
+		.await
+		.unwrap();
+		let mut buf = vec![0u8; 2048];
+		let n = server.recv(&mut buf).await.unwrap();
+		assert_eq!(
+			std::str::from_utf8(&buf[..n]).unwrap(),
+			"bme280.sbx.i2c-2.76.temperature 21.5 1600000000\nbme280.b.temperature 20 1600000001\n"
+		);
+		drop(tx);
+		run.await.unwrap();
+	}
+}
@@ -9,6 +9,7 @@ use log::{error, trace, warn};
 
 use smartstring::alias::String as SmartString;
 
+use schemars::JsonSchema;
 use serde_derive::Deserialize;
 
 use chrono::Utc;
@@ -18,9 +19,10 @@ use tokio::sync::broadcast;
 use crate::metric;
 
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Source};
 
-#[derive(Debug, Clone, Copy, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
 pub enum Type {
 	TempInput,
 }
@@ -204,16 +206,24 @@ impl Scrape {
 
 pub struct Hwmon {
 	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Hwmon {
-	pub fn new(scrape: Scrape) -> Self {
-		let (zygote, _) = broadcast::channel(8);
+	pub fn new(scope: &supervisor::Scope, scrape: Scrape, capacity: usize) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
 		let sink = zygote.clone();
-		tokio::spawn(async move {
-			scrape.run(sink).await;
+		let scrape = Arc::new(scrape);
+		let guard = scope.spawn("hwmon", move || {
+			let scrape = scrape.clone();
+			let sink = sink.clone();
+			async move {
+				scrape.run(sink).await;
+				Ok(())
+			}
 		});
-		Self { zygote }
+		Self { zygote, guard }
 	}
 }
 
@@ -1,150 +1,1141 @@
-use std::sync::Arc;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
 
-use log::warn;
+use log::{debug, info, warn};
 
-use tokio::sync::mpsc;
+use bytes::{Bytes, BytesMut};
+
+use enum_map::EnumMap;
+
+use tokio::sync::{mpsc, Mutex, MutexGuard, Notify};
+use tokio::task::JoinSet;
 
 use crate::influxdb;
 use crate::influxdb::Filter;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::retry::Backoff;
+use super::samplify;
+use super::supervisor;
 use super::traits;
 
+/// How lines are grouped into requests.
+pub struct Batching {
+	/// Maximum time lines are held back to wait for more.
+	pub flush_interval: Duration,
+	pub max_lines: usize,
+	pub max_bytes: usize,
+	/// Number of requests which may be in flight at the same time.
+	pub max_in_flight: usize,
+}
+
+/// Expansion of stream blocks into individual points.
+pub struct StreamPoints {
+	/// How the field name is derived from the stream's path.
+	pub component: samplify::ComponentMode,
+	/// Only every n-th sample of the stream is written.
+	pub decimation: usize,
+}
+
+/// Periodic check whether InfluxDB is reachable, and whether the database
+/// needs to be created.
+pub struct HealthCheck {
+	pub interval: Duration,
+	/// Create the (1.x) database if it does not exist, together with the
+	/// retention policy, if given.
+	pub create_database: Option<Option<influxdb::RetentionPolicy>>,
+}
+
+/// What is written to InfluxDB and how.
+pub struct Settings {
+	pub target: influxdb::Target,
+	pub precision: influxdb::Precision,
+	pub integer_counters: bool,
+	pub filters: Vec<Box<dyn Filter>>,
+	/// Merge readouts sharing measurement, tags and timestamp.
+	pub merge: Option<influxdb::Conflict>,
+	/// Also write stream blocks, as individual points.
+	pub streams: Option<StreamPoints>,
+	pub batching: Batching,
+	pub gzip: bool,
+	pub delivery: Backoff,
+	/// Buffer batches on disk while InfluxDB is unavailable.
+	pub buffer: Option<influxdb::DiskBuffer>,
+	pub health_check: HealthCheck,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Problem {
+	Unreachable(String),
+	Misconfigured(String),
+}
+
+impl fmt::Display for Problem {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Unreachable(reason) => write!(f, "unreachable: {}", reason),
+			Self::Misconfigured(reason) => write!(f, "misconfigured: {}", reason),
+		}
+	}
+}
+
+/// Health of the sink, as reported to the supervisor.
+struct Health {
+	scope: supervisor::Scope,
+	problem: StdMutex<Option<Problem>>,
+	/// Signalled when writes indicate that the database is missing.
+	recheck: Notify,
+}
+
+impl Health {
+	fn new(scope: supervisor::Scope) -> Self {
+		Self {
+			scope,
+			problem: StdMutex::new(None),
+			recheck: Notify::new(),
+		}
+	}
+
+	fn report(&self, problem: Option<Problem>) {
+		let mut current = self.problem.lock().unwrap();
+		if *current == problem {
+			return;
+		}
+		match problem.as_ref() {
+			Some(p) => warn!("influxdb is {}", p),
+			None => info!("influxdb is healthy again"),
+		}
+		self.scope
+			.report_health(problem.as_ref().map(|p| p.to_string()));
+		*current = problem;
+	}
+
+	/// Update the health after a write request.
+	fn observe(&self, result: &Result<(), influxdb::Error>) {
+		match result {
+			Ok(()) => self.report(None),
+			Err(e @ (influxdb::Error::DatabaseNotFound | influxdb::Error::PermissionError)) => {
+				self.report(Some(Problem::Misconfigured(e.to_string())));
+				self.recheck.notify_one();
+			}
+			Err(e) if e.is_retryable() => self.report(Some(Problem::Unreachable(e.to_string()))),
+			Err(_) => (),
+		}
+	}
+
+	fn is_unreachable(&self) -> bool {
+		matches!(*self.problem.lock().unwrap(), Some(Problem::Unreachable(_)))
+	}
+}
+
+struct StreamInput {
+	points: StreamPoints,
+	blocks: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
+}
+
+async fn recv_block(
+	blocks: &mut Option<MutexGuard<'_, mpsc::Receiver<payload::Stream>>>,
+) -> Option<payload::Stream> {
+	match blocks {
+		Some(rx) => rx.recv().await,
+		None => std::future::pending().await,
+	}
+}
+
+/// Split a line protocol body roughly in half, at a line boundary.
+///
+/// Returns `None` if the body consists of a single line.
+fn split_lines(body: &Bytes) -> Option<(Bytes, Bytes)> {
+	let middle = body.len() / 2;
+	let content = &body[..body.len().saturating_sub(1)];
+	let after = content[middle..].iter().position(|&b| b == b'\n');
+	let before = content[..middle].iter().rposition(|&b| b == b'\n');
+	let at = match (before, after) {
+		(Some(b), Some(a)) if middle - b < a => b,
+		(_, Some(a)) => middle + a,
+		(Some(b), None) => b,
+		(None, None) => return None,
+	};
+	Some((body.slice(..at + 1), body.slice(at + 1..)))
+}
+
 struct InfluxDBWorker {
 	client: influxdb::Client,
-	samples: mpsc::Receiver<payload::Sample>,
-	database: String,
-	retention_policy: Option<String>,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	streams: Option<StreamInput>,
+	target: influxdb::Target,
 	precision: influxdb::Precision,
+	integer_counters: bool,
 	filters: Vec<Box<dyn Filter>>,
+	merge: Option<influxdb::Conflict>,
+	batching: Batching,
+	delivery: Backoff,
+	buffer: Option<influxdb::SharedDiskBuffer>,
+	/// Held while draining the buffer, so that entries are submitted once.
+	draining: Mutex<()>,
+	health_check: HealthCheck,
+	health: Health,
 }
 
 impl InfluxDBWorker {
-	pub fn spawn(
-		client: influxdb::Client,
-		samples: mpsc::Receiver<payload::Sample>,
-		database: String,
-		retention_policy: Option<String>,
+	pub fn spawn(self, scope: &supervisor::Scope) -> Vec<supervisor::TaskGuard> {
+		let worker = Arc::new(self);
+		let health_worker = worker.clone();
+		vec![
+			scope.spawn("influxdb", move || {
+				let worker = worker.clone();
+				async move {
+					worker.run().await;
+					Ok(())
+				}
+			}),
+			scope.spawn("health", move || {
+				let worker = health_worker.clone();
+				async move {
+					worker.check_health().await;
+					Ok(())
+				}
+			}),
+		]
+	}
+
+	/// Ping InfluxDB periodically and create the database when needed.
+	///
+	/// The database is created on startup and whenever writes fail because
+	/// it is missing.
+	async fn check_health(&self) {
+		let mut create = true;
+		loop {
+			match self.client.ping().await {
+				Err(e) => self
+					.health
+					.report(Some(Problem::Unreachable(e.to_string()))),
+				Ok(()) => match (&self.health_check.create_database, &self.target) {
+					(Some(rp), influxdb::Target::V1 { database, .. }) if create => {
+						match self.client.create_database(database, rp.as_ref()).await {
+							Ok(()) => {
+								debug!("created influxdb database {:?}", database);
+								create = false;
+								self.health.report(None);
+							}
+							Err(e) => self.health.report(Some(Problem::Misconfigured(format!(
+								"failed to create database: {}",
+								e
+							)))),
+						}
+					}
+					// writes decide whether the database is usable
+					_ if self.health.is_unreachable() => self.health.report(None),
+					_ => (),
+				},
+			}
+			tokio::select! {
+				_ = tokio::time::sleep(self.health_check.interval) => (),
+				_ = self.health.recheck.notified() => create = true,
+			}
+		}
+	}
+
+	/// Submit a body, splitting it up to isolate lines which InfluxDB
+	/// rejects.
+	///
+	/// On other errors, the part of the body which has not been submitted
+	/// yet is returned together with the error.
+	async fn submit(
+		&self,
 		precision: influxdb::Precision,
-		filters: Vec<Box<dyn Filter>>,
+		body: Bytes,
+	) -> Result<(), (influxdb::Error, Bytes)> {
+		let mut pending = vec![body];
+		while let Some(body) = pending.pop() {
+			let result = self
+				.client
+				.post_body(&self.target, None, precision, body.clone())
+				.await;
+			self.health.observe(&result);
+			match result {
+				Ok(()) => (),
+				Err(influxdb::Error::DataError) => match split_lines(&body) {
+					Some((first, second)) => {
+						pending.push(second);
+						pending.push(first);
+					}
+					None => warn!(
+						"lost sample: influxdb rejected line: {}",
+						String::from_utf8_lossy(&body).trim_end()
+					),
+				},
+				Err(e) => {
+					let mut remainder = BytesMut::from(&body[..]);
+					for body in pending.iter().rev() {
+						remainder.extend_from_slice(body);
+					}
+					return Err((e, remainder.freeze()));
+				}
+			}
+		}
+		Ok(())
+	}
+
+	async fn submit_with_retries(
+		&self,
+		precision: influxdb::Precision,
+		body: Bytes,
+	) -> Result<(), (influxdb::Error, Bytes)> {
+		let (result, remainder) = self
+			.delivery
+			.run_with(
+				"submit to influxdb",
+				body,
+				|e: &influxdb::Error| e.is_retryable(),
+				|body| async move {
+					match self.submit(precision, body).await {
+						Ok(()) => (Ok(()), Bytes::new()),
+						Err((e, remainder)) => (Err(e), remainder),
+					}
+				},
+			)
+			.await;
+		result.map_err(|e| (e, remainder))
+	}
+
+	async fn deliver(&self, precision: influxdb::Precision, body: Bytes) {
+		if let Some(buffer) = self.buffer.as_ref() {
+			if !buffer.is_empty() {
+				// keep things in order while the buffer drains
+				Self::spool(buffer, precision, body).await;
+				return;
+			}
+		}
+
+		match self.submit_with_retries(precision, body).await {
+			Ok(()) => (),
+			Err((e, remainder)) => match self.buffer.as_ref() {
+				Some(buffer) if e.is_retryable() => {
+					warn!(
+						"failed to submit to influxdb, buffering {} bytes on disk: {}",
+						remainder.len(),
+						e
+					);
+					Self::spool(buffer, precision, remainder).await;
+				}
+				_ => warn!("lost sample: failed to submit to influxdb: {}", e),
+			},
+		}
+	}
+
+	async fn spool(
+		buffer: &influxdb::SharedDiskBuffer,
+		precision: influxdb::Precision,
+		body: Bytes,
 	) {
-		let mut worker = Self {
-			client,
-			samples,
-			database,
-			retention_policy,
-			precision,
-			filters,
-		};
-		tokio::spawn(async move { worker.run().await });
+		if let Err(e) = buffer.push(precision, body).await {
+			warn!(
+				"lost sample: failed to write to influxdb disk buffer: {}",
+				e
+			);
+		}
 	}
 
-	async fn run(&mut self) {
+	/// Submit buffered bodies until the buffer is empty or InfluxDB fails
+	/// again.
+	async fn drain(&self) {
+		let buffer = match self.buffer.as_ref() {
+			Some(v) => v,
+			None => return,
+		};
+		if buffer.is_empty() {
+			return;
+		}
+		// whoever holds the lock already takes care of everything buffered
+		let _draining = match self.draining.try_lock() {
+			Ok(v) => v,
+			Err(_) => return,
+		};
+		debug!(
+			"draining {} batches ({} bytes) from influxdb disk buffer",
+			buffer.len(),
+			buffer.size()
+		);
 		loop {
-			let mut maybe_readouts = Some(match self.samples.recv().await {
-				None => return,
-				Some(v) => v,
-			});
-
-			let mut by_precision = enum_map::enum_map! {
-				_ => Vec::new(),
+			let entry = match buffer.front().await {
+				Ok(Some(v)) => v,
+				Ok(None) => break,
+				Err(e) => {
+					warn!(
+						"lost sample: failed to read from influxdb disk buffer: {}",
+						e
+					);
+					return;
+				}
 			};
-			// instead of receiving just once, we'll try to get more and more
-			// samples until the queue is empty. as we don't yield, we're
-			// likely to be much faster consuming than anyone can be
-			// producing, or at least I hope so ... ok, you got me, let's
-			// build in a handbreak. But other than that, the idea is that
-			// influx is writing to disk and that may be slow and if we only
-			// submit stuff one by one we'll easily be too slow for the
-			// serializer.
-
-			// This is the promised handbreak: we'll only batch up to 256
-			// readouts. This is unlikely to ever happen because serializers
-			// are generally less deep than that.
-			for _ in 0..256 {
-				let mut readouts = match maybe_readouts {
-					Some(v) => v,
-					None => continue,
+			match self.submit(entry.precision, entry.body).await {
+				Ok(()) => (),
+				Err((e, _)) if e.is_retryable() => {
+					debug!("influxdb still unavailable: {}", e);
+					return;
+				}
+				Err((e, _)) => warn!("lost sample: failed to submit to influxdb: {}", e),
+			}
+			// the entry may have been evicted while it was submitted, in
+			// which case the one now in front was not submitted yet
+			if let Err(e) = buffer.remove(entry.id).await {
+				warn!("failed to remove entry from influxdb disk buffer: {}", e);
+				return;
+			}
+		}
+		info!("influxdb disk buffer drained");
+	}
+
+	fn has_buffered(&self) -> bool {
+		match self.buffer.as_ref() {
+			Some(v) => !v.is_empty(),
+			None => false,
+		}
+	}
+
+	/// Send batches handed to this lane one after the other.
+	async fn run_lane(&self, mut batches: mpsc::Receiver<(influxdb::Precision, Bytes)>) {
+		while let Some((precision, body)) = batches.recv().await {
+			self.deliver(precision, body).await;
+			self.drain().await;
+		}
+	}
+
+	/// Convert and serialize readouts into the pending batches, handing
+	/// off batches which are full.
+	///
+	/// If merging is enabled, readouts of the same sample are grouped before
+	/// and their lines coalesced after applying the filters.
+	async fn add(
+		&self,
+		pending: &mut Pending,
+		lanes: &[mpsc::Sender<Batch>],
+		sample: payload::Sample,
+	) {
+		let readouts: Vec<_> = sample
+			.iter()
+			.map(|readout| {
+				Arc::new(influxdb::Readout::from_metric(
+					readout,
+					self.precision,
+					self.integer_counters,
+				))
+			})
+			.collect();
+		let readouts = match self.merge {
+			Some(_) => influxdb::group(readouts),
+			None => readouts,
+		};
+		let readouts: Vec<_> = readouts
+			.into_iter()
+			.filter_map(|readout| self.filters.process(readout))
+			.collect();
+		let readouts = match self.merge {
+			Some(conflict) => influxdb::coalesce(readouts, conflict),
+			None => readouts,
+		};
+
+		let mut lines = Vec::new();
+		for influx_readout in readouts {
+			lines.clear();
+			influx_readout.write(&mut lines).unwrap(); // Vec is infallible
+			let nlines = lines.iter().filter(|&&b| b == b'\n').count();
+			if nlines == 0 {
+				continue;
+			}
+			let lane = pending.lane_of(&influx_readout.measurement);
+			let precision = influx_readout.precision;
+			let batch = &mut pending.lanes[lane][precision];
+			if !batch.is_empty()
+				&& (batch.lines + nlines > self.batching.max_lines
+					|| batch.body.len() + lines.len() > self.batching.max_bytes)
+			{
+				let body = batch.take();
+				Self::hand_off(&lanes[lane], precision, body).await;
+			}
+			let batch = &mut pending.lanes[lane][precision];
+			batch.push(&lines, nlines);
+			if batch.lines >= self.batching.max_lines || batch.body.len() >= self.batching.max_bytes
+			{
+				let body = batch.take();
+				Self::hand_off(&lanes[lane], precision, body).await;
+			}
+		}
+	}
+
+	async fn hand_off(lane: &mpsc::Sender<Batch>, precision: influxdb::Precision, body: Bytes) {
+		if lane.send((precision, body)).await.is_err() {
+			warn!("lost sample: influxdb submission task is gone");
+		}
+	}
+
+	/// Hand off all batches which have waited for at least the flush
+	/// interval (or all of them, if `all` is set).
+	async fn flush(&self, pending: &mut Pending, lanes: &[mpsc::Sender<Batch>], all: bool) {
+		let now = Instant::now();
+		for (lane, batches) in pending.lanes.iter_mut().enumerate() {
+			for (precision, batch) in batches.iter_mut() {
+				let due = match batch.since {
+					Some(since) => all || since + self.batching.flush_interval <= now,
+					None => false,
 				};
-				let nreadouts = readouts.len();
-				for readout in readouts.drain(..) {
-					let influx_readout =
-						match self
-							.filters
-							.process(Arc::new(influxdb::Readout::from_metric(
-								&readout,
-								self.precision,
-							))) {
-							Some(v) => v,
-							None => continue,
-						};
-					let target: &mut Vec<Arc<influxdb::Readout>> =
-						&mut by_precision[influx_readout.precision];
-					if target.capacity() == 0 {
-						// optimize for the general case where all samples will have the same precision
-						target.reserve(nreadouts);
-					}
-					target.push(influx_readout);
+				if due {
+					Self::hand_off(&lanes[lane], precision, batch.take()).await;
 				}
-				// if it fails, we don't care why -- we'll see it when we call
-				// recv() the next time and handle it there.
-				maybe_readouts = self.samples.try_recv().ok();
 			}
+		}
+	}
 
-			for (precision, readouts) in by_precision.iter() {
-				if readouts.len() == 0 {
-					continue;
+	async fn run(self: Arc<Self>) {
+		let mut samples = self.samples.resume().await;
+		let mut blocks = match self.streams.as_ref() {
+			Some(v) => Some(v.blocks.resume().await),
+			None => None,
+		};
+		// whatever is left over from the previous run goes first
+		self.drain().await;
+
+		// each measurement is always submitted through the same lane, which
+		// keeps its lines in order while allowing multiple requests in
+		// flight. The lanes are aborted along with this run if it is stopped
+		// or crashes.
+		let mut lanes = Vec::new();
+		let mut lane_tasks = JoinSet::new();
+		for _ in 0..self.batching.max_in_flight.max(1) {
+			let (tx, rx) = mpsc::channel(1);
+			let worker = self.clone();
+			lanes.push(tx);
+			lane_tasks.spawn(async move { worker.run_lane(rx).await });
+		}
+		let mut pending = Pending::new(lanes.len());
+
+		let mut receiving = true;
+		while receiving || blocks.is_some() {
+			let deadline = pending.oldest().map(|x| x + self.batching.flush_interval);
+			let flush_at = deadline.unwrap_or_else(Instant::now);
+			let buffered = self.has_buffered();
+			tokio::select! {
+				biased;
+				v = samples.recv(), if receiving => match v {
+					Some(sample) => self.add(&mut pending, &lanes, sample).await,
+					None => receiving = false,
+				},
+				v = recv_block(&mut blocks), if blocks.is_some() => match (v, self.streams.as_ref()) {
+					(Some(block), Some(streams)) => {
+						let sample = samplify::samplify_block(
+							&block,
+							&streams.points.component,
+							streams.points.decimation,
+						);
+						self.add(&mut pending, &lanes, sample).await;
+					}
+					_ => blocks = None,
+				},
+				_ = tokio::time::sleep_until(flush_at.into()), if deadline.is_some() => {
+					self.flush(&mut pending, &lanes, false).await;
+				}
+				_ = tokio::time::sleep(self.delivery.max_backoff), if buffered => {
+					self.drain().await;
 				}
-				match self
-					.client
-					.post(
-						&self.database,
-						self.retention_policy.as_ref().and_then(|x| Some(&x[..])),
-						None,
-						precision,
-						&readouts[..],
-					)
-					.await
-				{
-					Ok(_) => (),
-					Err(e) => warn!("lost sample: failed to submit to influxdb: {}", e),
-				};
 			}
 		}
+
+		self.flush(&mut pending, &lanes, true).await;
+		drop(lanes);
+		while lane_tasks.join_next().await.is_some() {}
+	}
+}
+
+type Batch = (influxdb::Precision, Bytes);
+
+/// Lines collected for a single request.
+#[derive(Debug, Default)]
+struct PendingBatch {
+	body: BytesMut,
+	lines: usize,
+	/// When the first line was added.
+	since: Option<Instant>,
+}
+
+impl PendingBatch {
+	fn is_empty(&self) -> bool {
+		self.lines == 0
+	}
+
+	fn push(&mut self, lines: &[u8], nlines: usize) {
+		if self.since.is_none() {
+			self.since = Some(Instant::now());
+		}
+		self.body.extend_from_slice(lines);
+		self.lines += nlines;
+	}
+
+	fn take(&mut self) -> Bytes {
+		self.lines = 0;
+		self.since = None;
+		self.body.split().freeze()
+	}
+}
+
+/// Batches which have not been handed off yet, by lane and precision.
+struct Pending {
+	lanes: Vec<EnumMap<influxdb::Precision, PendingBatch>>,
+}
+
+impl Pending {
+	fn new(nlanes: usize) -> Self {
+		Self {
+			lanes: (0..nlanes).map(|_| EnumMap::default()).collect(),
+		}
+	}
+
+	fn lane_of(&self, measurement: &str) -> usize {
+		let mut hasher = DefaultHasher::new();
+		measurement.hash(&mut hasher);
+		(hasher.finish() % self.lanes.len() as u64) as usize
+	}
+
+	fn oldest(&self) -> Option<Instant> {
+		self.lanes
+			.iter()
+			.flat_map(|x| x.values())
+			.filter_map(|x| x.since)
+			.min()
 	}
 }
 
 pub struct InfluxDBSink {
 	samples: Serializer<payload::Sample>,
+	streams: Option<Serializer<payload::Stream>>,
+	#[allow(dead_code)]
+	guards: Vec<supervisor::TaskGuard>,
 }
 
 impl InfluxDBSink {
 	pub fn new(
+		scope: &supervisor::Scope,
 		api_url: String,
 		auth: influxdb::Auth,
-		database: String,
-		retention_policy: Option<String>,
-		precision: influxdb::Precision,
-		filters: Vec<Box<dyn Filter>>,
+		settings: Settings,
+		capacity: usize,
 	) -> Self {
-		let (serializer, samples) = Serializer::new(128);
-		InfluxDBWorker::spawn(
-			influxdb::Client::new(api_url, auth),
-			samples,
-			database,
-			retention_policy,
+		let Settings {
+			target,
 			precision,
+			integer_counters,
 			filters,
-		);
+			merge,
+			streams,
+			batching,
+			gzip,
+			delivery,
+			buffer,
+			health_check,
+		} = settings;
+		let (serializer, samples) = Serializer::new(capacity);
+		let (stream_serializer, streams) = match streams {
+			Some(points) => {
+				let (serializer, blocks) = Serializer::new(capacity);
+				(
+					Some(serializer),
+					Some(StreamInput {
+						points,
+						blocks: supervisor::Resumable::new(blocks),
+					}),
+				)
+			}
+			None => (None, None),
+		};
+		let guards = InfluxDBWorker {
+			client: influxdb::Client::new(api_url, auth).with_gzip(gzip),
+			samples: supervisor::Resumable::new(samples),
+			streams,
+			target,
+			precision,
+			integer_counters,
+			filters,
+			merge,
+			batching,
+			delivery,
+			buffer: buffer.map(influxdb::SharedDiskBuffer::new),
+			draining: Mutex::new(()),
+			health_check,
+			health: Health::new(scope.clone()),
+		}
+		.spawn(scope);
 		Self {
 			samples: serializer,
+			streams: stream_serializer,
+			guards,
 		}
 	}
 }
 
 impl traits::Sink for InfluxDBSink {
-	fn attach_source<'x>(&self, src: &'x dyn traits::Source) {
-		self.samples.attach(src.subscribe_to_samples())
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+		if let Some(streams) = self.streams.as_ref() {
+			streams.attach(src.subscribe_to_streams(), link.clone());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::sync::Mutex as StdMutex;
+
+	use crate::testutil::StandIn;
+
+	fn batching() -> Batching {
+		Batching {
+			flush_interval: Duration::from_millis(0),
+			max_lines: 5000,
+			max_bytes: 1 << 20,
+			max_in_flight: 1,
+		}
+	}
+
+	fn worker_with(
+		url: &str,
+		max_retries: u32,
+		buffer: Option<influxdb::DiskBuffer>,
+		batching: Batching,
+	) -> (InfluxDBWorker, mpsc::Sender<payload::Sample>) {
+		let (tx, samples) = mpsc::channel(8);
+		let worker = InfluxDBWorker {
+			client: influxdb::Client::new(url.into(), influxdb::Auth::None),
+			samples: supervisor::Resumable::new(samples),
+			streams: None,
+			target: influxdb::Target::V1 {
+				database: "db".into(),
+				retention_policy: None,
+			},
+			precision: influxdb::Precision::Seconds,
+			integer_counters: false,
+			filters: Vec::new(),
+			merge: None,
+			batching,
+			delivery: Backoff {
+				max_retries,
+				initial_backoff: Duration::from_millis(1),
+				max_backoff: Duration::from_millis(10),
+			},
+			buffer: buffer.map(influxdb::SharedDiskBuffer::new),
+			draining: Mutex::new(()),
+			health_check: HealthCheck {
+				interval: Duration::from_secs(60),
+				create_database: None,
+			},
+			health: Health::new(
+				supervisor::Supervisor::new().scope("influxdb", supervisor::RestartPolicy::Never),
+			),
+		};
+		(worker, tx)
+	}
+
+	fn worker(url: &str, max_retries: u32, buffer: Option<influxdb::DiskBuffer>) -> InfluxDBWorker {
+		worker_with(url, max_retries, buffer, batching()).0
+	}
+
+	fn readout(device_type: &str, t: i64) -> Arc<crate::metric::Readout> {
+		crate::metric::ReadoutBuilder::new()
+			.device_type(device_type)
+			.instance("")
+			.at(t)
+			.component("v", t as f64, crate::metric::Unit::Arbitrary)
+			.arc()
+	}
+
+	#[test]
+	fn split_lines_splits_at_line_boundary() {
+		let body = Bytes::from_static(b"a 1\nbb 2\nc 3\n");
+		let (first, second) = split_lines(&body).unwrap();
+		assert_eq!(&first[..], b"a 1\nbb 2\n");
+		assert_eq!(&second[..], b"c 3\n");
+		assert!(split_lines(&Bytes::from_static(b"a 1\n")).is_none());
+	}
+
+	#[tokio::test]
+	async fn bad_line_is_isolated() {
+		let server = StandIn::start(Box::new(|req| {
+			if req.body_str().contains("bad") {
+				400
+			} else {
+				204
+			}
+		}))
+		.await;
+		let worker = worker(&server.url, 0, None);
+		let body = Bytes::from_static(b"m v=1 1\nm v=2 2\nm bad 3\nm v=4 4\nm v=5 5\n");
+		worker.deliver(influxdb::Precision::Seconds, body).await;
+		let accepted = server.accepted().concat();
+		assert_eq!(accepted, "m v=1 1\nm v=2 2\nm v=4 4\nm v=5 5\n");
+	}
+
+	#[tokio::test]
+	async fn retries_on_server_errors() {
+		let calls = Arc::new(StdMutex::new(0));
+		let calls_inner = calls.clone();
+		let server = StandIn::start(Box::new(move |_| {
+			let mut calls = calls_inner.lock().unwrap();
+			*calls += 1;
+			if *calls <= 2 {
+				503
+			} else {
+				204
+			}
+		}))
+		.await;
+		let worker = worker(&server.url, 3, None);
+		worker
+			.deliver(
+				influxdb::Precision::Seconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await;
+		assert_eq!(server.accepted(), vec!["m v=1 1\n".to_string()]);
+		assert_eq!(*calls.lock().unwrap(), 3);
+	}
+
+	#[tokio::test]
+	async fn buffers_on_disk_during_outage() {
+		let dir =
+			std::env::temp_dir().join(format!("metric-relay-influx-buffer-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		let server = StandIn::start(Box::new(|_| 503)).await;
+		let buffer = influxdb::DiskBuffer::open(dir.clone(), 1 << 20).unwrap();
+		let worker = worker(&server.url, 1, Some(buffer));
+		worker
+			.deliver(
+				influxdb::Precision::Seconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await;
+		worker
+			.deliver(
+				influxdb::Precision::Seconds,
+				Bytes::from_static(b"m v=2 2\n"),
+			)
+			.await;
+		assert_eq!(worker.buffer.as_ref().unwrap().len(), 2);
+		assert!(server.accepted().is_empty());
+
+		server.set_responder(Box::new(|_| 204));
+		worker.drain().await;
+		assert!(!worker.has_buffered());
+		assert_eq!(
+			server.accepted(),
+			vec!["m v=1 1\n".to_string(), "m v=2 2\n".to_string()]
+		);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn drain_survives_eviction_of_submitted_entry() {
+		let dir =
+			std::env::temp_dir().join(format!("metric-relay-influx-evict-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		let server = StandIn::start(Box::new(|_| 503)).await;
+		// room for two bodies
+		let buffer = influxdb::DiskBuffer::open(dir.clone(), 16).unwrap();
+		let worker = worker(&server.url, 0, Some(buffer));
+		for body in [&b"m v=1 1\n"[..], &b"m v=2 2\n"[..]] {
+			worker
+				.deliver(influxdb::Precision::Seconds, Bytes::from_static(body))
+				.await;
+		}
+		assert_eq!(worker.buffer.as_ref().unwrap().len(), 2);
+
+		let buffer = worker.buffer.clone().unwrap();
+		let pushed = std::sync::atomic::AtomicBool::new(false);
+		server.set_responder(Box::new(move |_| {
+			if !pushed.swap(true, std::sync::atomic::Ordering::SeqCst) {
+				// evicts the entry which is being submitted
+				tokio::task::block_in_place(|| {
+					tokio::runtime::Handle::current()
+						.block_on(buffer.push(
+							influxdb::Precision::Seconds,
+							Bytes::from_static(b"m v=3 3\n"),
+						))
+						.unwrap()
+				});
+			}
+			204
+		}));
+		worker.drain().await;
+		assert!(!worker.has_buffered());
+		assert_eq!(
+			server.accepted(),
+			vec![
+				"m v=1 1\n".to_string(),
+				"m v=2 2\n".to_string(),
+				"m v=3 3\n".to_string()
+			]
+		);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn drops_batch_on_permanent_error() {
+		let server = StandIn::start(Box::new(|_| 403)).await;
+		let dir = std::env::temp_dir().join(format!(
+			"metric-relay-influx-forbidden-{}",
+			std::process::id()
+		));
+		let _ = std::fs::remove_dir_all(&dir);
+		let buffer = influxdb::DiskBuffer::open(dir.clone(), 1 << 20).unwrap();
+		let worker = worker(&server.url, 3, Some(buffer));
+		worker
+			.deliver(
+				influxdb::Precision::Seconds,
+				Bytes::from_static(b"m v=1 1\n"),
+			)
+			.await;
+		assert!(!worker.has_buffered());
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[tokio::test]
+	async fn aborting_run_stops_lanes() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, _tx) = worker_with(
+			&server.url,
+			0,
+			None,
+			Batching {
+				max_in_flight: 4,
+				..batching()
+			},
+		);
+		let worker = Arc::new(worker);
+		let run = tokio::spawn(worker.clone().run());
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		// one for the run and one for each lane
+		assert_eq!(Arc::strong_count(&worker), 6);
+		run.abort();
+		let _ = run.await;
+		for _ in 0..100 {
+			if Arc::strong_count(&worker) == 1 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		assert_eq!(Arc::strong_count(&worker), 1);
+	}
+
+	#[tokio::test]
+	async fn batches_are_limited_in_lines() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, tx) = worker_with(
+			&server.url,
+			0,
+			None,
+			Batching {
+				max_lines: 2,
+				..batching()
+			},
+		);
+		let run = tokio::spawn(Arc::new(worker).run());
+		tx.send((1..=5).map(|t| readout("m", t)).collect())
+			.await
+			.unwrap();
+		drop(tx);
+		run.await.unwrap();
+		assert_eq!(
+			server.accepted(),
+			vec![
+				"m v=1.0 1\nm v=2.0 2\n".to_string(),
+				"m v=3.0 3\nm v=4.0 4\n".to_string(),
+				"m v=5.0 5\n".to_string(),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn lines_are_collected_during_flush_interval() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, tx) = worker_with(
+			&server.url,
+			0,
+			None,
+			Batching {
+				flush_interval: Duration::from_millis(100),
+				..batching()
+			},
+		);
+		let run = tokio::spawn(Arc::new(worker).run());
+		for t in 1..=3 {
+			tx.send(vec![readout("m", t)]).await.unwrap();
+			tokio::time::sleep(Duration::from_millis(5)).await;
+		}
+		assert!(server.accepted().is_empty());
+		tokio::time::sleep(Duration::from_millis(300)).await;
+		assert_eq!(
+			server.accepted(),
+			vec!["m v=1.0 1\nm v=2.0 2\nm v=3.0 3\n".to_string()]
+		);
+		drop(tx);
+		run.await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn measurements_stay_in_order_with_parallel_requests() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, tx) = worker_with(
+			&server.url,
+			0,
+			None,
+			Batching {
+				max_lines: 1,
+				max_in_flight: 4,
+				..batching()
+			},
+		);
+		let run = tokio::spawn(Arc::new(worker).run());
+		for t in 1..=20 {
+			tx.send(vec![readout("a", t), readout("b", t), readout("c", t)])
+				.await
+				.unwrap();
+		}
+		drop(tx);
+		run.await.unwrap();
+		let accepted = server.accepted();
+		assert_eq!(accepted.len(), 60);
+		for measurement in ["a", "b", "c"] {
+			let timestamps: Vec<i64> = accepted
+				.iter()
+				.filter(|x| x.starts_with(measurement))
+				.map(|x| x.trim_end().rsplit(' ').next().unwrap().parse().unwrap())
+				.collect();
+			assert_eq!(timestamps, (1..=20).collect::<Vec<_>>());
+		}
+	}
+
+	#[tokio::test]
+	async fn stream_blocks_are_written_as_points() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (mut worker, tx) = worker_with(&server.url, 0, None, batching());
+		let (block_tx, blocks) = mpsc::channel(1);
+		worker.streams = Some(StreamInput {
+			points: StreamPoints {
+				component: samplify::ComponentMode::PopFromPath,
+				decimation: 2,
+			},
+			blocks: supervisor::Resumable::new(blocks),
+		});
+		let run = tokio::spawn(Arc::new(worker).run());
+		let mut data = crate::metric::MaskedArray::from_unmasked_vec(vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+		data.mask(2..3);
+		block_tx
+			.send(Arc::new(crate::metric::StreamBlock {
+				t0: chrono::TimeZone::timestamp_opt(&chrono::Utc, 10, 0).unwrap(),
+				path: crate::metric::DevicePath {
+					device_type: "acc".into(),
+					instance: "/sbx/acc/x".into(),
+				},
+				seq0: 0,
+				period: Duration::from_secs(1),
+				scale: crate::metric::Value {
+					magnitude: 2.0,
+					unit: crate::metric::Unit::Arbitrary,
+				},
+				data: Arc::new(crate::metric::RawData::F64(data)),
+			}))
+			.await
+			.unwrap();
+		drop(block_tx);
+		drop(tx);
+		run.await.unwrap();
+		assert_eq!(
+			server.accepted(),
+			vec!["acc,instance=/sbx/acc x=2.0 10\nacc,instance=/sbx/acc x=10.0 14\n".to_string()]
+		);
+	}
+
+	#[tokio::test]
+	async fn merges_readouts_of_same_sample() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (mut worker, tx) = worker_with(&server.url, 0, None, batching());
+		worker.merge = Some(influxdb::Conflict::Last);
+		let run = tokio::spawn(Arc::new(worker).run());
+		let mut second = (*readout("m", 1)).clone();
+		second.components = crate::metric::OrderedVec::single(
+			"w".into(),
+			crate::metric::Value {
+				magnitude: 2.0,
+				unit: crate::metric::Unit::Arbitrary,
+			},
+		);
+		tx.send(vec![readout("m", 1), Arc::new(second), readout("m", 2)])
+			.await
+			.unwrap();
+		drop(tx);
+		run.await.unwrap();
+		assert_eq!(
+			server.accepted(),
+			vec!["m v=1.0,w=2.0 1\nm v=2.0 2\n".to_string()]
+		);
+	}
+
+	#[tokio::test]
+	async fn missing_database_is_reported_and_created() {
+		use std::sync::atomic::{AtomicBool, Ordering};
+
+		let exists = Arc::new(AtomicBool::new(false));
+		let server_exists = exists.clone();
+		let server = StandIn::start(Box::new(move |req| {
+			if req.target().starts_with("/query") {
+				server_exists.store(true, Ordering::SeqCst);
+				200
+			} else if req.target().starts_with("/write") && !server_exists.load(Ordering::SeqCst) {
+				404
+			} else {
+				204
+			}
+		}))
+		.await;
+		let (mut worker, _tx) = worker_with(&server.url, 0, None, batching());
+		let supervisor = supervisor::Supervisor::new();
+		worker.health = Health::new(supervisor.scope("influxdb", supervisor::RestartPolicy::Never));
+		worker.health_check.create_database = Some(None);
+		let worker = Arc::new(worker);
+		let body = Bytes::from_static(b"m v=1 1\n");
+
+		worker
+			.deliver(influxdb::Precision::Seconds, body.clone())
+			.await;
+		assert_eq!(
+			supervisor.status()[0].state,
+			supervisor::NodeState::Degraded("misconfigured: database or bucket not found".into())
+		);
+
+		let check = tokio::spawn({
+			let worker = worker.clone();
+			async move { worker.check_health().await }
+		});
+		for _ in 0..100 {
+			if supervisor.status()[0].state == supervisor::NodeState::Running {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		check.abort();
+		assert!(exists.load(Ordering::SeqCst));
+		assert_eq!(supervisor.status()[0].state, supervisor::NodeState::Running);
+
+		worker.deliver(influxdb::Precision::Seconds, body).await;
+		assert_eq!(
+			server.accepted().last().map(String::as_str),
+			Some("m v=1 1\n")
+		);
 	}
 }
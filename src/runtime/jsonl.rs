@@ -0,0 +1,414 @@
+//! Readouts and stream blocks as JSON Lines, one object per line.
+//!
+//! Each object carries a `type` of `readout` or `stream_block` besides the
+//! fields of [`crate::metric::Readout`] or [`crate::metric::StreamBlock`],
+//! e.g.:
+//!
+//! ```text
+//! {"type":"readout","timestamp":"2020-09-13T12:26:40Z","path":{"device_type":"bme280","instance":"/sbx/i2c/76"},"components":[["temperature",{"magnitude":21.5,"unit":"Celsius"}]]}
+//! ```
+//!
+//! JSON has no representation for non-finite numbers: they are written as
+//! `null`, and lines containing such values cannot be read back.
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use serde_derive::{Deserialize, Serialize};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+/// Time to wait before reading the first line, so that the sinks can be
+/// attached.
+const STARTUP_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+	Readout(payload::Readout),
+	StreamBlock(payload::Stream),
+}
+
+impl Record {
+	fn timestamp(&self) -> chrono::DateTime<chrono::Utc> {
+		match self {
+			Self::Readout(readout) => readout.timestamp,
+			Self::StreamBlock(block) => block.t0,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Output {
+	Stdout,
+	/// A file, which is appended to.
+	File(PathBuf),
+}
+
+impl Output {
+	async fn open(&self) -> io::Result<Box<dyn AsyncWrite + Unpin + Send>> {
+		match self {
+			Self::Stdout => Ok(Box::new(tokio::io::stdout())),
+			Self::File(path) => Ok(Box::new(
+				tokio::fs::OpenOptions::new()
+					.create(true)
+					.append(true)
+					.open(path)
+					.await?,
+			)),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum Input {
+	Stdin,
+	/// A file or named pipe.
+	File(PathBuf),
+}
+
+impl Input {
+	async fn open(&self) -> io::Result<Box<dyn AsyncRead + Unpin + Send>> {
+		match self {
+			Self::Stdin => Ok(Box::new(tokio::io::stdin())),
+			Self::File(path) => Ok(Box::new(tokio::fs::File::open(path).await?)),
+		}
+	}
+}
+
+struct JsonLinesWorker {
+	output: Output,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	stream: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
+}
+
+impl JsonLinesWorker {
+	async fn write<W: AsyncWrite + Unpin>(out: &mut W, record: &Record) -> io::Result<()> {
+		let mut line = serde_json::to_vec(record)?;
+		line.push(b'\n');
+		out.write_all(&line).await
+	}
+
+	async fn run(&self) -> supervisor::TaskResult {
+		let mut samples = self.samples.resume().await;
+		let mut stream = self.stream.resume().await;
+		let mut out = tokio::io::BufWriter::new(self.output.open().await?);
+		let mut samples_open = true;
+		let mut stream_open = true;
+		while samples_open || stream_open {
+			tokio::select! {
+				v = samples.recv(), if samples_open => match v {
+					Some(sample) => for readout in sample {
+						Self::write(&mut out, &Record::Readout(readout)).await?;
+					},
+					None => samples_open = false,
+				},
+				v = stream.recv(), if stream_open => match v {
+					Some(block) => Self::write(&mut out, &Record::StreamBlock(block)).await?,
+					None => stream_open = false,
+				},
+			}
+			// lines should show up right away when piping into other tools
+			out.flush().await?;
+		}
+		debug!("sources closed, exiting");
+		Ok(())
+	}
+}
+
+/// Writes readouts and stream blocks as JSON Lines.
+pub struct JsonLinesSink {
+	samples: Serializer<payload::Sample>,
+	stream: Serializer<payload::Stream>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl JsonLinesSink {
+	pub fn new(scope: &supervisor::Scope, output: Output, capacity: usize) -> Self {
+		let (samples, samples_src) = Serializer::new(capacity);
+		let (stream, stream_src) = Serializer::new(capacity);
+		let worker = Arc::new(JsonLinesWorker {
+			output,
+			samples: supervisor::Resumable::new(samples_src),
+			stream: supervisor::Resumable::new(stream_src),
+		});
+		let guard = scope.spawn("jsonl", move || {
+			let worker = worker.clone();
+			async move { worker.run().await }
+		});
+		Self {
+			samples,
+			stream,
+			guard,
+		}
+	}
+}
+
+impl traits::Sink for JsonLinesSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+		self.stream.attach(src.subscribe_to_streams(), link.clone());
+	}
+}
+
+struct ReaderWorker {
+	input: Input,
+	/// Replay at this multiple of the pace given by the timestamps.
+	speed: Option<f64>,
+	capacity: usize,
+	samples: broadcast::Sender<payload::Sample>,
+	stream: broadcast::Sender<payload::Stream>,
+}
+
+impl ReaderWorker {
+	/// Without pacing, wait for the receivers to catch up instead of
+	/// overrunning them.
+	async fn wait_for_room(&self) {
+		while self.samples.len().max(self.stream.len()) * 2 >= self.capacity {
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+	}
+
+	async fn run(&self, delay: Duration) {
+		tokio::time::sleep(delay).await;
+		let input = match self.input.open().await {
+			Ok(v) => v,
+			Err(e) => {
+				warn!("failed to open JSON Lines input {:?}: {}", self.input, e);
+				return;
+			}
+		};
+		let mut lines = tokio::io::BufReader::new(input).lines();
+		let mut origin = None;
+		let mut lineno = 0;
+		loop {
+			let line = match lines.next_line().await {
+				Ok(Some(v)) => v,
+				Ok(None) => break,
+				Err(e) => {
+					warn!("failed to read JSON Lines input: {}", e);
+					break;
+				}
+			};
+			lineno += 1;
+			if line.trim().is_empty() {
+				continue;
+			}
+			let record: Record = match serde_json::from_str(&line) {
+				Ok(v) => v,
+				Err(e) => {
+					warn!(
+						"invalid record in line {} of JSON Lines input: {}",
+						lineno, e
+					);
+					continue;
+				}
+			};
+			match self.speed {
+				Some(speed) => {
+					let (t0, start) =
+						*origin.get_or_insert_with(|| (record.timestamp(), Instant::now()));
+					if let Ok(offset) = (record.timestamp() - t0).to_std() {
+						tokio::time::sleep_until(start + offset.div_f64(speed)).await;
+					}
+				}
+				None => self.wait_for_room().await,
+			}
+			match record {
+				Record::Readout(readout) => {
+					let _ = self.samples.send(vec![readout]);
+				}
+				Record::StreamBlock(block) => {
+					let _ = self.stream.send(block);
+				}
+			}
+		}
+		info!("end of JSON Lines input");
+	}
+}
+
+/// Reads readouts and stream blocks from JSON Lines.
+pub struct JsonLinesSource {
+	samples: broadcast::Sender<payload::Sample>,
+	stream: broadcast::Sender<payload::Stream>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl JsonLinesSource {
+	pub fn new(
+		scope: &supervisor::Scope,
+		input: Input,
+		speed: Option<f64>,
+		capacity: usize,
+	) -> Self {
+		Self::with_delay(scope, input, speed, STARTUP_DELAY, capacity)
+	}
+
+	fn with_delay(
+		scope: &supervisor::Scope,
+		input: Input,
+		speed: Option<f64>,
+		delay: Duration,
+		capacity: usize,
+	) -> Self {
+		let (samples, _) = broadcast::channel(capacity);
+		let (stream, _) = broadcast::channel(capacity);
+		let worker = Arc::new(ReaderWorker {
+			input,
+			speed,
+			capacity,
+			samples: samples.clone(),
+			stream: stream.clone(),
+		});
+		let guard = scope.spawn("jsonl", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run(delay).await;
+				Ok(())
+			}
+		});
+		Self {
+			samples,
+			stream,
+			guard,
+		}
+	}
+}
+
+impl traits::Source for JsonLinesSource {
+	fn subscribe_to_samples(&self) -> broadcast::Receiver<payload::Sample> {
+		self.samples.subscribe()
+	}
+
+	fn subscribe_to_streams(&self) -> broadcast::Receiver<payload::Stream> {
+		self.stream.subscribe()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use chrono::{TimeZone, Utc};
+
+	use crate::metric;
+
+	use traits::Source;
+
+	fn readout(t: i64, value: f64) -> payload::Readout {
+		metric::ReadoutBuilder::new().at(t).temperature(value).arc()
+	}
+
+	fn block(t: i64) -> payload::Stream {
+		let mut data = metric::MaskedArray::masked_with_value(3, 0i16);
+		data.write_from(0, [1i16, 2].iter().cloned());
+		Arc::new(metric::StreamBlock {
+			t0: Utc.timestamp_opt(t, 0).unwrap(),
+			path: metric::DevicePath {
+				device_type: "lsm303d".into(),
+				instance: "/sbx/i2c/1e".into(),
+			},
+			seq0: 7,
+			period: Duration::from_millis(10),
+			scale: metric::Value {
+				magnitude: 1.0,
+				unit: metric::Unit::MeterPerSqSecond,
+			},
+			data: Arc::new(metric::RawData::I16(data)),
+		})
+	}
+
+	fn path(name: &str) -> PathBuf {
+		std::env::temp_dir().join(format!("sbx-jsonl-{}-{}.jsonl", name, std::process::id()))
+	}
+
+	#[tokio::test]
+	async fn writes_records_as_lines() {
+		let path = path("sink");
+		let _ = std::fs::remove_file(&path);
+		let (samples_tx, samples) = mpsc::channel(8);
+		let (stream_tx, stream) = mpsc::channel(8);
+		let worker = JsonLinesWorker {
+			output: Output::File(path.clone()),
+			samples: supervisor::Resumable::new(samples),
+			stream: supervisor::Resumable::new(stream),
+		};
+		samples_tx
+			.send(vec![readout(1600000000, 21.5), readout(1600000001, 22.0)])
+			.await
+			.unwrap();
+		stream_tx.send(block(1600000002)).await.unwrap();
+		drop(samples_tx);
+		drop(stream_tx);
+		worker.run().await.unwrap();
+
+		let content = std::fs::read_to_string(&path).unwrap();
+		let lines: Vec<serde_json::Value> = content
+			.lines()
+			.map(|line| serde_json::from_str(line).unwrap())
+			.collect();
+		assert_eq!(lines.len(), 3);
+		// samples and blocks are written in the order they arrive
+		let (readouts, blocks): (Vec<_>, Vec<_>) =
+			lines.iter().partition(|line| line["type"] == "readout");
+		assert_eq!(readouts.len(), 2);
+		assert_eq!(readouts[0]["path"]["device_type"], "bme280");
+		assert_eq!(readouts[1]["components"][0][1]["magnitude"], 22.0);
+		assert_eq!(blocks[0]["type"], "stream_block");
+		assert_eq!(blocks[0]["data"]["I16"], serde_json::json!([1, 2, null]));
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[tokio::test]
+	async fn reads_records_with_pacing() {
+		let path = path("source");
+		let mut content = String::new();
+		for record in [
+			Record::Readout(readout(1600000000, 21.5)),
+			Record::StreamBlock(block(1600000000)),
+			Record::Readout(readout(1600000010, 22.0)),
+		] {
+			content.push_str(&serde_json::to_string(&record).unwrap());
+			content.push('\n');
+		}
+		content.push_str("not json\n\n");
+		std::fs::write(&path, content).unwrap();
+
+		// ten seconds of data in 100ms
+		let supervisor = supervisor::Supervisor::new();
+		let scope = supervisor.scope("source", supervisor::RestartPolicy::Never);
+		let source = JsonLinesSource::with_delay(
+			&scope,
+			Input::File(path.clone()),
+			Some(100.0),
+			Duration::from_millis(10),
+			8,
+		);
+		let mut samples = source.subscribe_to_samples();
+		let mut stream = source.subscribe_to_streams();
+		let start = Instant::now();
+		let first = samples.recv().await.unwrap();
+		assert_eq!(first, vec![readout(1600000000, 21.5)]);
+		let received = stream.recv().await.unwrap();
+		assert_eq!(received.seq0, 7);
+		assert_eq!(received.data, block(1600000000).data);
+		let second = samples.recv().await.unwrap();
+		assert_eq!(second, vec![readout(1600000010, 22.0)]);
+		assert!(start.elapsed() >= Duration::from_millis(90));
+		std::fs::remove_file(path).unwrap();
+	}
+}
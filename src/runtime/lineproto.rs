@@ -0,0 +1,784 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use smartstring::alias::String as SmartString;
+
+use chrono::{DateTime, Utc};
+
+use tokio::io::AsyncReadExt;
+use tokio::sync::broadcast;
+use tokio::task::JoinSet;
+
+use crate::lineproto;
+use crate::metric;
+
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+/// How parsed lines are turned into readouts.
+///
+/// The measurement becomes the device type and the value of `instance_tag`
+/// the instance; other tags are not represented. Each numeric field becomes
+/// a component, with the unit looked up by `measurement.field` or `field`.
+pub struct Mapping {
+	pub instance_tag: SmartString,
+	pub units: HashMap<SmartString, metric::Unit>,
+}
+
+impl Mapping {
+	fn unit(&self, measurement: &str, field: &str) -> metric::Unit {
+		let mut qualified = SmartString::from(measurement);
+		qualified.push('.');
+		qualified.push_str(field);
+		self.units
+			.get(&qualified)
+			.or_else(|| self.units.get(field))
+			.cloned()
+			.unwrap_or(metric::Unit::Arbitrary)
+	}
+
+	/// Convert a line, using `now` if it has no timestamp.
+	///
+	/// Returns `None` if the line has no numeric fields or its timestamp is
+	/// out of range.
+	pub fn readout(
+		&self,
+		line: &lineproto::Line,
+		precision: lineproto::Precision,
+		now: DateTime<Utc>,
+	) -> Option<metric::Readout> {
+		let timestamp = match line.timestamp {
+			Some(ts) => precision.to_datetime(ts)?,
+			None => now,
+		};
+		let mut components = metric::OrderedVec::new();
+		for (field, value) in line.fields.iter() {
+			let magnitude = match value.as_f64() {
+				Some(v) => v,
+				None => continue,
+			};
+			components.insert(
+				field.clone(),
+				metric::Value {
+					magnitude,
+					unit: self.unit(&line.measurement, field),
+				},
+			);
+		}
+		if components.is_empty() {
+			return None;
+		}
+		Some(metric::Readout {
+			timestamp,
+			path: metric::DevicePath {
+				device_type: line.measurement.clone(),
+				instance: line.tag(&self.instance_tag).unwrap_or("").into(),
+			},
+			components,
+		})
+	}
+
+	/// Parse a request body into readouts.
+	///
+	/// Valid lines are converted even if others fail to parse; the first
+	/// error is returned alongside.
+	#[cfg(feature = "lineproto-http")]
+	pub fn convert(
+		&self,
+		body: &str,
+		precision: lineproto::Precision,
+	) -> (payload::Sample, Option<String>) {
+		let now = Utc::now();
+		let (lines, errors) = lineproto::parse(body);
+		let mut readouts = Vec::with_capacity(lines.len());
+		for line in lines.iter() {
+			match self.readout(line, precision, now) {
+				Some(v) => readouts.push(Arc::new(v)),
+				None => debug!(
+					"ignoring line protocol point of {:?} without numeric fields or with invalid timestamp",
+					line.measurement
+				),
+			}
+		}
+		let error = errors
+			.first()
+			.map(|(lineno, e)| format!("unable to parse line {}: {}", lineno, e));
+		(readouts, error)
+	}
+}
+
+#[cfg(feature = "lineproto-http")]
+mod http {
+	use std::convert::Infallible;
+	use std::io::Read;
+
+	use hyper::body::HttpBody;
+	use hyper::service::{make_service_fn, service_fn};
+	use hyper::{Body, Method, Request, Response, StatusCode};
+
+	use percent_encoding::percent_decode_str;
+
+	use super::*;
+
+	pub(super) struct Shared {
+		pub(super) mapping: Mapping,
+		pub(super) sink: broadcast::Sender<payload::Sample>,
+		pub(super) max_body_size: usize,
+	}
+
+	fn respond(status: StatusCode, message: Option<&str>) -> Response<Body> {
+		let body = match message {
+			// error format understood by both 1.x and 2.x clients
+			Some(message) => Body::from(
+				serde_json::json!({
+					"code": "invalid",
+					"error": message,
+					"message": message,
+				})
+				.to_string(),
+			),
+			None => Body::empty(),
+		};
+		let mut response = Response::new(body);
+		*response.status_mut() = status;
+		if message.is_some() {
+			response.headers_mut().insert(
+				hyper::header::CONTENT_TYPE,
+				hyper::header::HeaderValue::from_static("application/json"),
+			);
+		}
+		response
+	}
+
+	fn precision(req: &Request<Body>) -> Option<lineproto::Precision> {
+		let query = req.uri().query().unwrap_or("");
+		for pair in query.split('&') {
+			if let Some(value) = pair.strip_prefix("precision=") {
+				let value = percent_decode_str(value).decode_utf8().ok()?;
+				return lineproto::Precision::from_param(&value);
+			}
+		}
+		Some(lineproto::Precision::default())
+	}
+
+	async fn read_body(req: &mut Request<Body>, limit: usize) -> Result<Vec<u8>, Response<Body>> {
+		let mut result = Vec::new();
+		while let Some(chunk) = req.body_mut().data().await {
+			let chunk =
+				chunk.map_err(|e| respond(StatusCode::BAD_REQUEST, Some(&e.to_string())))?;
+			if result.len() + chunk.len() > limit {
+				return Err(respond(
+					StatusCode::PAYLOAD_TOO_LARGE,
+					Some("request body too large"),
+				));
+			}
+			result.extend_from_slice(&chunk);
+		}
+
+		let gzip = match req.headers().get(hyper::header::CONTENT_ENCODING) {
+			None => false,
+			Some(v) if v == "identity" => false,
+			Some(v) if v == "gzip" => true,
+			Some(_) => {
+				return Err(respond(
+					StatusCode::UNSUPPORTED_MEDIA_TYPE,
+					Some("unsupported content encoding"),
+				))
+			}
+		};
+		if !gzip {
+			return Ok(result);
+		}
+		let mut decoded = Vec::new();
+		flate2::read::GzDecoder::new(&result[..])
+			.take(limit as u64 + 1)
+			.read_to_end(&mut decoded)
+			.map_err(|e| respond(StatusCode::BAD_REQUEST, Some(&e.to_string())))?;
+		if decoded.len() > limit {
+			return Err(respond(
+				StatusCode::PAYLOAD_TOO_LARGE,
+				Some("request body too large"),
+			));
+		}
+		Ok(decoded)
+	}
+
+	async fn write(shared: &Shared, mut req: Request<Body>) -> Response<Body> {
+		if req.method() != Method::POST {
+			return respond(StatusCode::METHOD_NOT_ALLOWED, None);
+		}
+		let precision = match precision(&req) {
+			Some(v) => v,
+			None => return respond(StatusCode::BAD_REQUEST, Some("invalid precision")),
+		};
+		let body = match read_body(&mut req, shared.max_body_size).await {
+			Ok(v) => v,
+			Err(response) => return response,
+		};
+		let body = match std::str::from_utf8(&body) {
+			Ok(v) => v,
+			Err(_) => return respond(StatusCode::BAD_REQUEST, Some("body is not valid utf-8")),
+		};
+
+		let (readouts, error) = shared.mapping.convert(body, precision);
+		if !readouts.is_empty() {
+			// no subscribers is not the client's problem
+			let _ = shared.sink.send(readouts);
+		}
+		match error {
+			Some(e) => {
+				warn!("rejected line protocol write: {}", e);
+				respond(
+					StatusCode::BAD_REQUEST,
+					Some(&format!("partial write: {}", e)),
+				)
+			}
+			None => respond(StatusCode::NO_CONTENT, None),
+		}
+	}
+
+	async fn handle(shared: Arc<Shared>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+		Ok(match req.uri().path() {
+			"/write" | "/api/v2/write" => write(&shared, req).await,
+			"/ping" | "/health" => respond(StatusCode::NO_CONTENT, None),
+			_ => respond(StatusCode::NOT_FOUND, None),
+		})
+	}
+
+	pub(super) async fn serve(
+		listener: std::net::TcpListener,
+		shared: Arc<Shared>,
+	) -> supervisor::TaskResult {
+		let make_service = make_service_fn(move |_| {
+			let shared = shared.clone();
+			async move { Ok::<_, Infallible>(service_fn(move |req| handle(shared.clone(), req))) }
+		});
+		hyper::Server::from_tcp(listener)?
+			.serve(make_service)
+			.await?;
+		Ok(())
+	}
+}
+
+/// Source accepting line protocol writes via the InfluxDB HTTP API.
+#[cfg(feature = "lineproto-http")]
+pub struct LineProtocolHTTPSource {
+	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+#[cfg(feature = "lineproto-http")]
+impl LineProtocolHTTPSource {
+	pub fn new(
+		scope: &supervisor::Scope,
+		listener: std::net::TcpListener,
+		mapping: Mapping,
+		max_body_size: usize,
+		capacity: usize,
+	) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
+		let shared = Arc::new(http::Shared {
+			mapping,
+			sink: zygote.clone(),
+			max_body_size,
+		});
+		let guard = scope.spawn("http", move || {
+			let shared = shared.clone();
+			let listener = listener.try_clone();
+			async move { http::serve(listener?, shared).await }
+		});
+		Self { zygote, guard }
+	}
+}
+
+#[cfg(feature = "lineproto-http")]
+impl traits::Source for LineProtocolHTTPSource {
+	fn subscribe_to_samples(&self) -> broadcast::Receiver<payload::Sample> {
+		self.zygote.subscribe()
+	}
+
+	fn subscribe_to_streams(&self) -> broadcast::Receiver<payload::Stream> {
+		traits::null_receiver()
+	}
+}
+
+/// Counters of the lines received by a socket source.
+#[derive(Debug, Default)]
+pub struct IngestCounters {
+	pub lines: AtomicU64,
+	pub errors: AtomicU64,
+	/// Lines which were discarded because they were too long or not valid
+	/// UTF-8.
+	pub discarded: AtomicU64,
+	last_error: Mutex<Option<String>>,
+}
+
+/// Limits on the TCP connections of a socket source.
+#[derive(Debug, Clone, Copy)]
+pub struct TcpLimits {
+	/// No further connections are accepted while this many are open.
+	pub max_connections: usize,
+	/// Connections which send nothing for this long are closed.
+	pub read_timeout: Duration,
+}
+
+struct Ingest {
+	mapping: Mapping,
+	precision: lineproto::Precision,
+	sink: broadcast::Sender<payload::Sample>,
+	counters: Arc<IngestCounters>,
+	max_line_length: usize,
+}
+
+impl Ingest {
+	/// Parse complete lines and send the resulting readouts.
+	fn feed(&self, data: &[u8]) {
+		let now = Utc::now();
+		let mut readouts = Vec::new();
+		for line in data.split(|&b| b == b'\n') {
+			let line = match std::str::from_utf8(line) {
+				Ok(v) if v.len() <= self.max_line_length => v,
+				_ => {
+					self.counters.discarded.fetch_add(1, Ordering::Relaxed);
+					continue;
+				}
+			};
+			match lineproto::parse_line(line) {
+				Ok(Some(line)) => {
+					self.counters.lines.fetch_add(1, Ordering::Relaxed);
+					if let Some(readout) = self.mapping.readout(&line, self.precision, now) {
+						readouts.push(Arc::new(readout));
+					}
+				}
+				Ok(None) => (),
+				Err(e) => {
+					self.counters.errors.fetch_add(1, Ordering::Relaxed);
+					*self.counters.last_error.lock().unwrap() = Some(format!("{}: {:?}", e, line));
+				}
+			}
+		}
+		if !readouts.is_empty() {
+			let _ = self.sink.send(readouts);
+		}
+	}
+
+	async fn serve_udp(self: Arc<Self>, socket: std::net::UdpSocket) -> supervisor::TaskResult {
+		let socket = tokio::net::UdpSocket::from_std(socket)?;
+		let mut buf = vec![0u8; 65536];
+		loop {
+			let (n, _) = socket.recv_from(&mut buf[..]).await?;
+			// each datagram is self-contained; a missing trailing newline is
+			// fine
+			self.feed(&buf[..n]);
+		}
+	}
+
+	async fn serve_tcp(
+		self: Arc<Self>,
+		listener: std::net::TcpListener,
+		limits: TcpLimits,
+	) -> supervisor::TaskResult {
+		let listener = tokio::net::TcpListener::from_std(listener)?;
+		// the connections are aborted along with this task
+		let mut connections = JoinSet::new();
+		loop {
+			tokio::select! {
+				Some(_) = connections.join_next(), if !connections.is_empty() => (),
+				accepted = listener.accept(), if connections.len() < limits.max_connections => {
+					let (conn, peer) = accepted?;
+					debug!("line protocol connection from {}", peer);
+					let ingest = self.clone();
+					connections.spawn(async move {
+						if let Err(e) = ingest.read_lines(conn, limits.read_timeout).await {
+							debug!("line protocol connection from {} failed: {}", peer, e);
+						}
+					});
+				}
+			}
+		}
+	}
+
+	/// Read newline-delimited lines from a stream until it is closed or
+	/// stays silent for `read_timeout`.
+	async fn read_lines<R: tokio::io::AsyncRead + Unpin>(
+		&self,
+		mut conn: R,
+		read_timeout: Duration,
+	) -> std::io::Result<()> {
+		let mut buf = Vec::new();
+		// set while skipping the rest of an overly long line
+		let mut discarding = false;
+		let mut chunk = [0u8; 8192];
+		loop {
+			let n = tokio::time::timeout(read_timeout, conn.read(&mut chunk[..]))
+				.await
+				.map_err(|_| {
+					std::io::Error::new(std::io::ErrorKind::TimedOut, "read timed out")
+				})??;
+			if n == 0 {
+				if !discarding && !buf.is_empty() {
+					self.feed(&buf);
+				}
+				return Ok(());
+			}
+			let mut data = &chunk[..n];
+			if discarding {
+				match data.iter().position(|&b| b == b'\n') {
+					Some(at) => {
+						discarding = false;
+						data = &data[at + 1..];
+					}
+					None => continue,
+				}
+			}
+			buf.extend_from_slice(data);
+			if let Some(at) = buf.iter().rposition(|&b| b == b'\n') {
+				self.feed(&buf[..at + 1]);
+				buf.drain(..at + 1);
+			}
+			if buf.len() > self.max_line_length {
+				self.counters.discarded.fetch_add(1, Ordering::Relaxed);
+				buf.clear();
+				discarding = true;
+			}
+		}
+	}
+}
+
+/// Source accepting line protocol as UDP datagrams and/or newline-delimited
+/// over TCP connections.
+pub struct LineProtocolSocketSource {
+	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guards: Vec<supervisor::TaskGuard>,
+}
+
+/// Log parse errors periodically instead of once per line.
+async fn report(counters: Arc<IngestCounters>, interval: Duration) -> supervisor::TaskResult {
+	let mut reported_errors = counters.errors.load(Ordering::Relaxed);
+	let mut reported_discarded = counters.discarded.load(Ordering::Relaxed);
+	loop {
+		tokio::time::sleep(interval).await;
+		let errors = counters.errors.load(Ordering::Relaxed);
+		let discarded = counters.discarded.load(Ordering::Relaxed);
+		if errors != reported_errors || discarded != reported_discarded {
+			warn!(
+				"line protocol: {} unparseable and {} discarded lines since last report (last error: {})",
+				errors - reported_errors,
+				discarded - reported_discarded,
+				counters
+					.last_error
+					.lock()
+					.unwrap()
+					.as_deref()
+					.unwrap_or("none"),
+			);
+			reported_errors = errors;
+			reported_discarded = discarded;
+		}
+	}
+}
+
+impl LineProtocolSocketSource {
+	pub fn new(
+		scope: &supervisor::Scope,
+		udp: Option<std::net::UdpSocket>,
+		tcp: Option<(std::net::TcpListener, TcpLimits)>,
+		mapping: Mapping,
+		precision: lineproto::Precision,
+		max_line_length: usize,
+		capacity: usize,
+	) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
+		let counters = Arc::new(IngestCounters::default());
+		let ingest = Arc::new(Ingest {
+			mapping,
+			precision,
+			sink: zygote.clone(),
+			counters: counters.clone(),
+			max_line_length,
+		});
+		let mut guards = Vec::new();
+		if let Some(socket) = udp {
+			let ingest = ingest.clone();
+			guards.push(scope.spawn("udp", move || {
+				let ingest = ingest.clone();
+				let socket = socket.try_clone();
+				async move { ingest.serve_udp(socket?).await }
+			}));
+		}
+		if let Some((listener, limits)) = tcp {
+			let ingest = ingest.clone();
+			guards.push(scope.spawn("tcp", move || {
+				let ingest = ingest.clone();
+				let listener = listener.try_clone();
+				async move { ingest.serve_tcp(listener?, limits).await }
+			}));
+		}
+		{
+			let counters = counters.clone();
+			guards.push(scope.spawn("report", move || {
+				report(counters.clone(), Duration::from_secs(60))
+			}));
+		}
+		Self { zygote, guards }
+	}
+}
+
+impl traits::Source for LineProtocolSocketSource {
+	fn subscribe_to_samples(&self) -> broadcast::Receiver<payload::Sample> {
+		self.zygote.subscribe()
+	}
+
+	fn subscribe_to_streams(&self) -> broadcast::Receiver<payload::Stream> {
+		traits::null_receiver()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn mapping() -> Mapping {
+		let mut units = HashMap::new();
+		units.insert("temperature".into(), metric::Unit::Celsius);
+		units.insert("bme280.pressure".into(), metric::Unit::Pascal);
+		Mapping {
+			instance_tag: "host".into(),
+			units,
+		}
+	}
+
+	#[cfg(feature = "lineproto-http")]
+	#[test]
+	fn converts_lines_to_readouts() {
+		let (readouts, error) = mapping().convert(
+			"bme280,host=lab,room=1 temperature=21.5,pressure=1013i,label=\"x\" 1600000000\nother pressure=1 1600000000\n",
+			lineproto::Precision::Seconds,
+		);
+		assert!(error.is_none());
+		assert_eq!(readouts.len(), 2);
+		let r = &readouts[0];
+		assert_eq!(r.path.device_type, "bme280");
+		assert_eq!(r.path.instance, "lab");
+		assert_eq!(r.timestamp.timestamp(), 1600000000);
+		assert_eq!(r.components.len(), 2);
+		assert_eq!(
+			r.components.get("temperature").unwrap().unit,
+			metric::Unit::Celsius
+		);
+		assert_eq!(
+			r.components.get("pressure").unwrap(),
+			&metric::Value {
+				magnitude: 1013.0,
+				unit: metric::Unit::Pascal
+			}
+		);
+		assert_eq!(
+			readouts[1].components.get("pressure").unwrap().unit,
+			metric::Unit::Arbitrary
+		);
+		assert_eq!(readouts[1].path.instance, "");
+	}
+
+	#[cfg(feature = "lineproto-http")]
+	#[test]
+	fn reports_first_error_and_keeps_valid_lines() {
+		let (readouts, error) = mapping().convert(
+			"m v=1\nm v=oops\nm s=\"only strings\"\n",
+			lineproto::Precision::Nanoseconds,
+		);
+		assert_eq!(readouts.len(), 1);
+		assert_eq!(
+			error.as_deref(),
+			Some("unable to parse line 2: invalid field value")
+		);
+	}
+
+	#[cfg(feature = "lineproto-http")]
+	#[tokio::test]
+	async fn http_source_accepts_writes() {
+		use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+		async fn post(addr: std::net::SocketAddr, path: &str, body: &str) -> String {
+			let mut sock = tokio::net::TcpStream::connect(addr).await.unwrap();
+			let request = format!(
+				"POST {} HTTP/1.1\r\nhost: localhost\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+				path,
+				body.len(),
+				body
+			);
+			sock.write_all(request.as_bytes()).await.unwrap();
+			let mut response = String::new();
+			sock.read_to_string(&mut response).await.unwrap();
+			response
+		}
+
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		listener.set_nonblocking(true).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let supervisor = supervisor::Supervisor::new();
+		let source = LineProtocolHTTPSource::new(
+			&supervisor.scope("ingest", supervisor::RestartPolicy::default()),
+			listener,
+			mapping(),
+			1024,
+			8,
+		);
+		let mut samples = traits::Source::subscribe_to_samples(&source);
+
+		let response = post(addr, "/write?db=x&precision=s", "m,host=a v=1 1600000000\n").await;
+		assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+		let sample = samples.recv().await.unwrap();
+		assert_eq!(sample[0].path.instance, "a");
+		assert_eq!(sample[0].timestamp.timestamp(), 1600000000);
+
+		// clients may encode the parameter value
+		let response = post(
+			addr,
+			"/write?db=x&precision=%C2%B5s",
+			"m,host=b v=1 1600000000000000\n",
+		)
+		.await;
+		assert!(response.starts_with("HTTP/1.1 204"), "{}", response);
+		let sample = samples.recv().await.unwrap();
+		assert_eq!(sample[0].timestamp.timestamp(), 1600000000);
+
+		let response = post(addr, "/api/v2/write?org=o&bucket=b", "m v=\n").await;
+		assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+		assert!(response.contains("unable to parse line 1"));
+
+		let response = post(addr, "/write", &"m v=1\n".repeat(200)).await;
+		assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+		let response = post(addr, "/query", "").await;
+		assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+	}
+
+	fn ingest() -> (Ingest, broadcast::Receiver<payload::Sample>) {
+		let (sink, samples) = broadcast::channel(8);
+		(
+			Ingest {
+				mapping: mapping(),
+				precision: lineproto::Precision::Seconds,
+				sink,
+				counters: Arc::new(IngestCounters::default()),
+				max_line_length: 32,
+			},
+			samples,
+		)
+	}
+
+	#[tokio::test]
+	async fn stream_tolerates_partial_and_long_lines() {
+		let (ingest, mut samples) = ingest();
+		let (mut client, server) = tokio::io::duplex(64);
+		let reader = tokio::spawn(async move {
+			ingest
+				.read_lines(server, Duration::from_secs(60))
+				.await
+				.unwrap();
+			ingest
+		});
+		use tokio::io::AsyncWriteExt;
+		client.write_all(b"m,host=a v=1 1\nm,ho").await.unwrap();
+		client.flush().await.unwrap();
+		let first = samples.recv().await.unwrap();
+		assert_eq!(first[0].path.instance, "a");
+		client
+			.write_all(b"st=b v=2 2\nthis line is way too long to be accepted v=1\nm v=?\nm v=3 3")
+			.await
+			.unwrap();
+		drop(client);
+		let ingest = reader.await.unwrap();
+		let mut timestamps = Vec::new();
+		while let Ok(sample) = samples.try_recv() {
+			timestamps.extend(sample.iter().map(|r| r.timestamp.timestamp()));
+		}
+		assert_eq!(timestamps, vec![2, 3]);
+		assert_eq!(ingest.counters.lines.load(Ordering::Relaxed), 3);
+		assert_eq!(ingest.counters.errors.load(Ordering::Relaxed), 1);
+		assert_eq!(ingest.counters.discarded.load(Ordering::Relaxed), 1);
+	}
+
+	#[tokio::test]
+	async fn stream_times_out_when_idle() {
+		let (ingest, _samples) = ingest();
+		let (_client, server) = tokio::io::duplex(64);
+		let err = ingest
+			.read_lines(server, Duration::from_millis(10))
+			.await
+			.unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+	}
+
+	#[tokio::test]
+	async fn tcp_source_limits_connections() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		listener.set_nonblocking(true).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let supervisor = supervisor::Supervisor::new();
+		let source = LineProtocolSocketSource::new(
+			&supervisor.scope("ingest", supervisor::RestartPolicy::default()),
+			None,
+			Some((
+				listener,
+				TcpLimits {
+					max_connections: 1,
+					read_timeout: Duration::from_secs(60),
+				},
+			)),
+			mapping(),
+			lineproto::Precision::Seconds,
+			1024,
+			8,
+		);
+		let mut samples = traits::Source::subscribe_to_samples(&source);
+		use tokio::io::AsyncWriteExt;
+		let mut first = tokio::net::TcpStream::connect(addr).await.unwrap();
+		first.write_all(b"m,host=a v=1 1\n").await.unwrap();
+		assert_eq!(samples.recv().await.unwrap()[0].path.instance, "a");
+		// queued in the backlog until the first connection is closed
+		let mut second = tokio::net::TcpStream::connect(addr).await.unwrap();
+		second.write_all(b"m,host=b v=1 1\n").await.unwrap();
+		assert!(
+			tokio::time::timeout(Duration::from_millis(50), samples.recv())
+				.await
+				.is_err()
+		);
+		drop(first);
+		assert_eq!(samples.recv().await.unwrap()[0].path.instance, "b");
+	}
+
+	#[tokio::test]
+	async fn udp_source_parses_datagrams() {
+		let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_nonblocking(true).unwrap();
+		let addr = socket.local_addr().unwrap();
+		let supervisor = supervisor::Supervisor::new();
+		let source = LineProtocolSocketSource::new(
+			&supervisor.scope("ingest", supervisor::RestartPolicy::default()),
+			Some(socket),
+			None,
+			mapping(),
+			lineproto::Precision::Seconds,
+			1024,
+			8,
+		);
+		let mut samples = traits::Source::subscribe_to_samples(&source);
+		let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		client
+			.send_to(b"m,host=x v=1 10\nbroken", addr)
+			.await
+			.unwrap();
+		let sample = samples.recv().await.unwrap();
+		assert_eq!(sample.len(), 1);
+		assert_eq!(sample[0].path.instance, "x");
+	}
+}
@@ -0,0 +1,201 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use smartstring::alias::String as SmartString;
+
+use chrono::Utc;
+
+use tokio::sync::broadcast;
+
+use crate::metric;
+
+use super::payload;
+use super::supervisor;
+use super::traits::{null_receiver, Source};
+
+/// Counters for a single link between two nodes.
+#[derive(Debug)]
+pub struct LinkStats {
+	source: SmartString,
+	sink: SmartString,
+	lag_events: AtomicU64,
+	lost: AtomicU64,
+}
+
+impl LinkStats {
+	pub fn new(source: &str, sink: &str) -> Self {
+		Self {
+			source: source.into(),
+			sink: sink.into(),
+			lag_events: AtomicU64::new(0),
+			lost: AtomicU64::new(0),
+		}
+	}
+
+	pub fn source(&self) -> &str {
+		&self.source
+	}
+
+	pub fn sink(&self) -> &str {
+		&self.sink
+	}
+
+	/// Number of times the sink was too slow to keep up with the source.
+	pub fn lag_events(&self) -> u64 {
+		self.lag_events.load(Ordering::Relaxed)
+	}
+
+	/// Total number of items dropped because the sink was too slow.
+	pub fn lost(&self) -> u64 {
+		self.lost.load(Ordering::Relaxed)
+	}
+
+	pub fn record_lag(&self, nlost: u64) {
+		self.lag_events.fetch_add(1, Ordering::Relaxed);
+		let total = self.lost.fetch_add(nlost, Ordering::Relaxed) + nlost;
+		warn!(
+			"link {} -> {} lagged: lost {} items ({} in total)",
+			self.source, self.sink, nlost, total
+		);
+	}
+}
+
+/// Collection of the statistics of all links of a runtime.
+#[derive(Debug, Clone, Default)]
+pub struct LinkRegistry {
+	links: Arc<Mutex<Vec<Arc<LinkStats>>>>,
+}
+
+impl LinkRegistry {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn register(&self, source: &str, sink: &str) -> Arc<LinkStats> {
+		let stats = Arc::new(LinkStats::new(source, sink));
+		self.links.lock().unwrap().push(stats.clone());
+		stats
+	}
+
+	pub fn links(&self) -> Vec<Arc<LinkStats>> {
+		self.links.lock().unwrap().clone()
+	}
+}
+
+fn to_readout(stats: &LinkStats, timestamp: chrono::DateTime<Utc>) -> payload::Readout {
+	let mut components = metric::OrderedVec::new();
+	components.insert(
+		"lag_events".into(),
+		metric::Value {
+			magnitude: stats.lag_events() as f64,
+			unit: metric::Unit::Total,
+		},
+	);
+	components.insert(
+		"lost".into(),
+		metric::Value {
+			magnitude: stats.lost() as f64,
+			unit: metric::Unit::Total,
+		},
+	);
+	let mut instance = stats.source.clone();
+	instance.push_str("->");
+	instance.push_str(&stats.sink);
+	Arc::new(metric::Readout {
+		timestamp,
+		path: metric::DevicePath {
+			device_type: "link".into(),
+			instance,
+		},
+		components,
+	})
+}
+
+/// Source which periodically emits the lag counters of all links as readouts.
+pub struct LinkStatsSource {
+	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl LinkStatsSource {
+	pub fn new(
+		scope: &supervisor::Scope,
+		registry: LinkRegistry,
+		interval: Duration,
+		capacity: usize,
+	) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
+		let sink = zygote.clone();
+		let guard = scope.spawn("linkstats", move || {
+			let registry = registry.clone();
+			let sink = sink.clone();
+			async move {
+				let mut interval = tokio::time::interval(interval);
+				loop {
+					interval.tick().await;
+					let timestamp = Utc::now();
+					let readouts: payload::Sample = registry
+						.links()
+						.iter()
+						.map(|stats| to_readout(stats, timestamp))
+						.collect();
+					if readouts.is_empty() {
+						continue;
+					}
+					if sink.send(readouts).is_err() {
+						debug!("no receivers for link statistics, dropping");
+					}
+				}
+			}
+		});
+		Self { zygote, guard }
+	}
+}
+
+impl Source for LinkStatsSource {
+	fn subscribe_to_samples(&self) -> broadcast::Receiver<payload::Sample> {
+		self.zygote.subscribe()
+	}
+
+	fn subscribe_to_streams(&self) -> broadcast::Receiver<payload::Stream> {
+		null_receiver()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use super::super::adapter::Serializer;
+
+	#[tokio::test]
+	async fn serializer_records_lag_on_link() {
+		let registry = LinkRegistry::new();
+		let stats = registry.register("src", "dst");
+		let (tx, rx) = broadcast::channel::<u32>(2);
+		let (serializer, mut out) = Serializer::new(16);
+		// fill the channel before the forwarding task gets to run
+		for i in 0..5 {
+			tx.send(i).unwrap();
+		}
+		serializer.attach(rx, stats.clone());
+		assert_eq!(out.recv().await, Some(3));
+		assert_eq!(out.recv().await, Some(4));
+		assert_eq!(stats.lag_events(), 1);
+		assert_eq!(stats.lost(), 3);
+	}
+
+	#[test]
+	fn readout_carries_counters() {
+		let stats = LinkStats::new("a", "b");
+		stats.record_lag(7);
+		let readout = to_readout(&stats, Utc::now());
+		assert_eq!(readout.path.instance, "a->b");
+		assert_eq!(readout.components.get("lost").unwrap().magnitude, 7.0);
+		assert_eq!(readout.components.get("lag_events").unwrap().magnitude, 1.0);
+	}
+}
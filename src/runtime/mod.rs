@@ -1,8 +1,11 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 mod adapter;
 mod config;
 #[cfg(feature = "csv")]
+mod csvexport;
+#[cfg(feature = "csv")]
 mod csvinject;
 #[cfg(feature = "debug")]
 mod debug;
@@ -11,15 +14,27 @@ mod detrend;
 #[cfg(feature = "fft")]
 mod fft;
 mod filter;
+#[cfg(feature = "graphite")]
+mod graphite;
 mod hwmon;
 #[cfg(feature = "influxdb")]
 mod influxdb;
+#[cfg(feature = "jsonl")]
+mod jsonl;
+#[cfg(feature = "lineproto")]
+mod lineproto;
+mod linkstats;
+#[cfg(feature = "mqtt")]
+mod mqtt;
 mod payload;
+#[cfg(feature = "prometheus")]
+mod prometheus;
 #[cfg(feature = "pubsub")]
 mod pubsub;
 #[cfg(feature = "relay")]
 mod relay;
-#[cfg(feature = "sbm")]
+#[cfg(feature = "prometheus-remote-write")]
+mod remote_write;
 mod retry;
 mod router;
 mod samplify;
@@ -29,19 +44,103 @@ mod sbm;
 mod sbx;
 #[cfg(feature = "smbus")]
 mod smbus;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+#[cfg(feature = "statsd")]
+mod statsd;
 #[cfg(feature = "stream-filearchive")]
 mod stream;
 mod streamify;
 #[cfg(feature = "summary")]
 mod summary;
+mod supervisor;
 mod traits;
+#[cfg(feature = "webhook")]
+mod webhook;
 
-pub use config::{BuildError, Config};
+pub use config::{BuildContext, BuildError, Config, LoadError, NodeConfig, StateConfig};
+pub use linkstats::LinkStats;
+pub use state::StateHandle;
+pub use supervisor::{NodeState, NodeStatus, RestartPolicy};
 pub use traits::{Node, Sink, Source};
 
 pub struct Runtime {
-	#[allow(dead_code)]
 	nodes: HashMap<String, Node>,
+	supervisor: supervisor::Supervisor,
+	links: linkstats::LinkRegistry,
+	state: state::StateStore,
+	flusher: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Runtime {
+	/// Health of all nodes, as tracked by the supervisor.
+	pub fn status(&self) -> Vec<NodeStatus> {
+		self.supervisor.status()
+	}
+
+	/// Nodes which are running, but report that they cannot do their job.
+	pub fn degraded_nodes(&self) -> Vec<NodeStatus> {
+		self.supervisor.degraded()
+	}
+
+	/// Nodes whose tasks crashed and will not be restarted anymore.
+	pub fn failed_nodes(&self) -> Vec<NodeStatus> {
+		self.supervisor.failed()
+	}
+
+	/// One-line summary of the health of all nodes.
+	pub fn status_summary(&self) -> String {
+		self.supervisor.summary()
+	}
+
+	/// Wait until the tasks of all nodes have been started.
+	pub async fn started(&self) {
+		self.supervisor.started().await
+	}
+
+	/// Wait until the tasks of all nodes have been started and every source
+	/// reports that it is running.
+	///
+	/// Fails with the status of a source which failed permanently in the
+	/// meantime.
+	pub async fn ready(&self) -> Result<(), NodeStatus> {
+		let sources: Vec<&str> = self
+			.nodes
+			.iter()
+			.filter(|(_, node)| node.as_source().is_some())
+			.map(|(name, _)| name.as_str())
+			.collect();
+		self.supervisor.started().await;
+		self.supervisor.running(&sources).await
+	}
+
+	/// Problems with the supervision itself which went on for longer than
+	/// `grace`, such as supervising tasks which stopped responding.
+	pub fn stalled(&self, grace: std::time::Duration) -> Vec<String> {
+		self.supervisor.stalled(grace)
+	}
+
+	/// Lag statistics of all links.
+	pub fn links(&self) -> Vec<Arc<LinkStats>> {
+		self.links.links()
+	}
+
+	/// Write the persistent state of all nodes to the state directory.
+	///
+	/// This also happens periodically and when the runtime is dropped.
+	pub fn flush_state(&self) {
+		self.state.flush()
+	}
+}
+
+impl Drop for Runtime {
+	fn drop(&mut self) {
+		if let Some(flusher) = self.flusher.take() {
+			flusher.abort();
+		}
+		self.state.flush();
+	}
 }
 
 impl Config {
@@ -72,18 +171,39 @@ impl Config {
 	}
 
 	pub fn build(&self) -> Result<Runtime, BuildError> {
+		let supervisor = supervisor::Supervisor::new();
+		let links = linkstats::LinkRegistry::new();
+		let state = state::StateStore::new(self.state.directory.clone())
+			.map_err(|e| BuildError::Other(Box::new(e)))?;
 		let mut nodes = HashMap::new();
 		for (name, ref node_cfg) in self.node.iter() {
-			nodes.insert(name.clone(), node_cfg.build()?);
+			let ctx = BuildContext {
+				scope: supervisor.scope(name, node_cfg.restart.clone()),
+				capacity: node_cfg.capacity,
+				links: links.clone(),
+				state: state.open(name),
+			};
+			nodes.insert(name.clone(), node_cfg.class.build(&ctx)?);
 		}
 
 		for ref link_cfg in self.link.iter() {
 			let src = Self::get_source(&nodes, &link_cfg.source)?;
 			let sink = Self::get_sink(&nodes, &link_cfg.sink)?;
-			sink.attach_source(src);
+			let stats = links.register(&link_cfg.source, &link_cfg.sink);
+			sink.attach_source(src, &stats);
 		}
 
-		Ok(Runtime { nodes })
+		let flusher = state.spawn_flusher(std::time::Duration::from_secs(
+			self.state.flush_interval.max(1),
+		));
+
+		Ok(Runtime {
+			nodes,
+			supervisor,
+			links,
+			state,
+			flusher,
+		})
 
 		/* let mut sources: HashMap<String, Box<dyn Source>> = HashMap::new();
 		let mut sinks = HashMap::new();
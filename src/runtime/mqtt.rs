@@ -0,0 +1,386 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use chrono::Utc;
+
+use rumqttc::{AsyncClient, Event, EventLoop, Packet, QoS};
+
+use tokio::sync::{broadcast, mpsc};
+
+use crate::mqtt;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+/// Delay before reconnecting after the connection to the broker failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Number of requests which may be queued towards the event loop.
+const REQUEST_CAPACITY: usize = 64;
+
+/// How to reach the broker.
+pub struct Connection {
+	pub host: String,
+	pub port: u16,
+	/// Must be unique among all clients of the broker.
+	pub client_id: String,
+	pub credentials: Option<(String, String)>,
+	pub keep_alive: Duration,
+}
+
+impl Connection {
+	fn connect(&self) -> (AsyncClient, EventLoop) {
+		let mut options = rumqttc::MqttOptions::new(&self.client_id, &self.host, self.port);
+		options.set_keep_alive(self.keep_alive);
+		if let Some((username, password)) = self.credentials.as_ref() {
+			options.set_credentials(username, password);
+		}
+		AsyncClient::new(options, REQUEST_CAPACITY)
+	}
+}
+
+/// Poll the event loop forever, reconnecting as needed and reporting the
+/// state of the connection to the supervisor.
+async fn drive<F: FnMut(Event)>(
+	scope: &supervisor::Scope,
+	eventloop: &supervisor::Resumable<EventLoop>,
+	mut handle: F,
+) {
+	let mut eventloop = eventloop.resume().await;
+	let mut connected = true;
+	loop {
+		match eventloop.poll().await {
+			Ok(event) => {
+				if let Event::Incoming(Packet::ConnAck(_)) = event {
+					if !connected {
+						warn!("connection to mqtt broker restored");
+					}
+					connected = true;
+					scope.report_health(None);
+				}
+				handle(event);
+			}
+			Err(e) => {
+				if connected {
+					warn!("connection to mqtt broker failed: {}", e);
+				} else {
+					debug!("connection to mqtt broker failed: {}", e);
+				}
+				connected = false;
+				scope.report_health(Some(format!("mqtt broker unreachable: {}", e)));
+				tokio::time::sleep(RECONNECT_DELAY).await;
+			}
+		}
+	}
+}
+
+/// How readouts are published.
+pub struct Publication {
+	pub topic: mqtt::TopicTemplate,
+	pub format: mqtt::PayloadFormat,
+	pub qos: QoS,
+	pub retain: bool,
+}
+
+struct MqttPublisher {
+	client: AsyncClient,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	publication: Publication,
+}
+
+impl MqttPublisher {
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		while let Some(sample) = samples.recv().await {
+			for readout in sample.iter() {
+				for (topic, payload) in
+					mqtt::encode(readout, &self.publication.topic, self.publication.format)
+				{
+					// this only fails if the event loop is gone for good
+					if let Err(e) = self
+						.client
+						.publish(
+							topic,
+							self.publication.qos,
+							self.publication.retain,
+							payload,
+						)
+						.await
+					{
+						warn!("lost sample: failed to publish to mqtt: {}", e);
+					}
+				}
+			}
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Publishes readouts to an MQTT broker.
+pub struct MqttSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guards: Vec<supervisor::TaskGuard>,
+}
+
+impl MqttSink {
+	pub fn new(
+		scope: &supervisor::Scope,
+		connection: Connection,
+		publication: Publication,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let (client, eventloop) = connection.connect();
+		let eventloop = supervisor::Resumable::new(eventloop);
+		let publisher = Arc::new(MqttPublisher {
+			client,
+			samples: supervisor::Resumable::new(samples),
+			publication,
+		});
+		let connection_scope = scope.clone();
+		let guards = vec![
+			scope.spawn("mqtt", move || {
+				let scope = connection_scope.clone();
+				let eventloop = eventloop.clone();
+				async move {
+					drive(&scope, &eventloop, |_| ()).await;
+					Ok(())
+				}
+			}),
+			scope.spawn("publish", move || {
+				let publisher = publisher.clone();
+				async move {
+					publisher.run().await;
+					Ok(())
+				}
+			}),
+		];
+		Self {
+			samples: serializer,
+			guards,
+		}
+	}
+}
+
+impl traits::Sink for MqttSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+/// Topic filter to subscribe to and how to read the messages.
+pub struct Subscription {
+	pub filter: String,
+	pub topic: mqtt::TopicTemplate,
+	pub format: mqtt::PayloadFormat,
+}
+
+struct MqttSubscriber {
+	client: AsyncClient,
+	subscriptions: Vec<Subscription>,
+	qos: QoS,
+	sink: broadcast::Sender<payload::Sample>,
+}
+
+impl MqttSubscriber {
+	fn handle(&self, event: Event) {
+		match event {
+			// subscriptions do not survive the session
+			Event::Incoming(Packet::ConnAck(_)) => {
+				for subscription in self.subscriptions.iter() {
+					if let Err(e) = self.client.try_subscribe(&subscription.filter, self.qos) {
+						warn!("failed to subscribe to {:?}: {}", subscription.filter, e);
+					}
+				}
+			}
+			Event::Incoming(Packet::Publish(msg)) => self.receive(&msg.topic, &msg.payload),
+			_ => (),
+		}
+	}
+
+	fn receive(&self, topic: &str, payload: &[u8]) {
+		let matched = self
+			.subscriptions
+			.iter()
+			.filter(|s| rumqttc::matches(topic, &s.filter))
+			.find_map(|s| s.topic.match_topic(topic).map(|fields| (s, fields)));
+		let (subscription, fields) = match matched {
+			Some(v) => v,
+			None => {
+				debug!("ignoring message on {}: no matching topic template", topic);
+				return;
+			}
+		};
+		match mqtt::decode(&fields, payload, subscription.format, Utc::now()) {
+			Ok(readout) => {
+				// no subscribers is not the publisher's problem
+				let _ = self.sink.send(vec![Arc::new(readout)]);
+			}
+			Err(e) => warn!("dropping message on {}: {}", topic, e),
+		}
+	}
+}
+
+/// Subscribes to topic filters on an MQTT broker and turns the messages
+/// into readouts.
+pub struct MqttSource {
+	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl MqttSource {
+	pub fn new(
+		scope: &supervisor::Scope,
+		connection: Connection,
+		subscriptions: Vec<Subscription>,
+		qos: QoS,
+		capacity: usize,
+	) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
+		let (client, eventloop) = connection.connect();
+		let eventloop = supervisor::Resumable::new(eventloop);
+		let subscriber = Arc::new(MqttSubscriber {
+			client,
+			subscriptions,
+			qos,
+			sink: zygote.clone(),
+		});
+		let connection_scope = scope.clone();
+		let guard = scope.spawn("mqtt", move || {
+			let scope = connection_scope.clone();
+			let eventloop = eventloop.clone();
+			let subscriber = subscriber.clone();
+			async move {
+				drive(&scope, &eventloop, |event| subscriber.handle(event)).await;
+				Ok(())
+			}
+		});
+		Self { zygote, guard }
+	}
+}
+
+impl traits::Source for MqttSource {
+	fn subscribe_to_samples(&self) -> broadcast::Receiver<payload::Sample> {
+		self.zygote.subscribe()
+	}
+
+	fn subscribe_to_streams(&self) -> broadcast::Receiver<payload::Stream> {
+		traits::null_receiver()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::metric;
+	use crate::mqtt::testutil::Broker;
+
+	fn connection(broker: &Broker, client_id: &str) -> Connection {
+		Connection {
+			host: broker.host.clone(),
+			port: broker.port,
+			client_id: client_id.into(),
+			credentials: None,
+			keep_alive: Duration::from_secs(30),
+		}
+	}
+
+	fn readout(instance: &str, value: f64) -> Arc<metric::Readout> {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.temperature(value)
+			.arc()
+	}
+
+	#[tokio::test]
+	async fn publishes_readouts() {
+		let broker = Broker::start().await;
+		let supervisor = supervisor::Supervisor::new();
+		let sink = MqttSink::new(
+			&supervisor.scope("mqtt", supervisor::RestartPolicy::default()),
+			connection(&broker, "publisher"),
+			Publication {
+				topic: mqtt::TopicTemplate::parse("sensors/{device_type}{instance}/{component}")
+					.unwrap(),
+				format: mqtt::PayloadFormat::Plain,
+				qos: QoS::AtLeastOnce,
+				retain: true,
+			},
+			8,
+		);
+		let (tx, _) = broadcast::channel(8);
+		sink.samples
+			.attach(tx.subscribe(), Arc::new(LinkStats::new("source", "mqtt")));
+		tx.send(vec![readout("/a", 21.5), readout("/b", 19.0)])
+			.unwrap();
+
+		for _ in 0..100 {
+			let published = broker.published();
+			if published.len() == 2 {
+				assert_eq!(published[0].topic, "sensors/bme280/a/temperature");
+				assert_eq!(published[0].payload_str(), "21.5");
+				assert_eq!(published[0].qos, 1);
+				assert!(published[0].retain);
+				assert_eq!(published[1].topic, "sensors/bme280/b/temperature");
+				return;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("messages never showed up");
+	}
+
+	#[tokio::test]
+	async fn subscribes_and_decodes_messages() {
+		let broker = Broker::start().await;
+		let supervisor = supervisor::Supervisor::new();
+		let source = MqttSource::new(
+			&supervisor.scope("mqtt", supervisor::RestartPolicy::default()),
+			connection(&broker, "subscriber"),
+			vec![
+				Subscription {
+					filter: "plain/#".into(),
+					topic: mqtt::TopicTemplate::parse("plain/{device_type}/{component}").unwrap(),
+					format: mqtt::PayloadFormat::Plain,
+				},
+				Subscription {
+					filter: "tele/+/SENSOR".into(),
+					topic: mqtt::TopicTemplate::parse("tele/{instance}/SENSOR").unwrap(),
+					format: mqtt::PayloadFormat::Json,
+				},
+			],
+			QoS::AtMostOnce,
+			8,
+		);
+		let mut samples = traits::Source::subscribe_to_samples(&source);
+
+		for _ in 0..100 {
+			if broker.subscriptions().len() == 2 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		assert_eq!(broker.subscriptions(), vec!["plain/#", "tele/+/SENSOR"]);
+
+		broker.publish("plain/meter/power", b"not a number");
+		broker.publish("plain/meter", b"ignored, no component level");
+		broker.publish("plain/meter/power", b"12.5");
+		broker.publish("tele/kitchen/SENSOR", br#"{"Power": 3, "Name": "plug"}"#);
+
+		let sample = samples.recv().await.unwrap();
+		assert_eq!(sample[0].path.device_type, "meter");
+		assert_eq!(sample[0].components.get("power").unwrap().magnitude, 12.5);
+		let sample = samples.recv().await.unwrap();
+		assert_eq!(sample[0].path.instance, "kitchen");
+		assert_eq!(sample[0].components.len(), 1);
+		assert_eq!(sample[0].components.get("Power").unwrap().magnitude, 3.0);
+	}
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use smartstring::alias::String as SmartString;
+
+use chrono::{DateTime, Utc};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+use tokio::sync::mpsc;
+
+use crate::metric;
+use crate::prometheus;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+/// How readouts are exposed as series.
+pub struct Exposition {
+	/// Prepended to all metric names.
+	pub prefix: String,
+	/// Label carrying the instance of the device path.
+	pub instance_label: String,
+	/// Series which have not been updated for this long are dropped.
+	pub staleness: Duration,
+	/// Include the timestamps of the readouts.
+	pub timestamps: bool,
+}
+
+struct Entry {
+	value: metric::Value,
+	timestamp: DateTime<Utc>,
+	updated: Instant,
+}
+
+/// Latest value of each component of each device path.
+struct Store {
+	exposition: Exposition,
+	series: StdMutex<HashMap<(metric::DevicePath, SmartString), Entry>>,
+}
+
+impl Store {
+	fn update(&self, sample: &payload::Sample) {
+		let now = Instant::now();
+		let mut series = self.series.lock().unwrap();
+		for readout in sample.iter() {
+			for (component, value) in readout.components.iter() {
+				series.insert(
+					(readout.path.clone(), component.clone()),
+					Entry {
+						value: value.clone(),
+						timestamp: readout.timestamp,
+						updated: now,
+					},
+				);
+			}
+		}
+	}
+
+	/// Drop stale series and convert the others.
+	fn collect(&self) -> Vec<prometheus::Series> {
+		let now = Instant::now();
+		let mut series = self.series.lock().unwrap();
+		series.retain(|_, entry| now.duration_since(entry.updated) <= self.exposition.staleness);
+		series
+			.iter()
+			.map(|((path, component), entry)| {
+				prometheus::component_series(
+					&self.exposition.prefix,
+					&self.exposition.instance_label,
+					path,
+					component,
+					&entry.value,
+					entry.timestamp,
+				)
+			})
+			.collect()
+	}
+
+	fn scrape(&self, req: &Request<Body>) -> Response<Body> {
+		let accept = req
+			.headers()
+			.get(hyper::header::ACCEPT)
+			.and_then(|v| v.to_str().ok());
+		let format = prometheus::Format::negotiate(accept);
+		let body = prometheus::render(&self.collect(), format, self.exposition.timestamps);
+		let mut response = Response::new(Body::from(body));
+		response.headers_mut().insert(
+			hyper::header::CONTENT_TYPE,
+			hyper::header::HeaderValue::from_static(format.content_type()),
+		);
+		response
+	}
+
+	async fn handle(self: Arc<Self>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+		let status = match (req.method(), req.uri().path()) {
+			(&Method::GET, "/metrics") => return Ok(self.scrape(&req)),
+			(_, "/metrics") => StatusCode::METHOD_NOT_ALLOWED,
+			_ => StatusCode::NOT_FOUND,
+		};
+		let mut response = Response::new(Body::empty());
+		*response.status_mut() = status;
+		Ok(response)
+	}
+
+	async fn serve(self: Arc<Self>, listener: std::net::TcpListener) -> supervisor::TaskResult {
+		let make_service = make_service_fn(move |_| {
+			let store = self.clone();
+			async move { Ok::<_, Infallible>(service_fn(move |req| store.clone().handle(req))) }
+		});
+		hyper::Server::from_tcp(listener)?
+			.serve(make_service)
+			.await?;
+		Ok(())
+	}
+
+	async fn collect_samples(
+		&self,
+		samples: &supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	) {
+		let mut samples = samples.resume().await;
+		while let Some(sample) = samples.recv().await {
+			self.update(&sample);
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Serves the latest value of each component on `/metrics`.
+pub struct PrometheusSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guards: Vec<supervisor::TaskGuard>,
+}
+
+impl PrometheusSink {
+	pub fn new(
+		scope: &supervisor::Scope,
+		listener: std::net::TcpListener,
+		exposition: Exposition,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let samples = supervisor::Resumable::new(samples);
+		let store = Arc::new(Store {
+			exposition,
+			series: StdMutex::new(HashMap::new()),
+		});
+		let collector = store.clone();
+		let guards = vec![
+			scope.spawn("collect", move || {
+				let store = collector.clone();
+				let samples = samples.clone();
+				async move {
+					store.collect_samples(&samples).await;
+					Ok(())
+				}
+			}),
+			scope.spawn("http", move || {
+				let store = store.clone();
+				let listener = listener.try_clone();
+				async move { store.serve(listener?).await }
+			}),
+		];
+		Self {
+			samples: serializer,
+			guards,
+		}
+	}
+}
+
+impl traits::Sink for PrometheusSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn readout(
+		instance: &str,
+		component: &str,
+		value: f64,
+		unit: metric::Unit,
+	) -> Arc<metric::Readout> {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.component(component, value, unit)
+			.arc()
+	}
+
+	fn store(staleness: Duration) -> Store {
+		Store {
+			exposition: Exposition {
+				prefix: "".into(),
+				instance_label: "device".into(),
+				staleness,
+				timestamps: false,
+			},
+			series: StdMutex::new(HashMap::new()),
+		}
+	}
+
+	#[test]
+	fn keeps_latest_value_per_series() {
+		let store = store(Duration::from_secs(60));
+		store.update(&vec![
+			readout("a", "temperature", 20.0, metric::Unit::Celsius),
+			readout("b", "temperature", 22.0, metric::Unit::Celsius),
+		]);
+		store.update(&vec![
+			readout("a", "temperature", 21.0, metric::Unit::Celsius),
+			readout("a", "resets", 4.0, metric::Unit::Total),
+		]);
+		let text = prometheus::render(&store.collect(), prometheus::Format::Text, false);
+		assert_eq!(
+			text,
+			"# TYPE bme280_resets_total counter\nbme280_resets_total{device=\"a\"} 4\n\
+			 # TYPE bme280_temperature_celsius gauge\n\
+			 bme280_temperature_celsius{device=\"a\"} 21\nbme280_temperature_celsius{device=\"b\"} 22\n"
+		);
+	}
+
+	#[test]
+	fn stale_series_expire() {
+		let store = store(Duration::ZERO);
+		store.update(&vec![readout("a", "v", 1.0, metric::Unit::Arbitrary)]);
+		std::thread::sleep(Duration::from_millis(5));
+		assert!(store.collect().is_empty());
+		assert!(store.series.lock().unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn serves_metrics() {
+		use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		listener.set_nonblocking(true).unwrap();
+		let addr = listener.local_addr().unwrap();
+		let supervisor = supervisor::Supervisor::new();
+		let sink = PrometheusSink::new(
+			&supervisor.scope("prometheus", supervisor::RestartPolicy::default()),
+			listener,
+			Exposition {
+				prefix: "".into(),
+				instance_label: "device".into(),
+				staleness: Duration::from_secs(60),
+				timestamps: false,
+			},
+			8,
+		);
+		let (tx, _) = tokio::sync::broadcast::channel(8);
+		sink.samples.attach(
+			tx.subscribe(),
+			Arc::new(LinkStats::new("source", "prometheus")),
+		);
+		tx.send(vec![readout("a", "v", 1.0, metric::Unit::Arbitrary)])
+			.unwrap();
+
+		for _ in 0..100 {
+			let mut sock = tokio::net::TcpStream::connect(addr).await.unwrap();
+			sock.write_all(
+				b"GET /metrics HTTP/1.1\r\nHost: x\r\nAccept: application/openmetrics-text\r\nConnection: close\r\n\r\n",
+			)
+			.await
+			.unwrap();
+			let mut response = String::new();
+			sock.read_to_string(&mut response).await.unwrap();
+			assert!(response.starts_with("HTTP/1.1 200"));
+			assert!(response.contains("application/openmetrics-text"));
+			if response.contains("bme280_v{device=\"a\"} 1\n# EOF\n") {
+				return;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("sample never showed up");
+	}
+}
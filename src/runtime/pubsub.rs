@@ -1,55 +1,300 @@
-use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
 
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 
+use crate::http;
 use crate::pubsub;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::retry::Backoff;
+use super::supervisor;
 use super::traits;
 
+/// How readouts are grouped into requests; without it, a request is made
+/// for each readout.
+///
+/// Readouts for the same node are sent together in a `sample-batches`
+/// document.
+pub struct Batching {
+	/// Maximum time readouts are held back to wait for more.
+	pub flush_interval: Duration,
+	/// Maximum number of readouts per request.
+	pub max_readouts: usize,
+}
+
+/// Readouts waiting to be sent, by node, in order of arrival.
+#[derive(Default)]
+struct Pending {
+	nodes: Vec<(String, Vec<payload::Readout>)>,
+	since: Option<Instant>,
+}
+
+impl Pending {
+	/// Add a readout and return the node's readouts if they make up a full
+	/// batch.
+	fn add(
+		&mut self,
+		node: String,
+		readout: payload::Readout,
+		max: usize,
+	) -> Option<(String, Vec<payload::Readout>)> {
+		self.since.get_or_insert_with(Instant::now);
+		let i = match self.nodes.iter().position(|(n, _)| *n == node) {
+			Some(i) => i,
+			None => {
+				self.nodes.push((node, Vec::new()));
+				self.nodes.len() - 1
+			}
+		};
+		self.nodes[i].1.push(readout);
+		if self.nodes[i].1.len() >= max {
+			let full = self.nodes.remove(i);
+			if self.nodes.is_empty() {
+				self.since = None;
+			}
+			Some(full)
+		} else {
+			None
+		}
+	}
+}
+
 struct PubSubWorker {
 	client: pubsub::Client,
-	samples: mpsc::Receiver<payload::Sample>,
+	format: pubsub::Format,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	batching: Option<Batching>,
+	retry: Backoff,
 }
 
 impl PubSubWorker {
-	async fn run(&mut self) {
+	async fn send(&self, node: &str, payload: String, n: usize) {
+		let result = self
+			.retry
+			.run(
+				"submit to pubsub",
+				|e: &http::Error| e.is_retryable(),
+				|| self.client.post(node, payload.clone()),
+			)
+			.await;
+		if let Err(e) = result {
+			warn!("lost {} samples: failed to submit to pubsub: {}", n, e);
+		}
+	}
+
+	async fn send_batch(&self, node: &str, readouts: &[payload::Readout]) {
+		let payload = self.format.sample_batches(readouts.iter().map(|r| &**r));
+		self.send(node, payload, readouts.len()).await;
+	}
+
+	async fn run_per_readout(&self, samples: &mut mpsc::Receiver<payload::Sample>) {
+		while let Some(sample) = samples.recv().await {
+			for readout in sample.iter() {
+				let node = self.client.node(readout);
+				self.send(&node, self.format.sample_batch(readout), 1).await;
+			}
+		}
+	}
+
+	async fn run_batched(
+		&self,
+		samples: &mut mpsc::Receiver<payload::Sample>,
+		batching: &Batching,
+	) {
+		let mut pending = Pending::default();
 		loop {
-			let mut readouts = match self.samples.recv().await {
-				None => return,
-				Some(v) => v,
-			};
-			for readout in readouts.drain(..) {
-				match self.client.post(&readout).await {
-					Ok(_) => (),
-					Err(e) => warn!("lost sample: failed to submit to pubsub: {}", e),
-				};
+			let deadline = pending.since.map(|x| x + batching.flush_interval);
+			tokio::select! {
+				v = samples.recv() => match v {
+					Some(sample) => for readout in sample {
+						let node = self.client.node(&readout);
+						if let Some((node, readouts)) = pending.add(node, readout, batching.max_readouts) {
+							self.send_batch(&node, &readouts).await;
+						}
+					},
+					None => break,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					for (node, readouts) in std::mem::take(&mut pending).nodes {
+						self.send_batch(&node, &readouts).await;
+					}
+				}
 			}
 		}
+		for (node, readouts) in pending.nodes {
+			self.send_batch(&node, &readouts).await;
+		}
+	}
+
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		match self.batching.as_ref() {
+			Some(batching) => self.run_batched(&mut samples, batching).await,
+			None => self.run_per_readout(&mut samples).await,
+		}
+		debug!("sample source closed, exiting");
 	}
 }
 
 pub struct PubSubSink {
 	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl PubSubSink {
-	pub fn new(api_url: String, node_template: String, override_host: Option<String>) -> Self {
-		let (serializer, samples) = Serializer::new(32);
-		let mut worker = PubSubWorker {
-			client: pubsub::Client::new(api_url, node_template, override_host),
-			samples,
-		};
-		tokio::spawn(async move { worker.run().await });
+	pub fn new(
+		scope: &supervisor::Scope,
+		client: pubsub::Client,
+		format: pubsub::Format,
+		batching: Option<Batching>,
+		retry: Backoff,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let worker = Arc::new(PubSubWorker {
+			client,
+			format,
+			samples: supervisor::Resumable::new(samples),
+			batching,
+			retry,
+		});
+		let guard = scope.spawn("pubsub", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
 		Self {
 			samples: serializer,
+			guard,
 		}
 	}
 }
 
 impl traits::Sink for PubSubSink {
-	fn attach_source<'x>(&self, src: &'x dyn traits::Source) {
-		self.samples.attach(src.subscribe_to_samples())
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use xml::reader::{EventReader, XmlEvent};
+
+	use crate::metric;
+	use crate::testutil::StandIn;
+
+	fn readout(device_type: &str, instance: &str) -> payload::Readout {
+		metric::ReadoutBuilder::new()
+			.device_type(device_type)
+			.instance(instance)
+			.temperature(21.5)
+			.arc()
+	}
+
+	fn worker(
+		url: &str,
+		batching: Option<Batching>,
+	) -> (PubSubWorker, mpsc::Sender<payload::Sample>) {
+		let (tx, samples) = mpsc::channel(8);
+		let worker = PubSubWorker {
+			client: pubsub::Client::new(
+				url.into(),
+				"sensors/{device_type}".into(),
+				None,
+				http::Auth::Bearer {
+					token: "s3cr3t".into(),
+				},
+			),
+			format: pubsub::Format::default(),
+			samples: supervisor::Resumable::new(samples),
+			batching,
+			retry: Backoff {
+				max_retries: 2,
+				initial_backoff: Duration::from_millis(1),
+				max_backoff: Duration::from_millis(10),
+			},
+		};
+		(worker, tx)
+	}
+
+	/// Names of the elements in a document, in order.
+	fn element_names(doc: &str) -> Vec<String> {
+		EventReader::from_str(doc)
+			.into_iter()
+			.filter_map(|ev| match ev.unwrap() {
+				XmlEvent::StartElement { name, .. } => Some(name.local_name),
+				_ => None,
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn batches_readouts_per_node() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, tx) = worker(
+			&server.url,
+			Some(Batching {
+				flush_interval: Duration::from_secs(60),
+				max_readouts: 2,
+			}),
+		);
+		tx.send(vec![
+			readout("bme280", "a"),
+			readout("sht3x", "b"),
+			readout("bme280", "c"),
+		])
+		.await
+		.unwrap();
+		drop(tx);
+		worker.run().await;
+
+		let requests = server.requests();
+		assert_eq!(requests.len(), 2);
+		assert_eq!(requests[0].target(), "/sensors/bme280");
+		assert_eq!(requests[0].header("authorization"), Some("Bearer s3cr3t"));
+		assert_eq!(
+			element_names(&requests[0].body_str()),
+			vec![
+				"sample-batches",
+				"sample-batch",
+				"numeric",
+				"sample-batch",
+				"numeric"
+			]
+		);
+		assert_eq!(requests[1].target(), "/sensors/sht3x");
+		assert_eq!(
+			element_names(&requests[1].body_str()),
+			vec!["sample-batches", "sample-batch", "numeric"]
+		);
+	}
+
+	#[tokio::test]
+	async fn retries_unavailable_server() {
+		let server = StandIn::start(Box::new(|_| 503)).await;
+		let (worker, tx) = worker(&server.url, None);
+		tx.send(vec![readout("bme280", "a")]).await.unwrap();
+		drop(tx);
+		worker.run().await;
+
+		let requests = server.requests();
+		// the initial attempt and two retries
+		assert_eq!(requests.len(), 3);
+		assert_eq!(
+			element_names(&requests[2].body_str()),
+			vec!["sample-batch", "numeric"]
+		);
 	}
 }
@@ -6,45 +6,43 @@ use log::{error, warn};
 use tokio::select;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc;
-use tokio::sync::oneshot;
 
 use crate::relay;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::state::StateHandle;
+use super::supervisor;
 use super::traits;
 
 struct RelaySourceWorker {
 	sample_sink: broadcast::Sender<payload::Sample>,
 	stream_sink: broadcast::Sender<payload::Stream>,
-	stop_ch: oneshot::Receiver<()>,
 	socket: relay::RecvSocket,
 }
 
 impl RelaySourceWorker {
-	pub async fn run(&mut self) {
+	pub async fn run(&self) {
 		let mut recv_ch = self.socket.subscribe();
 		loop {
-			select! {
-				_ = &mut self.stop_ch => return,
-				v = recv_ch.recv() => match v {
-					Ok(relay::DataFrame::Readout(r)) => {
-						// we cannot use the result as indicator because the parent struct only holds on to senders, not to receivers.
-						// we use the stop_ch as a guard.
-						let _ = self.sample_sink.send(r.into());
-					},
-					Ok(relay::DataFrame::Stream(b)) => {
-						// we cannot use the result as indicator because the parent struct only holds on to senders, not to receivers.
-						// we use the stop_ch as a guard.
-						let _ = self.stream_sink.send(b.into());
-					},
-					Err(broadcast::error::RecvError::Closed) => {
-						// socket went down, close.
-						error!("lost RecvSocket somehow");
-						return;
-					},
-					Err(broadcast::error::RecvError::Lagged(n)) => warn!("lost {} relay messages", n),
-				},
+			match recv_ch.recv().await {
+				Ok(relay::DataFrame::Readout(r)) => {
+					// we cannot use the result as indicator because the parent struct only holds on to senders, not to receivers.
+					// the task guard stops us when the node goes away.
+					let _ = self.sample_sink.send(r.into());
+				}
+				Ok(relay::DataFrame::Stream(b)) => {
+					// we cannot use the result as indicator because the parent struct only holds on to senders, not to receivers.
+					// the task guard stops us when the node goes away.
+					let _ = self.stream_sink.send(b.into());
+				}
+				Err(broadcast::error::RecvError::Closed) => {
+					// socket went down, close.
+					error!("lost RecvSocket somehow");
+					return;
+				}
+				Err(broadcast::error::RecvError::Lagged(n)) => warn!("lost {} relay messages", n),
 			}
 		}
 	}
@@ -54,26 +52,34 @@ pub struct RelaySource {
 	sample_zygote: broadcast::Sender<payload::Sample>,
 	stream_zygote: broadcast::Sender<payload::Stream>,
 	#[allow(dead_code)]
-	guard: oneshot::Sender<()>,
+	guard: supervisor::TaskGuard,
 }
 
 impl RelaySource {
-	pub fn new(socket: tokio::net::TcpListener) -> Self {
+	pub fn new(
+		scope: &supervisor::Scope,
+		socket: tokio::net::TcpListener,
+		capacity: usize,
+	) -> Self {
 		let cfg = Arc::new(relay::SessionConfig {
 			soft_timeout: Duration::new(5, 0),
 			hard_timeout: Duration::new(30, 0),
 			session_timeout: Duration::new(1800, 0),
 		});
-		let (guard, stop_ch) = oneshot::channel();
-		let (sample_zygote, _) = broadcast::channel(8);
-		let (stream_zygote, _) = broadcast::channel(8);
-		let mut state = RelaySourceWorker {
+		let (sample_zygote, _) = broadcast::channel(capacity);
+		let (stream_zygote, _) = broadcast::channel(capacity);
+		let worker = Arc::new(RelaySourceWorker {
 			stream_sink: stream_zygote.clone(),
 			sample_sink: sample_zygote.clone(),
-			stop_ch,
 			socket: relay::RecvSocket::new(socket, cfg),
-		};
-		tokio::spawn(async move { state.run().await });
+		});
+		let guard = scope.spawn("relay", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
 		Self {
 			stream_zygote,
 			sample_zygote,
@@ -94,21 +100,23 @@ impl traits::Source for RelaySource {
 
 struct RelaySinkWorker {
 	sock: relay::SendSocket,
-	sample_source: mpsc::Receiver<payload::Sample>,
-	stream_source: mpsc::Receiver<payload::Stream>,
+	sample_source: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	stream_source: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
 }
 
 impl RelaySinkWorker {
-	async fn run(&mut self) {
+	async fn run(&self) {
+		let mut sample_source = self.sample_source.resume().await;
+		let mut stream_source = self.stream_source.resume().await;
 		loop {
 			select! {
-				v = self.sample_source.recv() => match v {
+				v = sample_source.recv() => match v {
 					Some(readout) => {
 						self.sock.send(relay::DataFrame::Readout(readout.into())).await
 					},
 					None => return,
 				},
-				v = self.stream_source.recv() => match v {
+				v = stream_source.recv() => match v {
 					Some(block) => {
 						self.sock.send(relay::DataFrame::Stream(block.into())).await
 					},
@@ -119,28 +127,75 @@ impl RelaySinkWorker {
 	}
 }
 
+/// Load the client id from the state, generating and storing a new one if
+/// there is none yet.
+fn persistent_client_id(state: &StateHandle) -> relay::frame::ClientId {
+	if let Some(client_id) = state
+		.get::<String>("client_id")
+		.and_then(|v| u128::from_str_radix(&v, 16).ok())
+	{
+		return client_id;
+	}
+	let client_id = relay::SendSocket::random_client_id();
+	// stored as string because JSON numbers cannot hold 128 bits
+	state.set("client_id", &format!("{:032x}", client_id));
+	client_id
+}
+
 pub struct RelaySink {
 	samples: Serializer<payload::Sample>,
 	stream: Serializer<payload::Stream>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl RelaySink {
-	pub fn new<T: tokio::net::ToSocketAddrs + Sync + Send + 'static>(addrs: T) -> Self {
-		let (samples, sample_source) = Serializer::new(8);
-		let (stream, stream_source) = Serializer::new(8);
-		let mut worker = RelaySinkWorker {
-			sample_source,
-			stream_source,
-			sock: relay::SendSocket::new(addrs),
-		};
-		tokio::spawn(async move { worker.run().await });
-		Self { samples, stream }
+	pub fn new<T: tokio::net::ToSocketAddrs + Sync + Send + 'static>(
+		scope: &supervisor::Scope,
+		addrs: T,
+		state: &StateHandle,
+		capacity: usize,
+	) -> Self {
+		let (samples, sample_source) = Serializer::new(capacity);
+		let (stream, stream_source) = Serializer::new(capacity);
+		let worker = Arc::new(RelaySinkWorker {
+			sample_source: supervisor::Resumable::new(sample_source),
+			stream_source: supervisor::Resumable::new(stream_source),
+			sock: relay::SendSocket::with_client_id(addrs, persistent_client_id(state)),
+		});
+		let guard = scope.spawn("relay", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
+		Self {
+			samples,
+			stream,
+			guard,
+		}
 	}
 }
 
 impl traits::Sink for RelaySink {
-	fn attach_source<'x>(&self, src: &'x dyn traits::Source) {
-		self.samples.attach(src.subscribe_to_samples());
-		self.stream.attach(src.subscribe_to_streams());
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+		self.stream.attach(src.subscribe_to_streams(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn client_id_is_reused_from_state() {
+		let state = StateHandle::ephemeral();
+		let first = persistent_client_id(&state);
+		assert_eq!(persistent_client_id(&state), first);
+		state.set("client_id", "2a");
+		assert_eq!(persistent_client_id(&state), 42);
 	}
 }
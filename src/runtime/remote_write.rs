@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::http;
+use crate::prometheus;
+use crate::prometheus::remote_write;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::retry::Backoff;
+use super::supervisor;
+use super::traits;
+
+/// How readouts are turned into labelled series.
+pub struct Labels {
+	/// Prepended to all metric names.
+	pub prefix: String,
+	/// Label carrying the instance of the device path.
+	pub instance_label: String,
+	/// Added to all series, unless a derived label has the same name.
+	pub extra: Vec<(String, String)>,
+}
+
+/// How samples are grouped into requests.
+pub struct Batching {
+	/// Maximum time samples are held back to wait for more.
+	pub flush_interval: Duration,
+	pub max_samples: usize,
+}
+
+/// Series collected for a single request.
+#[derive(Default)]
+struct PendingRequest {
+	series: Vec<remote_write::TimeSeries>,
+	index: HashMap<Vec<(String, String)>, usize>,
+	samples: usize,
+	since: Option<Instant>,
+}
+
+impl PendingRequest {
+	fn push(&mut self, labels: Vec<(String, String)>, sample: remote_write::Sample) {
+		let series = &mut self.series;
+		let i = *self.index.entry(labels).or_insert_with_key(|labels| {
+			series.push(remote_write::TimeSeries {
+				labels: labels
+					.iter()
+					.map(|(name, value)| remote_write::Label {
+						name: name.clone(),
+						value: value.clone(),
+					})
+					.collect(),
+				samples: Vec::new(),
+			});
+			series.len() - 1
+		});
+		self.series[i].samples.push(sample);
+		self.samples += 1;
+		self.since.get_or_insert_with(Instant::now);
+	}
+
+	fn take(&mut self) -> remote_write::WriteRequest {
+		let mut timeseries = std::mem::take(&mut self.series);
+		for series in timeseries.iter_mut() {
+			series.samples.sort_by_key(|s| s.timestamp);
+		}
+		*self = Self::default();
+		remote_write::WriteRequest { timeseries }
+	}
+}
+
+struct RemoteWriteWorker {
+	client: remote_write::Client,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	labels: Labels,
+	batching: Batching,
+	retry: Backoff,
+}
+
+impl RemoteWriteWorker {
+	fn add(&self, pending: &mut PendingRequest, sample: &payload::Sample) {
+		for readout in sample.iter() {
+			for (component, value) in readout.components.iter() {
+				let series = prometheus::component_series(
+					&self.labels.prefix,
+					&self.labels.instance_label,
+					&readout.path,
+					component,
+					value,
+					readout.timestamp,
+				);
+				let mut labels = vec![("__name__".to_string(), series.sample_name())];
+				labels.extend(series.labels);
+				for (name, value) in self.labels.extra.iter() {
+					if !labels.iter().any(|(k, _)| k == name) {
+						labels.push((name.clone(), value.clone()));
+					}
+				}
+				labels.sort();
+				pending.push(
+					labels,
+					remote_write::Sample {
+						value: series.value,
+						timestamp: series.timestamp.timestamp_millis(),
+					},
+				);
+			}
+		}
+	}
+
+	async fn send(&self, request: remote_write::WriteRequest) {
+		let result = self
+			.retry
+			.run(
+				"remote write",
+				|e: &http::Error| e.is_retryable(),
+				|| self.client.post(&request),
+			)
+			.await;
+		if let Err(e) = result {
+			warn!(
+				"lost {} series: failed to remote write: {}",
+				request.timeseries.len(),
+				e
+			);
+		}
+	}
+
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		let mut pending = PendingRequest::default();
+		loop {
+			let deadline = pending.since.map(|x| x + self.batching.flush_interval);
+			tokio::select! {
+				v = samples.recv() => match v {
+					Some(sample) => {
+						self.add(&mut pending, &sample);
+						if pending.samples >= self.batching.max_samples {
+							self.send(pending.take()).await;
+						}
+					}
+					None => break,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					self.send(pending.take()).await;
+				}
+			}
+		}
+		if pending.samples > 0 {
+			self.send(pending.take()).await;
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Pushes samples to a Prometheus remote write receiver.
+pub struct RemoteWriteSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl RemoteWriteSink {
+	pub fn new(
+		scope: &supervisor::Scope,
+		url: String,
+		auth: http::Auth,
+		labels: Labels,
+		batching: Batching,
+		retry: Backoff,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let worker = Arc::new(RemoteWriteWorker {
+			client: remote_write::Client::new(url, auth),
+			samples: supervisor::Resumable::new(samples),
+			labels,
+			batching,
+			retry,
+		});
+		let guard = scope.spawn("remote_write", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
+		Self {
+			samples: serializer,
+			guard,
+		}
+	}
+}
+
+impl traits::Sink for RemoteWriteSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::metric;
+	use crate::testutil::StandIn;
+
+	fn readout(instance: &str, t: i64, value: f64, unit: metric::Unit) -> Arc<metric::Readout> {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.at(t)
+			.component("temperature", value, unit)
+			.arc()
+	}
+
+	fn worker(url: &str, max_samples: usize) -> (RemoteWriteWorker, mpsc::Sender<payload::Sample>) {
+		let (tx, samples) = mpsc::channel(8);
+		let worker = RemoteWriteWorker {
+			client: remote_write::Client::new(format!("{}/api/v1/write", url), http::Auth::None),
+			samples: supervisor::Resumable::new(samples),
+			labels: Labels {
+				prefix: "".into(),
+				instance_label: "device".into(),
+				extra: vec![
+					("site".into(), "lab".into()),
+					("device".into(), "ignored".into()),
+				],
+			},
+			batching: Batching {
+				flush_interval: Duration::from_secs(60),
+				max_samples,
+			},
+			retry: Backoff {
+				max_retries: 2,
+				initial_backoff: Duration::from_millis(1),
+				max_backoff: Duration::from_millis(10),
+			},
+		};
+		(worker, tx)
+	}
+
+	fn label<'x>(series: &'x remote_write::TimeSeries, name: &str) -> Option<&'x str> {
+		series
+			.labels
+			.iter()
+			.find(|l| l.name == name)
+			.map(|l| l.value.as_str())
+	}
+
+	#[tokio::test]
+	async fn batches_samples_into_labelled_series() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, tx) = worker(&server.url, 3);
+		let run = tokio::spawn(async move { worker.run().await });
+		tx.send(vec![
+			readout("a", 2, 21.0, metric::Unit::Celsius),
+			readout("b", 1, 19.0, metric::Unit::Celsius),
+		])
+		.await
+		.unwrap();
+		tx.send(vec![readout("a", 1, 20.0, metric::Unit::Celsius)])
+			.await
+			.unwrap();
+		tx.send(vec![readout("a", 3, 5.0, metric::Unit::Total)])
+			.await
+			.unwrap();
+		drop(tx);
+		run.await.unwrap();
+
+		let requests = server.requests();
+		assert_eq!(requests.len(), 2);
+		let first = remote_write::WriteRequest::decode_body(&requests[0].body).unwrap();
+		assert_eq!(first.timeseries.len(), 2);
+		let a = &first.timeseries[0];
+		let names: Vec<_> = a.labels.iter().map(|l| l.name.as_str()).collect();
+		assert_eq!(names, vec!["__name__", "device", "site"]);
+		assert_eq!(label(a, "__name__"), Some("bme280_temperature_celsius"));
+		assert_eq!(label(a, "device"), Some("a"));
+		assert_eq!(label(a, "site"), Some("lab"));
+		let timestamps: Vec<_> = a.samples.iter().map(|s| s.timestamp).collect();
+		assert_eq!(timestamps, vec![1000, 2000]);
+
+		let second = remote_write::WriteRequest::decode_body(&requests[1].body).unwrap();
+		assert_eq!(
+			label(&second.timeseries[0], "__name__"),
+			Some("bme280_temperature_total")
+		);
+	}
+
+	#[tokio::test]
+	async fn retries_unavailable_receiver() {
+		let server = StandIn::start(Box::new(|_| 503)).await;
+		let (worker, _tx) = worker(&server.url, 1);
+		let mut pending = PendingRequest::default();
+		worker.add(
+			&mut pending,
+			&vec![readout("a", 1, 1.0, metric::Unit::Arbitrary)],
+		);
+		worker.send(pending.take()).await;
+		assert_eq!(server.requests().len(), 3);
+
+		server.set_responder(Box::new(|_| 400));
+		worker.add(
+			&mut pending,
+			&vec![readout("a", 1, 1.0, metric::Unit::Arbitrary)],
+		);
+		worker.send(pending.take()).await;
+		assert_eq!(server.requests().len(), 4);
+	}
+}
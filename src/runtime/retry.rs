@@ -1,13 +1,22 @@
+use std::fmt;
+use std::future::Future;
 use std::io;
 use std::ops::Fn;
 
+use log::debug;
+
+use schemars::JsonSchema;
+use serde_derive::Deserialize;
+
 use tokio::time::Duration;
 
+#[cfg_attr(not(feature = "sbm"), allow(dead_code))]
 pub struct Retry<T: ?Sized> {
 	interval: Duration,
 	inner: Box<T>,
 }
 
+#[cfg_attr(not(feature = "sbm"), allow(dead_code))]
 impl<U, T: Fn() -> io::Result<U> + ?Sized> Retry<T> {
 	pub fn new(inner: Box<T>, interval: Duration) -> Self {
 		Self { inner, interval }
@@ -25,3 +34,199 @@ impl<U, T: Fn() -> io::Result<U> + ?Sized> Retry<T> {
 		}
 	}
 }
+
+/// How hard a sink tries to get something delivered: failed attempts are
+/// retried with exponentially growing pauses in between.
+#[cfg_attr(not(feature = "retry"), allow(dead_code))]
+#[derive(Debug, Clone)]
+pub struct Backoff {
+	/// Number of retries after the initial attempt.
+	pub max_retries: u32,
+	pub initial_backoff: Duration,
+	pub max_backoff: Duration,
+}
+
+fn default_retry_max_retries() -> u32 {
+	3
+}
+
+fn default_retry_initial_backoff_ms() -> u64 {
+	1000
+}
+
+fn default_retry_max_backoff_ms() -> u64 {
+	30000
+}
+
+/// Retries of deliveries which failed for temporary reasons, such as
+/// timeouts or server errors.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[cfg_attr(not(feature = "retry"), allow(dead_code))]
+#[serde(deny_unknown_fields)]
+pub struct RetryConfig {
+	#[serde(default = "default_retry_max_retries")]
+	max_retries: u32,
+	#[serde(default = "default_retry_initial_backoff_ms")]
+	initial_backoff_ms: u64,
+	#[serde(default = "default_retry_max_backoff_ms")]
+	max_backoff_ms: u64,
+}
+
+#[cfg_attr(not(feature = "retry"), allow(dead_code))]
+impl RetryConfig {
+	pub fn build(&self) -> Backoff {
+		Backoff {
+			max_retries: self.max_retries,
+			initial_backoff: Duration::from_millis(self.initial_backoff_ms),
+			max_backoff: Duration::from_millis(self.max_backoff_ms.max(self.initial_backoff_ms)),
+		}
+	}
+}
+
+impl Default for RetryConfig {
+	fn default() -> Self {
+		Self {
+			max_retries: default_retry_max_retries(),
+			initial_backoff_ms: default_retry_initial_backoff_ms(),
+			max_backoff_ms: default_retry_max_backoff_ms(),
+		}
+	}
+}
+
+#[cfg_attr(not(feature = "retry"), allow(dead_code))]
+impl Backoff {
+	/// Run `attempt` until it succeeds, fails with an error which is not
+	/// `retryable`, or the retries are used up.
+	///
+	/// `state` is handed from each attempt to the next one, e.g. a
+	/// connection to reuse or the part of a request which has not been
+	/// delivered yet. The outcome of the last attempt is returned together
+	/// with the state it left behind.
+	pub async fn run_with<S, T, E, F, Fut, R>(
+		&self,
+		what: &str,
+		mut state: S,
+		retryable: R,
+		mut attempt: F,
+	) -> (Result<T, E>, S)
+	where
+		E: fmt::Display,
+		F: FnMut(S) -> Fut,
+		Fut: Future<Output = (Result<T, E>, S)>,
+		R: Fn(&E) -> bool,
+	{
+		let mut backoff = self.initial_backoff;
+		let mut retries = 0;
+		loop {
+			let (result, next) = attempt(state).await;
+			state = next;
+			match result {
+				Err(e) if retries < self.max_retries && retryable(&e) => {
+					debug!("failed to {}, retrying in {:?}: {}", what, backoff, e);
+					tokio::time::sleep(backoff).await;
+					backoff = (backoff * 2).min(self.max_backoff);
+					retries += 1;
+				}
+				result => return (result, state),
+			}
+		}
+	}
+
+	/// Like [`Self::run_with`], for attempts which do not carry any state.
+	#[cfg_attr(
+		not(any(
+			feature = "pubsub",
+			feature = "prometheus-remote-write",
+			feature = "webhook"
+		)),
+		allow(dead_code)
+	)]
+	pub async fn run<T, E, F, Fut, R>(
+		&self,
+		what: &str,
+		retryable: R,
+		mut attempt: F,
+	) -> Result<T, E>
+	where
+		E: fmt::Display,
+		F: FnMut() -> Fut,
+		Fut: Future<Output = Result<T, E>>,
+		R: Fn(&E) -> bool,
+	{
+		self.run_with(what, (), retryable, |()| {
+			let fut = attempt();
+			async move { (fut.await, ()) }
+		})
+		.await
+		.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::cell::Cell;
+
+	fn backoff(max_retries: u32) -> Backoff {
+		Backoff {
+			max_retries,
+			initial_backoff: Duration::from_millis(1),
+			max_backoff: Duration::from_millis(2),
+		}
+	}
+
+	#[tokio::test]
+	async fn gives_up_after_max_retries() {
+		let calls = Cell::new(0);
+		let result: Result<(), &str> = backoff(2)
+			.run(
+				"test",
+				|_| true,
+				|| {
+					calls.set(calls.get() + 1);
+					async { Err("nope") }
+				},
+			)
+			.await;
+		assert_eq!(result, Err("nope"));
+		assert_eq!(calls.get(), 3);
+	}
+
+	#[tokio::test]
+	async fn stops_at_non_retryable_error() {
+		let calls = Cell::new(0);
+		let result: Result<(), &str> = backoff(5)
+			.run(
+				"test",
+				|e| *e != "fatal",
+				|| {
+					calls.set(calls.get() + 1);
+					async { Err("fatal") }
+				},
+			)
+			.await;
+		assert_eq!(result, Err("fatal"));
+		assert_eq!(calls.get(), 1);
+	}
+
+	#[tokio::test]
+	async fn hands_state_to_next_attempt() {
+		let (result, state) = backoff(5)
+			.run_with(
+				"test",
+				0u32,
+				|_| true,
+				|n| async move {
+					if n < 3 {
+						(Err("again"), n + 1)
+					} else {
+						(Ok(n), n)
+					}
+				},
+			)
+			.await;
+		assert_eq!(result, Ok(3));
+		assert_eq!(state, 3);
+	}
+}
@@ -7,11 +7,17 @@ use tokio::sync::mpsc;
 
 use super::adapter::Serializer;
 use super::filter::Filter;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{Sink, Source};
 
 struct RouterWorker {
 	filters: Vec<Box<dyn Filter>>,
+	sample_source: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	stream_source: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
+	sample_sink: broadcast::Sender<payload::Sample>,
+	stream_sink: broadcast::Sender<payload::Stream>,
 }
 
 fn process_readouts(filters: &Vec<Box<dyn Filter>>, readouts: &mut payload::Sample) {
@@ -31,27 +37,42 @@ fn process_readouts(filters: &Vec<Box<dyn Filter>>, readouts: &mut payload::Samp
 
 impl RouterWorker {
 	fn spawn(
+		scope: &supervisor::Scope,
 		filters: Vec<Box<dyn Filter>>,
 		sample_source: mpsc::Receiver<payload::Sample>,
 		stream_source: mpsc::Receiver<payload::Stream>,
 		sample_sink: broadcast::Sender<payload::Sample>,
 		stream_sink: broadcast::Sender<payload::Stream>,
-	) {
-		let sample_worker = Arc::new(RouterWorker { filters });
-		let stream_worker = sample_worker.clone();
-		tokio::spawn(async move {
-			sample_worker.run_samples(sample_source, sample_sink).await;
-		});
-		tokio::spawn(async move {
-			stream_worker.run_streams(stream_source, stream_sink).await;
+	) -> Vec<supervisor::TaskGuard> {
+		let sample_worker = Arc::new(RouterWorker {
+			filters,
+			sample_source: supervisor::Resumable::new(sample_source),
+			stream_source: supervisor::Resumable::new(stream_source),
+			sample_sink,
+			stream_sink,
 		});
+		let stream_worker = sample_worker.clone();
+		vec![
+			scope.spawn("samples", move || {
+				let worker = sample_worker.clone();
+				async move {
+					worker.run_samples().await;
+					Ok(())
+				}
+			}),
+			scope.spawn("streams", move || {
+				let worker = stream_worker.clone();
+				async move {
+					worker.run_streams().await;
+					Ok(())
+				}
+			}),
+		]
 	}
 
-	async fn run_samples(
-		&self,
-		mut source: mpsc::Receiver<payload::Sample>,
-		sink: broadcast::Sender<payload::Sample>,
-	) {
+	async fn run_samples(&self) {
+		let mut source = self.sample_source.resume().await;
+		let sink = &self.sample_sink;
 		loop {
 			let mut readouts = match source.recv().await {
 				Some(item) => item,
@@ -78,11 +99,9 @@ impl RouterWorker {
 		}
 	}
 
-	async fn run_streams(
-		&self,
-		mut source: mpsc::Receiver<payload::Stream>,
-		sink: broadcast::Sender<payload::Stream>,
-	) {
+	async fn run_streams(&self) {
+		let mut source = self.stream_source.resume().await;
+		let sink = &self.stream_sink;
 		loop {
 			let mut item = match source.recv().await {
 				Some(item) => item,
@@ -112,15 +131,18 @@ pub struct Router {
 	streams: Serializer<payload::Stream>,
 	sample_zygote: broadcast::Sender<payload::Sample>,
 	stream_zygote: broadcast::Sender<payload::Stream>,
+	#[allow(dead_code)]
+	guards: Vec<supervisor::TaskGuard>,
 }
 
 impl Router {
-	pub fn new(filters: Vec<Box<dyn Filter>>) -> Self {
-		let (sample_zygote, _) = broadcast::channel(128);
+	pub fn new(scope: &supervisor::Scope, filters: Vec<Box<dyn Filter>>, capacity: usize) -> Self {
+		let (sample_zygote, _) = broadcast::channel(capacity);
 		let (samples, sample_source) = Serializer::new(128);
-		let (stream_zygote, _) = broadcast::channel(128);
+		let (stream_zygote, _) = broadcast::channel(capacity);
 		let (streams, stream_source) = Serializer::new(128);
-		RouterWorker::spawn(
+		let guards = RouterWorker::spawn(
+			scope,
 			filters,
 			sample_source,
 			stream_source,
@@ -132,6 +154,7 @@ impl Router {
 			streams,
 			sample_zygote,
 			stream_zygote,
+			guards,
 		}
 	}
 }
@@ -147,8 +170,10 @@ impl Source for Router {
 }
 
 impl Sink for Router {
-	fn attach_source<'x>(&self, src: &'x dyn Source) {
-		self.samples.attach(src.subscribe_to_samples());
-		self.streams.attach(src.subscribe_to_streams());
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+		self.streams
+			.attach(src.subscribe_to_streams(), link.clone());
 	}
 }
@@ -7,7 +7,9 @@ use tokio::sync::{broadcast, mpsc};
 use crate::metric::{DevicePath, OrderedVec, Readout, Value};
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Sink, Source};
 
 pub enum ComponentMode {
@@ -29,10 +31,56 @@ impl ComponentMode {
 	}
 }
 
+/// Expand a stream block into one readout per (unmasked) sample, keeping
+/// only every `step`-th sample as counted by sequence number.
+pub fn samplify_block(
+	block: &payload::Stream,
+	component: &ComponentMode,
+	step: usize,
+) -> payload::Sample {
+	let step = step.max(1);
+	let mut samples = Vec::new();
+	for (i, sample) in block.data.iter().enumerate() {
+		if !(block.seq0.wrapping_add(i as u16) as usize).is_multiple_of(step) {
+			continue;
+		}
+		let normalized = match sample.normalized() {
+			Some(v) => v,
+			None => continue,
+		};
+		let mut path = block.path.clone();
+		let component = match component.create(&mut path) {
+			Some(v) => v,
+			None => "value".into(),
+		};
+		let timestamp = block.t0
+			+ match chrono::Duration::from_std((i as u32) * block.period) {
+				Ok(v) => v,
+				Err(e) => {
+					log::warn!(
+						"discarding stream sample: cannot calculate timestamp: {}",
+						e
+					);
+					continue;
+				}
+			};
+		let value = Value {
+			magnitude: block.scale.magnitude * normalized,
+			unit: block.scale.unit.clone(),
+		};
+		samples.push(Arc::new(Readout {
+			timestamp,
+			path,
+			components: OrderedVec::single(component.clone(), value),
+		}));
+	}
+	samples
+}
+
 async fn samplify(
-	component: ComponentMode,
-	mut stream_source: mpsc::Receiver<payload::Stream>,
-	sample_sink: broadcast::Sender<payload::Sample>,
+	component: &ComponentMode,
+	stream_source: &mut mpsc::Receiver<payload::Stream>,
+	sample_sink: &broadcast::Sender<payload::Sample>,
 ) {
 	loop {
 		let block = match stream_source.recv().await {
@@ -40,38 +88,7 @@ async fn samplify(
 			None => return,
 		};
 
-		let mut samples = Vec::new();
-		for (i, sample) in block.data.iter().enumerate() {
-			let normalized = match sample.normalized() {
-				Some(v) => v,
-				None => continue,
-			};
-			let mut path = block.path.clone();
-			let component = match component.create(&mut path) {
-				Some(v) => v,
-				None => "value".into(),
-			};
-			let timestamp = block.t0
-				+ match chrono::Duration::from_std((i as u32) * block.period) {
-					Ok(v) => v,
-					Err(e) => {
-						log::warn!(
-							"discarding stream sample: cannot calculate timestamp: {}",
-							e
-						);
-						continue;
-					}
-				};
-			let value = Value {
-				magnitude: block.scale.magnitude * normalized,
-				unit: block.scale.unit.clone(),
-			};
-			samples.push(Arc::new(Readout {
-				timestamp,
-				path,
-				components: OrderedVec::single(component.clone(), value),
-			}));
-		}
+		let samples = samplify_block(&block, component, 1);
 		match sample_sink.send(samples) {
 			Ok(_) => (),
 			Err(_) => {
@@ -84,17 +101,31 @@ async fn samplify(
 pub struct Samplify {
 	streams: Serializer<payload::Stream>,
 	sample_zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Samplify {
-	pub fn new(component: ComponentMode) -> Self {
+	pub fn new(scope: &supervisor::Scope, component: ComponentMode, capacity: usize) -> Self {
 		let (streams, stream_source) = Serializer::new(128);
-		let (sample_zygote, _) = broadcast::channel(128);
+		let (sample_zygote, _) = broadcast::channel(capacity);
 		let sample_sink = sample_zygote.clone();
-		tokio::spawn(async move { samplify(component, stream_source, sample_sink).await });
+		let component = Arc::new(component);
+		let stream_source = supervisor::Resumable::new(stream_source);
+		let guard = scope.spawn("samplify", move || {
+			let component = component.clone();
+			let stream_source = stream_source.clone();
+			let sample_sink = sample_sink.clone();
+			async move {
+				let mut stream_source = stream_source.resume().await;
+				samplify(&component, &mut stream_source, &sample_sink).await;
+				Ok(())
+			}
+		});
 		Self {
 			streams,
 			sample_zygote,
+			guard,
 		}
 	}
 }
@@ -110,7 +141,8 @@ impl Source for Samplify {
 }
 
 impl Sink for Samplify {
-	fn attach_source<'x>(&self, src: &'x dyn Source) {
-		self.streams.attach(src.subscribe_to_streams())
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>) {
+		self.streams
+			.attach(src.subscribe_to_streams(), link.clone())
 	}
 }
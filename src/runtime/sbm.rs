@@ -4,7 +4,6 @@ use std::sync::Arc;
 use log::{debug, trace, warn};
 
 use tokio::sync::broadcast;
-use tokio::sync::oneshot;
 
 use chrono::{DateTime, TimeZone, Utc};
 
@@ -15,10 +14,13 @@ use crate::sbm::{DynSampleIterator, ReadoutIterable};
 use crate::snurl;
 
 use super::payload;
+use super::supervisor;
 use super::traits;
 
 pub type EndpointFactory = Box<dyn Fn() -> io::Result<snurl::Endpoint> + Send + Sync + 'static>;
+pub type PassthroughFactory = Box<dyn Fn() -> Box<dyn HandlePassthrough> + Send + Sync + 'static>;
 
+#[derive(Clone)]
 pub struct Sinks {
 	sample_sink: broadcast::Sender<payload::Sample>,
 	stream_sink: broadcast::Sender<payload::Stream>,
@@ -53,13 +55,28 @@ impl Sinks {
 	}
 }
 
+/// Failure to handle a single message.
+#[derive(Debug)]
+pub enum HandleError {
+	/// The message could not be decoded and is dropped.
+	Malformed(io::Error),
+	/// The handler cannot continue; the worker has to be restarted.
+	Fatal(supervisor::TaskError),
+}
+
+impl From<io::Error> for HandleError {
+	fn from(other: io::Error) -> Self {
+		Self::Malformed(other)
+	}
+}
+
 pub trait HandlePassthrough: Send + Sync {
 	fn handle(
 		&mut self,
 		timestamp: Option<DateTime<Utc>>,
 		data: Bytes,
 		sinks: &mut Sinks,
-	) -> io::Result<()>;
+	) -> Result<(), HandleError>;
 
 	fn reset(&mut self);
 }
@@ -69,43 +86,59 @@ pub(super) struct SbmSourceWorker {
 	rewrite_bme68x: bool,
 	sinks: Sinks,
 	passthrough: Option<Box<dyn HandlePassthrough + 'static>>,
-	stop_ch: oneshot::Receiver<()>,
 }
 
 enum Error {
 	ConnectionLost,
-	Shutdown,
+	Fatal(supervisor::TaskError),
 }
 
 impl SbmSourceWorker {
 	pub fn spawn_with_snurl(
+		scope: &supervisor::Scope,
 		epf: EndpointFactory,
 		path_prefix: String,
 		rewrite_bme68x: bool,
 		sample_sink: broadcast::Sender<payload::Sample>,
 		stream_sink: broadcast::Sender<payload::Stream>,
-		passthrough: Option<Box<dyn HandlePassthrough + 'static>>,
-		stop_ch: oneshot::Receiver<()>,
-	) -> io::Result<()> {
-		let ep = Box::new(epf()?);
-
-		let mut worker = Self {
-			path_prefix,
-			rewrite_bme68x,
-			sinks: Sinks {
-				sample_sink,
-				stream_sink,
-			},
-			passthrough,
-			stop_ch,
+		passthrough: Option<PassthroughFactory>,
+	) -> io::Result<supervisor::TaskGuard> {
+		// the first endpoint is created synchronously so that configuration
+		// errors surface when building the node
+		let mut initial_ep = Some(Box::new(epf()?));
+		let epf = Arc::new(epf);
+		let sinks = Sinks {
+			sample_sink,
+			stream_sink,
 		};
-		tokio::spawn(async move {
-			worker.run_with_snurl(ep, epf).await;
-		});
-		Ok(())
+
+		Ok(scope.spawn("snurl", move || {
+			let mut worker = Self {
+				path_prefix: path_prefix.clone(),
+				rewrite_bme68x,
+				sinks: sinks.clone(),
+				passthrough: passthrough.as_ref().map(|f| f()),
+			};
+			let initial_ep = initial_ep.take();
+			let epf = epf.clone();
+			async move {
+				let epf: EndpointFactory = Box::new(move || epf());
+				let epf = super::retry::Retry::new(epf, tokio::time::Duration::new(1, 0));
+				let ep = match initial_ep {
+					Some(ep) => ep,
+					None => Box::new(
+						epf.obtain(|e| {
+							warn!("failed to re-establish SNURL endpoint: {}", e);
+						})
+						.await,
+					),
+				};
+				worker.run_with_snurl(ep, epf).await
+			}
+		}))
 	}
 
-	fn process_buf(&mut self, mut src: Bytes) -> std::io::Result<()> {
+	fn process_buf(&mut self, mut src: Bytes) -> Result<(), HandleError> {
 		let hdr = sbm::EspMessageHeader::read(&mut src)?;
 		trace!("processing message {:?} {:?}", hdr, src);
 		let timestamp = if hdr.timestamp >= 86400 {
@@ -163,45 +196,43 @@ impl SbmSourceWorker {
 	}
 
 	async fn process_one(&mut self, ep: &mut snurl::Endpoint) -> Result<(), Error> {
-		tokio::select! {
-			received = ep.recv_data() => {
-				match received {
-					// the socket was closed somehow? not sure how that could happen, but we need to shutdown then
-					// well as it turns out it can happen when the network goes down, who would've thought.
-					// which can happen when the router reboots because wifi.
-					// which means we need to be smarter here than that.'
-					None => {
-						warn!("SBX SNURL endpoint closed unexpectedly");
-						Err(Error::ConnectionLost)
-					},
-					Some(snurl::RecvItem::ResyncMarker) => {
-						if let Some(pt) = self.passthrough.as_mut() {
-							pt.reset();
-						}
-						Ok(())
-					},
-					Some(snurl::RecvItem::Data(buf)) => {
-						match self.process_buf(buf) {
-							Ok(()) => (),
-							Err(e) => warn!("malformed packet received: {}", e),
-						}
-						Ok(())
-					},
+		match ep.recv_data().await {
+			// the socket was closed somehow? not sure how that could happen, but we need to shutdown then
+			// well as it turns out it can happen when the network goes down, who would've thought.
+			// which can happen when the router reboots because wifi.
+			// which means we need to be smarter here than that.'
+			None => {
+				warn!("SBX SNURL endpoint closed unexpectedly");
+				Err(Error::ConnectionLost)
+			}
+			Some(snurl::RecvItem::ResyncMarker) => {
+				if let Some(pt) = self.passthrough.as_mut() {
+					pt.reset();
+				}
+				Ok(())
+			}
+			Some(snurl::RecvItem::Data(buf)) => {
+				match self.process_buf(buf) {
+					Ok(()) => (),
+					Err(HandleError::Malformed(e)) => warn!("malformed packet received: {}", e),
+					Err(HandleError::Fatal(e)) => return Err(Error::Fatal(e)),
 				}
-			},
-			_ = &mut self.stop_ch => {
-				// SBXSource dropped, shut down
-				// this will cascade down to our recipients then :)
-				Err(Error::Shutdown)
-			},
+				Ok(())
+			}
 		}
 	}
 
-	async fn run_with_snurl(&mut self, mut ep: Box<snurl::Endpoint>, epf: EndpointFactory) {
-		let epf = super::retry::Retry::new(epf, tokio::time::Duration::new(1, 0));
+	// shutdown happens by the supervisor aborting the task when the source
+	// is dropped, which cascades down to our recipients then :)
+	async fn run_with_snurl(
+		&mut self,
+		mut ep: Box<snurl::Endpoint>,
+		epf: super::retry::Retry<dyn Fn() -> io::Result<snurl::Endpoint> + Send + Sync>,
+	) -> supervisor::TaskResult {
 		loop {
 			match self.process_one(&mut ep).await {
 				Ok(()) => (),
+				Err(Error::Fatal(e)) => return Err(e),
 				Err(Error::ConnectionLost) => {
 					ep = Box::new(
 						epf.obtain(|e| {
@@ -210,7 +241,6 @@ impl SbmSourceWorker {
 						.await,
 					);
 				}
-				Err(Error::Shutdown) => return,
 			}
 		}
 	}
@@ -220,26 +250,28 @@ pub struct MininodeSource {
 	sample_zygote: broadcast::Sender<payload::Sample>,
 	stream_zygote: broadcast::Sender<payload::Stream>,
 	#[allow(dead_code)]
-	guard: oneshot::Sender<()>,
+	guard: supervisor::TaskGuard,
 }
 
 impl MininodeSource {
 	pub fn new(
+		scope: &supervisor::Scope,
 		epf: EndpointFactory,
 		path_prefix: String,
 		rewrite_bme68x: bool,
+		sample_capacity: usize,
+		stream_capacity: usize,
 	) -> io::Result<Self> {
-		let (sample_zygote, _) = broadcast::channel(384);
-		let (stream_zygote, _) = broadcast::channel(1024);
-		let (guard, stop_ch) = oneshot::channel();
-		SbmSourceWorker::spawn_with_snurl(
+		let (sample_zygote, _) = broadcast::channel(sample_capacity);
+		let (stream_zygote, _) = broadcast::channel(stream_capacity);
+		let guard = SbmSourceWorker::spawn_with_snurl(
+			scope,
 			epf,
 			path_prefix,
 			rewrite_bme68x,
 			sample_zygote.clone(),
 			stream_zygote.clone(),
 			None,
-			stop_ch,
 		)?;
 		Ok(Self {
 			sample_zygote,
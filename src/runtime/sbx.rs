@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::io;
 use std::sync::Arc;
 use std::time::Duration;
@@ -7,7 +8,6 @@ use log::{debug, info, trace, warn};
 #[cfg(feature = "serial")]
 use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::sync::broadcast;
-use tokio::sync::oneshot;
 
 use chrono::{DateTime, Duration as ChronoDuration, Utc};
 
@@ -17,7 +17,7 @@ use enum_map::{enum_map, EnumMap};
 use bytes::Buf;
 use bytes::Bytes;
 
-use super::sbm::{HandlePassthrough, SbmSourceWorker, Sinks};
+use super::sbm::{HandleError, HandlePassthrough, PassthroughFactory, SbmSourceWorker, Sinks};
 
 use crate::metric;
 use crate::sbx;
@@ -26,13 +26,15 @@ use crate::snurl;
 use crate::stream;
 
 use super::payload;
+use super::state::StateHandle;
+use super::supervisor;
 use super::traits;
 
 pub struct SBXSource {
 	sample_zygote: broadcast::Sender<payload::Sample>,
 	stream_zygote: broadcast::Sender<payload::Stream>,
 	#[allow(dead_code)]
-	guard: oneshot::Sender<()>,
+	guard: supervisor::TaskGuard,
 }
 
 pub type EndpointFactory = Box<dyn Fn() -> io::Result<snurl::Endpoint> + Send + Sync + 'static>;
@@ -43,10 +45,26 @@ struct SbxHandler {
 	rtcifier: sbx::RangeRTC,
 	stream_decoders: EnumMap<sbx::StreamKind, sbx::StreamDecoder<stream::InMemoryBuffer>>,
 	buffer: Vec<Box<sbx::Message>>,
+	state: StateHandle,
+	rtc_snapshot: Option<sbx::RangeRTCSnapshot>,
+	stream_snapshots: EnumMap<sbx::StreamKind, Option<sbx::LinearRTCSnapshot>>,
+}
+
+impl From<sbx::TimelineOverflow> for HandleError {
+	fn from(other: sbx::TimelineOverflow) -> Self {
+		Self::Fatal(Box::new(other))
+	}
+}
+
+const STATE_RTC: &str = "rtc";
+const STATE_STREAMS: &str = "streams";
+
+fn stream_state_key(kind: sbx::StreamKind) -> String {
+	format!("{:?}", kind)
 }
 
 impl SbxHandler {
-	fn new(path_prefix: String, rewrite_bme68x: bool) -> Self {
+	fn new(path_prefix: String, rewrite_bme68x: bool, state: StateHandle) -> Self {
 		let accel_period = Duration::from_millis(5);
 		let accel_slice = ChronoDuration::seconds(60);
 		let accel_scale = metric::Value {
@@ -61,6 +79,15 @@ impl SbxHandler {
 			unit: metric::Unit::Tesla,
 		};
 
+		// the saved alignment is only applied once we know that it still
+		// matches the device, see handle()
+		let rtc_snapshot = state.get(STATE_RTC);
+		let mut saved_streams: BTreeMap<String, sbx::LinearRTCSnapshot> =
+			state.get(STATE_STREAMS).unwrap_or_default();
+		let stream_snapshots = enum_map! {
+			kind => saved_streams.remove(&stream_state_key(kind)),
+		};
+
 		Self {
 			path_prefix,
 			rewrite_bme68x,
@@ -74,14 +101,45 @@ impl SbxHandler {
 				sbx::StreamKind::CompassZ => sbx::StreamDecoder::new(compass_period, stream::InMemoryBuffer::new(compass_slice), compass_scale.clone()),
 			},
 			buffer: Vec::new(),
+			state,
+			rtc_snapshot,
+			stream_snapshots,
+		}
+	}
+
+	fn save_state(&self) {
+		match self.rtcifier.snapshot() {
+			Some(snapshot) => self.state.set(STATE_RTC, &snapshot),
+			None => self.state.remove(STATE_RTC),
+		}
+		let streams: BTreeMap<String, sbx::LinearRTCSnapshot> = self
+			.stream_decoders
+			.iter()
+			.filter_map(|(kind, dec)| Some((stream_state_key(kind), dec.snapshot()?)))
+			.collect();
+		self.state.set(STATE_STREAMS, &streams);
+	}
+
+	fn align_rtcifier(
+		&mut self,
+		rtc: DateTime<Utc>,
+		uptime: u16,
+	) -> Result<(), sbx::TimelineOverflow> {
+		if let Some(snapshot) = self.rtc_snapshot.take() {
+			if self.rtcifier.resume(&snapshot, rtc, uptime) {
+				info!("resumed rtc alignment from saved state");
+				return Ok(());
+			}
+			info!("saved rtc alignment does not match the device anymore, retraining");
 		}
+		self.rtcifier.align(rtc, uptime)
 	}
 
-	fn process_ready(&mut self, msg: sbx::Message, sinks: &mut Sinks) {
+	fn process_ready(&mut self, msg: sbx::Message, sinks: &mut Sinks) -> Result<(), HandleError> {
 		let prefix = &self.path_prefix;
 		let rewrite_bme68x = self.rewrite_bme68x;
 		let readouts = msg
-			.readouts(&mut self.rtcifier)
+			.readouts(&mut self.rtcifier)?
 			.map(|mut x| {
 				x.path.instance.insert_str(0, prefix);
 				if (x.path.device_type == "bme688" || x.path.device_type == "bme680")
@@ -116,15 +174,24 @@ impl SbxHandler {
 					};
 					let stream_info = &msg.imu_streams[index];
 					if stream_info.period != 0 {
-						let rtc = self.rtcifier.map_to_rtc(stream_info.timestamp);
+						let rtc = self.rtcifier.map_to_rtc(stream_info.timestamp)?;
 						let seq = stream_info.sequence_number;
 						let ready_pre = dec.ready();
-						dec.align(rtc, seq);
+						let resumed = match self.stream_snapshots[kind].take() {
+							Some(snapshot) => dec.resume(&snapshot, rtc, seq)?,
+							None => false,
+						};
+						if resumed {
+							debug!("resumed alignment of stream {:?} from saved state", kind)
+						} else {
+							dec.align(rtc, seq)?;
+						}
 						if !ready_pre && dec.ready() {
 							info!("decoder for stream {:?} became ready", kind);
 						}
 					}
 				}
+				self.save_state();
 			}
 			sbx::Message::StreamData(ref streammsg) => {
 				let decoder = &mut self.stream_decoders[streammsg.kind];
@@ -138,7 +205,10 @@ impl SbxHandler {
 				} else {
 					match decoder.decode(streammsg.kind, &streammsg.data) {
 						Ok(()) => trace!("samples sent to decoder successfully"),
-						Err(e) => warn!("malformed stream message received: {}", e),
+						Err(sbx::DecodeError::Malformed(e)) => {
+							warn!("malformed stream message received: {}", e)
+						}
+						Err(sbx::DecodeError::Timeline(e)) => return Err(e.into()),
 					};
 					match decoder.read_next() {
 						Some(block) => match sinks.send_stream(Arc::new(block)) {
@@ -153,6 +223,7 @@ impl SbxHandler {
 			}
 			_ => (),
 		}
+		Ok(())
 	}
 }
 
@@ -162,13 +233,13 @@ impl HandlePassthrough for SbxHandler {
 		timestamp: Option<DateTime<Utc>>,
 		mut src: Bytes,
 		sinks: &mut Sinks,
-	) -> std::io::Result<()> {
+	) -> Result<(), HandleError> {
 		let msg = sbx::Message::read(&mut src)?;
 		if let sbx::Message::Status(ref status) = msg {
 			if let Some(rtc) = timestamp {
-				self.rtcifier.align(rtc, status.uptime);
+				self.align_rtcifier(rtc, status.uptime)?;
 				if self.rtcifier.ready() {
-					let mapped_rtc = self.rtcifier.map_to_rtc(status.uptime);
+					let mapped_rtc = self.rtcifier.map_to_rtc(status.uptime)?;
 					let divergence = (rtc - mapped_rtc).num_seconds();
 					trace!(
 						"rtc mapping: uptime = {:>5}, remote rtc = {}, mapped rtc = {}, diff = {}",
@@ -180,7 +251,7 @@ impl HandlePassthrough for SbxHandler {
 					if divergence.abs() > 90 {
 						warn!("rtcifier is off by {}, resetting (uptime = {}, remote rtc = {}, mapped rtc = {}): {:?}", divergence, status.uptime, rtc, mapped_rtc, self.rtcifier);
 						self.rtcifier.reset();
-						self.rtcifier.align(rtc, status.uptime);
+						self.rtcifier.align(rtc, status.uptime)?;
 					}
 				} else {
 					trace!(
@@ -202,11 +273,10 @@ impl HandlePassthrough for SbxHandler {
 			let mut buffer = Vec::new();
 			std::mem::swap(&mut buffer, &mut self.buffer);
 			for msg in buffer.drain(..) {
-				self.process_ready(*msg, sinks);
+				self.process_ready(*msg, sinks)?;
 			}
 		}
-		self.process_ready(msg, sinks);
-		Ok(())
+		self.process_ready(msg, sinks)
 	}
 
 	fn reset(&mut self) {
@@ -215,6 +285,13 @@ impl HandlePassthrough for SbxHandler {
 		for dec in self.stream_decoders.values_mut() {
 			dec.reset();
 		}
+		// the device restarted, so whatever we saved does not apply anymore
+		self.rtc_snapshot = None;
+		for snapshot in self.stream_snapshots.values_mut() {
+			*snapshot = None;
+		}
+		self.state.remove(STATE_RTC);
+		self.state.remove(STATE_STREAMS);
 		if self.buffer.len() > 0 {
 			warn!(
 				"dropping {} buffered frames because of resync",
@@ -237,23 +314,30 @@ async fn wait_for_api_frame<S: AsyncRead + Unpin>(src: &mut S) -> io::Result<()>
 }
 
 fn spawn_with_snurl(
+	scope: &supervisor::Scope,
 	epf: EndpointFactory,
 	path_prefix: String,
 	rewrite_bme68x: bool,
 	sample_sink: broadcast::Sender<payload::Sample>,
 	stream_sink: broadcast::Sender<payload::Stream>,
-	stop_ch: oneshot::Receiver<()>,
-) -> io::Result<()> {
+	state: StateHandle,
+) -> io::Result<supervisor::TaskGuard> {
 	let gw_path_prefix = path_prefix.clone() + "gateway/";
-	let inner = SbxHandler::new(path_prefix, rewrite_bme68x);
+	let inner: PassthroughFactory = Box::new(move || {
+		Box::new(SbxHandler::new(
+			path_prefix.clone(),
+			rewrite_bme68x,
+			state.clone(),
+		))
+	});
 	SbmSourceWorker::spawn_with_snurl(
+		scope,
 		epf,
 		gw_path_prefix,
 		rewrite_bme68x,
 		sample_sink,
 		stream_sink,
-		Some(Box::new(inner)),
-		stop_ch,
+		Some(inner),
 	)
 }
 
@@ -266,21 +350,29 @@ struct SerialWorker {
 #[cfg(feature = "serial")]
 impl SerialWorker {
 	pub fn spawn(
-		src: tokio_serial::SerialStream,
+		scope: &supervisor::Scope,
+		port: String,
+		baudrate: u32,
 		path_prefix: String,
 		rewrite_bme68x: bool,
 		sample_sink: broadcast::Sender<payload::Sample>,
 		stream_sink: broadcast::Sender<payload::Stream>,
-		stop_ch: oneshot::Receiver<()>,
-	) {
-		let mut worker = Self {
-			sinks: Sinks::wrap(sample_sink, stream_sink),
-			inner: SbxHandler::new(path_prefix, rewrite_bme68x),
-		};
-		let src = Box::new(src);
-		tokio::spawn(async move {
-			worker.run_with_serialstream(src, stop_ch).await;
-		});
+		state: StateHandle,
+	) -> supervisor::TaskGuard {
+		let sinks = Sinks::wrap(sample_sink, stream_sink);
+		scope.spawn("serial", move || {
+			let mut worker = Self {
+				sinks: sinks.clone(),
+				inner: SbxHandler::new(path_prefix.clone(), rewrite_bme68x, state.clone()),
+			};
+			// opening the port happens inside the supervised task so that a
+			// missing device is retried instead of killing the node
+			let src = tokio_serial::SerialStream::open(&tokio_serial::new(&port, baudrate));
+			async move {
+				let src = Box::new(src?);
+				worker.run_with_serialstream(src).await
+			}
+		})
 	}
 
 	async fn decode_one_from_stream<S: AsyncRead + Unpin>(
@@ -345,7 +437,7 @@ impl SerialWorker {
 	async fn process_one_from_serial(
 		&mut self,
 		src: &mut tokio_serial::SerialStream,
-	) -> Result<(), ()> {
+	) -> supervisor::TaskResult {
 		match wait_for_api_frame(src).await {
 			Ok(v) => v,
 			Err(e) => {
@@ -365,7 +457,8 @@ impl SerialWorker {
 		let timestamp = Utc::now();
 		match self.inner.handle(Some(timestamp), frame, &mut self.sinks) {
 			Ok(()) => (),
-			Err(e) => warn!("malformed packet received: {:?}", e),
+			Err(HandleError::Malformed(e)) => warn!("malformed packet received: {:?}", e),
+			Err(HandleError::Fatal(e)) => return Err(e),
 		};
 		Ok(())
 	}
@@ -373,36 +466,33 @@ impl SerialWorker {
 	async fn run_with_serialstream(
 		&mut self,
 		mut src: Box<tokio_serial::SerialStream>,
-		mut stop_ch: oneshot::Receiver<()>,
-	) {
+	) -> supervisor::TaskResult {
 		loop {
-			tokio::select! {
-				result = self.process_one_from_serial(&mut src) => match result {
-					Ok(()) => (),
-					Err(()) => return,
-				},
-				_ = &mut stop_ch => return,
-			}
+			self.process_one_from_serial(&mut src).await?;
 		}
 	}
 }
 
 impl SBXSource {
 	pub fn new(
+		scope: &supervisor::Scope,
 		epf: EndpointFactory,
 		path_prefix: String,
 		rewrite_bme68x: bool,
+		state: StateHandle,
+		sample_capacity: usize,
+		stream_capacity: usize,
 	) -> io::Result<Self> {
-		let (sample_zygote, _) = broadcast::channel(384);
-		let (stream_zygote, _) = broadcast::channel(1024);
-		let (guard, stop_ch) = oneshot::channel();
-		spawn_with_snurl(
+		let (sample_zygote, _) = broadcast::channel(sample_capacity);
+		let (stream_zygote, _) = broadcast::channel(stream_capacity);
+		let guard = spawn_with_snurl(
+			scope,
 			epf,
 			path_prefix,
 			rewrite_bme68x,
 			sample_zygote.clone(),
 			stream_zygote.clone(),
-			stop_ch,
+			state,
 		)?;
 		Ok(Self {
 			sample_zygote,
@@ -413,20 +503,26 @@ impl SBXSource {
 
 	#[cfg(feature = "serial")]
 	pub fn with_serial(
-		src: tokio_serial::SerialStream,
+		scope: &supervisor::Scope,
+		port: String,
+		baudrate: u32,
 		path_prefix: String,
 		rewrite_bme68x: bool,
+		state: StateHandle,
+		sample_capacity: usize,
+		stream_capacity: usize,
 	) -> Self {
-		let (sample_zygote, _) = broadcast::channel(384);
-		let (stream_zygote, _) = broadcast::channel(1024);
-		let (guard, stop_ch) = oneshot::channel();
-		SerialWorker::spawn(
-			src,
+		let (sample_zygote, _) = broadcast::channel(sample_capacity);
+		let (stream_zygote, _) = broadcast::channel(stream_capacity);
+		let guard = SerialWorker::spawn(
+			scope,
+			port,
+			baudrate,
 			path_prefix,
 			rewrite_bme68x,
 			sample_zygote.clone(),
 			stream_zygote.clone(),
-			stop_ch,
+			state,
 		);
 		Self {
 			sample_zygote,
@@ -21,6 +21,7 @@ use crate::bme280;
 use crate::metric;
 
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Source};
 
 pub struct BME280Worker {
@@ -64,22 +65,31 @@ const CTRL_MEAS: u8 =
 
 impl BME280Worker {
 	pub fn spawn(
+		scope: &supervisor::Scope,
 		bus: I2c<File>,
 		instance: SmartString,
 		interval: Duration,
 		reconfigure_each: usize,
 		sink: broadcast::Sender<payload::Sample>,
-	) {
-		let mut worker = Self {
-			bus: Arc::new(Mutex::new(bus)),
-			interval,
-			instance: Arc::new(instance),
-			reconfigure_each,
-			backoff: Duration::from_secs(5),
-			calibration: None,
-			sink,
-		};
-		tokio::spawn(async move { worker.run().await });
+	) -> supervisor::TaskGuard {
+		let bus = Arc::new(Mutex::new(bus));
+		let instance = Arc::new(instance);
+		scope.spawn("bme280", move || {
+			// a restarted worker starts over with reading the calibration
+			let mut worker = Self {
+				bus: bus.clone(),
+				interval,
+				instance: instance.clone(),
+				reconfigure_each,
+				backoff: Duration::from_secs(5),
+				calibration: None,
+				sink: sink.clone(),
+			};
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		})
 	}
 
 	fn verified_write<T: AsRawFd>(bus: &mut I2c<T>, reg: u8, data: u8) -> io::Result<()> {
@@ -297,15 +307,19 @@ impl BME280Worker {
 
 pub struct BME280 {
 	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl BME280 {
 	pub fn new<P: AsRef<Path>>(
+		scope: &supervisor::Scope,
 		bus_device: P,
 		address: u8,
 		path_prefix: String,
 		interval: Duration,
 		reconfigure_each: usize,
+		capacity: usize,
 	) -> io::Result<BME280> {
 		let bus_device = bus_device.as_ref();
 		let mut bus = I2c::from_path(bus_device)?;
@@ -315,9 +329,16 @@ impl BME280 {
 		instance.push_str("/");
 		instance.push_str(bus_device.file_name().unwrap().to_string_lossy().as_ref());
 		write!(instance, "/{:x}", address).unwrap();
-		let (zygote, _) = broadcast::channel(8);
-		BME280Worker::spawn(bus, instance, interval, reconfigure_each, zygote.clone());
-		Ok(Self { zygote })
+		let (zygote, _) = broadcast::channel(capacity);
+		let guard = BME280Worker::spawn(
+			scope,
+			bus,
+			instance,
+			interval,
+			reconfigure_each,
+			zygote.clone(),
+		);
+		Ok(Self { zygote, guard })
 	}
 }
 
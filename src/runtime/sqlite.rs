@@ -0,0 +1,273 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use chrono::Utc;
+
+use tokio::sync::{mpsc, MutexGuard};
+use tokio::task::spawn_blocking;
+use tokio::time::Instant;
+
+use crate::metric;
+use crate::sqlite;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+/// How rows are grouped into transactions.
+pub struct Batching {
+	/// Maximum time rows are held back to wait for more.
+	pub flush_interval: Duration,
+	pub max_rows: usize,
+}
+
+/// How long data is kept.
+pub struct Retention {
+	pub max_age: Duration,
+	/// How often old data is deleted.
+	pub interval: Duration,
+}
+
+/// Readouts and blocks collected for a single transaction.
+#[derive(Default)]
+struct Pending {
+	readouts: Vec<payload::Readout>,
+	blocks: Vec<payload::Stream>,
+	rows: usize,
+	since: Option<Instant>,
+}
+
+impl Pending {
+	fn add_sample(&mut self, sample: payload::Sample) {
+		for readout in sample {
+			self.rows += readout.components.len();
+			self.readouts.push(readout);
+		}
+		self.since.get_or_insert_with(Instant::now);
+	}
+
+	fn add_block(&mut self, block: payload::Stream) {
+		self.rows += 1;
+		self.blocks.push(block);
+		self.since.get_or_insert_with(Instant::now);
+	}
+}
+
+async fn recv_block(
+	blocks: &mut Option<MutexGuard<'_, mpsc::Receiver<payload::Stream>>>,
+) -> Option<payload::Stream> {
+	match blocks {
+		Some(blocks) => blocks.recv().await,
+		None => std::future::pending().await,
+	}
+}
+
+struct SqliteWorker {
+	db: Arc<StdMutex<sqlite::Database>>,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	blocks: Option<supervisor::Resumable<mpsc::Receiver<payload::Stream>>>,
+	batching: Batching,
+	retention: Option<Retention>,
+}
+
+impl SqliteWorker {
+	async fn write(&self, pending: Pending) {
+		let db = self.db.clone();
+		let rows = pending.rows;
+		let result = spawn_blocking(move || {
+			let readouts: Vec<&metric::Readout> = pending.readouts.iter().map(|r| &**r).collect();
+			let blocks: Vec<&metric::StreamBlock> = pending.blocks.iter().map(|b| &**b).collect();
+			db.lock().unwrap().insert(&readouts, &blocks)
+		})
+		.await;
+		match result {
+			Ok(Ok(n)) => debug!("wrote {} rows to sqlite", n),
+			Ok(Err(e)) => warn!("lost {} rows: failed to write to sqlite: {}", rows, e),
+			Err(e) => warn!("lost {} rows: sqlite writer crashed: {}", rows, e),
+		}
+	}
+
+	async fn prune(&self, max_age: Duration) {
+		let db = self.db.clone();
+		let before = match chrono::Duration::from_std(max_age) {
+			Ok(max_age) => Utc::now() - max_age,
+			// nothing is that old
+			Err(_) => return,
+		};
+		match spawn_blocking(move || db.lock().unwrap().prune(before)).await {
+			Ok(Ok(n)) => debug!("pruned {} rows older than {} from sqlite", n, before),
+			Ok(Err(e)) => warn!("failed to prune sqlite database: {}", e),
+			Err(e) => warn!("sqlite pruning crashed: {}", e),
+		}
+	}
+
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		let mut blocks = match self.blocks.as_ref() {
+			Some(blocks) => Some(blocks.resume().await),
+			None => None,
+		};
+		let mut prune = tokio::time::interval(
+			self.retention
+				.as_ref()
+				.map(|r| r.interval)
+				.unwrap_or(Duration::from_secs(3600)),
+		);
+		let mut pending = Pending::default();
+		loop {
+			let deadline = pending.since.map(|x| x + self.batching.flush_interval);
+			tokio::select! {
+				v = samples.recv() => match v {
+					Some(sample) => pending.add_sample(sample),
+					None => break,
+				},
+				v = recv_block(&mut blocks) => match v {
+					Some(block) => pending.add_block(block),
+					// keep going with the samples
+					None => blocks = None,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					self.write(std::mem::take(&mut pending)).await;
+				}
+				_ = prune.tick(), if self.retention.is_some() => {
+					if let Some(retention) = self.retention.as_ref() {
+						self.prune(retention.max_age).await;
+					}
+				}
+			}
+			if pending.rows >= self.batching.max_rows {
+				self.write(std::mem::take(&mut pending)).await;
+			}
+		}
+		if pending.rows > 0 {
+			self.write(pending).await;
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Writes readouts (and optionally stream block metadata) into a SQLite
+/// database.
+pub struct SqliteSink {
+	samples: Serializer<payload::Sample>,
+	blocks: Option<Serializer<payload::Stream>>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl SqliteSink {
+	pub fn new<P: AsRef<Path>>(
+		scope: &supervisor::Scope,
+		path: P,
+		stream_blocks: bool,
+		batching: Batching,
+		retention: Option<Retention>,
+		capacity: usize,
+	) -> Result<Self, sqlite::Error> {
+		let db = sqlite::Database::open(path, stream_blocks)?;
+		let (serializer, samples) = Serializer::new(capacity);
+		let (block_serializer, blocks) = if stream_blocks {
+			let (serializer, blocks) = Serializer::new(capacity);
+			(Some(serializer), Some(supervisor::Resumable::new(blocks)))
+		} else {
+			(None, None)
+		};
+		let worker = Arc::new(SqliteWorker {
+			db: Arc::new(StdMutex::new(db)),
+			samples: supervisor::Resumable::new(samples),
+			blocks,
+			batching,
+			retention,
+		});
+		let guard = scope.spawn("sqlite", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
+		Ok(Self {
+			samples: serializer,
+			blocks: block_serializer,
+			guard,
+		})
+	}
+}
+
+impl traits::Sink for SqliteSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+		if let Some(blocks) = self.blocks.as_ref() {
+			blocks.attach(src.subscribe_to_streams(), link.clone());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn readout(t: i64, value: f64) -> payload::Readout {
+		metric::ReadoutBuilder::new()
+			.instance("a")
+			.timestamp(Utc::now() - chrono::Duration::seconds(t))
+			.temperature(value)
+			.arc()
+	}
+
+	fn count(path: &Path) -> i64 {
+		rusqlite::Connection::open(path)
+			.unwrap()
+			.query_row("SELECT COUNT(*) FROM readings", [], |row| row.get(0))
+			.unwrap()
+	}
+
+	#[tokio::test]
+	async fn batches_and_prunes() {
+		let path =
+			std::env::temp_dir().join(format!("metric-relay-sqlite-{}.db", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		let (tx, samples) = mpsc::channel(8);
+		let worker = Arc::new(SqliteWorker {
+			db: Arc::new(StdMutex::new(sqlite::Database::open(&path, false).unwrap())),
+			samples: supervisor::Resumable::new(samples),
+			blocks: None,
+			batching: Batching {
+				flush_interval: Duration::from_secs(60),
+				max_rows: 2,
+			},
+			retention: None,
+		});
+		let run = {
+			let worker = worker.clone();
+			tokio::spawn(async move { worker.run().await })
+		};
+		tx.send(vec![readout(7200, 1.0)]).await.unwrap();
+		tx.send(vec![readout(10, 2.0), readout(0, 3.0)])
+			.await
+			.unwrap();
+		// the batch is full and written without waiting for the flush
+		// interval
+		for _ in 0..100 {
+			if count(&path) == 3 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		assert_eq!(count(&path), 3);
+		tx.send(vec![readout(0, 4.0)]).await.unwrap();
+		drop(tx);
+		run.await.unwrap();
+		assert_eq!(count(&path), 4);
+
+		worker.prune(Duration::from_secs(3600)).await;
+		assert_eq!(count(&path), 3);
+		let _ = std::fs::remove_file(&path);
+	}
+}
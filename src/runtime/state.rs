@@ -0,0 +1,278 @@
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use tokio::task::JoinHandle;
+
+type Values = serde_json::Map<String, serde_json::Value>;
+
+#[derive(Debug)]
+struct Inner {
+	path: Option<PathBuf>,
+	values: Values,
+	dirty: bool,
+}
+
+/// Persistent key-value state of a single node.
+///
+/// Values are kept in memory and written to the state directory (if one is
+/// configured) whenever the store is flushed. Without a state directory, the
+/// state still survives restarts of the node's tasks, but not of the process.
+#[derive(Debug, Clone)]
+pub struct StateHandle {
+	inner: Arc<Mutex<Inner>>,
+}
+
+impl StateHandle {
+	fn load(path: Option<PathBuf>) -> Self {
+		let values = match path.as_ref() {
+			Some(path) => match read_values(path) {
+				Ok(v) => v,
+				Err(e) if e.kind() == io::ErrorKind::NotFound => Values::new(),
+				Err(e) => {
+					warn!(
+						"failed to load state from {}, starting afresh: {}",
+						path.display(),
+						e
+					);
+					Values::new()
+				}
+			},
+			None => Values::new(),
+		};
+		Self {
+			inner: Arc::new(Mutex::new(Inner {
+				path,
+				values,
+				dirty: false,
+			})),
+		}
+	}
+
+	/// Handle which is not backed by any file.
+	pub fn ephemeral() -> Self {
+		Self::load(None)
+	}
+
+	/// Return the value stored under `key`, if any.
+	///
+	/// Values which cannot be decoded (e.g. because they were written by an
+	/// incompatible version) are treated as absent.
+	pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+		let inner = self.inner.lock().unwrap();
+		let value = inner.values.get(key)?.clone();
+		match serde_json::from_value(value) {
+			Ok(v) => Some(v),
+			Err(e) => {
+				warn!("ignoring undecodable state for key {:?}: {}", key, e);
+				None
+			}
+		}
+	}
+
+	/// Store `value` under `key`; it is persisted on the next flush.
+	pub fn set<T: Serialize + ?Sized>(&self, key: &str, value: &T) {
+		let value = match serde_json::to_value(value) {
+			Ok(v) => v,
+			Err(e) => {
+				warn!("failed to encode state for key {:?}: {}", key, e);
+				return;
+			}
+		};
+		let mut inner = self.inner.lock().unwrap();
+		if inner.values.get(key) == Some(&value) {
+			return;
+		}
+		inner.values.insert(key.into(), value);
+		inner.dirty = true;
+	}
+
+	pub fn remove(&self, key: &str) {
+		let mut inner = self.inner.lock().unwrap();
+		if inner.values.remove(key).is_some() {
+			inner.dirty = true;
+		}
+	}
+
+	/// Write the state to disk if it changed since the last flush.
+	pub fn flush(&self) -> io::Result<()> {
+		let mut inner = self.inner.lock().unwrap();
+		if !inner.dirty {
+			return Ok(());
+		}
+		if let Some(path) = inner.path.as_ref() {
+			let data = serde_json::to_vec(&inner.values)?;
+			write_atomically(path, &data)?;
+			debug!("flushed state to {}", path.display());
+		}
+		inner.dirty = false;
+		Ok(())
+	}
+}
+
+fn read_values(path: &Path) -> io::Result<Values> {
+	let data = fs::read(path)?;
+	Ok(serde_json::from_slice(&data)?)
+}
+
+/// Replace the file at `path` with `data` so that readers see either the
+/// old or the new contents, even if we crash in between.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+	let tmp_path = PathBuf::from(tmp_path);
+	{
+		let mut f = fs::File::create(&tmp_path)?;
+		f.write_all(data)?;
+		f.sync_all()?;
+	}
+	fs::rename(&tmp_path, path)?;
+	if let Some(dir) = path.parent() {
+		// make the rename itself durable
+		fs::File::open(dir)?.sync_all()?;
+	}
+	Ok(())
+}
+
+/// Map a node name to a file name which cannot escape the state directory.
+///
+/// Anything but ASCII alphanumerics, `-` and `_` is percent-encoded, so that
+/// distinct node names always map to distinct files.
+fn file_name(node: &str) -> String {
+	let mut result = String::with_capacity(node.len() + 5);
+	for b in node.bytes() {
+		if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+			result.push(b as char);
+		} else {
+			result.push_str(&format!("%{:02X}", b));
+		}
+	}
+	result.push_str(".json");
+	result
+}
+
+/// The state of all nodes of a runtime.
+#[derive(Debug, Clone, Default)]
+pub struct StateStore {
+	directory: Option<PathBuf>,
+	handles: Arc<Mutex<Vec<StateHandle>>>,
+}
+
+impl StateStore {
+	/// Create a store persisting to `directory`, creating it if needed.
+	///
+	/// Without a directory, state is only kept in memory.
+	pub fn new(directory: Option<PathBuf>) -> io::Result<Self> {
+		if let Some(directory) = directory.as_ref() {
+			fs::create_dir_all(directory)?;
+		}
+		Ok(Self {
+			directory,
+			handles: Arc::new(Mutex::new(Vec::new())),
+		})
+	}
+
+	pub fn open(&self, node: &str) -> StateHandle {
+		let handle = StateHandle::load(self.directory.as_ref().map(|x| x.join(file_name(node))));
+		self.handles.lock().unwrap().push(handle.clone());
+		handle
+	}
+
+	/// Flush the state of all nodes, logging any errors.
+	pub fn flush(&self) {
+		for handle in self.handles.lock().unwrap().iter() {
+			if let Err(e) = handle.flush() {
+				warn!("failed to persist state: {}", e);
+			}
+		}
+	}
+
+	/// Spawn a task which flushes the state of all nodes periodically.
+	pub fn spawn_flusher(&self, interval: Duration) -> Option<JoinHandle<()>> {
+		self.directory.as_ref()?;
+		let store = self.clone();
+		Some(tokio::spawn(async move {
+			let mut interval = tokio::time::interval(interval);
+			loop {
+				interval.tick().await;
+				let store = store.clone();
+				let _ = tokio::task::spawn_blocking(move || store.flush()).await;
+			}
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tempdir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!(
+			"metric-relay-state-{}-{}",
+			name,
+			std::process::id()
+		));
+		let _ = fs::remove_dir_all(&dir);
+		dir
+	}
+
+	#[test]
+	fn state_survives_reopening() {
+		let dir = tempdir("reopen");
+		{
+			let store = StateStore::new(Some(dir.clone())).unwrap();
+			let handle = store.open("relay/a");
+			handle.set("client_id", "00ff");
+			handle.set("seq", &[1u16, 2, 3]);
+			store.flush();
+		}
+		assert!(dir.join("relay%2Fa.json").exists());
+		assert!(!dir.join("relay%2Fa.json.tmp").exists());
+		let store = StateStore::new(Some(dir.clone())).unwrap();
+		let handle = store.open("relay/a");
+		assert_eq!(handle.get::<String>("client_id").as_deref(), Some("00ff"));
+		assert_eq!(handle.get::<Vec<u16>>("seq"), Some(vec![1, 2, 3]));
+		assert_eq!(handle.get::<u32>("missing"), None);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn corrupt_state_is_discarded() {
+		let dir = tempdir("corrupt");
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("node.json"), b"{\"truncated").unwrap();
+		let store = StateStore::new(Some(dir.clone())).unwrap();
+		let handle = store.open("node");
+		assert_eq!(handle.get::<u32>("x"), None);
+		handle.set("x", &23u32);
+		store.flush();
+		assert_eq!(read_values(&dir.join("node.json")).unwrap()["x"], 23);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn file_names_are_distinct_and_confined() {
+		assert_eq!(file_name("node-1_a"), "node-1_a.json");
+		assert_eq!(file_name("../x"), "%2E%2E%2Fx.json");
+		assert_ne!(file_name("a/b"), file_name("a_b"));
+		assert_ne!(file_name("a/b"), file_name("a%2Fb"));
+		assert_eq!(file_name("ü"), "%C3%BC.json");
+	}
+
+	#[test]
+	fn undecodable_value_is_treated_as_absent() {
+		let handle = StateHandle::ephemeral();
+		handle.set("x", "not a number");
+		assert_eq!(handle.get::<u32>("x"), None);
+		handle.remove("x");
+		assert!(handle.flush().is_ok());
+	}
+}
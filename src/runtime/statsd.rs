@@ -0,0 +1,203 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::statsd;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::supervisor;
+use super::traits;
+
+/// How metrics are grouped into datagrams.
+pub struct Batching {
+	/// Maximum time metrics are held back to fill up a datagram.
+	pub flush_interval: Duration,
+	pub max_packet_size: usize,
+}
+
+async fn connect(address: &str) -> io::Result<UdpSocket> {
+	let addr = tokio::net::lookup_host(address)
+		.await?
+		.next()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "address did not resolve"))?;
+	let local = if addr.is_ipv4() {
+		"0.0.0.0:0"
+	} else {
+		"[::]:0"
+	};
+	let socket = UdpSocket::bind(local).await?;
+	socket.connect(addr).await?;
+	Ok(socket)
+}
+
+struct StatsdWorker {
+	address: String,
+	encoder: supervisor::Resumable<statsd::Encoder>,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	batching: Batching,
+}
+
+impl StatsdWorker {
+	async fn send(&self, socket: &UdpSocket, items: &mut Vec<String>, failing: &mut bool) {
+		for datagram in statsd::pack(items, self.batching.max_packet_size) {
+			match socket.send(datagram.as_bytes()).await {
+				Ok(_) => {
+					if *failing {
+						warn!("sending to statsd works again");
+					}
+					*failing = false;
+				}
+				// the agent not running shows up as refused connection;
+				// complain only once
+				Err(e) if *failing => debug!("failed to send to statsd: {}", e),
+				Err(e) => {
+					warn!("failed to send to statsd: {}", e);
+					*failing = true;
+				}
+			}
+		}
+		items.clear();
+	}
+
+	async fn run(&self) -> supervisor::TaskResult {
+		let mut samples = self.samples.resume().await;
+		let mut encoder = self.encoder.resume().await;
+		let socket = connect(&self.address).await?;
+		let mut items = Vec::new();
+		let mut bytes = 0;
+		let mut since = None;
+		let mut failing = false;
+		loop {
+			let deadline = since.map(|x: Instant| x + self.batching.flush_interval);
+			tokio::select! {
+				v = samples.recv() => match v {
+					Some(sample) => {
+						for readout in sample.iter() {
+							for item in encoder.encode(readout) {
+								bytes += item.len() + 1;
+								items.push(item);
+							}
+						}
+						if !items.is_empty() {
+							since.get_or_insert_with(Instant::now);
+						}
+						if bytes >= self.batching.max_packet_size {
+							self.send(&socket, &mut items, &mut failing).await;
+							bytes = 0;
+							since = None;
+						}
+					}
+					None => break,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					self.send(&socket, &mut items, &mut failing).await;
+					bytes = 0;
+					since = None;
+				}
+			}
+		}
+		self.send(&socket, &mut items, &mut failing).await;
+		debug!("sample source closed, exiting");
+		Ok(())
+	}
+}
+
+/// Sends readouts as StatsD gauges and counters over UDP.
+pub struct StatsdSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl StatsdSink {
+	pub fn new(
+		scope: &supervisor::Scope,
+		address: String,
+		encoder: statsd::Encoder,
+		batching: Batching,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let worker = Arc::new(StatsdWorker {
+			address,
+			encoder: supervisor::Resumable::new(encoder),
+			samples: supervisor::Resumable::new(samples),
+			batching,
+		});
+		let guard = scope.spawn("statsd", move || {
+			let worker = worker.clone();
+			async move { worker.run().await }
+		});
+		Self {
+			samples: serializer,
+			guard,
+		}
+	}
+}
+
+impl traits::Sink for StatsdSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::metric;
+
+	fn readout(instance: &str, value: f64) -> Arc<metric::Readout> {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.temperature(value)
+			.arc()
+	}
+
+	#[tokio::test]
+	async fn packs_metrics_into_datagrams() {
+		let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let (tx, samples) = mpsc::channel(8);
+		let worker = StatsdWorker {
+			address: server.local_addr().unwrap().to_string(),
+			encoder: supervisor::Resumable::new(statsd::Encoder::new(
+				statsd::NameTemplate::new("{device_type}.{instance}.{component}".into()),
+				None,
+			)),
+			samples: supervisor::Resumable::new(samples),
+			batching: Batching {
+				flush_interval: Duration::from_secs(60),
+				max_packet_size: 64,
+			},
+		};
+		let run = tokio::spawn(async move { worker.run().await });
+		// 24 bytes each, so that the third one does not fit anymore
+		tx.send(vec![readout("a", 1.0), readout("b", 2.0)])
+			.await
+			.unwrap();
+		tx.send(vec![readout("c", 3.0)]).await.unwrap();
+		drop(tx);
+		run.await.unwrap().unwrap();
+
+		let mut buf = vec![0u8; 2048];
+		let n = server.recv(&mut buf).await.unwrap();
+		assert_eq!(
+			std::str::from_utf8(&buf[..n]).unwrap(),
+			"bme280.a.temperature:1|g\nbme280.b.temperature:2|g"
+		);
+		let n = server.recv(&mut buf).await.unwrap();
+		assert_eq!(
+			std::str::from_utf8(&buf[..n]).unwrap(),
+			"bme280.c.temperature:3|g"
+		);
+	}
+}
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use log::warn;
 
 use tokio::sync::mpsc;
@@ -5,30 +7,44 @@ use tokio::sync::mpsc;
 use crate::stream::ArchiveWrite;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{Sink, Source};
 
 struct ArchiveWorker {
-	inner: Box<dyn ArchiveWrite + Send + Sync + 'static>,
-	source: mpsc::Receiver<payload::Stream>,
+	inner: supervisor::Resumable<Box<dyn ArchiveWrite + Send + Sync + 'static>>,
+	source: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
 }
 
 impl ArchiveWorker {
 	fn spawn(
+		scope: &supervisor::Scope,
 		inner: Box<dyn ArchiveWrite + Send + Sync + 'static>,
 		source: mpsc::Receiver<payload::Stream>,
-	) {
-		let mut worker = Self { inner, source };
-		tokio::spawn(async move { worker.run().await });
+	) -> supervisor::TaskGuard {
+		let worker = Arc::new(Self {
+			inner: supervisor::Resumable::new(inner),
+			source: supervisor::Resumable::new(source),
+		});
+		scope.spawn("archive", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		})
 	}
 
-	async fn run(&mut self) {
+	async fn run(&self) {
+		let mut source = self.source.resume().await;
+		let mut inner = self.inner.resume().await;
 		loop {
-			let block = match self.source.recv().await {
+			let block = match source.recv().await {
 				Some(v) => v,
 				None => return,
 			};
-			match self.inner.write(&block) {
+			match inner.write(&block) {
 				Ok(_) => (),
 				Err(e) => {
 					warn!("lost stream block: write to archive failed: {}", e);
@@ -40,18 +56,25 @@ impl ArchiveWorker {
 
 pub struct Archiver {
 	serializer: Serializer<payload::Stream>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Archiver {
-	pub fn new(inner: Box<dyn ArchiveWrite + Send + Sync + 'static>) -> Self {
-		let (serializer, source) = Serializer::new(32);
-		ArchiveWorker::spawn(inner, source);
-		Self { serializer }
+	pub fn new(
+		scope: &supervisor::Scope,
+		inner: Box<dyn ArchiveWrite + Send + Sync + 'static>,
+		capacity: usize,
+	) -> Self {
+		let (serializer, source) = Serializer::new(capacity);
+		let guard = ArchiveWorker::spawn(scope, inner, source);
+		Self { serializer, guard }
 	}
 }
 
 impl Sink for Archiver {
-	fn attach_source<'x>(&self, source: &'x dyn Source) {
-		self.serializer.attach(source.subscribe_to_streams());
+	fn attach_source(&self, source: &dyn Source, link: &Arc<LinkStats>) {
+		self.serializer
+			.attach(source.subscribe_to_streams(), link.clone());
 	}
 }
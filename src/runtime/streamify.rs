@@ -16,7 +16,9 @@ use crate::stream;
 use crate::stream::StreamBuffer;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Sink, Source};
 
 #[derive(Debug, Clone, Copy)]
@@ -125,24 +127,39 @@ impl Descriptor {
 	}
 }
 
-struct StreamifyWorker();
+struct StreamifyWorker {
+	streams: HashMap<metric::DevicePath, Descriptor>,
+	source: mpsc::Receiver<payload::Sample>,
+	sink: broadcast::Sender<payload::Stream>,
+}
 
 impl StreamifyWorker {
 	fn spawn(
+		scope: &supervisor::Scope,
 		streams: HashMap<metric::DevicePath, Descriptor>,
 		sample_source: mpsc::Receiver<payload::Sample>,
 		stream_sink: broadcast::Sender<payload::Stream>,
-	) {
-		tokio::spawn(
-			async move { StreamifyWorker::run(streams, sample_source, stream_sink).await },
-		);
+	) -> supervisor::TaskGuard {
+		let worker = supervisor::Resumable::new(StreamifyWorker {
+			streams,
+			source: sample_source,
+			sink: stream_sink,
+		});
+		scope.spawn("streamify", move || {
+			let worker = worker.clone();
+			async move {
+				worker.resume().await.run().await;
+				Ok(())
+			}
+		})
 	}
 
-	async fn run(
-		mut streams: HashMap<metric::DevicePath, Descriptor>,
-		mut source: mpsc::Receiver<payload::Sample>,
-		sink: broadcast::Sender<payload::Stream>,
-	) {
+	async fn run(&mut self) {
+		let Self {
+			streams,
+			source,
+			sink,
+		} = self;
 		loop {
 			let mut readouts = match source.recv().await {
 				Some(item) => item,
@@ -184,18 +201,26 @@ impl StreamifyWorker {
 }
 
 pub struct Streamify {
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 	samples: Serializer<payload::Sample>,
 	stream_zygote: broadcast::Sender<payload::Stream>,
 }
 
 impl Streamify {
-	pub fn new(descriptors: HashMap<metric::DevicePath, Descriptor>) -> Self {
+	pub fn new(
+		scope: &supervisor::Scope,
+		descriptors: HashMap<metric::DevicePath, Descriptor>,
+		capacity: usize,
+	) -> Self {
 		let (samples, sample_source) = Serializer::new(128);
-		let (stream_zygote, _) = broadcast::channel(128);
-		StreamifyWorker::spawn(descriptors, sample_source, stream_zygote.clone());
+		let (stream_zygote, _) = broadcast::channel(capacity);
+		let guard =
+			StreamifyWorker::spawn(scope, descriptors, sample_source, stream_zygote.clone());
 		Self {
 			samples,
 			stream_zygote,
+			guard,
 		}
 	}
 }
@@ -211,7 +236,8 @@ impl Source for Streamify {
 }
 
 impl Sink for Streamify {
-	fn attach_source<'x>(&self, src: &'x dyn Source) {
-		self.samples.attach(src.subscribe_to_samples());
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
 	}
 }
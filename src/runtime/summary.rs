@@ -11,23 +11,36 @@ use chrono::{DateTime, Duration, Utc};
 use crate::metric;
 
 use super::adapter::Serializer;
+use super::linkstats::LinkStats;
 use super::payload;
+use super::supervisor;
 use super::traits::{null_receiver, Sink, Source};
 
 struct SummaryWorker {
 	size: usize,
-	source: mpsc::Receiver<payload::Stream>,
+	source: supervisor::Resumable<mpsc::Receiver<payload::Stream>>,
 	sink: broadcast::Sender<payload::Sample>,
 }
 
 impl SummaryWorker {
 	pub fn spawn(
+		scope: &supervisor::Scope,
 		size: usize,
 		source: mpsc::Receiver<payload::Stream>,
 		sink: broadcast::Sender<payload::Sample>,
-	) {
-		let mut worker = Self { size, source, sink };
-		tokio::spawn(async move { worker.run().await });
+	) -> supervisor::TaskGuard {
+		let worker = Arc::new(Self {
+			size,
+			source: supervisor::Resumable::new(source),
+			sink,
+		});
+		scope.spawn("summary", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		})
 	}
 
 	fn process_chunk<X: Copy>(
@@ -155,9 +168,10 @@ impl SummaryWorker {
 		}
 	}
 
-	async fn run(&mut self) {
+	async fn run(&self) {
+		let mut source = self.source.resume().await;
 		loop {
-			let block = match self.source.recv().await {
+			let block = match source.recv().await {
 				Some(v) => v,
 				None => {
 					debug!("SummaryWorker shutting down");
@@ -182,14 +196,20 @@ impl SummaryWorker {
 pub struct Summary {
 	serializer: Serializer<payload::Stream>,
 	zygote: broadcast::Sender<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
 }
 
 impl Summary {
-	pub fn new(size: usize) -> Self {
-		let (zygote, _) = broadcast::channel(128);
+	pub fn new(scope: &supervisor::Scope, size: usize, capacity: usize) -> Self {
+		let (zygote, _) = broadcast::channel(capacity);
 		let (serializer, source) = Serializer::new(8);
-		SummaryWorker::spawn(size, source, zygote.clone());
-		Self { serializer, zygote }
+		let guard = SummaryWorker::spawn(scope, size, source, zygote.clone());
+		Self {
+			serializer,
+			zygote,
+			guard,
+		}
 	}
 }
 
@@ -204,7 +224,8 @@ impl Source for Summary {
 }
 
 impl Sink for Summary {
-	fn attach_source<'x>(&self, src: &'x dyn Source) {
-		self.serializer.attach(src.subscribe_to_streams())
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>) {
+		self.serializer
+			.attach(src.subscribe_to_streams(), link.clone())
 	}
 }
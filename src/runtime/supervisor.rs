@@ -0,0 +1,749 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+
+use schemars::JsonSchema;
+use serde_derive::Deserialize;
+
+use tokio::sync::{oneshot, watch};
+
+pub type TaskError = Box<dyn std::error::Error + Send + Sync + 'static>;
+pub type TaskResult = Result<(), TaskError>;
+
+/// How often supervising tasks show a sign of life.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+fn default_initial_backoff_ms() -> u64 {
+	1000
+}
+
+fn default_max_backoff_ms() -> u64 {
+	60000
+}
+
+/// Decides what happens when a supervised task of a node panics or returns
+/// an error.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "mode", deny_unknown_fields)]
+pub enum RestartPolicy {
+	/// Mark the node as failed on the first crash.
+	Never,
+	/// Restart crashed tasks with exponential backoff.
+	///
+	/// If `max_restarts` is given, the node is marked as failed after that
+	/// many consecutive crashes. A task which ran for longer than the
+	/// maximum backoff before crashing resets the backoff and the
+	/// consecutive crash counter.
+	OnFailure {
+		max_restarts: Option<u32>,
+		#[serde(default = "default_initial_backoff_ms")]
+		initial_backoff_ms: u64,
+		#[serde(default = "default_max_backoff_ms")]
+		max_backoff_ms: u64,
+	},
+}
+
+impl Default for RestartPolicy {
+	fn default() -> Self {
+		Self::OnFailure {
+			max_restarts: None,
+			initial_backoff_ms: default_initial_backoff_ms(),
+			max_backoff_ms: default_max_backoff_ms(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeState {
+	Running,
+	/// Running, but the node reports that it cannot do its job, e.g.
+	/// because a remote service is unreachable.
+	Degraded(String),
+	Restarting,
+	Failed(String),
+}
+
+impl fmt::Display for NodeState {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Running => f.write_str("running"),
+			Self::Degraded(reason) => write!(f, "degraded ({})", reason),
+			Self::Restarting => f.write_str("restarting"),
+			Self::Failed(reason) => write!(f, "failed ({})", reason),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+	pub name: String,
+	pub state: NodeState,
+	pub restarts: u64,
+	/// When a restarting node is expected to be running again.
+	restart_due: Option<Instant>,
+}
+
+impl fmt::Display for NodeStatus {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}: {}, {} restarts",
+			self.name, self.state, self.restarts
+		)
+	}
+}
+
+type Registry = Arc<Mutex<BTreeMap<String, NodeStatus>>>;
+
+/// Counts the spawned tasks which have not been started yet.
+type Pending = Arc<watch::Sender<usize>>;
+
+/// Marks a task as started when dropped.
+struct StartToken {
+	pending: Pending,
+}
+
+impl StartToken {
+	fn new(pending: Pending) -> Self {
+		pending.send_modify(|n| *n += 1);
+		Self { pending }
+	}
+}
+
+impl Drop for StartToken {
+	fn drop(&mut self) {
+		self.pending.send_modify(|n| *n -= 1);
+	}
+}
+
+struct Beat {
+	node: String,
+	task: &'static str,
+	at: Instant,
+}
+
+#[derive(Default)]
+struct Liveness {
+	next_id: u64,
+	beats: BTreeMap<u64, Beat>,
+}
+
+/// Last sign of life of every supervising task.
+type Heartbeats = Arc<Mutex<Liveness>>;
+
+/// Registration of a supervising task with the liveness tracking.
+struct Heartbeat {
+	heartbeats: Heartbeats,
+	id: u64,
+	interval: tokio::time::Interval,
+}
+
+impl Heartbeat {
+	fn new(heartbeats: Heartbeats, node: &str, task: &'static str) -> Self {
+		let id = {
+			let mut liveness = heartbeats.lock().unwrap();
+			let id = liveness.next_id;
+			liveness.next_id += 1;
+			liveness.beats.insert(
+				id,
+				Beat {
+					node: node.into(),
+					task,
+					at: Instant::now(),
+				},
+			);
+			id
+		};
+		let mut interval = tokio::time::interval_at(
+			tokio::time::Instant::now() + HEARTBEAT_INTERVAL,
+			HEARTBEAT_INTERVAL,
+		);
+		interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+		Self {
+			heartbeats,
+			id,
+			interval,
+		}
+	}
+
+	async fn tick(&mut self) {
+		self.interval.tick().await;
+		if let Some(beat) = self.heartbeats.lock().unwrap().beats.get_mut(&self.id) {
+			beat.at = Instant::now();
+		}
+	}
+
+	/// Deregister a supervising task which returned.
+	///
+	/// A supervising task which panicked never gets here, so its heartbeat
+	/// goes stale and it shows up as stalled.
+	fn finish(self) {
+		self.heartbeats.lock().unwrap().beats.remove(&self.id);
+	}
+}
+
+/// Tracks the health of the supervised tasks of all nodes.
+#[derive(Clone)]
+pub struct Supervisor {
+	registry: Registry,
+	pending: Pending,
+	changed: Arc<watch::Sender<()>>,
+	heartbeats: Heartbeats,
+}
+
+impl Default for Supervisor {
+	fn default() -> Self {
+		Self {
+			registry: Registry::default(),
+			pending: Arc::new(watch::channel(0).0),
+			changed: Arc::new(watch::channel(()).0),
+			heartbeats: Heartbeats::default(),
+		}
+	}
+}
+
+impl Supervisor {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Wait until every task spawned so far has been started at least once.
+	pub async fn started(&self) {
+		let mut pending = self.pending.subscribe();
+		while *pending.borrow_and_update() > 0 {
+			if pending.changed().await.is_err() {
+				return;
+			}
+		}
+	}
+
+	/// Wait until all of `nodes` are running.
+	///
+	/// Fails with the status of the first of them which failed
+	/// permanently in the meantime.
+	pub async fn running(&self, nodes: &[&str]) -> Result<(), NodeStatus> {
+		let mut changed = self.changed.subscribe();
+		loop {
+			let mut all_running = true;
+			for status in self.registry.lock().unwrap().values() {
+				if !nodes.contains(&status.name.as_str()) {
+					continue;
+				}
+				match status.state {
+					NodeState::Running => (),
+					NodeState::Failed(_) => return Err(status.clone()),
+					_ => all_running = false,
+				}
+			}
+			if all_running || changed.changed().await.is_err() {
+				return Ok(());
+			}
+		}
+	}
+
+	/// Describe supervising tasks which showed no sign of life and nodes
+	/// which stayed restarting for longer than `grace` past their backoff.
+	///
+	/// An empty result means that supervision is working.
+	pub fn stalled(&self, grace: Duration) -> Vec<String> {
+		let grace = grace.max(HEARTBEAT_INTERVAL * 3);
+		let now = Instant::now();
+		let mut result = Vec::new();
+		for beat in self.heartbeats.lock().unwrap().beats.values() {
+			if now.saturating_duration_since(beat.at) > grace {
+				result.push(format!(
+					"task {} of node {} is unresponsive",
+					beat.task, beat.node
+				));
+			}
+		}
+		for status in self.registry.lock().unwrap().values() {
+			if let (NodeState::Restarting, Some(due)) = (&status.state, status.restart_due) {
+				if now.saturating_duration_since(due) > grace {
+					result.push(format!("node {} is stuck restarting", status.name));
+				}
+			}
+		}
+		result
+	}
+
+	/// One-line description of the health of all nodes.
+	pub fn summary(&self) -> String {
+		let mut running = 0;
+		let mut degraded = Vec::new();
+		let mut restarting = Vec::new();
+		let mut failed = Vec::new();
+		for status in self.registry.lock().unwrap().values() {
+			match status.state {
+				NodeState::Running => running += 1,
+				NodeState::Degraded(_) => degraded.push(status.name.clone()),
+				NodeState::Restarting => restarting.push(status.name.clone()),
+				NodeState::Failed(_) => failed.push(status.name.clone()),
+			}
+		}
+		let mut result = format!("{} nodes running", running);
+		if !degraded.is_empty() {
+			result.push_str(&format!(", degraded: {}", degraded.join(", ")));
+		}
+		if !restarting.is_empty() {
+			result.push_str(&format!(", restarting: {}", restarting.join(", ")));
+		}
+		if !failed.is_empty() {
+			result.push_str(&format!(", failed: {}", failed.join(", ")));
+		}
+		result
+	}
+
+	/// Create the scope through which the tasks of a node are spawned.
+	pub fn scope(&self, node: &str, policy: RestartPolicy) -> Scope {
+		self.registry.lock().unwrap().insert(
+			node.into(),
+			NodeStatus {
+				name: node.into(),
+				state: NodeState::Running,
+				restarts: 0,
+				restart_due: None,
+			},
+		);
+		Scope {
+			registry: self.registry.clone(),
+			pending: self.pending.clone(),
+			changed: self.changed.clone(),
+			heartbeats: self.heartbeats.clone(),
+			node: node.into(),
+			policy,
+		}
+	}
+
+	pub fn status(&self) -> Vec<NodeStatus> {
+		self.registry.lock().unwrap().values().cloned().collect()
+	}
+
+	pub fn degraded(&self) -> Vec<NodeStatus> {
+		self.registry
+			.lock()
+			.unwrap()
+			.values()
+			.filter(|x| matches!(x.state, NodeState::Degraded(_)))
+			.cloned()
+			.collect()
+	}
+
+	pub fn failed(&self) -> Vec<NodeStatus> {
+		self.registry
+			.lock()
+			.unwrap()
+			.values()
+			.filter(|x| matches!(x.state, NodeState::Failed(_)))
+			.cloned()
+			.collect()
+	}
+}
+
+/// State which has to survive restarts of a supervised task.
+///
+/// The factory passed to [`Scope::spawn`] creates a fresh future for every
+/// run, but things like the receiving end of a channel or a sequence
+/// counter must be carried over from a crashed run to its replacement.
+/// Only one run ever holds the state at a time; the lock is merely there so
+/// that the next run can pick it up again once the previous one is gone.
+pub struct Resumable<T>(Arc<tokio::sync::Mutex<T>>);
+
+impl<T> Clone for Resumable<T> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<T> Resumable<T> {
+	pub fn new(inner: T) -> Self {
+		Self(Arc::new(tokio::sync::Mutex::new(inner)))
+	}
+
+	/// Take over the state, waiting for the previous run to let go of it.
+	pub async fn resume(&self) -> tokio::sync::MutexGuard<'_, T> {
+		self.0.lock().await
+	}
+}
+
+/// Handle to spawn supervised tasks on behalf of a single node.
+#[derive(Clone)]
+pub struct Scope {
+	registry: Registry,
+	pending: Pending,
+	changed: Arc<watch::Sender<()>>,
+	heartbeats: Heartbeats,
+	node: String,
+	policy: RestartPolicy,
+}
+
+/// Stops the supervised task (and any further restarts) when dropped.
+pub struct TaskGuard {
+	#[allow(dead_code)]
+	stop: oneshot::Sender<()>,
+}
+
+fn panic_message(payload: Box<dyn Any + Send + 'static>) -> String {
+	if let Some(s) = payload.downcast_ref::<&str>() {
+		(*s).into()
+	} else if let Some(s) = payload.downcast_ref::<String>() {
+		s.clone()
+	} else {
+		"panic with non-string payload".into()
+	}
+}
+
+impl Scope {
+	fn set_state(&self, state: NodeState) {
+		self.update_state(state, None)
+	}
+
+	/// Mark the node as restarting until `due`.
+	fn set_restarting(&self, due: Instant) {
+		self.update_state(NodeState::Restarting, Some(due))
+	}
+
+	fn update_state(&self, state: NodeState, restart_due: Option<Instant>) {
+		let mut registry = self.registry.lock().unwrap();
+		if let Some(status) = registry.get_mut(&self.node) {
+			// a failed node stays failed, even if other tasks are still
+			// around
+			if let NodeState::Failed(_) = status.state {
+				return;
+			}
+			status.state = state;
+			status.restart_due = restart_due;
+		}
+		self.changed.send_replace(());
+	}
+
+	/// Report a problem which keeps the node from doing its job, or that
+	/// a previously reported problem went away.
+	///
+	/// This does not affect nodes which are restarting or failed.
+	pub fn report_health(&self, problem: Option<String>) {
+		let mut registry = self.registry.lock().unwrap();
+		if let Some(status) = registry.get_mut(&self.node) {
+			if let NodeState::Running | NodeState::Degraded(_) = status.state {
+				let state = match problem {
+					Some(reason) => NodeState::Degraded(reason),
+					None => NodeState::Running,
+				};
+				if status.state != state {
+					status.state = state;
+					self.changed.send_replace(());
+				}
+			}
+		}
+	}
+
+	fn count_restart(&self) {
+		let mut registry = self.registry.lock().unwrap();
+		if let Some(status) = registry.get_mut(&self.node) {
+			status.restarts += 1;
+		}
+	}
+
+	/// Spawn a task which is re-created using `factory` whenever it panics
+	/// or returns an error, according to the restart policy of the node.
+	///
+	/// A task returning `Ok(())` is considered to have finished cleanly and
+	/// is not restarted.
+	pub fn spawn<F, Fut>(&self, task: &'static str, factory: F) -> TaskGuard
+	where
+		F: FnMut() -> Fut + Send + 'static,
+		Fut: Future<Output = TaskResult> + Send + 'static,
+	{
+		let (stop, stop_ch) = oneshot::channel();
+		let scope = self.clone();
+		let started = StartToken::new(self.pending.clone());
+		let heartbeat = Heartbeat::new(self.heartbeats.clone(), &self.node, task);
+		tokio::spawn(async move {
+			let mut heartbeat = heartbeat;
+			scope
+				.supervise(task, factory, stop_ch, started, &mut heartbeat)
+				.await;
+			heartbeat.finish();
+		});
+		TaskGuard { stop }
+	}
+
+	async fn supervise<F, Fut>(
+		&self,
+		task: &'static str,
+		mut factory: F,
+		mut stop_ch: oneshot::Receiver<()>,
+		started: StartToken,
+		heartbeat: &mut Heartbeat,
+	) where
+		F: FnMut() -> Fut + Send + 'static,
+		Fut: Future<Output = TaskResult> + Send + 'static,
+	{
+		let (max_restarts, initial_backoff, max_backoff) = match self.policy {
+			RestartPolicy::Never => (Some(0), Duration::ZERO, Duration::ZERO),
+			RestartPolicy::OnFailure {
+				max_restarts,
+				initial_backoff_ms,
+				max_backoff_ms,
+			} => (
+				max_restarts,
+				Duration::from_millis(initial_backoff_ms),
+				Duration::from_millis(max_backoff_ms.max(initial_backoff_ms)),
+			),
+		};
+		let mut backoff = initial_backoff;
+		let mut consecutive = 0u32;
+		let mut started_token = Some(started);
+
+		loop {
+			let started = Instant::now();
+			let mut handle = tokio::spawn(factory());
+			drop(started_token.take());
+			let result = loop {
+				tokio::select! {
+					result = &mut handle => break result,
+					_ = &mut stop_ch => {
+						debug!("stopping task {} of node {}", task, self.node);
+						handle.abort();
+						return;
+					},
+					_ = heartbeat.tick() => (),
+				}
+			};
+
+			let reason = match result {
+				Ok(Ok(())) => {
+					debug!("task {} of node {} finished", task, self.node);
+					return;
+				}
+				Ok(Err(e)) => e.to_string(),
+				Err(e) if e.is_panic() => panic_message(e.into_panic()),
+				// cancelled from outside the supervisor, i.e. the runtime is shutting down
+				Err(_) => return,
+			};
+
+			if started.elapsed() >= max_backoff {
+				backoff = initial_backoff;
+				consecutive = 0;
+			}
+
+			if max_restarts.map(|max| consecutive >= max).unwrap_or(false) {
+				error!(
+					"task {} of node {} crashed and will not be restarted: {}",
+					task, self.node, reason
+				);
+				self.set_state(NodeState::Failed(reason));
+				return;
+			}
+			consecutive += 1;
+
+			warn!(
+				"task {} of node {} crashed, restarting in {:?}: {}",
+				task, self.node, backoff, reason
+			);
+			let resume_at = tokio::time::Instant::now() + backoff;
+			self.set_restarting(resume_at.into_std());
+			self.count_restart();
+			loop {
+				tokio::select! {
+					_ = tokio::time::sleep_until(resume_at) => break,
+					_ = &mut stop_ch => return,
+					_ = heartbeat.tick() => (),
+				}
+			}
+			backoff = (backoff * 2).min(max_backoff);
+			self.set_state(NodeState::Running);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	fn fast_policy(max_restarts: Option<u32>) -> RestartPolicy {
+		RestartPolicy::OnFailure {
+			max_restarts,
+			initial_backoff_ms: 1,
+			max_backoff_ms: 1000,
+		}
+	}
+
+	#[tokio::test]
+	async fn restarts_panicking_task() {
+		let supervisor = Supervisor::new();
+		let scope = supervisor.scope("node", fast_policy(None));
+		let runs = Arc::new(AtomicUsize::new(0));
+		let runs_inner = runs.clone();
+		let _guard = scope.spawn("worker", move || {
+			let runs = runs_inner.clone();
+			async move {
+				if runs.fetch_add(1, Ordering::SeqCst) < 2 {
+					panic!("boom");
+				}
+				Ok(())
+			}
+		});
+		for _ in 0..100 {
+			if runs.load(Ordering::SeqCst) >= 3 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		assert_eq!(runs.load(Ordering::SeqCst), 3);
+		let status = supervisor.status();
+		assert_eq!(status[0].restarts, 2);
+		assert_eq!(status[0].state, NodeState::Running);
+	}
+
+	#[tokio::test]
+	async fn marks_node_failed_after_max_restarts() {
+		let supervisor = Supervisor::new();
+		let scope = supervisor.scope("node", fast_policy(Some(1)));
+		let _guard = scope.spawn("worker", || async { Err(io::Error::other("nope").into()) });
+		for _ in 0..100 {
+			if !supervisor.failed().is_empty() {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(10)).await;
+		}
+		let failed = supervisor.failed();
+		assert_eq!(failed.len(), 1);
+		assert_eq!(failed[0].state, NodeState::Failed("nope".into()));
+		assert_eq!(failed[0].restarts, 1);
+	}
+
+	#[tokio::test]
+	async fn started_waits_for_spawned_tasks() {
+		let supervisor = Supervisor::new();
+		let scope = supervisor.scope("node", RestartPolicy::Never);
+		let _guard = scope.spawn("worker", std::future::pending::<TaskResult>);
+		tokio::time::timeout(Duration::from_secs(1), supervisor.started())
+			.await
+			.unwrap();
+		assert_eq!(*supervisor.pending.borrow(), 0);
+	}
+
+	#[test]
+	fn summary_lists_unhealthy_nodes() {
+		let supervisor = Supervisor::new();
+		let _a = supervisor.scope("a", RestartPolicy::Never);
+		let b = supervisor.scope("b", RestartPolicy::Never);
+		let _c = supervisor.scope("c", RestartPolicy::Never);
+		assert_eq!(supervisor.summary(), "3 nodes running");
+		b.set_state(NodeState::Failed("boom".into()));
+		assert_eq!(supervisor.summary(), "2 nodes running, failed: b");
+	}
+
+	#[test]
+	fn reported_problems_degrade_running_nodes() {
+		let supervisor = Supervisor::new();
+		let a = supervisor.scope("a", RestartPolicy::Never);
+		let b = supervisor.scope("b", RestartPolicy::Never);
+		a.report_health(Some("unreachable".into()));
+		assert_eq!(supervisor.summary(), "1 nodes running, degraded: a");
+		b.set_state(NodeState::Failed("boom".into()));
+		b.report_health(None);
+		assert_eq!(
+			supervisor.status()[1].state,
+			NodeState::Failed("boom".into())
+		);
+		a.report_health(None);
+		assert_eq!(supervisor.summary(), "1 nodes running, failed: b");
+	}
+
+	#[tokio::test]
+	async fn restarted_task_resumes_state() {
+		let supervisor = Supervisor::new();
+		let scope = supervisor.scope("node", fast_policy(None));
+		let state = Resumable::new(0usize);
+		let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+		let _guard = scope.spawn("worker", move || {
+			let state = state.clone();
+			let tx = tx.clone();
+			async move {
+				let mut runs = state.resume().await;
+				*runs += 1;
+				if *runs < 3 {
+					panic!("boom");
+				}
+				tx.send(*runs).await.unwrap();
+				Ok(())
+			}
+		});
+		let runs = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+			.await
+			.unwrap();
+		assert_eq!(runs, Some(3));
+	}
+
+	#[tokio::test]
+	async fn running_waits_for_nodes() {
+		let supervisor = Supervisor::new();
+		let a = supervisor.scope("a", RestartPolicy::Never);
+		let b = supervisor.scope("b", RestartPolicy::Never);
+		a.set_restarting(Instant::now());
+		let waiting = supervisor.clone();
+		let waiter = tokio::spawn(async move { waiting.running(&["a"]).await });
+		tokio::time::sleep(Duration::from_millis(10)).await;
+		assert!(!waiter.is_finished());
+		b.set_state(NodeState::Failed("ignored".into()));
+		a.set_state(NodeState::Running);
+		let result = tokio::time::timeout(Duration::from_secs(1), waiter)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(result.is_ok());
+		let b = supervisor.running(&["a", "b"]).await.unwrap_err();
+		assert_eq!(b.name, "b");
+	}
+
+	#[tokio::test]
+	async fn stalled_reports_dead_supervision() {
+		let supervisor = Supervisor::new();
+		let scope = supervisor.scope("node", fast_policy(None));
+		let _guard = scope.spawn("worker", std::future::pending::<TaskResult>);
+		assert!(supervisor.stalled(Duration::ZERO).is_empty());
+		let long_ago = Instant::now() - Duration::from_secs(60);
+		scope.set_restarting(long_ago);
+		for beat in supervisor.heartbeats.lock().unwrap().beats.values_mut() {
+			beat.at = long_ago;
+		}
+		assert_eq!(
+			supervisor.stalled(Duration::ZERO),
+			vec![
+				"task worker of node node is unresponsive".to_string(),
+				"node node is stuck restarting".to_string(),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn dropping_guard_stops_task() {
+		let supervisor = Supervisor::new();
+		let scope = supervisor.scope("node", RestartPolicy::Never);
+		let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
+		let guard = scope.spawn("worker", move || {
+			let tx = tx.clone();
+			async move {
+				let _tx = tx;
+				std::future::pending::<TaskResult>().await
+			}
+		});
+		drop(guard);
+		// the sender held by the task goes away once the task is aborted
+		tokio::time::timeout(Duration::from_secs(1), async {
+			while rx.recv().await.is_some() {}
+		})
+		.await
+		.unwrap();
+	}
+}
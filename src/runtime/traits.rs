@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use tokio::sync::broadcast;
 
+use super::linkstats::LinkStats;
 use super::payload;
 
 pub trait Source {
@@ -10,7 +11,7 @@ pub trait Source {
 }
 
 pub trait Sink {
-	fn attach_source<'x>(&self, src: &'x dyn Source);
+	fn attach_source(&self, src: &dyn Source, link: &Arc<LinkStats>);
 }
 
 // may be unused depending on the feature set, but encoding that usedness in a cfg flag would be insane
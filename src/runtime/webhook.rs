@@ -0,0 +1,254 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::http;
+use crate::metric;
+use crate::webhook;
+
+use super::adapter::Serializer;
+use super::linkstats::LinkStats;
+use super::payload;
+use super::retry::Backoff;
+use super::supervisor;
+use super::traits;
+
+/// How readouts are grouped into requests; without it, a request is made
+/// for each readout.
+pub struct Batching {
+	/// Maximum time readouts are held back to wait for more.
+	pub flush_interval: Duration,
+	pub max_readouts: usize,
+}
+
+struct WebhookWorker {
+	client: webhook::Client,
+	samples: supervisor::Resumable<mpsc::Receiver<payload::Sample>>,
+	batching: Option<Batching>,
+	retry: Backoff,
+}
+
+impl WebhookWorker {
+	async fn send(&self, ctx: webhook::Context<'_>) {
+		let result = self
+			.retry
+			.run(
+				"send webhook request",
+				|e: &http::Error| e.is_retryable(),
+				|| self.client.send(ctx),
+			)
+			.await;
+		if let Err(e) = result {
+			let n = match ctx {
+				webhook::Context::Readout(_) => 1,
+				webhook::Context::Batch(readouts) => readouts.len(),
+			};
+			warn!("lost {} readouts: webhook request failed: {}", n, e);
+		}
+	}
+
+	async fn run_per_readout(&self, samples: &mut mpsc::Receiver<payload::Sample>) {
+		while let Some(sample) = samples.recv().await {
+			for readout in sample.iter() {
+				self.send(webhook::Context::Readout(readout)).await;
+			}
+		}
+	}
+
+	async fn run_batched(
+		&self,
+		samples: &mut mpsc::Receiver<payload::Sample>,
+		batching: &Batching,
+	) {
+		let mut pending: Vec<Arc<metric::Readout>> = Vec::new();
+		let mut since = None;
+		loop {
+			let deadline = since.map(|x: Instant| x + batching.flush_interval);
+			tokio::select! {
+				v = samples.recv() => match v {
+					Some(sample) => {
+						pending.extend(sample);
+						while pending.len() >= batching.max_readouts {
+							let rest = pending.split_off(batching.max_readouts);
+							self.send(webhook::Context::Batch(&pending)).await;
+							pending = rest;
+							since = None;
+						}
+						if !pending.is_empty() {
+							since.get_or_insert_with(Instant::now);
+						}
+					}
+					None => break,
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+					self.send(webhook::Context::Batch(&pending)).await;
+					pending.clear();
+					since = None;
+				}
+			}
+		}
+		if !pending.is_empty() {
+			self.send(webhook::Context::Batch(&pending)).await;
+		}
+	}
+
+	async fn run(&self) {
+		let mut samples = self.samples.resume().await;
+		match self.batching.as_ref() {
+			Some(batching) => self.run_batched(&mut samples, batching).await,
+			None => self.run_per_readout(&mut samples).await,
+		}
+		debug!("sample source closed, exiting");
+	}
+}
+
+/// Sends readouts to an HTTP endpoint in requests built from templates.
+pub struct WebhookSink {
+	samples: Serializer<payload::Sample>,
+	#[allow(dead_code)]
+	guard: supervisor::TaskGuard,
+}
+
+impl WebhookSink {
+	pub fn new(
+		scope: &supervisor::Scope,
+		request: webhook::RequestTemplate,
+		batching: Option<Batching>,
+		retry: Backoff,
+		capacity: usize,
+	) -> Self {
+		let (serializer, samples) = Serializer::new(capacity);
+		let worker = Arc::new(WebhookWorker {
+			client: webhook::Client::new(request),
+			samples: supervisor::Resumable::new(samples),
+			batching,
+			retry,
+		});
+		let guard = scope.spawn("webhook", move || {
+			let worker = worker.clone();
+			async move {
+				worker.run().await;
+				Ok(())
+			}
+		});
+		Self {
+			samples: serializer,
+			guard,
+		}
+	}
+}
+
+impl traits::Sink for WebhookSink {
+	fn attach_source(&self, src: &dyn traits::Source, link: &Arc<LinkStats>) {
+		self.samples
+			.attach(src.subscribe_to_samples(), link.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::testutil::StandIn;
+
+	fn readout(instance: &str, value: f64) -> Arc<metric::Readout> {
+		metric::ReadoutBuilder::new()
+			.instance(instance)
+			.temperature(value)
+			.arc()
+	}
+
+	fn worker(
+		request: webhook::RequestTemplate,
+		batching: Option<Batching>,
+	) -> (WebhookWorker, mpsc::Sender<payload::Sample>) {
+		let (tx, samples) = mpsc::channel(8);
+		let worker = WebhookWorker {
+			client: webhook::Client::new(request),
+			samples: supervisor::Resumable::new(samples),
+			batching,
+			retry: Backoff {
+				max_retries: 2,
+				initial_backoff: Duration::from_millis(1),
+				max_backoff: Duration::from_millis(10),
+			},
+		};
+		(worker, tx)
+	}
+
+	#[tokio::test]
+	async fn sends_request_per_readout() {
+		let server = StandIn::start(Box::new(|_| 204)).await;
+		let (worker, tx) = worker(
+			webhook::RequestTemplate {
+				method: reqwest::Method::PUT,
+				url: webhook::Template::parse(
+					&format!("{}/devices/{{{{instance}}}}", server.url),
+					false,
+				)
+				.unwrap(),
+				headers: vec![(
+					"Authorization".into(),
+					webhook::Template::parse("Bearer s3cr3t", false).unwrap(),
+				)],
+				body: Some(
+					webhook::Template::parse(r#"{"t": {{value.temperature}}}"#, false).unwrap(),
+				),
+				body_escape: webhook::Escape::Json,
+			},
+			None,
+		);
+		tx.send(vec![readout("a/1", 21.5), readout("b", 19.0)])
+			.await
+			.unwrap();
+		drop(tx);
+		worker.run().await;
+
+		let requests = server.requests();
+		assert_eq!(requests.len(), 2);
+		assert!(requests[0].head.starts_with("PUT "));
+		assert_eq!(requests[0].target(), "/devices/a%2F1");
+		assert_eq!(requests[0].header("authorization"), Some("Bearer s3cr3t"));
+		assert_eq!(requests[0].header("content-type"), Some("application/json"));
+		assert_eq!(requests[0].body_str(), r#"{"t": 21.5}"#);
+		assert_eq!(requests[1].body_str(), r#"{"t": 19.0}"#);
+	}
+
+	#[tokio::test]
+	async fn batches_readouts_and_retries() {
+		let server = StandIn::start(Box::new(|_| 503)).await;
+		let (worker, tx) = worker(
+			webhook::RequestTemplate {
+				method: reqwest::Method::POST,
+				url: webhook::Template::parse(&format!("{}/ingest", server.url), true).unwrap(),
+				headers: Vec::new(),
+				body: Some(webhook::Template::parse("{{count}} {{readouts}}", true).unwrap()),
+				body_escape: webhook::Escape::Text,
+			},
+			Some(Batching {
+				flush_interval: Duration::from_secs(60),
+				max_readouts: 2,
+			}),
+		);
+		tx.send(vec![
+			readout("a", 1.0),
+			readout("b", 2.0),
+			readout("c", 3.0),
+		])
+		.await
+		.unwrap();
+		drop(tx);
+		worker.run().await;
+
+		let requests = server.requests();
+		// two batches, three attempts each
+		assert_eq!(requests.len(), 6);
+		assert!(requests[0].body_str().starts_with("2 [{"));
+		assert!(requests[5].body_str().starts_with("1 [{"));
+		assert_eq!(requests[0].header("content-type"), None);
+	}
+}
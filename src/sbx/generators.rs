@@ -32,7 +32,10 @@ pub struct Empty();
 pub trait ReadoutIterable<'x, T: rtcifier::RTCifier> {
 	type GenIter: Iterator<Item = metric::Readout>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter;
+	/// Map the timestamps of the message to the RTC and iterate over the
+	/// readouts it contains.
+	fn readouts(&'x self, rtcifier: &'x mut T)
+		-> Result<Self::GenIter, rtcifier::TimelineOverflow>;
 }
 
 impl Iterator for Empty {
@@ -59,23 +62,26 @@ impl<'x> DynSampleIterator<'x> {
 	}
 }
 
-pub struct DS18B20Readouts<'x, T: rtcifier::RTCifier> {
-	rtc: &'x mut T,
+pub struct DS18B20Readouts<'x> {
+	ts: DateTime<Utc>,
 	src: &'x frame::SbxDS18B20Message,
 	at: usize,
 }
 
-impl<'x, T: rtcifier::RTCifier> DS18B20Readouts<'x, T> {
-	fn from_msg(msg: &'x frame::SbxDS18B20Message, rtcifier: &'x mut T) -> Self {
-		Self {
-			rtc: rtcifier,
+impl<'x> DS18B20Readouts<'x> {
+	fn from_msg(
+		msg: &'x frame::SbxDS18B20Message,
+		rtcifier: &mut impl rtcifier::RTCifier,
+	) -> Result<Self, rtcifier::TimelineOverflow> {
+		Ok(Self {
+			ts: rtcifier.map_to_rtc(msg.timestamp)?,
 			src: msg,
 			at: 0,
-		}
+		})
 	}
 }
 
-impl<'x, T: rtcifier::RTCifier> Iterator for DS18B20Readouts<'x, T> {
+impl<'x> Iterator for DS18B20Readouts<'x> {
 	type Item = metric::Readout;
 
 	fn next(&mut self) -> Option<Self::Item> {
@@ -100,7 +106,7 @@ impl<'x, T: rtcifier::RTCifier> Iterator for DS18B20Readouts<'x, T> {
 			write!(instance, "{:02x}", octet).unwrap();
 		}
 		Some(metric::Readout {
-			timestamp: self.rtc.map_to_rtc(self.src.timestamp),
+			timestamp: self.ts,
 			path: metric::DevicePath {
 				device_type: "ds18b20".into(),
 				instance,
@@ -111,9 +117,12 @@ impl<'x, T: rtcifier::RTCifier> Iterator for DS18B20Readouts<'x, T> {
 }
 
 impl<'x, T: rtcifier::RTCifier + 'static> ReadoutIterable<'x, T> for frame::SbxDS18B20Message {
-	type GenIter = DS18B20Readouts<'x, T>;
+	type GenIter = DS18B20Readouts<'x>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(
+		&'x self,
+		rtcifier: &'x mut T,
+	) -> Result<Self::GenIter, rtcifier::TimelineOverflow> {
 		DS18B20Readouts::from_msg(self, rtcifier)
 	}
 }
@@ -124,14 +133,14 @@ impl BME280Readouts {
 	fn from_msg(
 		msg: &frame::SbxBME280Message,
 		rtcifier: &mut impl rtcifier::RTCifier,
-	) -> BME280Readouts {
+	) -> Result<BME280Readouts, rtcifier::TimelineOverflow> {
 		let mut path: SmartString = "i2c-2/".into();
 		write!(path, "{:02x}", 0x76 | (msg.instance & 0x1)).expect("formatting");
 		debug!("{:?} {:?} {:?}", msg.dig88, msg.dige1, msg.readout);
 
 		let calibration = bme280::CalibrationData::from_registers(&msg.dig88[..], &msg.dige1[..]);
 		let readout = bme280::Readout::from_registers(&msg.readout[..]);
-		let ts = rtcifier.map_to_rtc(msg.timestamp);
+		let ts = rtcifier.map_to_rtc(msg.timestamp)?;
 		#[allow(non_snake_case)]
 		let (T, P, H) = readout.decodef(&calibration);
 		let mut components = metric::OrderedVec::new();
@@ -157,14 +166,14 @@ impl BME280Readouts {
 			},
 		);
 
-		BME280Readouts(Some(metric::Readout {
+		Ok(BME280Readouts(Some(metric::Readout {
 			timestamp: ts,
 			path: metric::DevicePath {
 				instance: path,
 				device_type: "bme280".into(),
 			},
 			components: components,
-		}))
+		})))
 	}
 }
 
@@ -181,7 +190,10 @@ impl Iterator for BME280Readouts {
 impl<'x, T: rtcifier::RTCifier> ReadoutIterable<'x, T> for frame::SbxBME280Message {
 	type GenIter = BME280Readouts;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(
+		&'x self,
+		rtcifier: &'x mut T,
+	) -> Result<Self::GenIter, rtcifier::TimelineOverflow> {
 		BME280Readouts::from_msg(self, rtcifier)
 	}
 }
@@ -192,14 +204,14 @@ impl BME688Readouts {
 	fn from_msg(
 		msg: &frame::SbxBME688Message,
 		rtcifier: &mut impl rtcifier::RTCifier,
-	) -> BME688Readouts {
+	) -> Result<BME688Readouts, rtcifier::TimelineOverflow> {
 		let mut path: SmartString = "i2c-2/".into();
 		write!(path, "{:02x}", 0x76 | (msg.instance & 0x1)).expect("formatting");
 
 		debug!("{:?} {:?} {:?}", msg.par8a, msg.pare1, msg.readout);
 		let calibration = bme68x::CalibrationData::from_registers(&msg.par8a[..], &msg.pare1[..]);
 		let readout = bme68x::Readout::from_registers(&msg.readout[2..]);
-		let ts = rtcifier.map_to_rtc(msg.timestamp);
+		let ts = rtcifier.map_to_rtc(msg.timestamp)?;
 		#[allow(non_snake_case)]
 		let (T, P, H) = readout.decodef(&calibration);
 		let mut components = metric::OrderedVec::new();
@@ -225,14 +237,14 @@ impl BME688Readouts {
 			},
 		);
 
-		BME688Readouts(Some(metric::Readout {
+		Ok(BME688Readouts(Some(metric::Readout {
 			timestamp: ts,
 			path: metric::DevicePath {
 				instance: path,
 				device_type: "bme688".into(),
 			},
 			components: components,
-		}))
+		})))
 	}
 }
 
@@ -249,28 +261,39 @@ impl Iterator for BME688Readouts {
 impl<'x, T: rtcifier::RTCifier> ReadoutIterable<'x, T> for frame::SbxBME688Message {
 	type GenIter = BME688Readouts;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(
+		&'x self,
+		rtcifier: &'x mut T,
+	) -> Result<Self::GenIter, rtcifier::TimelineOverflow> {
 		BME688Readouts::from_msg(self, rtcifier)
 	}
 }
 
-pub struct NoiseReadouts<'x, T: rtcifier::RTCifier> {
-	rtc: &'x mut T,
+pub struct NoiseReadouts<'x> {
+	timestamps: Vec<DateTime<Utc>>,
 	src: &'x frame::SbxNoiseMessage,
 	at: usize,
 }
 
-impl<'x, T: rtcifier::RTCifier> NoiseReadouts<'x, T> {
-	fn from_msg(msg: &'x frame::SbxNoiseMessage, rtcifier: &'x mut T) -> Self {
-		Self {
-			rtc: rtcifier,
+impl<'x> NoiseReadouts<'x> {
+	fn from_msg(
+		msg: &'x frame::SbxNoiseMessage,
+		rtcifier: &mut impl rtcifier::RTCifier,
+	) -> Result<Self, rtcifier::TimelineOverflow> {
+		let timestamps = msg
+			.samples
+			.iter()
+			.map(|sample| rtcifier.map_to_rtc(sample.timestamp))
+			.collect::<Result<_, _>>()?;
+		Ok(Self {
+			timestamps,
 			src: msg,
 			at: 0,
-		}
+		})
 	}
 }
 
-impl<'x, T: rtcifier::RTCifier> Iterator for NoiseReadouts<'x, T> {
+impl<'x> Iterator for NoiseReadouts<'x> {
 	type Item = metric::Readout;
 
 	fn next(&mut self) -> Option<Self::Item> {
@@ -280,6 +303,7 @@ impl<'x, T: rtcifier::RTCifier> Iterator for NoiseReadouts<'x, T> {
 		}
 
 		let sample = &samples[self.at];
+		let timestamp = self.timestamps[self.at];
 		self.at += 1;
 		let mut components = metric::OrderedVec::new();
 		components.insert(
@@ -305,7 +329,7 @@ impl<'x, T: rtcifier::RTCifier> Iterator for NoiseReadouts<'x, T> {
 		);
 		let instance = "ch-0".into();
 		Some(metric::Readout {
-			timestamp: self.rtc.map_to_rtc(sample.timestamp),
+			timestamp,
 			path: metric::DevicePath {
 				device_type: "mic-preamp".into(),
 				instance,
@@ -316,30 +340,41 @@ impl<'x, T: rtcifier::RTCifier> Iterator for NoiseReadouts<'x, T> {
 }
 
 impl<'x, T: rtcifier::RTCifier + 'static> ReadoutIterable<'x, T> for frame::SbxNoiseMessage {
-	type GenIter = NoiseReadouts<'x, T>;
+	type GenIter = NoiseReadouts<'x>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(
+		&'x self,
+		rtcifier: &'x mut T,
+	) -> Result<Self::GenIter, rtcifier::TimelineOverflow> {
 		NoiseReadouts::from_msg(self, rtcifier)
 	}
 }
 
-pub struct LightReadouts<'x, T: rtcifier::RTCifier> {
-	rtc: &'x mut T,
+pub struct LightReadouts<'x> {
+	timestamps: Vec<DateTime<Utc>>,
 	src: &'x frame::SbxLightMessage,
 	at: usize,
 }
 
-impl<'x, T: rtcifier::RTCifier> LightReadouts<'x, T> {
-	fn from_msg(msg: &'x frame::SbxLightMessage, rtcifier: &'x mut T) -> Self {
-		Self {
-			rtc: rtcifier,
+impl<'x> LightReadouts<'x> {
+	fn from_msg(
+		msg: &'x frame::SbxLightMessage,
+		rtcifier: &mut impl rtcifier::RTCifier,
+	) -> Result<Self, rtcifier::TimelineOverflow> {
+		let timestamps = msg
+			.samples
+			.iter()
+			.map(|sample| rtcifier.map_to_rtc(sample.timestamp))
+			.collect::<Result<_, _>>()?;
+		Ok(Self {
+			timestamps,
 			src: msg,
 			at: 0,
-		}
+		})
 	}
 }
 
-impl<'x, T: rtcifier::RTCifier> Iterator for LightReadouts<'x, T> {
+impl<'x> Iterator for LightReadouts<'x> {
 	type Item = metric::Readout;
 
 	fn next(&mut self) -> Option<Self::Item> {
@@ -349,6 +384,7 @@ impl<'x, T: rtcifier::RTCifier> Iterator for LightReadouts<'x, T> {
 		}
 
 		let sample = &samples[self.at];
+		let timestamp = self.timestamps[self.at];
 		self.at += 1;
 		let mut components = metric::OrderedVec::new();
 		components.insert(
@@ -381,7 +417,7 @@ impl<'x, T: rtcifier::RTCifier> Iterator for LightReadouts<'x, T> {
 		);
 		let instance = "ch-0".into();
 		Some(metric::Readout {
-			timestamp: self.rtc.map_to_rtc(sample.timestamp),
+			timestamp,
 			path: metric::DevicePath {
 				device_type: "tcs3200".into(),
 				instance,
@@ -392,9 +428,12 @@ impl<'x, T: rtcifier::RTCifier> Iterator for LightReadouts<'x, T> {
 }
 
 impl<'x, T: rtcifier::RTCifier + 'static> ReadoutIterable<'x, T> for frame::SbxLightMessage {
-	type GenIter = LightReadouts<'x, T>;
+	type GenIter = LightReadouts<'x>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(
+		&'x self,
+		rtcifier: &'x mut T,
+	) -> Result<Self::GenIter, rtcifier::TimelineOverflow> {
 		LightReadouts::from_msg(self, rtcifier)
 	}
 }
@@ -417,12 +456,12 @@ impl<'x> StatusReadouts<'x> {
 	fn from_msg(
 		msg: &'x frame::SbxStatusMessage,
 		rtcifier: &'x mut impl rtcifier::RTCifier,
-	) -> StatusReadouts<'x> {
-		StatusReadouts {
-			ts: rtcifier.map_to_rtc(msg.uptime),
+	) -> Result<StatusReadouts<'x>, rtcifier::TimelineOverflow> {
+		Ok(StatusReadouts {
+			ts: rtcifier.map_to_rtc(msg.uptime)?,
 			src: msg,
 			at: StatusPart::I2CMetrics(0),
-		}
+		})
 	}
 }
 
@@ -617,7 +656,10 @@ impl<'x> Iterator for StatusReadouts<'x> {
 impl<'x, T: rtcifier::RTCifier> ReadoutIterable<'x, T> for frame::SbxStatusMessage {
 	type GenIter = StatusReadouts<'x>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(
+		&'x self,
+		rtcifier: &'x mut T,
+	) -> Result<Self::GenIter, rtcifier::TimelineOverflow> {
 		StatusReadouts::from_msg(self, rtcifier)
 	}
 }
@@ -633,7 +675,7 @@ mod test_ds18b20readouts {
 	fn new_rtc() -> (rtcifier::LinearRTC, DateTime<Utc>) {
 		let dt0 = Utc.ymd(2021, 7, 17).and_hms(16, 28, 0);
 		let mut rtc = rtcifier::LinearRTC::default();
-		rtc.align(dt0, 0);
+		rtc.align(dt0, 0).unwrap();
 		(rtc, dt0)
 	}
 
@@ -648,7 +690,7 @@ mod test_ds18b20readouts {
 			}],
 		};
 
-		let mut iter = DS18B20Readouts::from_msg(&msg, &mut rtc);
+		let mut iter = DS18B20Readouts::from_msg(&msg, &mut rtc).unwrap();
 		let item = iter.next().unwrap();
 		assert_eq!(item.timestamp, dt0 + Duration::seconds(1));
 		assert_eq!(item.path.device_type, "ds18b20");
@@ -687,7 +729,7 @@ mod test_ds18b20readouts {
 			],
 		};
 
-		let mut iter = DS18B20Readouts::from_msg(&msg, &mut rtc);
+		let mut iter = DS18B20Readouts::from_msg(&msg, &mut rtc).unwrap();
 		let item = iter.next().unwrap();
 		assert_eq!(item.timestamp, dt0 + Duration::seconds(1));
 		assert_eq!(item.path.device_type, "ds18b20");
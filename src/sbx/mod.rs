@@ -16,9 +16,11 @@ pub use frame::{
 pub use generators::ReadoutIterable;
 #[cfg(feature = "unstable-rtcs")]
 pub use rtcifier::{FilteredRTC, RangeRTCLiori, RangeRTCv2};
-pub use rtcifier::{LinearRTC, RTCifier, RangeRTC};
+pub use rtcifier::{
+	LinearRTC, LinearRTCSnapshot, RTCifier, RangeRTC, RangeRTCSnapshot, TimelineOverflow,
+};
 
-pub use stream::{StreamDecoder, StreamKind};
+pub use stream::{DecodeError, StreamDecoder, StreamKind};
 
 #[derive(Debug, Clone)]
 pub enum ReadoutMessage {
@@ -62,14 +64,14 @@ impl From<SbxNoiseMessage> for ReadoutMessage {
 impl<'x, T: rtcifier::RTCifier + 'static> generators::ReadoutIterable<'x, T> for ReadoutMessage {
 	type GenIter = generators::DynSampleIterator<'x>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
-		match self {
-			Self::DS18B20(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)),
-			Self::BME280(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)),
-			Self::BME688(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)),
-			Self::Noise(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)),
-			Self::Light(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)),
-		}
+	fn readouts(&'x self, rtcifier: &'x mut T) -> Result<Self::GenIter, TimelineOverflow> {
+		Ok(match self {
+			Self::DS18B20(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)?),
+			Self::BME280(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)?),
+			Self::BME688(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)?),
+			Self::Noise(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)?),
+			Self::Light(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)?),
+		})
 	}
 }
 
@@ -89,8 +91,8 @@ impl StreamMessage {
 impl<'x, T: rtcifier::RTCifier> generators::ReadoutIterable<'x, T> for StreamMessage {
 	type GenIter = generators::DynSampleIterator<'x>;
 
-	fn readouts(&'x self, _rtcifier: &'x mut T) -> Self::GenIter {
-		Self::GenIter::wrap(generators::Empty())
+	fn readouts(&'x self, _rtcifier: &'x mut T) -> Result<Self::GenIter, TimelineOverflow> {
+		Ok(Self::GenIter::wrap(generators::Empty()))
 	}
 }
 
@@ -145,9 +147,9 @@ impl Message {
 impl<'x, T: rtcifier::RTCifier + 'static> generators::ReadoutIterable<'x, T> for Message {
 	type GenIter = generators::DynSampleIterator<'x>;
 
-	fn readouts(&'x self, rtcifier: &'x mut T) -> Self::GenIter {
+	fn readouts(&'x self, rtcifier: &'x mut T) -> Result<Self::GenIter, TimelineOverflow> {
 		match self {
-			Self::Status(msg) => Self::GenIter::wrap(msg.readouts(rtcifier)),
+			Self::Status(msg) => Ok(Self::GenIter::wrap(msg.readouts(rtcifier)?)),
 			Self::ReadoutData(msg) => msg.readouts(rtcifier),
 			Self::StreamData(msg) => msg.readouts(rtcifier),
 		}
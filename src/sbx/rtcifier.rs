@@ -1,10 +1,26 @@
 use std::collections::VecDeque;
+use std::fmt;
 use std::fmt::Debug;
 
 use log::warn;
 
 use chrono::{DateTime, Duration, Utc};
 
+use serde_derive::{Deserialize, Serialize};
+
+/// The local counter of a [`Timeline`] left the range of `i64` because it was
+/// not reset for too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimelineOverflow;
+
+impl fmt::Display for TimelineOverflow {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str("timeline overflowed without being reset")
+	}
+}
+
+impl std::error::Error for TimelineOverflow {}
+
 /// Map a local high-range, high-precision counter to a remote low-range counter of the same precision.
 #[derive(Debug)]
 pub struct Timeline {
@@ -33,14 +49,14 @@ impl Timeline {
 		return fwd_diff;
 	}
 
-	pub fn feed_and_transform(&mut self, remote: u16) -> i64 {
+	pub fn feed_and_transform(&mut self, remote: u16) -> Result<i64, TimelineOverflow> {
 		let change = self.wraparound_aware_minus(remote, self.remote_tip);
 		self.local_tip = self
 			.local_tip
 			.checked_add(change as i64)
-			.expect("should've reset in between *shrug*");
+			.ok_or(TimelineOverflow)?;
 		self.remote_tip = remote;
-		self.local_tip
+		Ok(self.local_tip)
 	}
 
 	pub fn reset(&mut self, new_remote_tip: u16) {
@@ -48,14 +64,18 @@ impl Timeline {
 		self.local_tip = 0;
 	}
 
+	/// Remote counter value which corresponds to local zero (modulo the
+	/// counter range).
+	pub fn origin(&self) -> u16 {
+		self.remote_tip.wrapping_sub(self.local_tip as u16)
+	}
+
 	#[cfg(test)]
-	pub fn forward(&mut self, offset: i64) {
+	pub fn forward(&mut self, offset: i64) -> Result<(), TimelineOverflow> {
 		assert!(offset >= 0);
-		self.local_tip = self
-			.local_tip
-			.checked_add(offset)
-			.expect("should've reset before");
+		self.local_tip = self.local_tip.checked_add(offset).ok_or(TimelineOverflow)?;
 		self.remote_tip = self.remote_tip.wrapping_add(offset as u16);
+		Ok(())
 	}
 }
 
@@ -79,84 +99,84 @@ mod tests_timeline {
 			}
 		}) {
 			let i_16 = i as u16;
-			assert_eq!(i, tl.feed_and_transform(i_16));
+			assert_eq!(i, tl.feed_and_transform(i_16).unwrap());
 		}
 	}
 
 	#[test]
 	fn test_feed_and_transform_wraparound() {
 		let mut tl = new_tl();
-		tl.feed_and_transform(0);
-		tl.feed_and_transform(10000);
-		tl.feed_and_transform(20000);
-		tl.feed_and_transform(30000);
-		tl.feed_and_transform(40000);
-		tl.feed_and_transform(50000);
-		tl.feed_and_transform(60000);
-		assert_eq!(65536, tl.feed_and_transform(0));
+		tl.feed_and_transform(0).unwrap();
+		tl.feed_and_transform(10000).unwrap();
+		tl.feed_and_transform(20000).unwrap();
+		tl.feed_and_transform(30000).unwrap();
+		tl.feed_and_transform(40000).unwrap();
+		tl.feed_and_transform(50000).unwrap();
+		tl.feed_and_transform(60000).unwrap();
+		assert_eq!(65536, tl.feed_and_transform(0).unwrap());
 	}
 
 	#[test]
 	fn test_feed_and_transform_wraparound_above_zero() {
 		let mut tl = new_tl();
-		tl.feed_and_transform(0);
-		tl.feed_and_transform(10000);
-		tl.feed_and_transform(20000);
-		tl.feed_and_transform(30000);
-		tl.feed_and_transform(40000);
-		tl.feed_and_transform(50000);
-		tl.feed_and_transform(60000);
-		assert_eq!(65536 + 1200, tl.feed_and_transform(1200));
+		tl.feed_and_transform(0).unwrap();
+		tl.feed_and_transform(10000).unwrap();
+		tl.feed_and_transform(20000).unwrap();
+		tl.feed_and_transform(30000).unwrap();
+		tl.feed_and_transform(40000).unwrap();
+		tl.feed_and_transform(50000).unwrap();
+		tl.feed_and_transform(60000).unwrap();
+		assert_eq!(65536 + 1200, tl.feed_and_transform(1200).unwrap());
 	}
 
 	#[test]
 	fn test_feed_and_transform_slack() {
 		let mut tl = new_tl();
-		tl.feed_and_transform(0);
-		tl.feed_and_transform(10000);
-		tl.feed_and_transform(20000);
-		tl.feed_and_transform(30000);
-		tl.feed_and_transform(40000);
-		tl.feed_and_transform(50000);
-		tl.feed_and_transform(60000);
-		assert_eq!(59001, tl.feed_and_transform(59001));
+		tl.feed_and_transform(0).unwrap();
+		tl.feed_and_transform(10000).unwrap();
+		tl.feed_and_transform(20000).unwrap();
+		tl.feed_and_transform(30000).unwrap();
+		tl.feed_and_transform(40000).unwrap();
+		tl.feed_and_transform(50000).unwrap();
+		tl.feed_and_transform(60000).unwrap();
+		assert_eq!(59001, tl.feed_and_transform(59001).unwrap());
 	}
 
 	#[test]
 	fn test_feed_and_transform_slack_after_wraparound() {
 		let mut tl = new_tl();
-		tl.feed_and_transform(0);
-		tl.feed_and_transform(10000);
-		tl.feed_and_transform(20000);
-		tl.feed_and_transform(30000);
-		tl.feed_and_transform(40000);
-		tl.feed_and_transform(50000);
-		tl.feed_and_transform(60000);
-		tl.feed_and_transform(10);
-		assert_eq!(65535, tl.feed_and_transform(65535));
+		tl.feed_and_transform(0).unwrap();
+		tl.feed_and_transform(10000).unwrap();
+		tl.feed_and_transform(20000).unwrap();
+		tl.feed_and_transform(30000).unwrap();
+		tl.feed_and_transform(40000).unwrap();
+		tl.feed_and_transform(50000).unwrap();
+		tl.feed_and_transform(60000).unwrap();
+		tl.feed_and_transform(10).unwrap();
+		assert_eq!(65535, tl.feed_and_transform(65535).unwrap());
 	}
 
 	#[test]
 	fn test_feed_and_transform_slack_wraparound_slack() {
 		let mut tl = new_tl();
-		tl.feed_and_transform(0);
-		tl.feed_and_transform(10000);
-		tl.feed_and_transform(20000);
-		tl.feed_and_transform(30000);
-		tl.feed_and_transform(40000);
-		tl.feed_and_transform(50000);
-		tl.feed_and_transform(60000);
-		assert_eq!(59001, tl.feed_and_transform(59001));
-		tl.feed_and_transform(10);
-		assert_eq!(65535, tl.feed_and_transform(65535));
-		assert_eq!(65546, tl.feed_and_transform(10));
+		tl.feed_and_transform(0).unwrap();
+		tl.feed_and_transform(10000).unwrap();
+		tl.feed_and_transform(20000).unwrap();
+		tl.feed_and_transform(30000).unwrap();
+		tl.feed_and_transform(40000).unwrap();
+		tl.feed_and_transform(50000).unwrap();
+		tl.feed_and_transform(60000).unwrap();
+		assert_eq!(59001, tl.feed_and_transform(59001).unwrap());
+		tl.feed_and_transform(10).unwrap();
+		assert_eq!(65535, tl.feed_and_transform(65535).unwrap());
+		assert_eq!(65546, tl.feed_and_transform(10).unwrap());
 	}
 
 	#[test]
 	fn test_reset_and_feed() {
 		let mut tl = new_tl();
 		tl.reset(1000);
-		assert_eq!(0, tl.feed_and_transform(1000));
+		assert_eq!(0, tl.feed_and_transform(1000).unwrap());
 		for i in std::iter::successors(Some(1000), |&prev| {
 			let next = prev + 100;
 			if next < 65536 {
@@ -166,34 +186,75 @@ mod tests_timeline {
 			}
 		}) {
 			let i_16 = i as u16;
-			assert_eq!(i - 1000, tl.feed_and_transform(i_16));
+			assert_eq!(i - 1000, tl.feed_and_transform(i_16).unwrap());
 		}
 	}
 
 	#[test]
 	fn test_feed_and_transform_slack_after_reset() {
 		let mut tl = new_tl();
-		assert_eq!(-999, tl.feed_and_transform(64537));
+		assert_eq!(-999, tl.feed_and_transform(64537).unwrap());
 	}
 
 	#[test]
 	fn test_forward() {
 		let mut tl = new_tl();
-		tl.forward(65536 + 5);
-		assert_eq!(65536 + 10, tl.feed_and_transform(10));
+		tl.forward(65536 + 5).unwrap();
+		assert_eq!(65536 + 10, tl.feed_and_transform(10).unwrap());
+	}
+
+	#[test]
+	fn test_feed_and_transform_reports_overflow() {
+		let mut tl = new_tl();
+		tl.forward(i64::MAX - 5).unwrap();
+		let remote = tl.origin().wrapping_add((i64::MAX - 5) as u16);
+		assert_eq!(
+			tl.feed_and_transform(remote.wrapping_add(10)),
+			Err(TimelineOverflow)
+		);
+	}
+
+	#[test]
+	fn test_resolve_elapsed_counts_wraparounds() {
+		assert_eq!(resolve_elapsed(1000, 1500, 400), Some(500));
+		assert_eq!(resolve_elapsed(1000, 1500, 131000), Some(131572));
+		assert_eq!(resolve_elapsed(1000, 1500, 120000), None);
+		assert_eq!(resolve_elapsed(1000, 1500, -5), None);
 	}
 
 	#[test]
 	fn test_forward_wrapping() {
 		let mut tl = new_tl();
-		tl.forward(2 * 65536 + 5);
-		assert_eq!(2 * 65536 + 10, tl.feed_and_transform(10));
+		tl.forward(2 * 65536 + 5).unwrap();
+		assert_eq!(2 * 65536 + 10, tl.feed_and_transform(10).unwrap());
 	}
 }
 
+/// Estimate how far a 16 bit millisecond counter advanced from `origin` to
+/// `remote`, given that roughly `elapsed_ms` passed according to the RTC.
+///
+/// The RTC is used to recover the number of counter wraparounds. If the
+/// counter value is not consistent with the RTC (e.g. because the remote
+/// rebooted in between), `None` is returned.
+pub fn resolve_elapsed(origin: u16, remote: u16, elapsed_ms: i64) -> Option<i64> {
+	const WRAP: i64 = 65536;
+	const TOLERANCE_MS: i64 = 2000;
+
+	if elapsed_ms < 0 {
+		return None;
+	}
+	let fwd = remote.wrapping_sub(origin) as i64;
+	let wraps = ((elapsed_ms - fwd) as f64 / WRAP as f64).round().max(0.) as i64;
+	let offset = fwd + wraps * WRAP;
+	if (offset - elapsed_ms).abs() > TOLERANCE_MS {
+		return None;
+	}
+	Some(offset)
+}
+
 pub trait RTCifier: Debug {
-	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16);
-	fn map_to_rtc(&mut self, timestamp: u16) -> DateTime<Utc>;
+	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) -> Result<(), TimelineOverflow>;
+	fn map_to_rtc(&mut self, timestamp: u16) -> Result<DateTime<Utc>, TimelineOverflow>;
 	fn reset(&mut self);
 	fn ready(&self) -> bool;
 }
@@ -235,6 +296,53 @@ impl LinearRTC {
 			self.history.pop_front();
 		}
 	}
+
+	/// Capture the learned state so that it can be restored with
+	/// [`resume`](Self::resume) later.
+	pub fn snapshot(&self) -> Option<LinearRTCSnapshot> {
+		if self.history.is_empty() {
+			return None;
+		}
+		Some(LinearRTCSnapshot {
+			origin: self.timeline.origin(),
+			rtcbase: self.rtcbase,
+			history: self.history.iter().cloned().collect(),
+		})
+	}
+
+	/// Restore a snapshot and align it to the given point.
+	///
+	/// Returns false (and leaves the RTCifier untouched) if the snapshot
+	/// cannot be reconciled with the given point.
+	pub fn resume(
+		&mut self,
+		snapshot: &LinearRTCSnapshot,
+		rtc: DateTime<Utc>,
+		timestamp: u16,
+	) -> Result<bool, TimelineOverflow> {
+		let elapsed = (rtc - snapshot.rtcbase).num_milliseconds();
+		let offset = match resolve_elapsed(snapshot.origin, timestamp, elapsed) {
+			Some(v) => v,
+			None => return Ok(false),
+		};
+		self.rtcbase = snapshot.rtcbase + Duration::milliseconds(offset);
+		self.history = snapshot
+			.history
+			.iter()
+			.map(|(hist_rtc, hist_offset)| (*hist_rtc, *hist_offset - offset))
+			.collect();
+		self.timeline.reset(timestamp);
+		self.align(rtc, timestamp)?;
+		Ok(true)
+	}
+}
+
+/// Persistable state of a [`LinearRTC`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinearRTCSnapshot {
+	origin: u16,
+	rtcbase: DateTime<Utc>,
+	history: Vec<(DateTime<Utc>, i64)>,
 }
 
 impl Default for LinearRTC {
@@ -244,11 +352,11 @@ impl Default for LinearRTC {
 }
 
 impl RTCifier for LinearRTC {
-	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) {
+	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) -> Result<(), TimelineOverflow> {
 		self.truncate_history();
 
 		let new_ref = timestamp;
-		let offset = self.timeline.feed_and_transform(new_ref);
+		let offset = self.timeline.feed_and_transform(new_ref)?;
 
 		for (_, hist_offset) in self.history.iter_mut() {
 			*hist_offset = *hist_offset - offset;
@@ -278,10 +386,11 @@ impl RTCifier for LinearRTC {
 			};
 
 		self.timeline.reset(new_ref);
+		Ok(())
 	}
 
-	fn map_to_rtc(&mut self, timestamp: u16) -> DateTime<Utc> {
-		self.rtcbase + Duration::milliseconds(self.timeline.feed_and_transform(timestamp))
+	fn map_to_rtc(&mut self, timestamp: u16) -> Result<DateTime<Utc>, TimelineOverflow> {
+		Ok(self.rtcbase + Duration::milliseconds(self.timeline.feed_and_transform(timestamp)?))
 	}
 
 	fn reset(&mut self) {
@@ -314,10 +423,13 @@ mod tests_rtcifier {
 		let t0 = 5;
 		let mut rtcifier = new_rtcifier();
 
-		rtcifier.align(dt0, t0);
+		rtcifier.align(dt0, t0).unwrap();
 
-		assert_eq!(rtcifier.map_to_rtc(5), dt0);
-		assert_eq!(rtcifier.map_to_rtc(0), dt0 - Duration::milliseconds(5));
+		assert_eq!(rtcifier.map_to_rtc(5).unwrap(), dt0);
+		assert_eq!(
+			rtcifier.map_to_rtc(0).unwrap(),
+			dt0 - Duration::milliseconds(5)
+		);
 	}
 
 	#[test]
@@ -329,33 +441,33 @@ mod tests_rtcifier {
 		let t1 = 65500;
 		let mut rtcifier = new_rtcifier();
 
-		rtcifier.align(dt0, t0);
+		rtcifier.align(dt0, t0).unwrap();
 
 		assert_eq!(
-			rtcifier.map_to_rtc(65500),
+			rtcifier.map_to_rtc(65500).unwrap(),
 			dt0 + Duration::milliseconds(500)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(65200),
+			rtcifier.map_to_rtc(65200).unwrap(),
 			dt0 + Duration::milliseconds(200)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(64800),
+			rtcifier.map_to_rtc(64800).unwrap(),
 			dt0 - Duration::milliseconds(200)
 		);
 
-		rtcifier.align(dt1, t1);
+		rtcifier.align(dt1, t1).unwrap();
 
 		assert_eq!(
-			rtcifier.map_to_rtc(65500),
+			rtcifier.map_to_rtc(65500).unwrap(),
 			dt0 + Duration::milliseconds(500)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(65200),
+			rtcifier.map_to_rtc(65200).unwrap(),
 			dt0 + Duration::milliseconds(200)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(64800),
+			rtcifier.map_to_rtc(64800).unwrap(),
 			dt0 - Duration::milliseconds(200)
 		);
 	}
@@ -365,21 +477,24 @@ mod tests_rtcifier {
 		let dt0 = Utc.ymd(2017, 6, 10).and_hms(9, 41, 0);
 		let mut rtcifier = new_rtcifier();
 
-		rtcifier.align(dt0, 0);
-		assert_eq!(rtcifier.map_to_rtc(0), dt0);
+		rtcifier.align(dt0, 0).unwrap();
+		assert_eq!(rtcifier.map_to_rtc(0).unwrap(), dt0);
 
-		rtcifier.align(dt0 + Duration::seconds(1), 1000);
-		assert_eq!(rtcifier.map_to_rtc(1000), dt0 + Duration::seconds(1));
+		rtcifier.align(dt0 + Duration::seconds(1), 1000).unwrap();
+		assert_eq!(
+			rtcifier.map_to_rtc(1000).unwrap(),
+			dt0 + Duration::seconds(1)
+		);
 
-		rtcifier.align(dt0 + Duration::seconds(3), 2000);
+		rtcifier.align(dt0 + Duration::seconds(3), 2000).unwrap();
 		assert_eq!(
-			rtcifier.map_to_rtc(2000),
+			rtcifier.map_to_rtc(2000).unwrap(),
 			dt0 + Duration::nanoseconds(2333333334)
 		);
 
-		rtcifier.align(dt0 + Duration::seconds(4), 3000);
+		rtcifier.align(dt0 + Duration::seconds(4), 3000).unwrap();
 		assert_eq!(
-			rtcifier.map_to_rtc(3000),
+			rtcifier.map_to_rtc(3000).unwrap(),
 			dt0 + Duration::milliseconds(3500)
 		);
 	}
@@ -389,24 +504,30 @@ mod tests_rtcifier {
 		let dt0 = Utc.ymd(2017, 6, 10).and_hms(9, 41, 0);
 		let mut rtcifier = new_rtcifier();
 
-		rtcifier.align(dt0, 0);
-		assert_eq!(rtcifier.map_to_rtc(0), dt0);
+		rtcifier.align(dt0, 0).unwrap();
+		assert_eq!(rtcifier.map_to_rtc(0).unwrap(), dt0);
 
-		rtcifier.align(dt0 + Duration::seconds(1), 1000);
-		assert_eq!(rtcifier.map_to_rtc(1000), dt0 + Duration::seconds(1));
+		rtcifier.align(dt0 + Duration::seconds(1), 1000).unwrap();
+		assert_eq!(
+			rtcifier.map_to_rtc(1000).unwrap(),
+			dt0 + Duration::seconds(1)
+		);
 
-		rtcifier.align(dt0 + Duration::seconds(3), 2000);
+		rtcifier.align(dt0 + Duration::seconds(3), 2000).unwrap();
 		assert_eq!(
-			rtcifier.map_to_rtc(2000),
+			rtcifier.map_to_rtc(2000).unwrap(),
 			dt0 + Duration::nanoseconds(2333333334)
 		);
 
-		rtcifier.align(dt0 + Duration::seconds(120), 3000);
-		assert_eq!(rtcifier.map_to_rtc(3000), dt0 + Duration::seconds(120));
+		rtcifier.align(dt0 + Duration::seconds(120), 3000).unwrap();
+		assert_eq!(
+			rtcifier.map_to_rtc(3000).unwrap(),
+			dt0 + Duration::seconds(120)
+		);
 	}
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 struct Range {
 	lower: i64,
 	upper: i64,
@@ -487,6 +608,53 @@ impl RangeRTC {
 	fn get_offset(&self) -> i64 {
 		self.range.as_ref().unwrap().threshold()
 	}
+
+	/// Capture the learned offset range so that it can be restored with
+	/// [`resume`](Self::resume) later.
+	pub fn snapshot(&self) -> Option<RangeRTCSnapshot> {
+		match (self.range, self.state.as_ref()) {
+			(Some(range), Some(state)) => Some(RangeRTCSnapshot {
+				origin: self.timeline.origin(),
+				rtc: state.rtc,
+				range,
+			}),
+			_ => None,
+		}
+	}
+
+	/// Restore a snapshot, using the given point as the first alignment.
+	///
+	/// Returns false (and leaves the RTCifier untouched) if the snapshot
+	/// cannot be reconciled with the given point, for instance because the
+	/// remote rebooted in between.
+	pub fn resume(
+		&mut self,
+		snapshot: &RangeRTCSnapshot,
+		rtc: DateTime<Utc>,
+		timestamp: u16,
+	) -> bool {
+		let elapsed = (rtc - snapshot.rtc).num_milliseconds();
+		let offset = match resolve_elapsed(snapshot.origin, timestamp, elapsed) {
+			Some(v) => v,
+			None => return false,
+		};
+		// the range describes a phase within a second, so whole seconds
+		// can be dropped
+		let mut range = snapshot.range;
+		range.shift(offset.rem_euclid(1000));
+		self.range = Some(range);
+		self.state = Some(State { rtc });
+		self.timeline.reset(timestamp);
+		true
+	}
+}
+
+/// Persistable state of a [`RangeRTC`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeRTCSnapshot {
+	origin: u16,
+	rtc: DateTime<Utc>,
+	range: Range,
 }
 
 impl Default for RangeRTC {
@@ -496,21 +664,21 @@ impl Default for RangeRTC {
 }
 
 impl RTCifier for RangeRTC {
-	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) {
+	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) -> Result<(), TimelineOverflow> {
 		let state = match self.state.as_mut() {
 			None => {
 				self.state = Some(State { rtc });
 				self.timeline.reset(timestamp);
-				return;
+				return Ok(());
 			}
 			Some(st) => st,
 		};
 
 		// first, we need to advance the timeline
-		let offset = self.timeline.feed_and_transform(timestamp);
+		let offset = self.timeline.feed_and_transform(timestamp)?;
 		if offset <= 0 {
 			// we cannot have that, it'll only cause pain
-			return;
+			return Ok(());
 		}
 
 		// now we reset it and de-advance all our internal ranges
@@ -537,14 +705,15 @@ impl RTCifier for RangeRTC {
 			};
 			// println!("{:?} {} {}", self.range, lower_bound, upper_bound);
 		}
+		Ok(())
 	}
 
-	fn map_to_rtc(&mut self, timestamp: u16) -> DateTime<Utc> {
+	fn map_to_rtc(&mut self, timestamp: u16) -> Result<DateTime<Utc>, TimelineOverflow> {
 		let offset = self.get_offset();
-		let timestamp = self.timeline.feed_and_transform(timestamp);
+		let timestamp = self.timeline.feed_and_transform(timestamp)?;
 		let ms = timestamp - offset;
 		// println!("{} {} {} {}", threshold, timestamp, ms, self.state.as_ref().unwrap().rtc);
-		self.state.as_ref().unwrap().rtc + Duration::milliseconds(ms)
+		Ok(self.state.as_ref().unwrap().rtc + Duration::milliseconds(ms))
 	}
 
 	fn reset(&mut self) {
@@ -589,13 +758,13 @@ impl Default for RangeRTCv2 {
 
 #[cfg(feature = "unstable-rtcs")]
 impl RTCifier for RangeRTCv2 {
-	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) {
+	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) -> Result<(), TimelineOverflow> {
 		let state = match self.state.as_mut() {
 			None => {
 				// this is now our forever epoch for the timestamp value
 				self.state = Some((rtc, timestamp, timestamp));
 				self.timeline.reset(timestamp);
-				return;
+				return Ok(());
 			}
 			Some(st) => st,
 		};
@@ -605,8 +774,8 @@ impl RTCifier for RangeRTCv2 {
 			// first, we need to advance the timeline
 			state.2 = state.2.wrapping_add(1000);
 			self.timeline.reset(state.2);
-			let lower_bound = self.timeline.feed_and_transform(state.1);
-			let upper_bound = self.timeline.feed_and_transform(timestamp);
+			let lower_bound = self.timeline.feed_and_transform(state.1)?;
+			let upper_bound = self.timeline.feed_and_transform(timestamp)?;
 
 			self.range = Some((lower_bound, upper_bound));
 			state.0 = rtc;
@@ -615,14 +784,15 @@ impl RTCifier for RangeRTCv2 {
 		// advance the internal timestamp value so that we know the bound
 		// for a transition
 		state.1 = timestamp;
+		Ok(())
 	}
 
-	fn map_to_rtc(&mut self, timestamp: u16) -> DateTime<Utc> {
+	fn map_to_rtc(&mut self, timestamp: u16) -> Result<DateTime<Utc>, TimelineOverflow> {
 		let offset = self.get_offset();
-		let timestamp = self.timeline.feed_and_transform(timestamp);
+		let timestamp = self.timeline.feed_and_transform(timestamp)?;
 		let ms = timestamp - offset;
 		eprintln!("{} {} {} {:?}", offset, timestamp, ms, self.state);
-		self.state.as_ref().unwrap().0 + Duration::milliseconds(ms)
+		Ok(self.state.as_ref().unwrap().0 + Duration::milliseconds(ms))
 	}
 
 	fn reset(&mut self) {
@@ -836,11 +1006,11 @@ mod test_ring_median {
 
 #[cfg(feature = "unstable-rtcs")]
 impl RTCifier for FilteredRTC {
-	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) {
-		let diff = self.timeline.feed_and_transform(timestamp);
-		self.rangertc.align(rtc, timestamp);
+	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) -> Result<(), TimelineOverflow> {
+		let diff = self.timeline.feed_and_transform(timestamp)?;
+		self.rangertc.align(rtc, timestamp)?;
 		if diff <= 0 {
-			return;
+			return Ok(());
 		}
 		self.timeline.reset(timestamp);
 		if self.rangertc.ready() {
@@ -870,16 +1040,17 @@ impl RTCifier for FilteredRTC {
 			self.offsets.push(offset);
 			// eprintln!("{:?}", self.offsets);
 		}
+		Ok(())
 	}
 
-	fn map_to_rtc(&mut self, timestamp: u16) -> DateTime<Utc> {
+	fn map_to_rtc(&mut self, timestamp: u16) -> Result<DateTime<Utc>, TimelineOverflow> {
 		let mut buf = self.offsets.clone();
 		buf.sort();
 		let offset = ring_median(&buf).unwrap();
-		let timestamp = self.rangertc.timeline.feed_and_transform(timestamp);
+		let timestamp = self.rangertc.timeline.feed_and_transform(timestamp)?;
 		let ms = timestamp - offset;
 		// eprintln!("{} {} {} {}", offset, timestamp, ms, self.rangertc.state.as_ref().unwrap().rtc);
-		self.rangertc.state.as_ref().unwrap().rtc + Duration::milliseconds(ms)
+		Ok(self.rangertc.state.as_ref().unwrap().rtc + Duration::milliseconds(ms))
 	}
 
 	fn reset(&mut self) {
@@ -972,11 +1143,11 @@ mod test_rtcifierv2 {
 	fn test_unready_if_only_equal_rtc_timestamps_are_fed() {
 		let mut rtcifier = new_rtcifier();
 		let dt0 = Utc.ymd(2021, 8, 1).and_hms(17, 3, 15);
-		rtcifier.align(dt0, 1200);
+		rtcifier.align(dt0, 1200).unwrap();
 		assert!(!rtcifier.ready());
-		rtcifier.align(dt0, 1250);
+		rtcifier.align(dt0, 1250).unwrap();
 		assert!(!rtcifier.ready());
-		rtcifier.align(dt0, 1270);
+		rtcifier.align(dt0, 1270).unwrap();
 		assert!(!rtcifier.ready());
 	}
 
@@ -985,29 +1156,29 @@ mod test_rtcifierv2 {
 		let mut rtcifier = new_rtcifier();
 		let dt0 = Utc.ymd(2021, 8, 1).and_hms(17, 3, 15);
 		let dt1 = Utc.ymd(2021, 8, 1).and_hms(17, 3, 16);
-		rtcifier.align(dt0, 1200);
+		rtcifier.align(dt0, 1200).unwrap();
 		assert!(!rtcifier.ready());
-		rtcifier.align(dt0, 1250);
+		rtcifier.align(dt0, 1250).unwrap();
 		assert!(!rtcifier.ready());
-		rtcifier.align(dt0, 1270);
+		rtcifier.align(dt0, 1270).unwrap();
 		assert!(!rtcifier.ready());
-		rtcifier.align(dt1, 1300);
+		rtcifier.align(dt1, 1300).unwrap();
 		assert!(rtcifier.ready());
 
 		assert_eq!(
-			rtcifier.map_to_rtc(1285),
+			rtcifier.map_to_rtc(1285).unwrap(),
 			Utc.ymd(2021, 8, 1).and_hms(17, 3, 16)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(1300),
+			rtcifier.map_to_rtc(1300).unwrap(),
 			Utc.ymd(2021, 8, 1).and_hms_milli(17, 3, 16, 15)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(1400),
+			rtcifier.map_to_rtc(1400).unwrap(),
 			Utc.ymd(2021, 8, 1).and_hms_milli(17, 3, 16, 115)
 		);
 		assert_eq!(
-			rtcifier.map_to_rtc(2300),
+			rtcifier.map_to_rtc(2300).unwrap(),
 			Utc.ymd(2021, 8, 1).and_hms_milli(17, 3, 17, 15)
 		);
 	}
@@ -1018,18 +1189,67 @@ mod test_rtcifierv2 {
 		let dt0 = Utc.ymd(2021, 8, 1).and_hms(17, 3, 15);
 		let dt1 = Utc.ymd(2021, 8, 1).and_hms(17, 3, 16);
 		let dt2 = Utc.ymd(2021, 8, 1).and_hms(17, 3, 17);
-		rtcifier.align(dt0, 1270);
-		rtcifier.align(dt1, 1300);
+		rtcifier.align(dt0, 1270).unwrap();
+		rtcifier.align(dt1, 1300).unwrap();
 		// lower bound is 270, upper bound is 300
-		rtcifier.align(dt1, 2200);
-		rtcifier.align(dt2, 2290);
+		rtcifier.align(dt1, 2200).unwrap();
+		rtcifier.align(dt2, 2290).unwrap();
 		// lower bound is 270, upper bound is 290 -> midpoint is
 
 		assert_eq!(
-			rtcifier.map_to_rtc(2290),
+			rtcifier.map_to_rtc(2290).unwrap(),
 			Utc.ymd(2021, 8, 1).and_hms_milli(17, 3, 17, 10)
 		);
 	}
+
+	fn trained_rtcifier() -> RangeRTC {
+		let mut rtcifier = new_rtcifier();
+		rtcifier
+			.align(Utc.ymd(2021, 8, 1).and_hms(17, 3, 15), 1270)
+			.unwrap();
+		rtcifier
+			.align(Utc.ymd(2021, 8, 1).and_hms(17, 3, 16), 1300)
+			.unwrap();
+		rtcifier
+	}
+
+	#[test]
+	fn test_resume_restores_offset_across_wraparounds() {
+		let mut trained = trained_rtcifier();
+		// advance the local timeline so that the snapshot has to account for it
+		assert_eq!(
+			trained.map_to_rtc(1400).unwrap(),
+			Utc.ymd(2021, 8, 1).and_hms_milli(17, 3, 16, 115)
+		);
+		let snapshot = trained.snapshot().unwrap();
+
+		// 100.25s later, the counter wrapped around once
+		let mut rtcifier = new_rtcifier();
+		assert!(rtcifier.resume(&snapshot, Utc.ymd(2021, 8, 1).and_hms(17, 4, 56), 36014));
+		assert!(rtcifier.ready());
+		assert_eq!(
+			rtcifier.map_to_rtc(36014).unwrap(),
+			Utc.ymd(2021, 8, 1).and_hms_milli(17, 4, 56, 265)
+		);
+	}
+
+	#[test]
+	fn test_resume_rejects_inconsistent_counter() {
+		let snapshot = trained_rtcifier().snapshot().unwrap();
+		let mut rtcifier = new_rtcifier();
+		assert!(!rtcifier.resume(&snapshot, Utc.ymd(2021, 8, 1).and_hms(17, 4, 56), 20000));
+		assert!(!rtcifier.ready());
+	}
+
+	#[test]
+	fn test_untrained_has_no_snapshot() {
+		let mut rtcifier = new_rtcifier();
+		assert!(rtcifier.snapshot().is_none());
+		rtcifier
+			.align(Utc.ymd(2021, 8, 1).and_hms(17, 3, 15), 1270)
+			.unwrap();
+		assert!(rtcifier.snapshot().is_none());
+	}
 }
 
 #[derive(Debug, Clone)]
@@ -1113,17 +1333,17 @@ impl Default for RangeRTCLiori {
 
 #[cfg(feature = "unstable-rtcs")]
 impl RTCifier for RangeRTCLiori {
-	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) {
+	fn align(&mut self, rtc: DateTime<Utc>, timestamp: u16) -> Result<(), TimelineOverflow> {
 		let state = match self.state.as_mut() {
 			None => {
 				self.timeline.reset(timestamp);
 				self.state = Some(RangeRTCLioriState::new(rtc, 0));
-				return;
+				return Ok(());
 			}
 			Some(st) => st,
 		};
 
-		let abs_ctr = self.timeline.feed_and_transform(timestamp);
+		let abs_ctr = self.timeline.feed_and_transform(timestamp)?;
 		if rtc != state.prev_rtc {
 			// second transition
 			// calculate the differences
@@ -1142,14 +1362,15 @@ impl RTCifier for RangeRTCLiori {
 		}
 		state.prev_abs_ctr = abs_ctr;
 		state.prev_rtc = rtc;
+		Ok(())
 	}
 
-	fn map_to_rtc(&mut self, timestamp: u16) -> DateTime<Utc> {
+	fn map_to_rtc(&mut self, timestamp: u16) -> Result<DateTime<Utc>, TimelineOverflow> {
 		let min = self.get_min_estimate();
 		let max = self.get_max_estimate();
 		let offset = max / 2 + min / 2;
-		let abs_ctr = self.timeline.feed_and_transform(timestamp);
-		self.state.as_ref().unwrap().rtc_epoch + Duration::milliseconds(abs_ctr + offset)
+		let abs_ctr = self.timeline.feed_and_transform(timestamp)?;
+		Ok(self.state.as_ref().unwrap().rtc_epoch + Duration::milliseconds(abs_ctr + offset))
 	}
 
 	fn reset(&mut self) {
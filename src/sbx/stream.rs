@@ -1,4 +1,5 @@
 use std::convert::TryFrom;
+use std::fmt;
 use std::io;
 use std::sync::Arc;
 use std::time::Duration as StdDuration;
@@ -15,7 +16,39 @@ use crate::metric;
 use crate::stream;
 
 use super::frame;
-use super::rtcifier::{LinearRTC, RTCifier};
+use super::rtcifier::{LinearRTC, LinearRTCSnapshot, RTCifier, TimelineOverflow};
+
+/// Failure to decode a stream message.
+#[derive(Debug)]
+pub enum DecodeError {
+	/// The message is malformed; the decoder is unaffected.
+	Malformed(io::Error),
+	/// The timestamp of the message could not be mapped to the RTC.
+	Timeline(TimelineOverflow),
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Malformed(e) => write!(f, "malformed stream message: {}", e),
+			Self::Timeline(e) => fmt::Display::fmt(e, f),
+		}
+	}
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+	fn from(other: io::Error) -> Self {
+		Self::Malformed(other)
+	}
+}
+
+impl From<TimelineOverflow> for DecodeError {
+	fn from(other: TimelineOverflow) -> Self {
+		Self::Timeline(other)
+	}
+}
 
 #[derive(Debug, Clone, Copy, Enum)]
 pub enum StreamKind {
@@ -238,7 +271,7 @@ impl<T: stream::StreamBuffer + Sync + Send + 'static> StreamDecoder<T> {
 		}
 	}
 
-	pub fn align(&mut self, rtc: DateTime<Utc>, seq: u16) {
+	pub fn align(&mut self, rtc: DateTime<Utc>, seq: u16) -> Result<(), TimelineOverflow> {
 		self.rtcifier.align(rtc, seq)
 	}
 
@@ -250,17 +283,31 @@ impl<T: stream::StreamBuffer + Sync + Send + 'static> StreamDecoder<T> {
 		self.rtcifier.reset()
 	}
 
+	pub fn snapshot(&self) -> Option<LinearRTCSnapshot> {
+		self.rtcifier.snapshot()
+	}
+
+	/// Restore the alignment from a snapshot; see [`LinearRTC::resume`].
+	pub fn resume(
+		&mut self,
+		snapshot: &LinearRTCSnapshot,
+		rtc: DateTime<Utc>,
+		seq: u16,
+	) -> Result<bool, TimelineOverflow> {
+		self.rtcifier.resume(snapshot, rtc, seq)
+	}
+
 	pub fn decode<'m>(
 		&mut self,
 		kind: StreamKind,
 		message: &'m frame::SbxStreamMessage,
-	) -> io::Result<()> {
+	) -> Result<(), DecodeError> {
 		let mut samples = Vec::new();
 		let iter = Decompress::new(message.avg, message.coded.clone())?;
 		samples.reserve(iter.size_hint().1.unwrap());
 		samples.extend(iter);
 		samples.shrink_to_fit();
-		let t0 = self.rtcifier.map_to_rtc(message.seq);
+		let t0 = self.rtcifier.map_to_rtc(message.seq)?;
 		match self.buffer.write(&metric::StreamBlock {
 			t0,
 			seq0: message.seq,
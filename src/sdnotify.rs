@@ -0,0 +1,155 @@
+//! # systemd service notifications
+//!
+//! Minimal implementation of the `sd_notify(3)` protocol: state changes are
+//! sent as newline-separated `KEY=value` assignments in a single datagram to
+//! the unix socket named by the `NOTIFY_SOCKET` environment variable.
+use std::env;
+use std::io;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::time::Duration;
+
+pub struct Notifier {
+	socket: UnixDatagram,
+	addr: SocketAddr,
+}
+
+fn parse_addr(path: &str) -> io::Result<SocketAddr> {
+	match path.strip_prefix('@') {
+		#[cfg(target_os = "linux")]
+		Some(name) => {
+			use std::os::linux::net::SocketAddrExt;
+			SocketAddr::from_abstract_name(name)
+		}
+		#[cfg(not(target_os = "linux"))]
+		Some(_) => Err(io::Error::new(
+			io::ErrorKind::Unsupported,
+			"abstract notify sockets are only supported on linux",
+		)),
+		None => SocketAddr::from_pathname(path),
+	}
+}
+
+impl Notifier {
+	/// Create a notifier for the socket systemd passed to us, if any.
+	pub fn from_env() -> io::Result<Option<Self>> {
+		match env::var("NOTIFY_SOCKET") {
+			Ok(path) if !path.is_empty() => Ok(Some(Self::new(&path)?)),
+			_ => Ok(None),
+		}
+	}
+
+	/// Create a notifier sending to the socket at `path`.
+	///
+	/// Paths starting with `@` refer to the abstract namespace.
+	pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		let path = path.as_ref();
+		let addr = match path.to_str() {
+			Some(path) => parse_addr(path)?,
+			None => SocketAddr::from_pathname(path)?,
+		};
+		Ok(Self {
+			socket: UnixDatagram::unbound()?,
+			addr,
+		})
+	}
+
+	/// Send a set of state assignments in a single message.
+	pub fn notify(&self, state: &[(&str, &str)]) -> io::Result<()> {
+		let mut msg = String::new();
+		for (key, value) in state.iter() {
+			msg.push_str(key);
+			msg.push('=');
+			// newlines would start a new assignment
+			msg.extend(value.chars().map(|ch| if ch == '\n' { ' ' } else { ch }));
+			msg.push('\n');
+		}
+		self.socket.send_to_addr(msg.as_bytes(), &self.addr)?;
+		Ok(())
+	}
+
+	/// Signal that startup is complete, together with a status text.
+	pub fn ready(&self, status: &str) -> io::Result<()> {
+		self.notify(&[("READY", "1"), ("STATUS", status)])
+	}
+
+	pub fn status(&self, status: &str) -> io::Result<()> {
+		self.notify(&[("STATUS", status)])
+	}
+
+	pub fn watchdog(&self) -> io::Result<()> {
+		self.notify(&[("WATCHDOG", "1")])
+	}
+
+	pub fn stopping(&self) -> io::Result<()> {
+		self.notify(&[("STOPPING", "1")])
+	}
+}
+
+/// Interval in which systemd expects watchdog pings from this process, if
+/// the watchdog is enabled.
+pub fn watchdog_interval() -> Option<Duration> {
+	if let Ok(pid) = env::var("WATCHDOG_PID") {
+		if pid.parse::<u32>().ok()? != std::process::id() {
+			return None;
+		}
+	}
+	let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+	if usec == 0 {
+		return None;
+	}
+	Some(Duration::from_micros(usec))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn bind(name: &str) -> (UnixDatagram, std::path::PathBuf) {
+		let path = env::temp_dir().join(format!(
+			"metric-relay-notify-{}-{}",
+			name,
+			std::process::id()
+		));
+		let _ = std::fs::remove_file(&path);
+		(UnixDatagram::bind(&path).unwrap(), path)
+	}
+
+	fn recv(sock: &UnixDatagram) -> String {
+		let mut buf = [0u8; 1024];
+		let n = sock.recv(&mut buf[..]).unwrap();
+		String::from_utf8(buf[..n].to_vec()).unwrap()
+	}
+
+	#[test]
+	fn sends_ready_with_status() {
+		let (sock, path) = bind("ready");
+		let notifier = Notifier::new(&path).unwrap();
+		notifier.ready("3 nodes running").unwrap();
+		assert_eq!(recv(&sock), "READY=1\nSTATUS=3 nodes running\n");
+		notifier.watchdog().unwrap();
+		assert_eq!(recv(&sock), "WATCHDOG=1\n");
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn strips_newlines_from_values() {
+		let (sock, path) = bind("newline");
+		let notifier = Notifier::new(&path).unwrap();
+		notifier.status("a\nb").unwrap();
+		assert_eq!(recv(&sock), "STATUS=a b\n");
+		std::fs::remove_file(&path).unwrap();
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn supports_abstract_sockets() {
+		use std::os::linux::net::SocketAddrExt;
+		let name = format!("metric-relay-notify-{}", std::process::id());
+		let sock =
+			UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+		let notifier = Notifier::new(format!("@{}", name)).unwrap();
+		notifier.stopping().unwrap();
+		assert_eq!(recv(&sock), "STOPPING=1\n");
+	}
+}
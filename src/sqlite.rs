@@ -0,0 +1,344 @@
+//! # Local storage of readouts in SQLite
+//!
+//! Each component of a device path becomes a series, and its values are
+//! stored with millisecond timestamps:
+//!
+//! ```sql
+//! series(id, device_type, instance, component, unit)
+//! readings(series_id, timestamp_ms, value)
+//! ```
+//!
+//! The `readings_view` view joins both and renders the timestamps as ISO
+//! 8601, so that the data can be looked at with the `sqlite3` shell
+//! directly. Metadata of stream blocks (but not their samples) may
+//! optionally be kept in `stream_blocks`.
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use smartstring::alias::String as SmartString;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::metric;
+
+pub use rusqlite::Error;
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS series (
+	id INTEGER PRIMARY KEY,
+	device_type TEXT NOT NULL,
+	instance TEXT NOT NULL,
+	component TEXT NOT NULL,
+	unit TEXT NOT NULL,
+	UNIQUE (device_type, instance, component, unit)
+);
+CREATE TABLE IF NOT EXISTS readings (
+	series_id INTEGER NOT NULL REFERENCES series (id),
+	timestamp_ms INTEGER NOT NULL,
+	value REAL
+);
+CREATE INDEX IF NOT EXISTS readings_by_series ON readings (series_id, timestamp_ms);
+CREATE INDEX IF NOT EXISTS readings_by_time ON readings (timestamp_ms);
+CREATE VIEW IF NOT EXISTS readings_view AS
+	SELECT
+		strftime('%Y-%m-%dT%H:%M:%fZ', r.timestamp_ms / 1000.0, 'unixepoch') AS time,
+		s.device_type, s.instance, s.component, s.unit, r.value
+	FROM readings r JOIN series s ON s.id = r.series_id;
+";
+
+const STREAM_SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS stream_blocks (
+	device_type TEXT NOT NULL,
+	instance TEXT NOT NULL,
+	t0_ms INTEGER NOT NULL,
+	seq0 INTEGER NOT NULL,
+	period_us INTEGER NOT NULL,
+	length INTEGER NOT NULL,
+	scale REAL NOT NULL,
+	unit TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS stream_blocks_by_time ON stream_blocks (t0_ms);
+";
+
+/// Name under which a unit is stored; unlike its display form, this
+/// distinguishes counters from arbitrary values.
+fn unit_name(unit: &metric::Unit) -> String {
+	match unit {
+		metric::Unit::Total => "cnt".into(),
+		metric::Unit::Status => "status".into(),
+		other => other.to_string(),
+	}
+}
+
+type SeriesKey = (metric::DevicePath, SmartString, String);
+
+pub struct Database {
+	conn: Connection,
+	stream_blocks: bool,
+	/// Ids of the series seen so far.
+	series: HashMap<SeriesKey, i64>,
+}
+
+impl Database {
+	/// Open (or create) the database file and set up the schema.
+	pub fn open<P: AsRef<Path>>(path: P, stream_blocks: bool) -> Result<Self, Error> {
+		let conn = Connection::open(path)?;
+		// allow reading the database while we write to it
+		conn.pragma_update(None, "journal_mode", "WAL")?;
+		conn.pragma_update(None, "synchronous", "NORMAL")?;
+		// others may hold a lock briefly, e.g. while running a query
+		conn.busy_timeout(BUSY_TIMEOUT)?;
+		Self::with_connection(conn, stream_blocks)
+	}
+
+	pub fn with_connection(conn: Connection, stream_blocks: bool) -> Result<Self, Error> {
+		conn.execute_batch(SCHEMA)?;
+		if stream_blocks {
+			conn.execute_batch(STREAM_SCHEMA)?;
+		}
+		Ok(Self {
+			conn,
+			stream_blocks,
+			series: HashMap::new(),
+		})
+	}
+
+	fn series_id(
+		tx: &rusqlite::Transaction,
+		cache: &mut HashMap<SeriesKey, i64>,
+		path: &metric::DevicePath,
+		component: &SmartString,
+		unit: &metric::Unit,
+	) -> Result<i64, Error> {
+		let key = (path.clone(), component.clone(), unit_name(unit));
+		if let Some(id) = cache.get(&key) {
+			return Ok(*id);
+		}
+		let (path, component, unit) = &key;
+		let params = params![
+			path.device_type.as_str(),
+			path.instance.as_str(),
+			component.as_str(),
+			unit
+		];
+		let existing = tx
+			.prepare_cached(
+				"SELECT id FROM series WHERE device_type = ?1 AND instance = ?2 AND component = ?3 AND unit = ?4",
+			)?
+			.query_row(params, |row| row.get(0))
+			.optional()?;
+		let id = match existing {
+			Some(id) => id,
+			None => {
+				tx.prepare_cached(
+					"INSERT INTO series (device_type, instance, component, unit) VALUES (?1, ?2, ?3, ?4)",
+				)?
+				.execute(params)?;
+				tx.last_insert_rowid()
+			}
+		};
+		cache.insert(key, id);
+		Ok(id)
+	}
+
+	fn insert_tx(
+		&mut self,
+		readouts: &[&metric::Readout],
+		blocks: &[&metric::StreamBlock],
+	) -> Result<usize, Error> {
+		let tx = self.conn.transaction()?;
+		let mut rows = 0;
+		for readout in readouts {
+			let timestamp = readout.timestamp.timestamp_millis();
+			for (component, value) in readout.components.iter() {
+				let id =
+					Self::series_id(&tx, &mut self.series, &readout.path, component, &value.unit)?;
+				// NaN would be stored as NULL anyway, make it explicit
+				let magnitude = Some(value.magnitude).filter(|v| !v.is_nan());
+				tx.prepare_cached(
+					"INSERT INTO readings (series_id, timestamp_ms, value) VALUES (?1, ?2, ?3)",
+				)?
+				.execute(params![id, timestamp, magnitude])?;
+				rows += 1;
+			}
+		}
+		if self.stream_blocks {
+			for block in blocks {
+				tx.prepare_cached(
+					"INSERT INTO stream_blocks (device_type, instance, t0_ms, seq0, period_us, length, scale, unit) \
+					 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+				)?
+				.execute(params![
+					block.path.device_type.as_str(),
+					block.path.instance.as_str(),
+					block.t0.timestamp_millis(),
+					block.seq0,
+					block.period.as_micros() as i64,
+					block.data.len() as i64,
+					block.scale.magnitude,
+					unit_name(&block.scale.unit),
+				])?;
+				rows += 1;
+			}
+		}
+		tx.commit()?;
+		Ok(rows)
+	}
+
+	/// Write readouts and stream block metadata in a single transaction.
+	///
+	/// Returns the number of rows written.
+	pub fn insert(
+		&mut self,
+		readouts: &[&metric::Readout],
+		blocks: &[&metric::StreamBlock],
+	) -> Result<usize, Error> {
+		let result = self.insert_tx(readouts, blocks);
+		if result.is_err() {
+			// series created in the rolled back transaction are gone again
+			self.series.clear();
+		}
+		result
+	}
+
+	/// Delete all values and stream blocks older than `before`.
+	///
+	/// Series are kept, even if they have no values left. Returns the
+	/// number of rows deleted.
+	pub fn prune(&mut self, before: DateTime<Utc>) -> Result<usize, Error> {
+		let before = before.timestamp_millis();
+		let tx = self.conn.transaction()?;
+		let mut rows = tx.execute(
+			"DELETE FROM readings WHERE timestamp_ms < ?1",
+			params![before],
+		)?;
+		if self.stream_blocks {
+			rows += tx.execute(
+				"DELETE FROM stream_blocks WHERE t0_ms < ?1",
+				params![before],
+			)?;
+		}
+		tx.commit()?;
+		Ok(rows)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::sync::Arc;
+
+	use chrono::TimeZone;
+
+	fn readout(t: i64, components: &[(&str, f64, metric::Unit)]) -> metric::Readout {
+		let mut result = metric::ReadoutBuilder::new()
+			.instance("/sbx/i2c/0x76")
+			.at(t);
+		for (name, magnitude, unit) in components {
+			result = result.component(name, *magnitude, unit.clone());
+		}
+		result.build()
+	}
+
+	fn count(db: &Database, table: &str) -> i64 {
+		db.conn
+			.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+				row.get(0)
+			})
+			.unwrap()
+	}
+
+	#[test]
+	fn stores_readouts_by_series() {
+		let mut db =
+			Database::with_connection(Connection::open_in_memory().unwrap(), false).unwrap();
+		let a = readout(
+			1600000000,
+			&[
+				("temperature", 21.5, metric::Unit::Celsius),
+				("resets", 2.0, metric::Unit::Total),
+			],
+		);
+		let b = readout(
+			1600000001,
+			&[("temperature", f64::NAN, metric::Unit::Celsius)],
+		);
+		assert_eq!(db.insert(&[&a, &b], &[]).unwrap(), 3);
+		assert_eq!(count(&db, "series"), 2);
+
+		// a fresh connection has to find the existing series again
+		db.series.clear();
+		db.insert(&[&a], &[]).unwrap();
+		assert_eq!(count(&db, "series"), 2);
+
+		let rows: Vec<(String, String, String, Option<f64>)> = db
+			.conn
+			.prepare(
+				"SELECT time, component, unit, value FROM readings_view ORDER BY time, component",
+			)
+			.unwrap()
+			.query_map([], |row| {
+				Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+			})
+			.unwrap()
+			.collect::<Result<_, _>>()
+			.unwrap();
+		assert_eq!(
+			rows[0],
+			(
+				"2020-09-13T12:26:40.000Z".into(),
+				"resets".into(),
+				"cnt".into(),
+				Some(2.0)
+			)
+		);
+		assert_eq!(rows[2].2, "°C");
+		assert_eq!(rows[4].3, None);
+	}
+
+	#[test]
+	fn prunes_old_values_and_blocks() {
+		let mut db =
+			Database::with_connection(Connection::open_in_memory().unwrap(), true).unwrap();
+		let block = metric::StreamBlock {
+			t0: Utc.timestamp_opt(1600000000, 0).unwrap(),
+			path: metric::DevicePath {
+				device_type: "lsm303d".into(),
+				instance: "accel".into(),
+			},
+			seq0: 7,
+			period: Duration::from_millis(10),
+			scale: metric::Value {
+				magnitude: 0.5,
+				unit: metric::Unit::MeterPerSqSecond,
+			},
+			data: Arc::new(metric::RawData::I16(
+				metric::MaskedArray::from_unmasked_vec(vec![1, 2, 3]),
+			)),
+		};
+		let old = readout(1600000000, &[("temperature", 20.0, metric::Unit::Celsius)]);
+		let new = readout(1600000100, &[("temperature", 21.0, metric::Unit::Celsius)]);
+		assert_eq!(db.insert(&[&old, &new], &[&block]).unwrap(), 3);
+		let (period, length): (i64, i64) = db
+			.conn
+			.query_row("SELECT period_us, length FROM stream_blocks", [], |row| {
+				Ok((row.get(0)?, row.get(1)?))
+			})
+			.unwrap();
+		assert_eq!((period, length), (10000, 3));
+
+		assert_eq!(
+			db.prune(Utc.timestamp_opt(1600000050, 0).unwrap()).unwrap(),
+			2
+		);
+		assert_eq!(count(&db, "readings"), 1);
+		assert_eq!(count(&db, "stream_blocks"), 0);
+		assert_eq!(count(&db, "series"), 1);
+	}
+}
@@ -0,0 +1,253 @@
+//! # StatsD and DogStatsD metrics
+//!
+//! Components become gauges, except for [`metric::Unit::Total`] components:
+//! these are running totals, while StatsD counters are increments, so the
+//! difference to the previous readout is sent instead.
+//!
+//! StatsD reads a signed gauge value as relative change. Negative values
+//! are thus sent as a reset to zero followed by the (relative) value, which
+//! gives the right result with either interpretation.
+use std::collections::HashMap;
+
+use microtemplate::{render, Substitutions};
+
+use smartstring::alias::String as SmartString;
+
+use crate::metric;
+
+#[derive(Substitutions)]
+struct TemplateArgs<'a> {
+	device_type: &'a str,
+	instance: &'a str,
+	component: &'a str,
+}
+
+fn sanitize(s: &str, keep: &[char]) -> String {
+	s.chars()
+		.map(|ch| {
+			if ch.is_ascii_alphanumeric() || keep.contains(&ch) {
+				ch
+			} else {
+				'_'
+			}
+		})
+		.collect()
+}
+
+/// Builds metric names from a template over `{device_type}`, `{instance}`
+/// and `{component}`; slashes in instances become dots.
+pub struct NameTemplate {
+	template: String,
+}
+
+impl NameTemplate {
+	pub fn new(template: String) -> Self {
+		Self { template }
+	}
+
+	pub fn render(&self, path: &metric::DevicePath, component: &str) -> String {
+		let instance = path
+			.instance
+			.split('/')
+			.filter(|part| !part.is_empty())
+			.map(|part| sanitize(part, &['-', '_']))
+			.collect::<Vec<_>>()
+			.join(".");
+		let rendered = render(
+			&self.template,
+			TemplateArgs {
+				device_type: &sanitize(&path.device_type, &['-', '_']),
+				instance: &instance,
+				component: &sanitize(component, &['-', '_']),
+			},
+		);
+		rendered
+			.split('.')
+			.filter(|node| !node.is_empty())
+			.collect::<Vec<_>>()
+			.join(".")
+	}
+}
+
+/// DogStatsD tags added to each metric.
+pub struct Tags {
+	/// Name of the tag carrying the device type, if any.
+	pub device_type: Option<String>,
+	/// Name of the tag carrying the instance, if any.
+	pub instance: Option<String>,
+	pub extra: Vec<(String, String)>,
+}
+
+fn tag_value(s: &str) -> String {
+	sanitize(s, &['-', '_', '.', '/', ':'])
+}
+
+impl Tags {
+	fn write(&self, path: &metric::DevicePath, out: &mut String) {
+		let mut tags = Vec::new();
+		if let Some(name) = self.device_type.as_ref() {
+			tags.push(format!("{}:{}", name, tag_value(&path.device_type)));
+		}
+		if let Some(name) = self.instance.as_ref() {
+			if !path.instance.is_empty() {
+				tags.push(format!("{}:{}", name, tag_value(&path.instance)));
+			}
+		}
+		for (k, v) in self.extra.iter() {
+			tags.push(format!("{}:{}", tag_value(k), tag_value(v)));
+		}
+		if !tags.is_empty() {
+			out.push_str("|#");
+			out.push_str(&tags.join(","));
+		}
+	}
+}
+
+/// Turns readouts into metric lines, keeping track of totals.
+pub struct Encoder {
+	name: NameTemplate,
+	tags: Option<Tags>,
+	totals: HashMap<(metric::DevicePath, SmartString), f64>,
+}
+
+impl Encoder {
+	pub fn new(name: NameTemplate, tags: Option<Tags>) -> Self {
+		Self {
+			name,
+			tags,
+			totals: HashMap::new(),
+		}
+	}
+
+	fn line(&self, path: &metric::DevicePath, name: &str, value: f64, kind: &str) -> String {
+		let mut line = format!("{}:{}|{}", name, value, kind);
+		if let Some(tags) = self.tags.as_ref() {
+			tags.write(path, &mut line);
+		}
+		line
+	}
+
+	/// Metrics for a readout.
+	///
+	/// Each item is sent as is and may consist of multiple lines, which
+	/// must not be split across datagrams.
+	pub fn encode(&mut self, readout: &metric::Readout) -> Vec<String> {
+		let mut result = Vec::new();
+		for (component, value) in readout.components.iter() {
+			if !value.magnitude.is_finite() {
+				continue;
+			}
+			let name = self.name.render(&readout.path, component);
+			match value.unit {
+				metric::Unit::Total => {
+					let previous = self
+						.totals
+						.insert((readout.path.clone(), component.clone()), value.magnitude);
+					let delta = match previous {
+						// the first readout only tells us where we start
+						None => continue,
+						// a counter reset starts over from zero
+						Some(prev) if value.magnitude < prev => value.magnitude,
+						Some(prev) => value.magnitude - prev,
+					};
+					if delta != 0.0 {
+						result.push(self.line(&readout.path, &name, delta, "c"));
+					}
+				}
+				_ => {
+					let mut item = String::new();
+					if value.magnitude < 0.0 {
+						item = self.line(&readout.path, &name, 0.0, "g");
+						item.push('\n');
+					}
+					item.push_str(&self.line(&readout.path, &name, value.magnitude, "g"));
+					result.push(item);
+				}
+			}
+		}
+		result
+	}
+}
+
+/// Pack items into newline-separated datagrams of at most `max` bytes.
+///
+/// Items which are longer than `max` on their own get a datagram of their
+/// own.
+pub fn pack(items: &[String], max: usize) -> Vec<String> {
+	let mut result = Vec::new();
+	let mut current = String::new();
+	for item in items {
+		if !current.is_empty() && current.len() + 1 + item.len() > max {
+			result.push(std::mem::take(&mut current));
+		}
+		if !current.is_empty() {
+			current.push('\n');
+		}
+		current.push_str(item);
+	}
+	if !current.is_empty() {
+		result.push(current);
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn readout(components: &[(&str, f64, metric::Unit)]) -> metric::Readout {
+		let mut result = metric::ReadoutBuilder::new().instance("/sbx/i2c-2/76");
+		for (name, magnitude, unit) in components {
+			result = result.component(name, *magnitude, unit.clone());
+		}
+		result.build()
+	}
+
+	#[test]
+	fn encodes_gauges_and_counter_deltas() {
+		let mut encoder = Encoder::new(
+			NameTemplate::new("relay.{device_type}.{instance}.{component}".into()),
+			None,
+		);
+		let items = encoder.encode(&readout(&[
+			("temperature", -3.5, metric::Unit::Celsius),
+			("resets", 4.0, metric::Unit::Total),
+		]));
+		assert_eq!(
+			items,
+			vec!["relay.bme280.sbx.i2c-2.76.temperature:0|g\nrelay.bme280.sbx.i2c-2.76.temperature:-3.5|g"]
+		);
+		let items = encoder.encode(&readout(&[("resets", 6.0, metric::Unit::Total)]));
+		assert_eq!(items, vec!["relay.bme280.sbx.i2c-2.76.resets:2|c"]);
+		assert!(encoder
+			.encode(&readout(&[("resets", 6.0, metric::Unit::Total)]))
+			.is_empty());
+		let items = encoder.encode(&readout(&[("resets", 1.0, metric::Unit::Total)]));
+		assert_eq!(items, vec!["relay.bme280.sbx.i2c-2.76.resets:1|c"]);
+	}
+
+	#[test]
+	fn adds_dogstatsd_tags() {
+		let mut encoder = Encoder::new(
+			NameTemplate::new("{device_type}.{component}".into()),
+			Some(Tags {
+				device_type: None,
+				instance: Some("instance".into()),
+				extra: vec![("site".into(), "lab, 2".into())],
+			}),
+		);
+		let items = encoder.encode(&readout(&[("humidity", 40.0, metric::Unit::Percent)]));
+		assert_eq!(
+			items,
+			vec!["bme280.humidity:40|g|#instance:/sbx/i2c-2/76,site:lab__2"]
+		);
+	}
+
+	#[test]
+	fn packs_items_into_datagrams() {
+		let items: Vec<String> = vec!["a:1|g".into(), "b:2|g".into(), "c:3|g\nc:4|g".into()];
+		assert_eq!(pack(&items, 11), vec!["a:1|g\nb:2|g", "c:3|g\nc:4|g"]);
+		assert_eq!(pack(&items, 4).len(), 3);
+		assert!(pack(&[], 10).is_empty());
+	}
+}
@@ -0,0 +1,136 @@
+//! Minimal stand-in for HTTP APIs, for use in tests.
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+	/// Request line and headers, as sent by the client.
+	pub head: String,
+	pub body: Vec<u8>,
+}
+
+impl Request {
+	/// The request target, e.g. `/write?db=foo`.
+	pub fn target(&self) -> &str {
+		self.head.split(' ').nth(1).unwrap_or("")
+	}
+
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.head.lines().skip(1).find_map(|line| {
+			let (k, v) = line.split_once(':')?;
+			if k.eq_ignore_ascii_case(name) {
+				Some(v.trim())
+			} else {
+				None
+			}
+		})
+	}
+
+	pub fn body_str(&self) -> String {
+		String::from_utf8_lossy(&self.body).into()
+	}
+}
+
+pub type Responder = Box<dyn Fn(&Request) -> u16 + Send + Sync>;
+
+/// HTTP server answering each request with the status code chosen by the
+/// responder and recording all requests.
+pub struct StandIn {
+	pub url: String,
+	requests: Arc<Mutex<Vec<(Request, u16)>>>,
+	respond: Arc<Mutex<Responder>>,
+}
+
+async fn read_request(sock: &mut TcpStream) -> Option<Request> {
+	let mut buf = Vec::new();
+	let header_end = loop {
+		let mut chunk = [0u8; 1024];
+		let n = sock.read(&mut chunk).await.ok()?;
+		if n == 0 {
+			return None;
+		}
+		buf.extend_from_slice(&chunk[..n]);
+		if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+			break pos + 4;
+		}
+	};
+	let head = String::from_utf8_lossy(&buf[..header_end - 4]).into_owned();
+	let mut request = Request {
+		head,
+		body: Vec::new(),
+	};
+	let length: usize = request
+		.header("content-length")
+		.map(|v| v.parse().unwrap())
+		.unwrap_or(0);
+	while buf.len() < header_end + length {
+		let mut chunk = [0u8; 1024];
+		let n = sock.read(&mut chunk).await.ok()?;
+		if n == 0 {
+			return None;
+		}
+		buf.extend_from_slice(&chunk[..n]);
+	}
+	request.body = buf[header_end..header_end + length].to_vec();
+	Some(request)
+}
+
+impl StandIn {
+	pub async fn start(respond: Responder) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		let respond = Arc::new(Mutex::new(respond));
+		let result = Self {
+			url,
+			requests: requests.clone(),
+			respond: respond.clone(),
+		};
+		tokio::spawn(async move {
+			loop {
+				let (mut sock, _) = listener.accept().await.unwrap();
+				let requests = requests.clone();
+				let respond = respond.clone();
+				tokio::spawn(async move {
+					while let Some(request) = read_request(&mut sock).await {
+						let status = (respond.lock().unwrap())(&request);
+						requests.lock().unwrap().push((request, status));
+						let response =
+							format!("HTTP/1.1 {} Whatever\r\ncontent-length: 0\r\n\r\n", status);
+						if sock.write_all(response.as_bytes()).await.is_err() {
+							return;
+						}
+					}
+				});
+			}
+		});
+		result
+	}
+
+	pub fn set_responder(&self, respond: Responder) {
+		*self.respond.lock().unwrap() = respond;
+	}
+
+	/// All requests received so far.
+	pub fn requests(&self) -> Vec<Request> {
+		self.requests
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|(req, _)| req.clone())
+			.collect()
+	}
+
+	/// Bodies of the requests which were answered with 204 No Content.
+	pub fn accepted(&self) -> Vec<String> {
+		self.requests
+			.lock()
+			.unwrap()
+			.iter()
+			.filter(|(_, status)| *status == 204)
+			.map(|(req, _)| req.body_str())
+			.collect()
+	}
+}
@@ -0,0 +1,394 @@
+//! # Templated HTTP requests
+//!
+//! URLs, headers and bodies of webhook requests are templates with
+//! `{{field}}` placeholders (single braces are left alone, so that JSON
+//! bodies can be written as is).
+//!
+//! Requests are either made per readout, with these fields:
+//!
+//! - `device_type`, `instance`
+//! - `timestamp` (RFC 3339), `timestamp_s`, `timestamp_ms`
+//! - `value.<component>`, `unit.<component>`
+//! - `components`: JSON object mapping component names to values
+//! - `readout`: the whole readout as JSON object
+//!
+//! or per batch of readouts, with these fields:
+//!
+//! - `readouts`: JSON array of the readouts, as in `readout`
+//! - `count`: number of readouts in the batch
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::http;
+use crate::metric;
+
+/// Everything but the unreserved characters of RFC 3986.
+const URL_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'_')
+	.remove(b'~');
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+	DeviceType,
+	Instance,
+	Timestamp,
+	TimestampS,
+	TimestampMs,
+	Value(String),
+	Unit(String),
+	Components,
+	Readout,
+	Readouts,
+	Count,
+}
+
+impl Field {
+	fn parse(name: &str) -> Option<Self> {
+		Some(match name {
+			"device_type" => Self::DeviceType,
+			"instance" => Self::Instance,
+			"timestamp" => Self::Timestamp,
+			"timestamp_s" => Self::TimestampS,
+			"timestamp_ms" => Self::TimestampMs,
+			"components" => Self::Components,
+			"readout" => Self::Readout,
+			"readouts" => Self::Readouts,
+			"count" => Self::Count,
+			other => {
+				if let Some(component) = other.strip_prefix("value.") {
+					Self::Value(component.into())
+				} else if let Some(component) = other.strip_prefix("unit.") {
+					Self::Unit(component.into())
+				} else {
+					return None;
+				}
+			}
+		})
+	}
+
+	fn is_batch(&self) -> bool {
+		matches!(self, Self::Readouts | Self::Count)
+	}
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+	Literal(String),
+	Field(Field),
+}
+
+/// How substituted values are escaped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+	/// Insert as is; line breaks are removed.
+	Text,
+	/// Percent-encode, for use in URLs.
+	Url,
+	/// Escape as within a JSON string; JSON fields are inserted as is.
+	Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+	Unterminated,
+	UnknownField(String),
+	/// A per readout field was used in a batch template or vice versa.
+	WrongMode(String),
+}
+
+impl fmt::Display for TemplateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Unterminated => f.write_str("unterminated {{ in template"),
+			Self::UnknownField(name) => write!(f, "unknown template field {:?}", name),
+			Self::WrongMode(name) => write!(
+				f,
+				"template field {:?} is not available in this mode (per readout or batch)",
+				name
+			),
+		}
+	}
+}
+
+impl std::error::Error for TemplateError {}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+	parts: Vec<Part>,
+}
+
+/// What a template is rendered for.
+#[derive(Clone, Copy)]
+pub enum Context<'a> {
+	Readout(&'a metric::Readout),
+	Batch(&'a [Arc<metric::Readout>]),
+}
+
+fn unit_str(unit: &metric::Unit) -> String {
+	match unit {
+		metric::Unit::Total => "cnt".into(),
+		other => other.to_string(),
+	}
+}
+
+fn components_json(readout: &metric::Readout) -> serde_json::Value {
+	readout
+		.components
+		.iter()
+		.map(|(k, v)| (k.to_string(), serde_json::Value::from(v.magnitude)))
+		.collect::<serde_json::Map<_, _>>()
+		.into()
+}
+
+/// The readout as JSON object, with value and unit of each component.
+pub fn readout_json(readout: &metric::Readout) -> serde_json::Value {
+	let components: serde_json::Map<_, _> = readout
+		.components
+		.iter()
+		.map(|(k, v)| {
+			(
+				k.to_string(),
+				serde_json::json!({
+					"value": v.magnitude,
+					"unit": unit_str(&v.unit),
+				}),
+			)
+		})
+		.collect();
+	serde_json::json!({
+		"device_type": readout.path.device_type.as_str(),
+		"instance": readout.path.instance.as_str(),
+		"timestamp": readout.timestamp.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+		"components": components,
+	})
+}
+
+/// A rendered value, before escaping.
+enum Rendered {
+	Text(String),
+	/// Numbers, `null` and JSON structures.
+	Json(String),
+}
+
+impl Template {
+	/// Parse a template for requests per readout or, if `batch` is set,
+	/// per batch of readouts.
+	pub fn parse(template: &str, batch: bool) -> Result<Self, TemplateError> {
+		let mut parts = Vec::new();
+		let mut rest = template;
+		while let Some(start) = rest.find("{{") {
+			if start > 0 {
+				parts.push(Part::Literal(rest[..start].into()));
+			}
+			let end = rest[start..]
+				.find("}}")
+				.ok_or(TemplateError::Unterminated)?;
+			let name = rest[start + 2..start + end].trim();
+			let field =
+				Field::parse(name).ok_or_else(|| TemplateError::UnknownField(name.into()))?;
+			if field.is_batch() != batch {
+				return Err(TemplateError::WrongMode(name.into()));
+			}
+			parts.push(Part::Field(field));
+			rest = &rest[start + end + 2..];
+		}
+		if !rest.is_empty() {
+			parts.push(Part::Literal(rest.into()));
+		}
+		Ok(Self { parts })
+	}
+
+	fn field(field: &Field, ctx: Context) -> Rendered {
+		match (ctx, field) {
+			(Context::Batch(readouts), Field::Readouts) => Rendered::Json(
+				serde_json::Value::Array(readouts.iter().map(|r| readout_json(r)).collect())
+					.to_string(),
+			),
+			(Context::Batch(readouts), Field::Count) => Rendered::Json(readouts.len().to_string()),
+			(Context::Readout(r), Field::DeviceType) => {
+				Rendered::Text(r.path.device_type.to_string())
+			}
+			(Context::Readout(r), Field::Instance) => Rendered::Text(r.path.instance.to_string()),
+			(Context::Readout(r), Field::Timestamp) => Rendered::Text(
+				r.timestamp
+					.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+			),
+			(Context::Readout(r), Field::TimestampS) => {
+				Rendered::Json(r.timestamp.timestamp().to_string())
+			}
+			(Context::Readout(r), Field::TimestampMs) => {
+				Rendered::Json(r.timestamp.timestamp_millis().to_string())
+			}
+			(Context::Readout(r), Field::Value(component)) => Rendered::Json(
+				serde_json::Value::from(
+					r.components
+						.get(component.as_str())
+						.map(|v| v.magnitude)
+						.unwrap_or(f64::NAN),
+				)
+				.to_string(),
+			),
+			(Context::Readout(r), Field::Unit(component)) => Rendered::Text(
+				r.components
+					.get(component.as_str())
+					.map(|v| unit_str(&v.unit))
+					.unwrap_or_default(),
+			),
+			(Context::Readout(r), Field::Components) => {
+				Rendered::Json(components_json(r).to_string())
+			}
+			(Context::Readout(r), Field::Readout) => Rendered::Json(readout_json(r).to_string()),
+			// excluded when parsing
+			_ => Rendered::Text(String::new()),
+		}
+	}
+
+	pub fn render(&self, ctx: Context, escape: Escape) -> String {
+		let mut out = String::new();
+		for part in self.parts.iter() {
+			let field = match part {
+				Part::Literal(s) => {
+					out.push_str(s);
+					continue;
+				}
+				Part::Field(field) => Self::field(field, ctx),
+			};
+			match (field, escape) {
+				(Rendered::Text(s), Escape::Json) => {
+					let quoted = serde_json::Value::String(s).to_string();
+					out.push_str(&quoted[1..quoted.len() - 1]);
+				}
+				(Rendered::Json(s), Escape::Json) => out.push_str(&s),
+				(Rendered::Text(s) | Rendered::Json(s), Escape::Url) => {
+					out.extend(utf8_percent_encode(&s, URL_ENCODE))
+				}
+				(Rendered::Text(s) | Rendered::Json(s), Escape::Text) => {
+					out.extend(s.chars().filter(|ch| *ch != '\r' && *ch != '\n'))
+				}
+			}
+		}
+		out
+	}
+}
+
+/// Templates for the parts of a request.
+pub struct RequestTemplate {
+	pub method: reqwest::Method,
+	pub url: Template,
+	pub headers: Vec<(String, Template)>,
+	pub body: Option<Template>,
+	/// Escaping of the body; JSON bodies are sent as `application/json`
+	/// unless a content type header is given.
+	pub body_escape: Escape,
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Client {
+	client: reqwest::Client,
+	request: RequestTemplate,
+}
+
+impl Client {
+	pub fn new(request: RequestTemplate) -> Self {
+		Self {
+			client: reqwest::Client::builder()
+				.timeout(REQUEST_TIMEOUT)
+				.build()
+				.expect("building the http client"),
+			request,
+		}
+	}
+
+	pub async fn send(&self, ctx: Context<'_>) -> Result<(), http::Error> {
+		let url = self.request.url.render(ctx, Escape::Url);
+		let mut req = self.client.request(self.request.method.clone(), url);
+		let mut has_content_type = false;
+		for (name, value) in self.request.headers.iter() {
+			has_content_type |= name.eq_ignore_ascii_case("content-type");
+			req = req.header(name, value.render(ctx, Escape::Text));
+		}
+		if let Some(body) = self.request.body.as_ref() {
+			if !has_content_type && self.request.body_escape == Escape::Json {
+				req = req.header("Content-Type", "application/json");
+			}
+			req = req.body(body.render(ctx, self.request.body_escape));
+		}
+		http::send(req).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn readout() -> metric::Readout {
+		metric::ReadoutBuilder::new()
+			.instance("/sbx/i2c/\"76\"")
+			.temperature(21.5)
+			.build()
+	}
+
+	#[test]
+	fn renders_per_readout_fields() {
+		let r = readout();
+		let body = Template::parse(
+			r#"{"device": "{{device_type}}{{ instance }}", "t": {{value.temperature}}, "p": {{value.pressure}}, "at": {{timestamp_s}}}"#,
+			false,
+		)
+		.unwrap();
+		let body = body.render(Context::Readout(&r), Escape::Json);
+		let v: serde_json::Value = serde_json::from_str(&body).unwrap();
+		assert_eq!(
+			v,
+			serde_json::json!({
+				"device": "bme280/sbx/i2c/\"76\"",
+				"t": 21.5,
+				"p": null,
+				"at": 1600000000,
+			})
+		);
+
+		let url = Template::parse("http://x/{{device_type}}?i={{instance}}", false).unwrap();
+		assert_eq!(
+			url.render(Context::Readout(&r), Escape::Url),
+			"http://x/bme280?i=%2Fsbx%2Fi2c%2F%2276%22"
+		);
+	}
+
+	#[test]
+	fn renders_batches() {
+		let readouts = vec![Arc::new(readout()), Arc::new(readout())];
+		let body = Template::parse(r#"{"n": {{count}}, "data": {{readouts}}}"#, true).unwrap();
+		let v: serde_json::Value =
+			serde_json::from_str(&body.render(Context::Batch(&readouts), Escape::Json)).unwrap();
+		assert_eq!(v["n"], 2);
+		assert_eq!(v["data"][1]["components"]["temperature"]["unit"], "°C");
+		assert_eq!(v["data"][0]["timestamp"], "2020-09-13T12:26:40.000000Z");
+	}
+
+	#[test]
+	fn rejects_invalid_templates() {
+		assert_eq!(
+			Template::parse("{{readouts}}", false).unwrap_err(),
+			TemplateError::WrongMode("readouts".into())
+		);
+		assert_eq!(
+			Template::parse("{{instance}}", true).unwrap_err(),
+			TemplateError::WrongMode("instance".into())
+		);
+		assert!(matches!(
+			Template::parse("{{nope}}", false),
+			Err(TemplateError::UnknownField(_))
+		));
+		assert_eq!(
+			Template::parse("{{instance", false).unwrap_err(),
+			TemplateError::Unterminated
+		);
+	}
+}